@@ -0,0 +1,108 @@
+//! Transparency: the modes only work on the colour channels, the alpha plane
+//! of the input is set aside and put back unchanged into the output.
+
+use image::{DynamicImage, GrayImage, ImageFormat, RgbImage, RgbaImage};
+
+/// Alpha value of a pixel that is not drawn at all.
+pub const TRANSPARENT: u8 = 0;
+
+/// Splits an image into its colour channels and, when it has one, its alpha plane.
+pub fn split_alpha(img: &DynamicImage) -> (RgbImage, Option<GrayImage>) {
+    if !img.color().has_alpha() {
+        return (img.to_rgb8(), None);
+    }
+    let (rgb, alpha) = split_rgba(&img.to_rgba8());
+    (rgb, Some(alpha))
+}
+
+/// The colour channels and the alpha plane of an RGBA buffer.
+pub fn split_rgba(img: &RgbaImage) -> (RgbImage, GrayImage) {
+    let rgb = img.chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect();
+    let alpha = img.chunks_exact(4).map(|pixel| pixel[3]).collect();
+    (
+        RgbImage::from_raw(img.width(), img.height(), rgb).expect("three channels per pixel"),
+        GrayImage::from_raw(img.width(), img.height(), alpha).expect("one channel per pixel"),
+    )
+}
+
+/// The RGBA buffer made of the colour channels `img` and the plane `alpha`,
+/// of the same size.
+pub fn join_rgba(img: &RgbImage, alpha: &GrayImage) -> RgbaImage {
+    assert_eq!(img.dimensions(), alpha.dimensions(), "an alpha plane of another size");
+    let rgba = img.chunks_exact(3).zip(alpha.iter()).flat_map(|(pixel, &a)| [pixel[0], pixel[1], pixel[2], a]).collect();
+    RgbaImage::from_raw(img.width(), img.height(), rgba).expect("four channels per pixel")
+}
+
+/// Whether the pixel at `(x, y)` is fully transparent; always false without an alpha plane.
+pub fn is_transparent(alpha: Option<&GrayImage>, x: u32, y: u32) -> bool {
+    alpha.is_some_and(|alpha| alpha.get_pixel(x, y)[0] == TRANSPARENT)
+}
+
+/// Puts the alpha plane back into the processed colour channels. Formats that
+/// cannot store transparency, such as JPEG, get the colour channels alone.
+pub fn merge_alpha(img: RgbImage, alpha: Option<&GrayImage>, format: Option<ImageFormat>) -> DynamicImage {
+    match alpha {
+        Some(alpha) if !matches!(format, Some(ImageFormat::Jpeg | ImageFormat::Pnm)) => DynamicImage::ImageRgba8(join_rgba(&img, alpha)),
+        _ => DynamicImage::ImageRgb8(img),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageOutputFormat, Rgba};
+
+    use super::*;
+    use crate::random::Rng;
+    use crate::threshold::{modify_image_seuil, DEFAULT_THRESHOLD};
+    use crate::{Dither, BLACK, WHITE};
+
+    // A disc of greys, opaque inside, half transparent around, on a fully
+    // transparent background whose colours are drawn from `seed`
+    fn logo(seed: u64) -> RgbaImage {
+        let mut rng = Rng::new(seed);
+        RgbaImage::from_fn(32, 32, |x, y| {
+            let distance = ((x as f64 - 15.5).powi(2) + (y as f64 - 15.5).powi(2)).sqrt();
+            match distance {
+                d if d < 10.0 => Rgba([(x * 8) as u8, (y * 8) as u8, 128, 255]),
+                d if d < 14.0 => Rgba([(x * 8) as u8, 200, (y * 8) as u8, 128]),
+                _ => Rgba([0, 1, 2, 3].map(|c| if c == 3 { TRANSPARENT } else { rng.below(256) as u8 })),
+            }
+        })
+    }
+
+    fn round_trip(img: DynamicImage) -> DynamicImage {
+        let mut png = Cursor::new(Vec::new());
+        img.write_to(&mut png, ImageOutputFormat::Png).unwrap();
+        image::load_from_memory(png.get_ref()).unwrap()
+    }
+
+    #[test]
+    fn a_half_transparent_logo_keeps_its_alpha() {
+        let logo = logo(52);
+        let (rgb, alpha) = split_alpha(&DynamicImage::ImageRgba8(logo.clone()));
+        let alpha = alpha.expect("un plan alpha");
+        assert_eq!(join_rgba(&rgb, &alpha), logo);
+
+        let seuil = modify_image_seuil(rgb.clone(), DEFAULT_THRESHOLD, WHITE, BLACK, false);
+        let dithered = Dither::new().alpha(&alpha).apply(&rgb).unwrap();
+        for result in [seuil, dithered] {
+            let written = round_trip(merge_alpha(result.clone(), Some(&alpha), Some(ImageFormat::Png)));
+            assert_eq!(written.to_rgba8(), join_rgba(&result, &alpha));
+            assert_eq!(split_alpha(&written).1.as_ref(), Some(&alpha));
+        }
+        assert_eq!(merge_alpha(rgb, Some(&alpha), Some(ImageFormat::Jpeg)).color(), image::ColorType::Rgb8);
+    }
+
+    #[test]
+    fn fully_transparent_pixels_spread_no_error() {
+        // Two logos that differ only under their transparent pixels dither
+        // to the same visible pixels
+        let [first, second] = [52, 53].map(|seed| Dither::new().apply_rgba(&logo(seed)).unwrap());
+        for (a, b) in first.pixels().zip(second.pixels()).filter(|(pixel, _)| pixel[3] != TRANSPARENT) {
+            assert_eq!(a, b);
+        }
+        assert!(first.pixels().zip(logo(52).pixels()).all(|(dithered, original)| dithered[3] == original[3]));
+    }
+}
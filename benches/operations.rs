@@ -0,0 +1,96 @@
+//! The cost of the library's operations on synthetic images, 512 × 512 and
+//! 4096 × 4096, before and after performance work: `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use image::{DynamicImage, Rgb, RgbImage};
+use tp_eval::ops::dither::{Algo, Dither};
+use tp_eval::ops::palette::{modify_image_palette, Distance, Palette};
+use tp_eval::ops::seuil::{modify_image_seuil, modify_image_seuil_adaptatif};
+use tp_eval::{BLACK, WHITE};
+
+const SIDES: [u32; 2] = [512, 4096];
+
+// Ramps of red and green crossed with a pattern of blue, so that every
+// colour of the palettes gets its share of pixels
+fn synthetic(side: u32) -> RgbImage {
+    RgbImage::from_fn(side, side, |x, y| Rgb([(x * 256 / side) as u8, (y * 256 / side) as u8, ((x ^ y) & 0xff) as u8]))
+}
+
+// 8 levels of red and green by 4 of blue
+fn palette_256() -> Palette {
+    let level = |i: u32, count: u32| (i * 255 / (count - 1)) as u8;
+    Palette::new((0..256).map(|i| Rgb([level(i % 8, 8), level(i / 8 % 8, 8), level(i / 64, 4)])).collect())
+}
+
+fn seuil(c: &mut Criterion) {
+    let mut group = c.benchmark_group("seuil");
+    for side in SIDES {
+        let img = synthetic(side);
+        group.throughput(Throughput::Elements(side as u64 * side as u64));
+        group.sample_size(if side > 512 { 10 } else { 50 });
+        group.bench_function(BenchmarkId::from_parameter(side), |b| {
+            b.iter_batched(|| img.clone(), |img| modify_image_seuil(img, 128, WHITE, BLACK, false), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+fn palette(c: &mut Criterion) {
+    let palettes = [("4", Palette::builtin(4)), ("9", Palette::builtin(9)), ("256", palette_256())];
+    for (name, palette) in palettes {
+        let mut group = c.benchmark_group(format!("palette-{}", name));
+        for side in SIDES {
+            let img = synthetic(side);
+            group.throughput(Throughput::Elements(side as u64 * side as u64));
+            group.sample_size(10);
+            group.bench_function(BenchmarkId::from_parameter(side), |b| {
+                b.iter_batched(|| img.clone(), |img| modify_image_palette(img, &palette, Distance::Rgb, false).unwrap(), BatchSize::LargeInput)
+            });
+        }
+        group.finish();
+    }
+}
+
+fn seuil_adaptatif(c: &mut Criterion) {
+    let mut group = c.benchmark_group("seuil-adaptatif");
+    for side in SIDES {
+        let img = synthetic(side);
+        group.throughput(Throughput::Elements(side as u64 * side as u64));
+        group.sample_size(10);
+        group.bench_function(BenchmarkId::from_parameter(side), |b| {
+            b.iter_batched(|| img.clone(), |img| modify_image_seuil_adaptatif(img, 15, 0.0, WHITE, BLACK, false).unwrap(), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+fn floyd_steinberg(c: &mut Criterion) {
+    let mut group = c.benchmark_group("floyd-steinberg");
+    let dither = Dither::new();
+    for side in SIDES {
+        let img = synthetic(side);
+        group.throughput(Throughput::Elements(side as u64 * side as u64));
+        group.sample_size(10);
+        group.bench_function(BenchmarkId::from_parameter(side), |b| b.iter(|| dither.apply(&img).unwrap()));
+    }
+    group.finish();
+}
+
+// The other ways through the loops of error diffusion: a single channel,
+// transparent pixels and the Hilbert curve of Riemersma, on the smaller
+// image only
+fn diffusion_paths(c: &mut Criterion) {
+    let side = SIDES[0];
+    let img = DynamicImage::ImageRgb8(synthetic(side));
+    let (gray, rgba) = (img.to_luma8(), img.to_rgba8());
+    let mut group = c.benchmark_group("diffusion");
+    group.throughput(Throughput::Elements(side as u64 * side as u64));
+    group.sample_size(10);
+    group.bench_function("floyd-steinberg-gris", |b| b.iter(|| Dither::new().apply_gray(&gray).unwrap()));
+    group.bench_function("floyd-steinberg-rgba", |b| b.iter(|| Dither::new().apply_rgba(&rgba).unwrap()));
+    group.bench_function("riemersma", |b| b.iter(|| Dither::new().algorithm(Algo::Riemersma).apply(img.as_rgb8().unwrap()).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, seuil, seuil_adaptatif, palette, floyd_steinberg, diffusion_paths);
+criterion_main!(benches);
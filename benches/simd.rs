@@ -0,0 +1,62 @@
+//! The loops of `simd` on one thread, row by row, with the instructions this
+//! processor has and with the scalar ones, on the same 512 × 512 image: the
+//! gain of the explicit SIMD, which the end-to-end benchmarks of seuil and
+//! palette mix with the rest of their work. `cargo bench --bench simd
+//! --features bench-simd`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use image::{Rgb, RgbImage};
+use tp_eval::ops::palette::Palette;
+use tp_eval::simd::{nearest_row, seuil_row, Isa};
+use tp_eval::{BLACK, WHITE};
+
+const SIDE: u32 = 512;
+
+// Ramps of red and green crossed with a pattern of blue, as in the other
+// benchmarks
+fn synthetic(side: u32) -> RgbImage {
+    RgbImage::from_fn(side, side, |x, y| Rgb([(x * 256 / side) as u8, (y * 256 / side) as u8, ((x ^ y) & 0xff) as u8]))
+}
+
+// `img` with `f` run on each of its rows in turn
+fn by_rows(mut img: RgbImage, f: impl FnMut(&mut [u8])) -> RgbImage {
+    let row_len = img.width() as usize * 3;
+    img.chunks_mut(row_len).for_each(f);
+    img
+}
+
+// The scalar loops, then those of this processor, which are the scalar ones
+// again where AVX2 is missing
+fn isas() -> [(&'static str, Isa); 2] {
+    [("scalaire", Isa::Scalar), ("detecte", Isa::detect())]
+}
+
+fn seuil(c: &mut Criterion) {
+    let img = synthetic(SIDE);
+    let mut group = c.benchmark_group("simd/seuil");
+    group.throughput(Throughput::Elements(SIDE as u64 * SIDE as u64));
+    group.sample_size(20);
+    for (name, isa) in isas() {
+        group.bench_function(name, |b| {
+            b.iter_batched(|| img.clone(), |img| by_rows(img, |row| seuil_row(isa, row, 128, WHITE, BLACK)), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+fn palette(c: &mut Criterion) {
+    let img = synthetic(SIDE);
+    let palette = Palette::builtin(9);
+    let mut group = c.benchmark_group("simd/palette-9");
+    group.throughput(Throughput::Elements(SIDE as u64 * SIDE as u64));
+    group.sample_size(20);
+    for (name, isa) in isas() {
+        group.bench_function(name, |b| {
+            b.iter_batched(|| img.clone(), |img| by_rows(img, |row| nearest_row(isa, row, palette.colours())), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, seuil, palette);
+criterion_main!(benches);
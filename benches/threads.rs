@@ -0,0 +1,62 @@
+//! How the row-parallel operations scale with the number of threads, on a
+//! 4096 × 4096 image: each is run in rayon pools of 1, 2, 4 and 8 threads,
+//! and the time should drop nearly in proportion up to the number of cores.
+//! Error diffusion, serial by design, is left out. `cargo bench --bench
+//! threads`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use image::{Rgb, RgbImage};
+use rayon::ThreadPoolBuilder;
+use tp_eval::ops::dither::Dither;
+use tp_eval::ops::palette::{modify_image_palette, Distance, Palette};
+use tp_eval::ops::seuil::modify_image_seuil;
+use tp_eval::ops::tramage::{ThresholdMatrix, ThresholdSource};
+use tp_eval::{BLACK, WHITE};
+
+const SIDE: u32 = 4096;
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+
+// Ramps of red and green crossed with a pattern of blue, as in the other
+// benchmarks
+fn synthetic(side: u32) -> RgbImage {
+    RgbImage::from_fn(side, side, |x, y| Rgb([(x * 256 / side) as u8, (y * 256 / side) as u8, ((x ^ y) & 0xff) as u8]))
+}
+
+// `operation` on the image in a pool of each size of `THREADS`
+fn scaling(c: &mut Criterion, name: &str, operation: impl Fn(RgbImage) -> RgbImage + Sync) {
+    let img = synthetic(SIDE);
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(SIDE as u64 * SIDE as u64));
+    group.sample_size(10);
+    for threads in THREADS {
+        let pool = ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        group.bench_function(BenchmarkId::from_parameter(threads), |b| {
+            b.iter_batched(|| img.clone(), |img| pool.install(|| operation(img)), BatchSize::LargeInput)
+        });
+    }
+    group.finish();
+}
+
+fn seuil(c: &mut Criterion) {
+    scaling(c, "threads/seuil", |img| modify_image_seuil(img, 128, WHITE, BLACK, false));
+}
+
+// The settings are made in each run, `Dither` not being shared between
+// threads; the 8 × 8 matrix costs nothing next to the image
+fn bayer(c: &mut Criterion) {
+    scaling(c, "threads/bayer", |img| Dither::new().ordered(ThresholdSource::Matrix(ThresholdMatrix::bayer(3))).apply(&img).unwrap());
+}
+
+fn bayer_palette(c: &mut Criterion) {
+    scaling(c, "threads/bayer-palette-9", |img| {
+        Dither::new().ordered(ThresholdSource::Matrix(ThresholdMatrix::bayer(3))).palette(Palette::builtin(9)).apply(&img).unwrap()
+    });
+}
+
+fn palette(c: &mut Criterion) {
+    let palette = Palette::builtin(9);
+    scaling(c, "threads/palette-9", |img| modify_image_palette(img, &palette, Distance::Rgb, false).unwrap());
+}
+
+criterion_group!(benches, seuil, bayer, bayer_palette, palette);
+criterion_main!(benches);
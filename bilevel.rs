@@ -0,0 +1,94 @@
+//! 1-bit output: an image made only of black and white, such as the result of
+//! seuil or of dithering without a palette, packed eight pixels to a byte as
+//! a bilevel PNG stores it, which makes it 24 times smaller than in RGB
+//! before compression.
+//!
+//! ```
+//! use image::{DynamicImage, RgbImage};
+//! use tp_eval::bilevel::pack_bilevel;
+//! use tp_eval::{BLACK, WHITE};
+//!
+//! let img = RgbImage::from_fn(10, 2, |x, _| if x < 9 { WHITE } else { BLACK });
+//! let packed = pack_bilevel(&DynamicImage::ImageRgb8(img)).unwrap();
+//! assert_eq!(packed, [0xff, 0x80, 0xff, 0x80]);
+//! ```
+
+use std::io::Write;
+
+use image::DynamicImage;
+
+/// The bytes of a packed row of `width` pixels.
+pub fn packed_row_len(width: u32) -> usize {
+    (width as usize).div_ceil(8)
+}
+
+/// Packs `row`, pixels of `channels` 8-bit channels, eight pixels to a byte:
+/// the first pixel in the highest bit, 1 for white and 0 for black, and the
+/// bits after the last pixel left at 0. With two or four channels, the last
+/// one is alpha. `None` if a pixel is neither black nor white, or is not
+/// opaque.
+pub fn pack_row(row: &[u8], channels: usize) -> Option<Vec<u8>> {
+    let colour_channels = match channels {
+        2 | 4 => channels - 1,
+        _ => channels,
+    };
+    let mut packed = vec![0; row.len().div_ceil(channels * 8)];
+    for (i, pixel) in row.chunks_exact(channels).enumerate() {
+        if pixel[colour_channels..].iter().any(|&alpha| alpha != u8::MAX) {
+            return None;
+        }
+        match pixel[..colour_channels] {
+            ref colour if colour.iter().all(|&channel| channel == u8::MAX) => packed[i / 8] |= 0x80 >> (i % 8),
+            ref colour if colour.iter().all(|&channel| channel == 0) => {}
+            _ => return None,
+        }
+    }
+    Some(packed)
+}
+
+/// The rows of `img` packed one after the other by `pack_row`, each in
+/// `packed_row_len` bytes. `None` if `img` has a pixel that is neither black
+/// nor white or is not opaque, or has more than 8 bits per channel.
+pub fn pack_bilevel(img: &DynamicImage) -> Option<Vec<u8>> {
+    let channels = match img {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => img.color().channel_count() as usize,
+        _ => return None,
+    };
+    // An image without columns has no bytes either
+    let row_bytes = (img.width() as usize * channels).max(1);
+    let mut packed = Vec::with_capacity(packed_row_len(img.width()) * img.height() as usize);
+    for row in img.as_bytes().chunks_exact(row_bytes) {
+        packed.extend(pack_row(row, channels)?);
+    }
+    Some(packed)
+}
+
+/// Encodes as a grayscale PNG of 1 bit per pixel an image of `width` ×
+/// `height` pixels, whose rows `packed` holds as `pack_bilevel` gives them.
+pub fn write_png<W: Write>(writer: W, width: u32, height: u32, packed: &[u8]) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::One);
+    let mut png = encoder.write_header()?;
+    png.write_image_data(packed)?;
+    png.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    #[test]
+    fn a_row_takes_a_byte_for_every_eight_pixels_begun() {
+        assert_eq!([0, 1, 8, 9, 16, 17].map(packed_row_len), [0, 1, 1, 2, 2, 3]);
+        let row: Vec<u8> = (0..9).map(|x| if x % 3 == 0 { 255 } else { 0 }).collect();
+        assert_eq!(pack_row(&row, 1), Some(vec![0b1001_0010, 0]));
+        // Images without columns or rows pack to nothing
+        assert_eq!(pack_bilevel(&DynamicImage::ImageLuma8(GrayImage::new(0, 4))), Some(vec![]));
+        assert_eq!(pack_bilevel(&DynamicImage::ImageLuma8(GrayImage::new(4, 0))), Some(vec![]));
+        let white = GrayImage::from_pixel(12, 2, Luma([255]));
+        assert_eq!(pack_bilevel(&DynamicImage::ImageLuma8(white)), Some(vec![0xff, 0xf0, 0xff, 0xf0]));
+    }
+}
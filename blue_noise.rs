@@ -0,0 +1,194 @@
+//! Blue-noise threshold masks generated with Ulichney's void-and-cluster method.
+
+use image::{GrayImage, Luma};
+
+use crate::random::Rng;
+
+/// Largest mask generated by `genere-masque`; the cost grows with the square
+/// of the number of cells.
+pub const MAX_MASK_SIZE: usize = 256;
+
+/// Ranks every cell of a toroidal side × side grid so that the cells of rank
+/// below k form an evenly spread pattern for every k. `sigma` is the width of
+/// the Gaussian filter used to measure how crowded a cell's surroundings are.
+pub fn void_and_cluster(side: usize, sigma: f64, rng: &mut Rng) -> Vec<u32> {
+    let count = side * side;
+    let mut field = EnergyField::new(side, sigma);
+
+    // Initial binary pattern: about a tenth of the cells, placed at random
+    let mut ones = vec![false; count];
+    let initial = (count / 10).max(1);
+    let mut placed = 0;
+    while placed < initial {
+        let index = rng.below(count as u64) as usize;
+        if !ones[index] {
+            ones[index] = true;
+            field.add(index, 1.0);
+            placed += 1;
+        }
+    }
+
+    // Move the tightest cluster into the largest void until they coincide
+    loop {
+        let cluster = field.tightest_cluster(&ones);
+        ones[cluster] = false;
+        field.add(cluster, -1.0);
+        let void = field.largest_void(&ones);
+        ones[void] = true;
+        field.add(void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; count];
+
+    // Phase 1: removing the tightest clusters of the initial pattern one by
+    // one gives the ranks below it
+    let mut phase1_ones = ones.clone();
+    let mut phase1_field = field.clone();
+    for rank in (0..initial).rev() {
+        let cluster = phase1_field.tightest_cluster(&phase1_ones);
+        phase1_ones[cluster] = false;
+        phase1_field.add(cluster, -1.0);
+        ranks[cluster] = rank as u32;
+    }
+
+    // Phases 2 and 3: filling the largest voids gives the ranks above it.
+    // Past half of the cells, the largest void among the ones is also the
+    // tightest cluster among the zeros, so a single loop covers both phases.
+    for rank in initial..count {
+        let void = field.largest_void(&ones);
+        ones[void] = true;
+        field.add(void, 1.0);
+        ranks[void] = rank as u32;
+    }
+
+    ranks
+}
+
+/// Writes ranks in the text format read by `tramage --matrice`.
+pub fn ranks_to_text(ranks: &[u32], side: usize) -> String {
+    let mut text = String::new();
+    for row in ranks.chunks(side) {
+        let row: Vec<String> = row.iter().map(|rank| rank.to_string()).collect();
+        text.push_str(&row.join(" "));
+        text.push('\n');
+    }
+    text
+}
+
+/// Spreads ranks evenly over the 256 grey levels.
+pub fn ranks_to_image(ranks: &[u32], side: usize) -> GrayImage {
+    let count = ranks.len() as u64;
+    GrayImage::from_fn(side as u32, side as u32, |x, y| {
+        let rank = ranks[y as usize * side + x as usize] as u64;
+        Luma([(rank * 256 / count) as u8])
+    })
+}
+
+/// Parses the `--taille` of a generated mask, a power of two.
+pub fn parse_mask_size(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(size) if (2..=MAX_MASK_SIZE).contains(&size) && size.is_power_of_two() => Ok(size),
+        _ => Err(format!("la taille doit être une puissance de deux entre 2 et {}", MAX_MASK_SIZE)),
+    }
+}
+
+/// Parses the `--sigma` of the Gaussian filter, which must be positive.
+pub fn parse_sigma(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(sigma) if sigma > 0.0 && sigma.is_finite() => Ok(sigma),
+        _ => Err(format!("sigma invalide : {} (attendu : un réel strictement positif)", value)),
+    }
+}
+
+// Sum, for every cell, of the Gaussian filter centred on each set cell
+#[derive(Clone)]
+struct EnergyField {
+    side: usize,
+    filter: Vec<f64>,
+    energy: Vec<f64>,
+}
+
+impl EnergyField {
+    fn new(side: usize, sigma: f64) -> EnergyField {
+        // filter[dy * side + dx] for the toroidal offset (dx, dy)
+        let mut filter = vec![0.0; side * side];
+        for dy in 0..side {
+            for dx in 0..side {
+                let wx = dx.min(side - dx) as f64;
+                let wy = dy.min(side - dy) as f64;
+                filter[dy * side + dx] = (-(wx * wx + wy * wy) / (2.0 * sigma * sigma)).exp();
+            }
+        }
+        EnergyField { side, filter, energy: vec![0.0; side * side] }
+    }
+
+    fn add(&mut self, index: usize, sign: f64) {
+        let (x0, y0) = (index % self.side, index / self.side);
+        for y in 0..self.side {
+            let dy = (y + self.side - y0) % self.side;
+            for x in 0..self.side {
+                let dx = (x + self.side - x0) % self.side;
+                self.energy[y * self.side + x] += sign * self.filter[dy * self.side + dx];
+            }
+        }
+    }
+
+    // The set cell with the highest energy
+    fn tightest_cluster(&self, ones: &[bool]) -> usize {
+        let mut best = None;
+        for (index, &energy) in self.energy.iter().enumerate() {
+            if ones[index] && best.is_none_or(|(_, best_energy)| energy > best_energy) {
+                best = Some((index, energy));
+            }
+        }
+        best.map(|(index, _)| index).unwrap()
+    }
+
+    // The unset cell with the lowest energy
+    fn largest_void(&self, ones: &[bool]) -> usize {
+        let mut best = None;
+        for (index, &energy) in self.energy.iter().enumerate() {
+            if !ones[index] && best.is_none_or(|(_, best_energy)| energy < best_energy) {
+                best = Some((index, energy));
+            }
+        }
+        best.map(|(index, _)| index).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use crate::ordered::{modify_image_tramage, ThresholdMatrix, ThresholdSource};
+    use crate::WHITE;
+
+    #[test]
+    fn the_ranks_are_each_cell_once() {
+        let mut ranks = void_and_cluster(16, 1.5, &mut Rng::new(1));
+        ranks.sort_unstable();
+        assert_eq!(ranks, (0..256).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn the_mask_renders_mid_grey_half_white_in_every_row_and_column() {
+        let grey = RgbImage::from_pixel(128, 128, Rgb([128; 3]));
+        let result = modify_image_tramage(grey, &ThresholdSource::Matrix(ThresholdMatrix::blue_noise()), false);
+        let white = result.pixels().filter(|pixel| **pixel == WHITE).count();
+        assert!(white.abs_diff(128 * 128 / 2) <= 128 * 128 / 100, "{} pixels blancs", white);
+
+        // The mask tiles seamlessly, and neither its rows nor its columns lean
+        // to black or to white
+        for y in 0..128 {
+            assert_eq!((0..64).map(|x| result.get_pixel(x, y)).collect::<Vec<_>>(), (64..128).map(|x| result.get_pixel(x, y)).collect::<Vec<_>>());
+        }
+        for line in 0..64 {
+            let row = (0..64).filter(|&x| *result.get_pixel(x, line) == WHITE).count();
+            let column = (0..64).filter(|&y| *result.get_pixel(line, y) == WHITE).count();
+            assert!(row.abs_diff(32) <= 8 && column.abs_diff(32) <= 8, "ligne {} : {} pixels blancs, colonne {} : {}", line, row, line, column);
+        }
+    }
+}
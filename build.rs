@@ -0,0 +1,18 @@
+// With the `ffi` feature, the C header of ffi.rs is written to OUT_DIR as
+// tp_eval.h
+fn main() {
+    #[cfg(feature = "ffi")]
+    {
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("cargo sets CARGO_MANIFEST_DIR");
+        let out_dir = std::env::var("OUT_DIR").expect("cargo sets OUT_DIR");
+        let config = cbindgen::Config::from_root_or_default(&crate_dir);
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(std::path::Path::new(&crate_dir).join("ffi.rs"))
+            .generate()
+            .expect("the header of ffi.rs cannot be generated")
+            .write_to_file(std::path::Path::new(&out_dir).join("tp_eval.h"));
+        println!("cargo:rerun-if-changed=ffi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+    }
+}
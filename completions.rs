@@ -0,0 +1,235 @@
+//! Completion scripts for bash, zsh and fish, printed by `--completions`.
+//! They are written from the description argh gives of the command line, so
+//! that new modes and options are completed without listing them here.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use argh::{CommandInfoWithArgs, FlagInfo, FlagInfoKind};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+// Names accepted by `--completions`, in the order they are listed in error messages
+const SHELLS: [(&str, Shell); 3] = [
+    ("bash", Shell::Bash),
+    ("zsh", Shell::Zsh),
+    ("fish", Shell::Fish),
+];
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SHELLS.iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, shell)| *shell)
+            .ok_or_else(|| {
+                let names: Vec<&str> = SHELLS.iter().map(|(name, _)| *name).collect();
+                format!("shell inconnu : {} (attendus : {})", s, names.join(", "))
+            })
+    }
+}
+
+/// What the value of an option is completed with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueHint {
+    /// One of these words
+    Values(Vec<&'static str>),
+    File,
+    Directory,
+    /// Anything, a number for instance: nothing is offered
+    Any,
+}
+
+/// The names of a table of names, such as `ALGOS`, as a hint.
+pub fn names<T>(table: &[(&'static str, T)]) -> ValueHint {
+    ValueHint::Values(table.iter().map(|(name, _)| *name).collect())
+}
+
+/// The completion script of `program` for `shell`. `hint` tells how the value
+/// of an option is completed, from the name of its mode (empty for the options
+/// that come before the mode) and its long name, such as `--algo`.
+pub fn completion_script(shell: Shell, program: &str, info: &CommandInfoWithArgs, hint: impl Fn(&str, &str) -> ValueHint) -> String {
+    let mut commands = vec![Command { name: "", description: info.description, flags: info.flags, positionals: !info.positionals.is_empty() }];
+    commands.extend(info.commands.iter().map(|command| Command {
+        name: command.name,
+        description: command.command.description,
+        flags: command.command.flags,
+        positionals: !command.command.positionals.is_empty(),
+    }));
+    let script = Script { program, function: format!("_{}", program.replace(|c: char| !c.is_ascii_alphanumeric(), "_")), commands, hint: &hint };
+    match shell {
+        Shell::Bash => script.bash(),
+        Shell::Zsh => script.zsh(),
+        Shell::Fish => script.fish(),
+    }
+}
+
+struct Command<'a> {
+    // Empty for the program itself
+    name: &'a str,
+    description: &'a str,
+    flags: &'a [FlagInfo<'a>],
+    positionals: bool,
+}
+
+struct Script<'a> {
+    program: &'a str,
+    function: String,
+    // The program itself first, then its modes
+    commands: Vec<Command<'a>>,
+    hint: &'a dyn Fn(&str, &str) -> ValueHint,
+}
+
+impl Script<'_> {
+    fn modes(&self) -> impl Iterator<Item = &Command<'_>> {
+        self.commands.iter().skip(1)
+    }
+
+    fn mode_names(&self, separator: &str) -> String {
+        self.modes().map(|mode| mode.name).collect::<Vec<_>>().join(separator)
+    }
+
+    // The options of `command` that take a value, with their hint
+    fn options<'c>(&'c self, command: &'c Command) -> impl Iterator<Item = (&'c FlagInfo<'c>, ValueHint)> {
+        command.flags.iter()
+            .filter(|flag| matches!(flag.kind, FlagInfoKind::Option { .. }))
+            .map(|flag| (flag, (self.hint)(command.name, flag.long)))
+    }
+
+    fn bash(&self) -> String {
+        let mut s = String::new();
+        let _ = writeln!(s, "# Complétion bash de {0}, à charger avec : source <({0} --completions bash)", self.program);
+        let _ = writeln!(s, "{}() {{", self.function);
+        s.push_str("    local cur=${COMP_WORDS[COMP_CWORD]} prev=${COMP_WORDS[COMP_CWORD-1]} command= i\n");
+        s.push_str("    for ((i = 1; i < COMP_CWORD; i++)); do\n");
+        s.push_str("        case ${COMP_WORDS[i]} in\n");
+        let _ = writeln!(s, "            {}) command=${{COMP_WORDS[i]}}; break ;;", self.mode_names("|"));
+        s.push_str("        esac\n    done\n\n");
+
+        s.push_str("    case $command:$prev in\n");
+        for command in &self.commands {
+            for (flag, hint) in self.options(command) {
+                let reply = match hint {
+                    ValueHint::Values(values) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", values.join(" ")),
+                    ValueHint::File => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+                    ValueHint::Directory => "COMPREPLY=($(compgen -d -- \"$cur\"))".to_string(),
+                    ValueHint::Any => "COMPREPLY=()".to_string(),
+                };
+                let _ = writeln!(s, "        {}:{}) {}; return ;;", command.name, flag.long, reply);
+            }
+        }
+        s.push_str("    esac\n\n");
+
+        s.push_str("    case $command in\n");
+        for command in &self.commands {
+            let flags: Vec<&str> = command.flags.iter().map(|flag| flag.long).collect();
+            let mut words = String::new();
+            if command.name.is_empty() {
+                let _ = write!(words, " $(compgen -W \"{}\" -- \"$cur\")", self.mode_names(" "));
+            }
+            if command.positionals {
+                words.push_str(" $(compgen -f -- \"$cur\")");
+            }
+            let _ = writeln!(s, "        \"{}\")", command.name);
+            let _ = writeln!(s, "            if [[ $cur == -* ]]; then COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); else COMPREPLY=({}); fi ;;", flags.join(" "), words.trim_start());
+        }
+        s.push_str("    esac\n}\n");
+        let _ = writeln!(s, "complete -o filenames -F {} {}", self.function, self.program);
+        s
+    }
+
+    fn zsh(&self) -> String {
+        let mut s = String::new();
+        let _ = writeln!(s, "#compdef {}", self.program);
+        let _ = writeln!(s, "# Complétion zsh de {0}, à placer dans un dossier du $fpath sous le nom _{0}\n", self.program);
+        let _ = writeln!(s, "{}() {{", self.function);
+        s.push_str("    local i command=\n");
+        s.push_str("    for (( i = 2; i < CURRENT; i++ )); do\n");
+        s.push_str("        case ${words[i]} in\n");
+        let _ = writeln!(s, "            ({}) command=${{words[i]}}; break ;;", self.mode_names("|"));
+        s.push_str("        esac\n    done\n\n");
+
+        s.push_str("    case $command in\n");
+        for command in &self.commands {
+            let _ = writeln!(s, "        ({})", if command.name.is_empty() { "''" } else { command.name });
+            if !command.name.is_empty() {
+                // The options of a mode are completed as if it were the command
+                s.push_str("            words=(\"${(@)words[i,-1]}\"); (( CURRENT -= i - 1 ))\n");
+            }
+            s.push_str("            _arguments");
+            for flag in command.flags {
+                let description = zsh_escape(flag.description);
+                let spec = match (&flag.kind, (self.hint)(command.name, flag.long)) {
+                    (FlagInfoKind::Switch, _) => format!("{}[{}]", flag.long, description),
+                    (FlagInfoKind::Option { arg_name }, hint) => {
+                        let action = match hint {
+                            ValueHint::Values(values) => format!("({})", values.join(" ")),
+                            ValueHint::File => "_files".to_string(),
+                            ValueHint::Directory => "_files -/".to_string(),
+                            ValueHint::Any => " ".to_string(),
+                        };
+                        format!("{}[{}]:{}:{}", flag.long, description, arg_name, action)
+                    }
+                };
+                let _ = write!(s, " \\\n                '{}'", spec.replace('\'', "'\\''"));
+            }
+            if command.name.is_empty() {
+                let _ = write!(s, " \\\n                '*: :_alternative \"modes:mode:({})\"{}'", self.mode_names(" "), if command.positionals { " \"fichiers:fichier:_files\"" } else { "" });
+            } else if command.positionals {
+                s.push_str(" \\\n                '*:fichier:_files'");
+            }
+            s.push_str("\n            ;;\n");
+        }
+        s.push_str("    esac\n}\n\n");
+        let _ = writeln!(s, "if [[ $zsh_eval_context[-1] == loadautofunc ]]; then\n    {0} \"$@\"\nelse\n    compdef {0} {1}\nfi", self.function, self.program);
+        s
+    }
+
+    fn fish(&self) -> String {
+        let mut s = String::new();
+        let _ = writeln!(s, "# Complétion fish de {0}, à charger avec : {0} --completions fish | source", self.program);
+        let modes = self.mode_names(" ");
+        for command in &self.commands {
+            let condition = if command.name.is_empty() {
+                format!("not __fish_seen_subcommand_from {}", modes)
+            } else {
+                format!("__fish_seen_subcommand_from {}", command.name)
+            };
+            let complete = format!("complete -c {} -n '{}'", self.program, condition);
+            if command.name.is_empty() {
+                for mode in self.modes() {
+                    let _ = writeln!(s, "{} -f -a {} -d '{}'", complete, mode.name, fish_escape(mode.description));
+                }
+            } else if !command.positionals {
+                let _ = writeln!(s, "{} -f", complete);
+            }
+            for flag in command.flags {
+                let value = match (&flag.kind, (self.hint)(command.name, flag.long)) {
+                    (FlagInfoKind::Switch, _) => String::new(),
+                    (FlagInfoKind::Option { .. }, ValueHint::Values(values)) => format!(" -x -a '{}'", values.join(" ")),
+                    (FlagInfoKind::Option { .. }, ValueHint::File) => " -r -F".to_string(),
+                    (FlagInfoKind::Option { .. }, ValueHint::Directory) => " -x -a '(__fish_complete_directories)'".to_string(),
+                    (FlagInfoKind::Option { .. }, ValueHint::Any) => " -x".to_string(),
+                };
+                let _ = writeln!(s, "{} -l {}{} -d '{}'", complete, flag.long.trim_start_matches("--"), value, fish_escape(flag.description));
+            }
+        }
+        s
+    }
+}
+
+// A description inside the brackets of an `_arguments` spec
+fn zsh_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('[', "\\[").replace(']', "\\]").replace(':', "\\:")
+}
+
+// A description inside single quotes
+fn fish_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
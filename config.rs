@@ -0,0 +1,178 @@
+//! The defaults of `dither.toml`, merged into the command line before it is
+//! parsed. Its top-level keys are the options that come before the mode, its
+//! tables those of each mode, all spelled like their long name without the
+//! dashes:
+//!
+//! ```toml
+//! lineaire = true
+//!
+//! [palette]
+//! distance = "lab"
+//! fichier = "ma_palette.gpl"
+//! ```
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use argh::{CommandInfoWithArgs, FlagInfo, FlagInfoKind};
+use toml::{Table, Value};
+
+use crate::completions::ValueHint;
+
+pub const CONFIG_NAME: &str = "dither.toml";
+
+// The options that only make sense typed, not as defaults
+const NOT_CONFIGURABLE: [&str; 2] = ["--help", "--no-config"];
+
+/// The configuration in use: `dither.toml` in the current directory, or else
+/// in `dither/` under `$XDG_CONFIG_HOME`, which defaults to `~/.config`.
+pub fn find_config() -> Option<PathBuf> {
+    let local = PathBuf::from(CONFIG_NAME);
+    if local.is_file() {
+        return Some(local);
+    }
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    let path = config_home.join("dither").join(CONFIG_NAME);
+    path.is_file().then_some(path)
+}
+
+pub struct Config {
+    path: PathBuf,
+    table: Table,
+}
+
+impl Config {
+    /// Reads `path`; a syntax error is reported with its line.
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("impossible de lire {} : {}", path.display(), error))?;
+        let table = text.parse::<Table>().map_err(|error| {
+            let line = error.span().map_or(1, |span| text[..span.start].matches('\n').count() + 1);
+            let message: Vec<&str> = error.message().lines().collect();
+            format!("{} : TOML invalide à la ligne {} : {}", path.display(), line, message.join(", "))
+        })?;
+        Ok(Config { path: path.to_path_buf(), table })
+    }
+
+    /// `args`, the command line after the program name, with the defaults
+    /// added: those of the program first, those of the mode used right after
+    /// its name. An option already on the command line keeps its value there,
+    /// and replaces the defaults of the options that cannot be used with it,
+    /// listed in `exclusive` by mode, empty for the program. `hint` tells which
+    /// values are paths, taken relative to the file.
+    pub fn apply(&self, args: &[String], info: &CommandInfoWithArgs, exclusive: &[(&str, &[&str])], hint: impl Fn(&str, &str) -> ValueHint) -> Result<Vec<String>, String> {
+        let mode_index = mode_index(args, info);
+        let (program_args, mode_args) = match mode_index {
+            Some(index) => (&args[..index], &args[index + 1..]),
+            None => (args, &[][..]),
+        };
+
+        // Whether `key` of `command` is given in `given_args`, or one that excludes it
+        let overridden = |command: &str, key: &str, given_args: &[String]| {
+            given(given_args, key) || exclusive.iter()
+                .filter(|(mode, group)| *mode == command && group.contains(&key))
+                .any(|(_, group)| group.iter().any(|other| given(given_args, other)))
+        };
+
+        let mut program_defaults = Vec::new();
+        let mut mode_defaults = Vec::new();
+        for (key, value) in &self.table {
+            if let Value::Table(table) = value {
+                let command = info.commands.iter()
+                    .find(|command| command.name == key)
+                    .ok_or_else(|| format!("{} : mode inconnu : [{}]", self.path.display(), key))?;
+                let used = mode_index.is_some_and(|index| args[index] == command.name);
+                for (key, value) in table {
+                    let tokens = self.tokens(command.name, command.command.flags, key, value, &hint)?;
+                    if used && !overridden(command.name, key, mode_args) {
+                        mode_defaults.extend(tokens);
+                    }
+                }
+            } else {
+                let tokens = self.tokens("", info.flags, key, value, &hint)?;
+                if !overridden("", key, program_args) {
+                    program_defaults.extend(tokens);
+                }
+            }
+        }
+
+        let mut merged = program_defaults;
+        match mode_index {
+            Some(index) => {
+                merged.extend_from_slice(&args[..=index]);
+                merged.extend(mode_defaults);
+                merged.extend_from_slice(&args[index + 1..]);
+            }
+            None => merged.extend_from_slice(args),
+        }
+        Ok(merged)
+    }
+
+    // The command-line tokens of the default `key = value` of `command`,
+    // empty for the program itself
+    fn tokens(&self, command: &str, flags: &[FlagInfo], key: &str, value: &Value, hint: impl Fn(&str, &str) -> ValueHint) -> Result<Vec<String>, String> {
+        let long = format!("--{}", key);
+        let scope = if command.is_empty() { String::new() } else { format!(" dans [{}]", command) };
+        let flag = flags.iter()
+            .find(|flag| flag.long == long && !NOT_CONFIGURABLE.contains(&flag.long))
+            .ok_or_else(|| format!("{} : option inconnue{} : {}", self.path.display(), scope, key))?;
+        let invalid = |expected: &str| format!("{} : {}{} attend {}", self.path.display(), key, scope, expected);
+
+        match (&flag.kind, value) {
+            (FlagInfoKind::Switch, Value::Boolean(true)) => Ok(vec![long]),
+            (FlagInfoKind::Switch, Value::Boolean(false)) => Ok(Vec::new()),
+            (FlagInfoKind::Switch, _) => Err(invalid("true ou false")),
+            (FlagInfoKind::Option { .. }, value) => {
+                let text = match value {
+                    Value::Array(values) => values.iter().map(scalar).collect::<Option<Vec<_>>>().map(|values| values.join(",")),
+                    value => scalar(value),
+                }
+                .ok_or_else(|| invalid("une chaîne, un nombre ou une liste"))?;
+                let text = match hint(command, &long) {
+                    ValueHint::File | ValueHint::Directory if text != "-" => self.resolve(&text),
+                    _ => text,
+                };
+                Ok(vec![long, text])
+            }
+        }
+    }
+
+    // A path of the file, which is relative to the directory of the file
+    fn resolve(&self, path: &str) -> String {
+        match self.path.parent() {
+            Some(directory) if Path::new(path).is_relative() => directory.join(path).to_string_lossy().into_owned(),
+            _ => path.to_string(),
+        }
+    }
+}
+
+// The position of the mode on the command line: the first word naming one
+// that is not the value of an option of the program
+fn mode_index(args: &[String], info: &CommandInfoWithArgs) -> Option<usize> {
+    let mut index = 0;
+    while index < args.len() {
+        let arg = &args[index];
+        if info.commands.iter().any(|command| command.name == arg) {
+            return Some(index);
+        }
+        let takes_value = info.flags.iter().any(|flag| flag.long == arg && matches!(flag.kind, FlagInfoKind::Option { .. }));
+        index += if takes_value { 2 } else { 1 };
+    }
+    None
+}
+
+fn given(args: &[String], key: &str) -> bool {
+    args.iter().any(|arg| arg.strip_prefix("--") == Some(key))
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Integer(number) => Some(number.to_string()),
+        Value::Float(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,977 @@
+//! Error-diffusion dithering: the fixed kernels, Ostromoukhov's variable
+//! coefficients and Riemersma's Hilbert-curve dithering, along with random
+//! thresholding which is also selected through `--algo`. Unlike seuil and
+//! ordered dithering, error diffusion runs on one thread: the error of each
+//! pixel reaches the ones after it, so splitting the rows would change the
+//! result.
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Pixel, Rgb, Rgb32FImage, RgbImage};
+
+use crate::alpha::TRANSPARENT;
+use crate::dither_error::DitherError;
+use crate::ditherer::{BlackAndWhite, Ditherer, PixelQuantizer};
+use crate::ordered::Random;
+use crate::progress::Progress;
+use crate::distance::Distance;
+use crate::palette::Palette;
+use crate::srgb::{rec709_luma, working_value, working_value_f64};
+use crate::threshold::is_light;
+
+/// The dithering algorithms of `--algo`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algo {
+    /// Floyd-Steinberg, over four neighbours
+    FloydSteinberg,
+    /// Atkinson, which spreads only three quarters of the error
+    Atkinson,
+    /// Jarvis, Judice and Ninke, over two rows below
+    JarvisJudiceNinke,
+    /// Stucki, a sharper variant of Jarvis-Judice-Ninke
+    Stucki,
+    /// Burkes, Stucki reduced to one row below
+    Burkes,
+    /// Sierra, over two rows below
+    Sierra,
+    /// Sierra reduced to two rows
+    TwoRowSierra,
+    /// Sierra Lite, over three neighbours
+    SierraLite,
+    /// Ostromoukhov, with coefficients varying with the level
+    Ostromoukhov,
+    /// Riemersma, along a Hilbert curve
+    Riemersma,
+    /// Stevenson-Arce, for hexagonal grids
+    StevensonArce,
+    /// A random threshold for each pixel
+    Random,
+}
+
+/// Names accepted by `--algo`, in the order they are listed in error messages.
+pub const ALGOS: [(&str, Algo); 12] = [
+    ("floyd-steinberg", Algo::FloydSteinberg),
+    ("atkinson", Algo::Atkinson),
+    ("jjn", Algo::JarvisJudiceNinke),
+    ("stucki", Algo::Stucki),
+    ("burkes", Algo::Burkes),
+    ("sierra", Algo::Sierra),
+    ("sierra2", Algo::TwoRowSierra),
+    ("sierra-lite", Algo::SierraLite),
+    ("ostromoukhov", Algo::Ostromoukhov),
+    ("riemersma", Algo::Riemersma),
+    ("stevenson-arce", Algo::StevensonArce),
+    ("aleatoire", Algo::Random),
+];
+
+impl FromStr for Algo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ALGOS.iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, algo)| *algo)
+            .ok_or_else(|| {
+                let names: Vec<&str> = ALGOS.iter().map(|(name, _)| *name).collect();
+                format!("algorithme inconnu : {} (attendus : {})", s, names.join(", "))
+            })
+    }
+}
+
+impl Algo {
+    /// Whether the algorithm diffuses its error through a kernel, which is
+    /// what dithering towards a palette relies on.
+    pub fn has_kernel(self) -> bool {
+        self.diffusion().is_some()
+    }
+
+    /// The `Ditherer` running the algorithm with `options`.
+    pub fn ditherer<'a>(self, options: &'a DitherOptions<'a>) -> Box<dyn Ditherer + 'a> {
+        match self.diffusion() {
+            Some(diffusion) => Box::new(ErrorDiffusion { diffusion, options }),
+            None if self == Algo::Random => Box::new(Random::new(options.seed, options.linear)),
+            None => Box::new(Riemersma::new(options)),
+        }
+    }
+
+    // None for the algorithms that do not scan the image row by row
+    pub(crate) fn diffusion(self) -> Option<Diffusion<'static>> {
+        let diffusion = match self {
+            Algo::FloydSteinberg => Diffusion::Fixed(&FLOYD_STEINBERG),
+            Algo::Atkinson => Diffusion::Fixed(&ATKINSON),
+            Algo::JarvisJudiceNinke => Diffusion::Fixed(&JARVIS_JUDICE_NINKE),
+            Algo::Stucki => Diffusion::Fixed(&STUCKI),
+            Algo::Burkes => Diffusion::Fixed(&BURKES),
+            Algo::Sierra => Diffusion::Fixed(&SIERRA),
+            Algo::TwoRowSierra => Diffusion::Fixed(&TWO_ROW_SIERRA),
+            Algo::SierraLite => Diffusion::Fixed(&SIERRA_LITE),
+            Algo::StevensonArce => Diffusion::Fixed(&STEVENSON_ARCE),
+            Algo::Ostromoukhov => Diffusion::Ostromoukhov,
+            Algo::Riemersma | Algo::Random => return None,
+        };
+        Some(diffusion)
+    }
+}
+
+/// How the quantization error of a pixel is spread over its neighbours.
+#[derive(Clone, Copy)]
+pub(crate) enum Diffusion<'a> {
+    /// The same kernel for every pixel
+    Fixed(&'a Kernel),
+    /// Weights for `OSTROMOUKHOV_TAPS` that depend on the intensity of the
+    /// input pixel
+    Ostromoukhov,
+}
+
+impl Diffusion<'_> {
+    /// The number of rows the error reaches, the current one included.
+    pub(crate) fn depth(self) -> usize {
+        match self {
+            Diffusion::Fixed(kernel) => kernel.taps.iter().map(|&(_, dy, _)| dy as usize).max().unwrap_or(0) + 1,
+            Diffusion::Ostromoukhov => 2,
+        }
+    }
+
+    /// The number of columns the error reaches on either side.
+    pub(crate) fn reach(self) -> usize {
+        match self {
+            Diffusion::Fixed(kernel) => kernel.taps.iter().map(|&(dx, _, _)| dx.unsigned_abs() as usize).max().unwrap_or(0),
+            Diffusion::Ostromoukhov => 1,
+        }
+    }
+
+    /// Calls `spread` with the offset and the share of the error of each
+    /// neighbour receiving some, for an input pixel of working value `value`,
+    /// before the error carried to it.
+    pub(crate) fn for_each_tap<const N: usize>(self, value: [f64; N], mut spread: impl FnMut(i64, i64, f64)) {
+        match self {
+            Diffusion::Fixed(kernel) => {
+                for &(dx, dy, weight) in kernel.taps.iter() {
+                    spread(dx, dy, weight as f64 / kernel.divisor as f64);
+                }
+            }
+            Diffusion::Ostromoukhov => {
+                let weights = ostromoukhov_weights(buffer_luma(value).round() as u8);
+                for (&(dx, dy), weight) in OSTROMOUKHOV_TAPS.iter().zip(weights) {
+                    spread(dx, dy, weight);
+                }
+            }
+        }
+    }
+}
+
+/// An error-diffusion kernel: each tap `(dx, dy, weight)` receives
+/// `weight / divisor` of the quantization error of the current pixel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kernel {
+    taps: Cow<'static, [(i64, i64, u32)]>,
+    divisor: u32,
+}
+
+impl Kernel {
+    /// The same kernel, with its weights divided by `divisor`.
+    pub fn with_divisor(self, divisor: u32) -> Kernel {
+        Kernel { divisor, ..self }
+    }
+}
+
+impl FromStr for Kernel {
+    type Err = String;
+
+    // Rows are separated by `;` and coefficients by spaces, `*` marking the
+    // current pixel; the divisor defaults to the sum of the coefficients
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows: Vec<Vec<&str>> = s.split(';').map(|row| row.split_whitespace().collect()).collect();
+
+        let mut stars = rows.iter().enumerate().flat_map(|(y, row)| {
+            row.iter().enumerate().filter(|(_, token)| **token == "*").map(move |(x, _)| (x, y))
+        });
+        let (star_x, star_y) = stars.next().ok_or("le noyau doit contenir `*` pour marquer le pixel courant")?;
+        if stars.next().is_some() {
+            return Err("le noyau ne doit contenir qu’un seul `*`".to_string());
+        }
+
+        let width = rows[star_y].len();
+        let mut taps = Vec::new();
+        for (y, row) in rows.iter().enumerate() {
+            if row.len() != width {
+                return Err(format!("la ligne {} du noyau contient {} coefficients au lieu de {}", y + 1, row.len(), width));
+            }
+            for (x, token) in row.iter().enumerate() {
+                if *token == "*" {
+                    continue;
+                }
+                let weight: i64 = token.parse().map_err(|_| format!("coefficient invalide dans le noyau : {}", token))?;
+                if weight < 0 {
+                    return Err(format!("coefficient négatif dans le noyau : {}", token));
+                }
+                let weight = u32::try_from(weight).map_err(|_| format!("coefficient trop grand dans le noyau : {}", token))?;
+                if weight == 0 {
+                    continue;
+                }
+                let dx = x as i64 - star_x as i64;
+                let dy = y as i64 - star_y as i64;
+                if dy < 0 || (dy == 0 && dx < 0) {
+                    return Err(format!("le coefficient {} du noyau vise un pixel déjà traité", token));
+                }
+                taps.push((dx, dy, weight));
+            }
+        }
+
+        let divisor = taps.iter().try_fold(0u32, |sum, &(_, _, weight)| sum.checked_add(weight))
+            .ok_or("la somme des coefficients du noyau est trop grande")?;
+        if divisor == 0 {
+            return Err("le noyau ne contient aucun coefficient non nul".to_string());
+        }
+
+        Ok(Kernel { taps: Cow::Owned(taps), divisor })
+    }
+}
+
+/// Parses the `--diviseur` of a custom kernel, which must be positive.
+pub fn parse_divisor(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(0) => Err("le diviseur doit être strictement positif".to_string()),
+        Ok(divisor) => Ok(divisor),
+        Err(_) => Err(format!("diviseur invalide : {}", value)),
+    }
+}
+
+const FLOYD_STEINBERG: Kernel = Kernel {
+    taps: Cow::Borrowed(&[
+                           (1, 0, 7),
+        (-1, 1, 3), (0, 1, 5), (1, 1, 1),
+    ]),
+    divisor: 16,
+};
+
+// Only 6/8 of the error is propagated, which lightens highlights
+const ATKINSON: Kernel = Kernel {
+    taps: Cow::Borrowed(&[
+                           (1, 0, 1), (2, 0, 1),
+        (-1, 1, 1), (0, 1, 1), (1, 1, 1),
+                    (0, 2, 1),
+    ]),
+    divisor: 8,
+};
+
+const JARVIS_JUDICE_NINKE: Kernel = Kernel {
+    taps: Cow::Borrowed(&[
+                                                (1, 0, 7), (2, 0, 5),
+        (-2, 1, 3), (-1, 1, 5), (0, 1, 7), (1, 1, 5), (2, 1, 3),
+        (-2, 2, 1), (-1, 2, 3), (0, 2, 5), (1, 2, 3), (2, 2, 1),
+    ]),
+    divisor: 48,
+};
+
+const STUCKI: Kernel = Kernel {
+    taps: Cow::Borrowed(&[
+                                                (1, 0, 8), (2, 0, 4),
+        (-2, 1, 2), (-1, 1, 4), (0, 1, 8), (1, 1, 4), (2, 1, 2),
+        (-2, 2, 1), (-1, 2, 2), (0, 2, 4), (1, 2, 2), (2, 2, 1),
+    ]),
+    divisor: 42,
+};
+
+const BURKES: Kernel = Kernel {
+    taps: Cow::Borrowed(&[
+                                                (1, 0, 8), (2, 0, 4),
+        (-2, 1, 2), (-1, 1, 4), (0, 1, 8), (1, 1, 4), (2, 1, 2),
+    ]),
+    divisor: 32,
+};
+
+const SIERRA: Kernel = Kernel {
+    taps: Cow::Borrowed(&[
+                                                (1, 0, 5), (2, 0, 3),
+        (-2, 1, 2), (-1, 1, 4), (0, 1, 5), (1, 1, 4), (2, 1, 2),
+                    (-1, 2, 2), (0, 2, 3), (1, 2, 2),
+    ]),
+    divisor: 32,
+};
+
+const TWO_ROW_SIERRA: Kernel = Kernel {
+    taps: Cow::Borrowed(&[
+                                                (1, 0, 4), (2, 0, 3),
+        (-2, 1, 1), (-1, 1, 2), (0, 1, 3), (1, 1, 2), (2, 1, 1),
+    ]),
+    divisor: 16,
+};
+
+// Only touches the current and the next row, so it never needs more than two
+// rows of the image at once
+const SIERRA_LITE: Kernel = Kernel {
+    taps: Cow::Borrowed(&[
+                    (1, 0, 2),
+        (-1, 1, 1), (0, 1, 1),
+    ]),
+    divisor: 4,
+};
+
+// Designed for hexagonal grids, it skips every other neighbour and reaches x ± 3
+const STEVENSON_ARCE: Kernel = Kernel {
+    taps: Cow::Borrowed(&[
+                                                            (2, 0, 32),
+        (-3, 1, 12),             (-1, 1, 26),             (1, 1, 30),             (3, 1, 16),
+                     (-2, 2, 12),             (0, 2, 26),             (2, 2, 12),
+        (-3, 3, 5),              (-1, 3, 12),             (1, 3, 12),             (3, 3, 5),
+    ]),
+    divisor: 200,
+};
+
+// Neighbours receiving the error in Ostromoukhov's variable-coefficient diffusion
+const OSTROMOUKHOV_TAPS: [(i64, i64); 3] = [(1, 0), (-1, 1), (0, 1)];
+
+// Ostromoukhov's coefficients for `OSTROMOUKHOV_TAPS`, as his paper gives them
+// for the intensities 0 to 127, each row to be divided by its sum. The upper
+// half mirrors them, 255 - intensity having the coefficients of intensity.
+const OSTROMOUKHOV_COEFFICIENTS: [[u32; 3]; 128] = [
+    [13, 0, 5], // 0
+    [13, 0, 5], // 1
+    [21, 0, 10], // 2
+    [7, 0, 4], // 3
+    [8, 0, 5], // 4
+    [47, 3, 28], // 5
+    [23, 3, 13], // 6
+    [15, 3, 8], // 7
+    [22, 6, 11], // 8
+    [43, 15, 20], // 9
+    [7, 3, 3], // 10
+    [501, 224, 211], // 11
+    [249, 116, 103], // 12
+    [165, 80, 67], // 13
+    [123, 62, 49], // 14
+    [489, 256, 191], // 15
+    [81, 44, 31], // 16
+    [483, 272, 181], // 17
+    [60, 35, 22], // 18
+    [53, 32, 19], // 19
+    [237, 148, 83], // 20
+    [471, 304, 161], // 21
+    [3, 2, 1], // 22
+    [481, 314, 185], // 23
+    [354, 226, 155], // 24
+    [1389, 866, 685], // 25
+    [227, 138, 125], // 26
+    [267, 158, 163], // 27
+    [327, 188, 220], // 28
+    [61, 34, 45], // 29
+    [627, 338, 505], // 30
+    [1227, 638, 1075], // 31
+    [20, 10, 19], // 32
+    [1937, 1000, 1767], // 33
+    [977, 520, 855], // 34
+    [657, 360, 551], // 35
+    [71, 40, 57], // 36
+    [2005, 1160, 1539], // 37
+    [337, 200, 247], // 38
+    [2039, 1240, 1425], // 39
+    [257, 160, 171], // 40
+    [691, 440, 437], // 41
+    [1045, 680, 627], // 42
+    [301, 200, 171], // 43
+    [177, 120, 95], // 44
+    [2141, 1480, 1083], // 45
+    [1079, 760, 513], // 46
+    [725, 520, 323], // 47
+    [137, 100, 57], // 48
+    [2209, 1640, 855], // 49
+    [53, 40, 19], // 50
+    [2243, 1720, 741], // 51
+    [565, 440, 171], // 52
+    [759, 600, 209], // 53
+    [1147, 920, 285], // 54
+    [2311, 1880, 513], // 55
+    [97, 80, 19], // 56
+    [335, 280, 57], // 57
+    [1181, 1000, 171], // 58
+    [793, 680, 95], // 59
+    [599, 520, 57], // 60
+    [2413, 2120, 171], // 61
+    [405, 360, 19], // 62
+    [2447, 2200, 57], // 63
+    [11, 10, 0], // 64
+    [158, 151, 3], // 65
+    [178, 179, 7], // 66
+    [1030, 1091, 63], // 67
+    [248, 277, 21], // 68
+    [318, 375, 35], // 69
+    [458, 571, 63], // 70
+    [878, 1159, 147], // 71
+    [5, 7, 1], // 72
+    [172, 181, 37], // 73
+    [97, 76, 22], // 74
+    [72, 41, 17], // 75
+    [119, 47, 29], // 76
+    [4, 1, 1], // 77
+    [4, 1, 1], // 78
+    [4, 1, 1], // 79
+    [4, 1, 1], // 80
+    [4, 1, 1], // 81
+    [4, 1, 1], // 82
+    [4, 1, 1], // 83
+    [4, 1, 1], // 84
+    [4, 1, 1], // 85
+    [65, 18, 17], // 86
+    [95, 29, 26], // 87
+    [185, 62, 53], // 88
+    [30, 11, 9], // 89
+    [35, 14, 11], // 90
+    [85, 37, 28], // 91
+    [55, 26, 19], // 92
+    [80, 41, 29], // 93
+    [155, 86, 59], // 94
+    [5, 3, 2], // 95
+    [5, 3, 2], // 96
+    [5, 3, 2], // 97
+    [5, 3, 2], // 98
+    [5, 3, 2], // 99
+    [5, 3, 2], // 100
+    [5, 3, 2], // 101
+    [5, 3, 2], // 102
+    [5, 3, 2], // 103
+    [5, 3, 2], // 104
+    [5, 3, 2], // 105
+    [5, 3, 2], // 106
+    [5, 3, 2], // 107
+    [305, 176, 119], // 108
+    [155, 86, 59], // 109
+    [105, 56, 39], // 110
+    [80, 41, 29], // 111
+    [65, 32, 23], // 112
+    [55, 26, 19], // 113
+    [335, 152, 113], // 114
+    [85, 37, 28], // 115
+    [115, 48, 37], // 116
+    [35, 14, 11], // 117
+    [355, 136, 109], // 118
+    [30, 11, 9], // 119
+    [365, 128, 107], // 120
+    [185, 62, 53], // 121
+    [25, 8, 7], // 122
+    [95, 29, 26], // 123
+    [385, 112, 103], // 124
+    [65, 18, 17], // 125
+    [395, 104, 101], // 126
+    [4, 1, 1], // 127
+];
+
+// Normalized weights for `OSTROMOUKHOV_TAPS` at the given intensity
+fn ostromoukhov_weights(intensity: u8) -> [f64; 3] {
+    let coefs = OSTROMOUKHOV_COEFFICIENTS[intensity.min(255 - intensity) as usize];
+    let sum = (coefs[0] + coefs[1] + coefs[2]) as f64;
+    coefs.map(|c| c as f64 / sum)
+}
+
+// Number of past errors remembered along the curve by Riemersma dithering, and
+// the ratio between the weights of the most recent and the oldest one
+const RIEMERSMA_QUEUE: usize = 16;
+const RIEMERSMA_RATIO: f64 = 16.0;
+
+/// Settings shared by the error-diffusion algorithms.
+pub struct DitherOptions<'a> {
+    /// Scan odd rows right to left, with the kernel mirrored
+    pub serpentin: bool,
+    /// Seed of random thresholding
+    pub seed: u64,
+    /// Measure and diffuse the error in linear light
+    pub linear: bool,
+    /// Fraction of the error that is diffused, from 0 (plain thresholding) to 1
+    pub strength: f64,
+    /// Luma from which a pixel becomes white, as in seuil
+    pub threshold: u8,
+    /// Alpha plane of the image, whose fully transparent pixels spread no error
+    pub alpha: Option<&'a GrayImage>,
+    /// Full-precision pixels of a 16-bit input, diffused instead of the 8-bit image
+    pub source: Option<&'a Rgb32FImage>,
+    /// Bar counting the rows done
+    pub progress: Option<&'a Progress>,
+}
+
+/// Parses the `--force` of error diffusion, between 0 and 1.
+pub fn parse_strength(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(strength) if (0.0..=1.0).contains(&strength) => Ok(strength),
+        _ => Err(format!("force invalide : {} (attendu : un réel entre 0 et 1)", value)),
+    }
+}
+
+/// Error-diffusion dithering of `img` to black and white with `algo`, or
+/// random thresholding. A custom `noyau` takes precedence over `algo`. With a
+/// `palette`, pixels are quantized to its nearest colour instead of black or
+/// white, which requires an algorithm with a kernel.
+pub fn modify_image_dithering(img: RgbImage, algo: Algo, noyau: Option<&Kernel>, palette: Option<&Palette>, options: &DitherOptions) -> Result<RgbImage, DitherError> {
+    check_strength(options)?;
+    match palette {
+        Some(palette) if palette.is_empty() => Err(DitherError::EmptyPalette),
+        Some(_) if noyau.is_none() && !algo.has_kernel() => {
+            Err(DitherError::InvalidParameter("le dithering vers une palette n’est pas disponible avec riemersma ou aleatoire".to_string()))
+        }
+        _ => Ok(dither(img, algo, noyau, palette, options)),
+    }
+}
+
+// The strength of the diffusion goes from 0 to 1, as --force
+pub(crate) fn check_strength(options: &DitherOptions) -> Result<(), DitherError> {
+    match options.strength {
+        strength if (0.0..=1.0).contains(&strength) => Ok(()),
+        strength => Err(DitherError::InvalidParameter(format!("force invalide : {} (attendu : un réel entre 0 et 1)", strength))),
+    }
+}
+
+// `modify_image_dithering` once its parameters are checked
+fn dither(img: RgbImage, algo: Algo, noyau: Option<&Kernel>, palette: Option<&Palette>, options: &DitherOptions) -> RgbImage {
+    let ditherer = match noyau {
+        Some(kernel) => Box::new(ErrorDiffusion::with_kernel(kernel, options)),
+        None => algo.ditherer(options),
+    };
+    match palette {
+        Some(palette) => ditherer.dither(img, &palette.matcher(Distance::Rgb, options.linear)),
+        None => ditherer.dither(img, &BlackAndWhite::new(options.threshold)),
+    }
+}
+
+/// Error diffusion through a kernel: each pixel is quantized in turn and its
+/// error spread over the neighbours still to come.
+pub struct ErrorDiffusion<'a> {
+    diffusion: Diffusion<'a>,
+    options: &'a DitherOptions<'a>,
+}
+
+impl<'a> ErrorDiffusion<'a> {
+    /// The kernel of `algo` with `options`, or None for the algorithms
+    /// without one.
+    pub fn new(algo: Algo, options: &'a DitherOptions<'a>) -> Option<ErrorDiffusion<'a>> {
+        algo.diffusion().map(|diffusion| ErrorDiffusion { diffusion, options })
+    }
+
+    /// A custom `kernel` with `options`.
+    pub fn with_kernel(kernel: &'a Kernel, options: &'a DitherOptions<'a>) -> ErrorDiffusion<'a> {
+        ErrorDiffusion { diffusion: Diffusion::Fixed(kernel), options }
+    }
+}
+
+impl Ditherer for ErrorDiffusion<'_> {
+    fn dither(&self, img: RgbImage, quantizer: &dyn PixelQuantizer) -> RgbImage {
+        diffuse(img, |x, y, pixel| input_value(pixel, self.options.source, self.options.linear, x, y), self.diffusion, self.options, |value| quantizer.quantize(value))
+    }
+}
+
+/// `modify_image_dithering` to black and white on a grayscale image, which
+/// the kernels diffuse on its single channel; a grey pixel gets the same
+/// result as through the RGB path.
+pub fn modify_image_dithering_gray(img: GrayImage, algo: Algo, noyau: Option<&Kernel>, options: &DitherOptions) -> Result<GrayImage, DitherError> {
+    check_strength(options)?;
+    let diffusion = match noyau {
+        Some(kernel) => Diffusion::Fixed(kernel),
+        None => match algo.diffusion() {
+            Some(diffusion) => diffusion,
+            // Not scanned row by row, these go through RGB
+            None => {
+                let rgb = DynamicImage::ImageLuma8(img).to_rgb8();
+                let dithered = algo.ditherer(options).dither(rgb, &BlackAndWhite::new(options.threshold));
+                return Ok(DynamicImage::ImageRgb8(dithered).to_luma8());
+            }
+        },
+    };
+    Ok(diffuse(img, |_, _, Luma([grey])| [working_value(grey, options.linear)], diffusion, options, |value| {
+        if is_light(buffer_luma(value), options.threshold) { Luma([255]) } else { Luma([0]) }
+    }))
+}
+
+/// The working value of the input `pixel` at (x, y), from the full-precision
+/// `source` when there is one.
+pub(crate) fn input_value(Rgb(pixel): Rgb<u8>, source: Option<&Rgb32FImage>, linear: bool, x: u32, y: u32) -> [f64; 3] {
+    match source {
+        Some(source) => {
+            let start = (y as usize * source.width() as usize + x as usize) * 3;
+            let full: [f32; 3] = source.as_raw()[start..start + 3].try_into().expect("three channels");
+            full.map(|c| working_value_f64(c as f64 * 255.0, linear))
+        }
+        None => pixel.map(|c| working_value(c, linear)),
+    }
+}
+
+// The luma of a buffer value, a grey one being the same on all three channels
+fn buffer_luma<const N: usize>(value: [f64; N]) -> f64 {
+    match *value.as_slice() {
+        [grey] => rec709_luma([grey; 3]),
+        [r, g, b] => rec709_luma([r, g, b]),
+        _ => unreachable!("a pixel has one or three channels"),
+    }
+}
+
+// Error diffusion of a whole image, row by row, where `value` gives the
+// working value of each input pixel from its coordinates and itself, and
+// `quantize` picks its output colour. The rows are read and written as
+// slices of the buffer.
+fn diffuse<P, const N: usize>(mut img: ImageBuffer<P, Vec<u8>>, value: impl Fn(u32, u32, P) -> [f64; N], diffusion: Diffusion, options: &DitherOptions, quantize: impl Fn([f64; N]) -> P) -> ImageBuffer<P, Vec<u8>>
+where
+    P: Pixel<Subpixel = u8>,
+{
+    let (width, height) = img.dimensions();
+    let (channels, w) = (P::CHANNEL_COUNT as usize, width as usize);
+    let mut rows = RowDiffusion::new(diffusion, width, options);
+    let buffer: &mut [u8] = &mut img;
+    for y in 0..height {
+        let row = &mut buffer[y as usize * w * channels..(y as usize + 1) * w * channels];
+        let values: Vec<[f64; N]> = row.chunks_exact(channels).enumerate().map(|(x, pixel)| value(x as u32, y, *P::from_slice(pixel))).collect();
+        let alpha = options.alpha.map(|alpha| &alpha.as_raw()[y as usize * w..(y as usize + 1) * w]);
+        let transparent = |x: u32| alpha.is_some_and(|alpha| alpha[x as usize] == TRANSPARENT);
+        rows.next_row(&values, transparent, &quantize, |x, pixel| row[x as usize * channels..(x as usize + 1) * channels].copy_from_slice(pixel.channels()));
+        if let Some(progress) = options.progress {
+            progress.update(y as u64 + 1);
+        }
+    }
+    img
+}
+
+/// The state of error diffusion between two rows: the errors still to be
+/// added to the current row and to the rows below it, as many as the kernel
+/// reaches. The errors are accumulated per channel in floats rather than in
+/// the image itself, so that they are neither truncated nor clamped at 0 and
+/// 255.
+pub(crate) struct RowDiffusion<'a, const N: usize> {
+    diffusion: Diffusion<'a>,
+    serpentin: bool,
+    linear: bool,
+    strength: f64,
+    y: u32,
+    errors: VecDeque<Vec<[f64; N]>>,
+}
+
+impl<'a, const N: usize> RowDiffusion<'a, N> {
+    /// Diffusion through `diffusion` over rows `width` pixels wide, with the
+    /// scan, the light and the strength of `options`.
+    pub(crate) fn new(diffusion: Diffusion<'a>, width: u32, options: &DitherOptions) -> RowDiffusion<'a, N> {
+        RowDiffusion {
+            diffusion,
+            serpentin: options.serpentin,
+            linear: options.linear,
+            strength: options.strength,
+            y: 0,
+            errors: (0..diffusion.depth()).map(|_| vec![[0.0; N]; width as usize]).collect(),
+        }
+    }
+
+    /// Quantizes the next row, whose input pixels have the working values
+    /// `values`, giving the output colour of each pixel to `put` with its
+    /// column; the pixels for which `transparent` is true spread no error.
+    pub(crate) fn next_row<P>(&mut self, values: &[[f64; N]], transparent: impl Fn(u32) -> bool, quantize: impl Fn([f64; N]) -> P, mut put: impl FnMut(u32, P))
+    where
+        P: Pixel<Subpixel = u8>,
+    {
+        let width = values.len();
+        let reversed = self.serpentin && self.y % 2 == 1;
+        let direction = if reversed { -1 } else { 1 };
+
+        for i in 0..width {
+            let x = if reversed { width - 1 - i } else { i };
+            let (new_color, error) = quantize_with_error(values[x], self.errors[0][x], self.linear, self.strength, &quantize);
+            put(x as u32, new_color);
+            if transparent(x as u32) {
+                continue;
+            }
+
+            let errors = &mut self.errors;
+            self.diffusion.for_each_tap(values[x], |dx, dy, weight| {
+                let nx = x as i64 + dx * direction;
+                if nx < 0 || nx >= width as i64 {
+                    return;
+                }
+                let neighbor = &mut errors[dy as usize][nx as usize];
+                for c in 0..N {
+                    neighbor[c] += error[c] * weight;
+                }
+            });
+        }
+
+        // The row below becomes the current one
+        let mut done = self.errors.pop_front().expect("the kernel reaches at least the current row");
+        done.fill([0.0; N]);
+        self.errors.push_back(done);
+        self.y += 1;
+    }
+}
+
+/// The colour `quantize` gives to the working value `input` plus the error
+/// `carried` to it, that sum clipped to the RGB cube, and the error it leaves
+/// to spread, scaled by `strength`.
+pub(crate) fn quantize_with_error<P, const N: usize>(input: [f64; N], carried: [f64; N], linear: bool, strength: f64, quantize: impl Fn([f64; N]) -> P) -> (P, [f64; N])
+where
+    P: Pixel<Subpixel = u8>,
+{
+    // The search uses the value clipped to the RGB cube, otherwise large
+    // accumulated errors would keep picking the extreme colours
+    let value: [f64; N] = std::array::from_fn(|c| (input[c] + carried[c]).clamp(0.0, 255.0));
+    let colour = quantize(value);
+    // Scaled before being split, so that the kernel keeps its proportions
+    let error = std::array::from_fn(|c| (value[c] - working_value(colour.channels()[c], linear)) * strength);
+    (colour, error)
+}
+
+// Position of the d-th point of the Hilbert curve filling a side × side square,
+// side being a power of two. The curve starts at (0, 0) and ends at (side - 1, 0).
+fn hilbert_d2xy(side: u32, d: u64) -> (u32, u32) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < side {
+        let rx = (1 & (t / 2)) as u32;
+        let ry = (1 & (t ^ rx as u64)) as u32;
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+// Visits every pixel of the image once, following a Hilbert curve. Images that
+// are not square powers of two are covered by a row of square curves along
+// their longest side, each one ending next to where the following one starts;
+// the points falling outside of the image are skipped.
+fn hilbert_path(width: u32, height: u32) -> impl Iterator<Item = (u32, u32)> {
+    let transpose = height > width;
+    let (long, short) = if transpose { (height, width) } else { (width, height) };
+    let side = short.next_power_of_two();
+    let tiles = long.div_ceil(side);
+
+    (0..tiles)
+        .flat_map(move |tile| {
+            (0..side as u64 * side as u64).map(move |d| {
+                let (u, v) = hilbert_d2xy(side, d);
+                (tile * side + u, v)
+            })
+        })
+        .filter(move |&(u, v)| u < long && v < short)
+        .map(move |(u, v)| if transpose { (v, u) } else { (u, v) })
+}
+
+/// Riemersma dithering: the pixels are visited along a Hilbert curve and each
+/// one receives the exponentially decaying sum of the last errors met on the
+/// way.
+pub struct Riemersma<'a> {
+    options: &'a DitherOptions<'a>,
+}
+
+impl<'a> Riemersma<'a> {
+    /// The curve with `options`, whose `serpentin` has no effect.
+    pub fn new(options: &'a DitherOptions<'a>) -> Riemersma<'a> {
+        Riemersma { options }
+    }
+}
+
+impl Ditherer for Riemersma<'_> {
+    fn dither(&self, mut img: RgbImage, quantizer: &dyn PixelQuantizer) -> RgbImage {
+        let options = self.options;
+        let (width, height) = img.dimensions();
+
+        // Oldest error first, the most recent one has weight 1
+        let weights: Vec<f64> = (0..RIEMERSMA_QUEUE)
+            .map(|i| RIEMERSMA_RATIO.powf(i as f64 / (RIEMERSMA_QUEUE - 1) as f64) / RIEMERSMA_RATIO)
+            .collect();
+        let mut errors = VecDeque::from(vec![[0.0; 3]; RIEMERSMA_QUEUE]);
+        let buffer: &mut [u8] = &mut img;
+
+        for (i, (x, y)) in hilbert_path(width, height).enumerate() {
+            // The curve does not go row by row, a row's worth of pixels counts as one
+            if let (Some(progress), 0) = (options.progress, (i + 1) % width as usize) {
+                progress.update(((i + 1) / width as usize) as u64);
+            }
+            let index = y as usize * width as usize + x as usize;
+            let channels = &mut buffer[index * 3..index * 3 + 3];
+            let pixel = input_value(*Rgb::from_slice(channels), options.source, options.linear, x, y);
+            let value = [0, 1, 2].map(|c| {
+                pixel[c] + errors.iter().zip(&weights).map(|(error, weight)| error[c] * weight).sum::<f64>()
+            });
+            let new_color = quantizer.quantize(value);
+            channels.copy_from_slice(&new_color.0);
+            if options.alpha.is_some_and(|alpha| alpha.as_raw()[index] == TRANSPARENT) {
+                continue;
+            }
+
+            errors.pop_front();
+            errors.push_back([0, 1, 2].map(|c| (pixel[c] - working_value(new_color[c], options.linear)) * options.strength));
+        }
+
+        img
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::srgb::{linear_to_srgb, srgb_to_linear};
+    use crate::{BLACK, WHITE};
+
+    fn options() -> DitherOptions<'static> {
+        DitherOptions { serpentin: false, seed: 0, linear: false, strength: 1.0, threshold: 128, alpha: None, source: None, progress: None }
+    }
+
+    #[test]
+    fn the_coefficients_are_those_of_the_paper_and_mirror_around_the_middle() {
+        // The table of the paper, the intensities 0 to 127 eight to a line
+        let paper: [u32; 3 * 128] = [
+            13, 0, 5, 13, 0, 5, 21, 0, 10, 7, 0, 4, 8, 0, 5, 47, 3, 28, 23, 3, 13, 15, 3, 8,
+            22, 6, 11, 43, 15, 20, 7, 3, 3, 501, 224, 211, 249, 116, 103, 165, 80, 67, 123, 62, 49, 489, 256, 191,
+            81, 44, 31, 483, 272, 181, 60, 35, 22, 53, 32, 19, 237, 148, 83, 471, 304, 161, 3, 2, 1, 481, 314, 185,
+            354, 226, 155, 1389, 866, 685, 227, 138, 125, 267, 158, 163, 327, 188, 220, 61, 34, 45, 627, 338, 505, 1227, 638, 1075,
+            20, 10, 19, 1937, 1000, 1767, 977, 520, 855, 657, 360, 551, 71, 40, 57, 2005, 1160, 1539, 337, 200, 247, 2039, 1240, 1425,
+            257, 160, 171, 691, 440, 437, 1045, 680, 627, 301, 200, 171, 177, 120, 95, 2141, 1480, 1083, 1079, 760, 513, 725, 520, 323,
+            137, 100, 57, 2209, 1640, 855, 53, 40, 19, 2243, 1720, 741, 565, 440, 171, 759, 600, 209, 1147, 920, 285, 2311, 1880, 513,
+            97, 80, 19, 335, 280, 57, 1181, 1000, 171, 793, 680, 95, 599, 520, 57, 2413, 2120, 171, 405, 360, 19, 2447, 2200, 57,
+            11, 10, 0, 158, 151, 3, 178, 179, 7, 1030, 1091, 63, 248, 277, 21, 318, 375, 35, 458, 571, 63, 878, 1159, 147,
+            5, 7, 1, 172, 181, 37, 97, 76, 22, 72, 41, 17, 119, 47, 29, 4, 1, 1, 4, 1, 1, 4, 1, 1,
+            4, 1, 1, 4, 1, 1, 4, 1, 1, 4, 1, 1, 4, 1, 1, 4, 1, 1, 65, 18, 17, 95, 29, 26,
+            185, 62, 53, 30, 11, 9, 35, 14, 11, 85, 37, 28, 55, 26, 19, 80, 41, 29, 155, 86, 59, 5, 3, 2,
+            5, 3, 2, 5, 3, 2, 5, 3, 2, 5, 3, 2, 5, 3, 2, 5, 3, 2, 5, 3, 2, 5, 3, 2,
+            5, 3, 2, 5, 3, 2, 5, 3, 2, 5, 3, 2, 305, 176, 119, 155, 86, 59, 105, 56, 39, 80, 41, 29,
+            65, 32, 23, 55, 26, 19, 335, 152, 113, 85, 37, 28, 115, 48, 37, 35, 14, 11, 355, 136, 109, 30, 11, 9,
+            365, 128, 107, 185, 62, 53, 25, 8, 7, 95, 29, 26, 385, 112, 103, 65, 18, 17, 395, 104, 101, 4, 1, 1,
+        ];
+        for (intensity, row) in paper.chunks_exact(3).enumerate() {
+            assert_eq!(OSTROMOUKHOV_COEFFICIENTS[intensity][..], *row, "intensité {}", intensity);
+            let sum = (row[0] + row[1] + row[2]) as f64;
+            let weights = row.iter().map(|&c| c as f64 / sum);
+            for mirrored in [intensity as u8, 255 - intensity as u8] {
+                assert!(ostromoukhov_weights(mirrored).into_iter().zip(weights.clone()).all(|(a, b)| a == b), "intensité {}", mirrored);
+            }
+        }
+    }
+
+    #[test]
+    fn ostromoukhov_weighs_the_error_by_the_grey_of_the_input() {
+        // On a flat grey, whatever error the pixels carry, the kernel is the
+        // row of that grey
+        for grey in [3u8, 40, 72, 100, 127, 128, 183, 250] {
+            let row = OSTROMOUKHOV_COEFFICIENTS[grey.min(255 - grey) as usize];
+            let taps = OSTROMOUKHOV_TAPS.iter().zip(row).map(|(&(dx, dy), weight)| (dx, dy, weight)).collect();
+            let kernel = Kernel { taps: Cow::Owned(taps), divisor: row.iter().sum() };
+            let flat = RgbImage::from_pixel(48, 24, Rgb([grey; 3]));
+            for serpentin in [false, true] {
+                let options = DitherOptions { serpentin, ..options() };
+                let ostromoukhov = modify_image_dithering(flat.clone(), Algo::Ostromoukhov, None, None, &options).unwrap();
+                assert_eq!(ostromoukhov, modify_image_dithering(flat.clone(), Algo::Ostromoukhov, Some(&kernel), None, &options).unwrap(), "gris {}", grey);
+            }
+        }
+
+        // From white down to 224, a level every 8 rows: the tone is kept, a
+        // sixteenth of it black
+        let ramp = RgbImage::from_fn(256, 256, |_, y| Rgb([255 - (y / 8) as u8; 3]));
+        let ostromoukhov = modify_image_dithering(ramp, Algo::Ostromoukhov, None, None, &options()).unwrap();
+        let black = ostromoukhov.pixels().filter(|pixel| **pixel == BLACK).count() as f64;
+        let expected = (0..32).map(|level| level as f64 / 255.0 * 8.0 * 256.0).sum::<f64>();
+        assert!((black - expected).abs() < 0.1 * expected, "{} pixels noirs sur {} attendus", black, expected);
+    }
+
+    #[test]
+    fn jarvis_judice_ninke_renders_a_gradient_unlike_floyd_steinberg() {
+        let gradient = RgbImage::from_fn(64, 16, |x, _| Rgb([(x * 4) as u8; 3]));
+        let floyd_steinberg = modify_image_dithering(gradient.clone(), Algo::FloydSteinberg, None, None, &options()).unwrap();
+        let jjn = modify_image_dithering(gradient, Algo::JarvisJudiceNinke, None, None, &options()).unwrap();
+        let differing = floyd_steinberg.pixels().zip(jjn.pixels()).filter(|(a, b)| a != b).count();
+        assert!(differing > 64 * 16 / 10, "{} pixels différents", differing);
+    }
+
+    #[test]
+    fn burkes_renders_mid_grey_as_a_checkerboard() {
+        let grey = RgbImage::from_pixel(16, 12, Rgb([128; 3]));
+        let result = modify_image_dithering(grey, Algo::Burkes, None, None, &options()).unwrap();
+        for (x, y, pixel) in result.enumerate_pixels() {
+            let expected = if (x + y) % 2 == 0 { WHITE } else { BLACK };
+            assert_eq!(*pixel, expected, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn sierra_dithers_an_image_narrower_than_its_kernel() {
+        let img = RgbImage::from_fn(3, 3, |x, y| Rgb([(x * 100 + y * 20) as u8; 3]));
+        let result = modify_image_dithering(img, Algo::Sierra, None, None, &options()).unwrap();
+        assert_eq!(result.dimensions(), (3, 3));
+        assert!(result.pixels().all(|pixel| *pixel == WHITE || *pixel == BLACK));
+    }
+
+    #[test]
+    fn serpentine_scanning_does_not_shear_a_quarter_grey() {
+        // White pixels followed by another one diagonally down to the right,
+        // and down to the left
+        let diagonals = |serpentin: bool| {
+            let grey = RgbImage::from_pixel(64, 64, Rgb([64; 3]));
+            let result = modify_image_dithering(grey, Algo::FloydSteinberg, None, None, &DitherOptions { serpentin, ..options() }).unwrap();
+            let white = |x: u32, y: u32| *result.get_pixel(x, y) == WHITE;
+            let pairs = |dx: i64| (0..63).flat_map(|y| (1..63).map(move |x| (x, y))).filter(|&(x, y)| white(x, y) && white((x as i64 + dx) as u32, y + 1)).count();
+            (pairs(1), pairs(-1))
+        };
+        let (right, left) = diagonals(false);
+        assert!(right > 2 * left, "{} vers la droite, {} vers la gauche", right, left);
+        let (right, left) = diagonals(true);
+        assert!(right.abs_diff(left) * 5 <= right.max(left), "{} vers la droite, {} vers la gauche", right, left);
+    }
+
+    #[test]
+    fn stevenson_arce_dithers_a_2x2_image() {
+        let img = RgbImage::from_fn(2, 2, |x, y| Rgb([(x * 150 + y * 60) as u8; 3]));
+        let result = modify_image_dithering(img, Algo::StevensonArce, None, None, &options()).unwrap();
+        assert_eq!(result.dimensions(), (2, 2));
+        assert!(result.pixels().all(|pixel| *pixel == WHITE || *pixel == BLACK));
+        // Serpentine scanning mirrors its reach of x ± 3 as well
+        let img = RgbImage::from_pixel(2, 2, Rgb([100; 3]));
+        modify_image_dithering(img, Algo::StevensonArce, None, None, &DitherOptions { serpentin: true, ..options() }).unwrap();
+    }
+
+    #[test]
+    fn a_grey_half_as_bright_in_linear_light_gets_half_white_pixels() {
+        let white_share = |grey: u8, linear: bool| {
+            let img = RgbImage::from_pixel(64, 64, Rgb([grey; 3]));
+            let dithered = dither(img, Algo::FloydSteinberg, None, None, &DitherOptions { linear, ..options() });
+            dithered.pixels().filter(|&&pixel| pixel == WHITE).count() as f64 / (64 * 64) as f64
+        };
+        // 188 in sRGB is 50 % of the light of white: the gamma-encoded
+        // value would make it 74 % white
+        let grey = linear_to_srgb(0.5).round() as u8;
+        assert_eq!(grey, 188);
+        assert!((white_share(grey, true) - 0.5).abs() < 0.01, "{} de blanc", white_share(grey, true));
+        assert!(white_share(grey, false) > 0.7, "{} de blanc", white_share(grey, false));
+        // And 128 is barely more than a fifth of it, half white only
+        // without the linear light
+        assert!((white_share(128, true) - srgb_to_linear(128.0)).abs() < 0.01, "{} de blanc", white_share(128, true));
+        assert!((white_share(128, false) - 0.5).abs() < 0.01, "{} de blanc", white_share(128, false));
+    }
+
+    #[test]
+    fn a_ten_percent_grey_gets_ten_percent_white_pixels() {
+        // 25.5 is a tenth of white: the error below black must not be lost,
+        // only what the kernels push past the borders
+        let img = RgbImage::from_pixel(100, 100, Rgb([25; 3]));
+        let mut with_half = img.clone();
+        with_half.pixels_mut().step_by(2).for_each(|pixel| *pixel = Rgb([26; 3]));
+        for algo in [Algo::FloydSteinberg, Algo::Burkes, Algo::Sierra] {
+            let white = dither(with_half.clone(), algo, None, None, &options()).pixels().filter(|&&pixel| pixel == WHITE).count();
+            assert!((white as f64 / 10_000.0 - 0.1).abs() < 0.01, "{:?} : {} pixels blancs", algo, white);
+        }
+    }
+
+    #[test]
+    fn no_strength_is_plain_thresholding() {
+        let mut rng = crate::random::Rng::new(50);
+        let img = RgbImage::from_fn(40, 30, |_, _| Rgb([0, 1, 2].map(|_| rng.below(256) as u8)));
+        for linear in [false, true] {
+            let seuil = crate::threshold::modify_image_seuil(img.clone(), 128, WHITE, BLACK, linear);
+            for algo in [Algo::FloydSteinberg, Algo::JarvisJudiceNinke, Algo::Atkinson, Algo::Riemersma] {
+                let options = DitherOptions { strength: 0.0, linear, ..options() };
+                assert_eq!(dither(img.clone(), algo, None, None, &options), seuil, "{:?}", algo);
+            }
+        }
+    }
+}
@@ -0,0 +1,375 @@
+//! Colour distances used to pick the nearest palette entry.
+
+use std::str::FromStr;
+
+use image::Rgb;
+
+use crate::lattice::Lattice;
+use crate::simd::{nearest_row, Isa};
+use crate::srgb::{linear_to_srgb, srgb_to_linear, working_value};
+use crate::BLACK;
+
+/// How far apart two colours are, to find the nearest of a palette.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distance {
+    /// Euclidean distance between the sRGB values
+    Rgb,
+    /// Euclidean sRGB distance weighted by the mean red level ("redmean")
+    Redmean,
+    /// ΔE76: Euclidean distance in CIELAB
+    Lab,
+    /// ΔE00: the CIEDE2000 colour difference, computed in CIELAB
+    Ciede2000,
+    /// Weighted distance between the hue, saturation and value components
+    Hsv([f64; 3]),
+}
+
+/// Weights of the hue, saturation and value differences of `--distance hsv`.
+pub const DEFAULT_HSV_WEIGHTS: [f64; 3] = [1.0, 1.0, 1.0];
+
+// Below this saturation the hue of a colour is meaningless, so the pixel is
+// compared on its value alone
+const ACHROMATIC_SATURATION: f64 = 0.1;
+
+/// Names accepted by `--distance`, in the order they are listed in error messages.
+pub const DISTANCES: [(&str, Distance); 5] = [
+    ("rgb", Distance::Rgb),
+    ("redmean", Distance::Redmean),
+    ("lab", Distance::Lab),
+    ("ciede2000", Distance::Ciede2000),
+    ("hsv", Distance::Hsv(DEFAULT_HSV_WEIGHTS)),
+];
+
+impl FromStr for Distance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DISTANCES.iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, distance)| *distance)
+            .ok_or_else(|| {
+                let names: Vec<&str> = DISTANCES.iter().map(|(name, _)| *name).collect();
+                format!("distance inconnue : {} (attendues : {})", s, names.join(", "))
+            })
+    }
+}
+
+impl Distance {
+    // Coordinates of an sRGB value in the space where the distance is computed
+    fn convert(self, value: [f64; 3]) -> [f64; 3] {
+        match self {
+            Distance::Rgb | Distance::Redmean => value,
+            Distance::Lab | Distance::Ciede2000 => srgb_to_lab(value),
+            Distance::Hsv(_) => srgb_to_hsv(value),
+        }
+    }
+
+    // Distance between a converted palette colour `a` and pixel `b`; only its
+    // order matters, so Euclidean distances are left squared
+    fn between(self, a: [f64; 3], b: [f64; 3]) -> f64 {
+        match self {
+            Distance::Rgb | Distance::Lab => (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2),
+            Distance::Redmean => {
+                // Red counts more in reddish colours and blue in the others
+                let red_mean = (a[0] + b[0]) / 2.0;
+                (2.0 + red_mean / 256.0) * (a[0] - b[0]).powi(2) + 4.0 * (a[1] - b[1]).powi(2) + (2.0 + (255.0 - red_mean) / 256.0) * (a[2] - b[2]).powi(2)
+            }
+            Distance::Ciede2000 => ciede2000(a, b),
+            Distance::Hsv([hue_weight, saturation_weight, value_weight]) => {
+                let value = value_weight * (a[2] - b[2]).powi(2);
+                if b[1] < ACHROMATIC_SATURATION {
+                    return value;
+                }
+                let saturation = saturation_weight * (a[1] - b[1]).powi(2);
+                if a[1] < ACHROMATIC_SATURATION {
+                    return saturation + value;
+                }
+                // The hue difference goes the short way round the circle,
+                // scaled to 0..1 like the other components
+                let hue = (a[0] - b[0]).abs();
+                let hue = hue.min(360.0 - hue) / 180.0;
+                hue_weight * hue * hue + saturation + value
+            }
+        }
+    }
+}
+
+/// From this number of colours, the nearest one to an 8-bit pixel by the rgb
+/// distance is looked up in a `Lattice` rather than among all of them.
+pub const LATTICE_MIN_COLOURS: usize = 32;
+
+/// Finds the nearest colour of a palette, whose entries are converted to
+/// the space of the distance once and for all.
+pub struct PaletteMatcher {
+    distance: Distance,
+    linear: bool,
+    colours: Vec<Rgb<u8>>,
+    converted: Vec<[f64; 3]>,
+    lattice: Option<Lattice>,
+}
+
+impl PaletteMatcher {
+    /// With `linear`, the values looked up are in linear light (see
+    /// `working_value`) and the rgb distance is computed in linear light too.
+    pub fn new(palette: &[Rgb<u8>], distance: Distance, linear: bool) -> PaletteMatcher {
+        let mut matcher = PaletteMatcher { distance, linear, colours: palette.to_vec(), converted: Vec::new(), lattice: None };
+        matcher.converted = palette.iter().map(|c| matcher.convert(c.0.map(|channel| working_value(channel, linear)))).collect();
+        if distance == Distance::Rgb && !linear && palette.len() >= LATTICE_MIN_COLOURS {
+            matcher.lattice = Some(Lattice::new(palette));
+        }
+        matcher
+    }
+
+    // Coordinates of a working-space value in the space of the distance
+    fn convert(&self, value: [f64; 3]) -> [f64; 3] {
+        match self.distance {
+            Distance::Lab | Distance::Ciede2000 if self.linear => linear_to_lab(value.map(|c| c / 255.0)),
+            // These distances are defined on gamma-encoded values
+            Distance::Redmean | Distance::Hsv(_) if self.linear => self.distance.convert(value.map(|c| linear_to_srgb(c / 255.0))),
+            _ => self.distance.convert(value),
+        }
+    }
+
+    /// The palette colour closest to the 8-bit `pixel`, the same as `nearest`
+    /// gives for its working value. The rgb distance between sRGB values is
+    /// computed on integers, without any conversion, and only to the
+    /// candidates of its `Lattice` cell in large palettes.
+    pub fn nearest_pixel(&self, pixel: Rgb<u8>) -> Rgb<u8> {
+        if self.distance != Distance::Rgb || self.linear {
+            return self.nearest(pixel.0.map(|c| working_value(c, self.linear)));
+        }
+        if let Some(lattice) = &self.lattice {
+            return self.colours[lattice.nearest(pixel)];
+        }
+        let mut best_distance = u32::MAX;
+        let mut best_color = BLACK;
+        for color in &self.colours {
+            let distance = squared_distance(*color, pixel);
+            if distance < best_distance {
+                best_distance = distance;
+                best_color = *color;
+            }
+        }
+        best_color
+    }
+
+    /// Replaces each pixel of `row`, packed 8-bit RGB, by `nearest_pixel` of
+    /// it. Small palettes compared by the rgb distance between sRGB values
+    /// take several pixels at a time where the processor allows it (see
+    /// `simd::nearest_row`).
+    pub fn nearest_row(&self, row: &mut [u8]) {
+        if self.distance == Distance::Rgb && !self.linear && self.lattice.is_none() && !self.colours.is_empty() {
+            return nearest_row(Isa::detect(), row, &self.colours);
+        }
+        for pixel in row.chunks_exact_mut(3) {
+            let nearest = self.nearest_pixel(Rgb([pixel[0], pixel[1], pixel[2]]));
+            pixel.copy_from_slice(&nearest.0);
+        }
+    }
+
+    /// The palette colour closest to `value`, which may lie outside of the
+    /// RGB cube; black if the palette is empty.
+    pub fn nearest(&self, value: [f64; 3]) -> Rgb<u8> {
+        let value = self.convert(value);
+        let mut best_distance = f64::INFINITY;
+        let mut best_color = BLACK;
+        for (color, converted) in self.colours.iter().zip(self.converted.iter()) {
+            let distance = self.distance.between(*converted, value);
+            if distance < best_distance {
+                best_distance = distance;
+                best_color = *color;
+            }
+        }
+        best_color
+    }
+}
+
+// The squared Euclidean distance between two sRGB colours, at most
+// 3 × 255², exact where the one on f64 is too
+pub(crate) fn squared_distance(Rgb(a): Rgb<u8>, Rgb(b): Rgb<u8>) -> u32 {
+    let difference = |c: usize| a[c].abs_diff(b[c]) as u32;
+    difference(0) * difference(0) + difference(1) * difference(1) + difference(2) * difference(2)
+}
+
+/// Parses the `--poids-hsv` weights: three non-negative numbers for the hue,
+/// the saturation and the value, separated by commas.
+pub fn parse_hsv_weights(value: &str) -> Result<[f64; 3], String> {
+    let weights: Vec<Option<f64>> = value.split(',')
+        .map(|token| token.trim().parse::<f64>().ok().filter(|w| *w >= 0.0 && w.is_finite()))
+        .collect();
+    match weights[..] {
+        [Some(h), Some(s), Some(v)] => Ok([h, s, v]),
+        _ => Err(format!("poids invalides : {} (attendus : trois réels positifs séparés par des virgules, par exemple \"2,1,1\")", value)),
+    }
+}
+
+// Hue in degrees, saturation and value in 0..1
+fn srgb_to_hsv(value: [f64; 3]) -> [f64; 3] {
+    let [r, g, b] = value.map(|c| c / 255.0);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    [hue, saturation, max]
+}
+
+// CIELAB coordinates of an sRGB colour, relative to the D65 white point
+fn srgb_to_lab(value: [f64; 3]) -> [f64; 3] {
+    linear_to_lab(value.map(srgb_to_linear))
+}
+
+fn linear_to_lab([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let x = (0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b) / 0.950_47;
+    let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175_0 * b;
+    let z = (0.019_333_9 * r + 0.119_192_0 * g + 0.950_304_1 * b) / 1.088_83;
+
+    let f = |t: f64| {
+        const DELTA: f64 = 6.0 / 29.0;
+        if t > DELTA.powi(3) { t.cbrt() } else { t / (3.0 * DELTA * DELTA) + 4.0 / 29.0 }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// The CIEDE2000 difference between two CIELAB colours, with the parametric
+/// factors kL, kC and kH set to 1, following Sharma, Wu and Dalal (2005).
+pub fn ciede2000(lab1: [f64; 3], lab2: [f64; 3]) -> f64 {
+    let [l1, a1, b1] = lab1;
+    let [l2, a2, b2] = lab2;
+    let pow25_7 = 25f64.powi(7);
+
+    // a* is stretched for low chromas, which corrects the blue region
+    let c_mean = ((a1.hypot(b1) + a2.hypot(b2)) / 2.0).powi(7);
+    let g = 0.5 * (1.0 - (c_mean / (c_mean + pow25_7)).sqrt());
+    let (a1, a2) = ((1.0 + g) * a1, (1.0 + g) * a2);
+    let (c1, c2) = (a1.hypot(b1), a2.hypot(b2));
+    let hue = |a: f64, b: f64| if a == 0.0 && b == 0.0 { 0.0 } else { b.atan2(a).to_degrees().rem_euclid(360.0) };
+    let (h1, h2) = (hue(a1, b1), hue(a2, b2));
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else {
+        match h2 - h1 {
+            d if d > 180.0 => d - 360.0,
+            d if d < -180.0 => d + 360.0,
+            d => d,
+        }
+    };
+    let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let l_mean = (l1 + l2) / 2.0;
+    let c_mean = (c1 + c2) / 2.0;
+    let h_mean = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+
+    let cos = |degrees: f64| degrees.to_radians().cos();
+    let t = 1.0 - 0.17 * cos(h_mean - 30.0) + 0.24 * cos(2.0 * h_mean) + 0.32 * cos(3.0 * h_mean + 6.0) - 0.20 * cos(4.0 * h_mean - 63.0);
+    let delta_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (c_mean.powi(7) / (c_mean.powi(7) + pow25_7)).sqrt();
+    let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_mean;
+    let s_h = 1.0 + 0.015 * c_mean * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let (dl, dc, dh) = (delta_l / s_l, delta_c / s_c, delta_h / s_h);
+    (dl * dl + dc * dc + dh * dh + r_t * dc * dh).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The 34 pairs of Sharma, Wu and Dalal (2005), table 1, and their
+    // differences to four decimals
+    const SHARMA: [([f64; 3], [f64; 3], f64); 34] = [
+        ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+        ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+        ([50.0, 2.8361, -74.0200], [50.0, 0.0, -82.7485], 3.4412),
+        ([50.0, -1.3802, -84.2814], [50.0, 0.0, -82.7485], 1.0),
+        ([50.0, -1.1848, -84.8006], [50.0, 0.0, -82.7485], 1.0),
+        ([50.0, -0.9009, -85.5211], [50.0, 0.0, -82.7485], 1.0),
+        ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+        ([50.0, -1.0, 2.0], [50.0, 0.0, 0.0], 2.3669),
+        ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0009], 7.1792),
+        ([50.0, 2.49, -0.001], [50.0, -2.49, 0.001], 7.1792),
+        ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0011], 7.2195),
+        ([50.0, 2.49, -0.001], [50.0, -2.49, 0.0012], 7.2195),
+        ([50.0, -0.001, 2.49], [50.0, 0.0009, -2.49], 4.8045),
+        ([50.0, -0.001, 2.49], [50.0, 0.001, -2.49], 4.8045),
+        ([50.0, -0.001, 2.49], [50.0, 0.0011, -2.49], 4.7461),
+        ([50.0, 2.5, 0.0], [50.0, 0.0, -2.5], 4.3065),
+        ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+        ([50.0, 2.5, 0.0], [61.0, -5.0, 29.0], 22.8977),
+        ([50.0, 2.5, 0.0], [56.0, -27.0, -3.0], 31.9030),
+        ([50.0, 2.5, 0.0], [58.0, 24.0, 15.0], 19.4535),
+        ([50.0, 2.5, 0.0], [50.0, 3.1736, 0.5854], 1.0),
+        ([50.0, 2.5, 0.0], [50.0, 3.2972, 0.0], 1.0),
+        ([50.0, 2.5, 0.0], [50.0, 1.8634, 0.5757], 1.0),
+        ([50.0, 2.5, 0.0], [50.0, 3.2592, 0.3350], 1.0),
+        ([60.2574, -34.0099, 36.2677], [60.4626, -34.1751, 39.4387], 1.2644),
+        ([63.0109, -31.0961, -5.8663], [62.8187, -29.7946, -4.0864], 1.2630),
+        ([61.2901, 3.7196, -5.3901], [61.4292, 2.2480, -4.9620], 1.8731),
+        ([35.0831, -44.1164, 3.7933], [35.0232, -40.0716, 1.5901], 1.8645),
+        ([22.7233, 20.0904, -46.6940], [23.0331, 14.9730, -42.5619], 2.0373),
+        ([36.4612, 47.8580, 18.3852], [36.2715, 50.5065, 21.2231], 1.4146),
+        ([90.8027, -2.0831, 1.4410], [91.1528, -1.6435, 0.0447], 1.4441),
+        ([90.9257, -0.5406, -0.9208], [88.6381, -0.8985, -0.7239], 1.5381),
+        ([6.7747, -0.2908, -2.4247], [5.8714, -0.0985, -2.2286], 0.6377),
+        ([2.0776, 0.0795, -1.1350], [0.9033, -0.0636, -0.5514], 0.9082),
+    ];
+
+    #[test]
+    fn ciede2000_matches_the_pairs_of_sharma() {
+        for (i, (lab1, lab2, expected)) in SHARMA.into_iter().enumerate() {
+            let difference = ciede2000(lab1, lab2);
+            assert!((difference - expected).abs() < 1e-4, "paire {} : {} au lieu de {}", i + 1, difference, expected);
+            assert!((ciede2000(lab2, lab1) - difference).abs() < 1e-12, "paire {} dans l’autre sens", i + 1);
+        }
+    }
+
+    #[test]
+    fn redmean_puts_a_dark_brown_nearer_dark_red_than_blue() {
+        let (dark_red, blue, brown) = (Rgb([139, 0, 0]), Rgb([0, 0, 96]), Rgb([44, 24, 12]));
+        let palette = [dark_red, blue];
+        assert_eq!(PaletteMatcher::new(&palette, Distance::Rgb, false).nearest_pixel(brown), blue);
+        assert_eq!(PaletteMatcher::new(&palette, Distance::Redmean, false).nearest_pixel(brown), dark_red);
+    }
+
+    #[test]
+    fn hsv_hues_wrap_around_and_greys_go_by_value() {
+        let hsv = Distance::Hsv(DEFAULT_HSV_WEIGHTS);
+        // Hues of 359° and 1° are 2° apart, not 358°
+        let (hue_359, hue_1) = ([255.0, 0.0, 4.25], [255.0, 4.25, 0.0]);
+        assert!((hsv.convert(hue_359)[0] - 359.0).abs() < 1e-9 && (hsv.convert(hue_1)[0] - 1.0).abs() < 1e-9);
+        let near = hsv.between(hsv.convert(hue_1), hsv.convert(hue_359));
+        assert!((near - (2.0f64 / 180.0).powi(2)).abs() < 1e-12, "distance {}", near);
+        let palette = [Rgb([255, 4, 0]), Rgb([255, 0, 85])];
+        assert_eq!(PaletteMatcher::new(&palette, hsv, false).nearest_pixel(Rgb([255, 0, 4])), palette[0]);
+
+        // A nearly grey pixel has a hue of noise: it goes to the colour of
+        // its value, whatever that hue
+        let (red, blue) = (Rgb([200, 0, 0]), Rgb([0, 0, 128]));
+        let matcher = PaletteMatcher::new(&[red, blue], hsv, false);
+        for grey in [Rgb([128, 128, 128]), Rgb([130, 128, 126]), Rgb([126, 128, 130]), Rgb([128, 130, 126])] {
+            assert_eq!(matcher.nearest_pixel(grey), blue, "{:?}", grey);
+        }
+        assert_eq!(matcher.nearest_pixel(Rgb([200, 128, 126])), red);
+    }
+}
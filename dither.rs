@@ -0,0 +1,439 @@
+//! A builder gathering the settings of dithering, error diffusion or ordered,
+//! so that programs set only the ones they need and get the combinations
+//! that make no sense rejected in one place.
+
+use image::{DynamicImage, GrayImage, Rgb, Rgb32FImage, RgbImage, RgbaImage};
+
+use crate::alpha::{join_rgba, split_rgba};
+use crate::diffusion::{check_strength, modify_image_dithering, modify_image_dithering_gray, Algo, Diffusion, DitherOptions, ErrorDiffusion, Kernel, RowDiffusion};
+use crate::distance::Distance;
+use crate::dither_error::DitherError;
+use crate::ditherer::{BlackAndWhite, Ditherer, PixelQuantizer};
+use crate::ordered::{check_force, modify_image_tramage, modify_image_tramage_palette, Ordered, ThresholdSource, DEFAULT_FORCE};
+use crate::palette::Palette;
+use crate::progress::Progress;
+use crate::random::Rng;
+use crate::stream::{Rows, Streamer};
+use crate::threshold::DEFAULT_THRESHOLD;
+use crate::tiles::{TileDiffusion, TileMethod, Tiler};
+
+// How the pixels are visited and their error carried
+enum Method {
+    Diffusion(Algo),
+    Kernel(Kernel),
+    Ordered(ThresholdSource),
+}
+
+/// The settings of a dithering, Floyd-Steinberg to black and white unless
+/// told otherwise.
+///
+/// ```
+/// use image::RgbImage;
+/// use tp_eval::ops::dither::{Algo, Dither};
+/// use tp_eval::Palette;
+///
+/// let img = RgbImage::from_pixel(8, 8, image::Rgb([90, 160, 220]));
+/// let palette = Palette::builtin(8);
+/// let result = Dither::new().algorithm(Algo::Atkinson).palette(palette.clone()).linear_light(true).strength(0.9).apply(&img).unwrap();
+/// assert!(result.pixels().all(|pixel| palette.iter().any(|colour| colour == pixel)));
+/// ```
+pub struct Dither<'a> {
+    method: Method,
+    palette: Option<Palette>,
+    colours: Option<(Rgb<u8>, Rgb<u8>)>,
+    linear: bool,
+    serpentine: bool,
+    strength: Option<f64>,
+    threshold: Option<u8>,
+    force: Option<f32>,
+    seed: Option<u64>,
+    alpha: Option<&'a GrayImage>,
+    source: Option<&'a Rgb32FImage>,
+    progress: Option<&'a Progress>,
+}
+
+impl Default for Dither<'_> {
+    fn default() -> Self {
+        Dither::new()
+    }
+}
+
+impl<'a> Dither<'a> {
+    /// Floyd-Steinberg error diffusion to black and white.
+    pub fn new() -> Dither<'a> {
+        Dither {
+            method: Method::Diffusion(Algo::FloydSteinberg),
+            palette: None,
+            colours: None,
+            linear: false,
+            serpentine: false,
+            strength: None,
+            threshold: None,
+            force: None,
+            seed: None,
+            alpha: None,
+            source: None,
+            progress: None,
+        }
+    }
+
+    /// Error diffusion, or random thresholding, with `algo`.
+    pub fn algorithm(self, algo: Algo) -> Dither<'a> {
+        Dither { method: Method::Diffusion(algo), ..self }
+    }
+
+    /// Error diffusion through a custom `kernel`.
+    pub fn kernel(self, kernel: Kernel) -> Dither<'a> {
+        Dither { method: Method::Kernel(kernel), ..self }
+    }
+
+    /// Ordered dithering against the thresholds of `source`.
+    pub fn ordered(self, source: ThresholdSource) -> Dither<'a> {
+        Dither { method: Method::Ordered(source), ..self }
+    }
+
+    /// Quantizes to the nearest colour of `palette` instead of black or white.
+    pub fn palette(self, palette: Palette) -> Dither<'a> {
+        Dither { palette: Some(palette), ..self }
+    }
+
+    /// Quantizes to `dark` and `light` instead of black and white, chosen on
+    /// the luma as black and white are, and spreads the error they leave.
+    pub fn colours(self, dark: Rgb<u8>, light: Rgb<u8>) -> Dither<'a> {
+        Dither { colours: Some((dark, light)), ..self }
+    }
+
+    /// Measures and spreads the error in linear light.
+    pub fn linear_light(self, linear: bool) -> Dither<'a> {
+        Dither { linear, ..self }
+    }
+
+    /// Scans odd rows right to left, in error diffusion.
+    pub fn serpentine(self, serpentine: bool) -> Dither<'a> {
+        Dither { serpentine, ..self }
+    }
+
+    /// The fraction of the error that is diffused, from 0 to 1 (the default).
+    pub fn strength(self, strength: f64) -> Dither<'a> {
+        Dither { strength: Some(strength), ..self }
+    }
+
+    /// The luma from which a pixel becomes white in error diffusion to black
+    /// and white, `DEFAULT_THRESHOLD` by default.
+    pub fn threshold(self, threshold: u8) -> Dither<'a> {
+        Dither { threshold: Some(threshold), ..self }
+    }
+
+    /// The amplitude, in channel levels, of the perturbation of ordered
+    /// dithering to a palette, `DEFAULT_FORCE` by default.
+    pub fn force(self, force: f32) -> Dither<'a> {
+        Dither { force: Some(force), ..self }
+    }
+
+    /// The seed of random thresholding, drawn from the OS when not given.
+    pub fn seed(self, seed: u64) -> Dither<'a> {
+        Dither { seed: Some(seed), ..self }
+    }
+
+    /// The alpha plane of the image, whose fully transparent pixels spread no
+    /// error.
+    pub fn alpha(self, alpha: &'a GrayImage) -> Dither<'a> {
+        Dither { alpha: Some(alpha), ..self }
+    }
+
+    /// The full-precision pixels of a 16-bit input, diffused instead of the
+    /// 8-bit image.
+    pub fn full_precision(self, source: &'a Rgb32FImage) -> Dither<'a> {
+        Dither { source: Some(source), ..self }
+    }
+
+    /// A bar counting the rows done.
+    pub fn progress(self, progress: &'a Progress) -> Dither<'a> {
+        Dither { progress: Some(progress), ..self }
+    }
+
+    /// `img` dithered with these settings.
+    pub fn apply(&self, img: &RgbImage) -> Result<RgbImage, DitherError> {
+        self.check()?;
+        self.run(img.clone(), self.alpha)
+    }
+
+    /// `img` dithered with these settings, its alpha channel kept as it is;
+    /// its fully transparent pixels spread no error, whatever `alpha` says.
+    pub fn apply_rgba(&self, img: &RgbaImage) -> Result<RgbaImage, DitherError> {
+        self.check()?;
+        let (rgb, alpha) = split_rgba(img);
+        Ok(join_rgba(&self.run(rgb, Some(&alpha))?, &alpha))
+    }
+
+    /// `img` dithered with these settings, in the pixel type of `img` when it
+    /// can hold the result: a grayscale image stays one without a palette, an
+    /// image with transparency keeps its alpha channel. The other images give
+    /// 8-bit RGB or RGBA.
+    ///
+    /// ```
+    /// use image::{DynamicImage, GrayImage};
+    /// use tp_eval::Dither;
+    ///
+    /// let img = DynamicImage::ImageLuma8(GrayImage::from_pixel(8, 8, image::Luma([128])));
+    /// let result = Dither::new().apply_dynamic(&img).unwrap();
+    /// assert!(result.as_luma8().is_some_and(|gray| gray.pixels().all(|pixel| pixel[0] == 0 || pixel[0] == 255)));
+    /// ```
+    pub fn apply_dynamic(&self, img: &DynamicImage) -> Result<DynamicImage, DitherError> {
+        match img {
+            DynamicImage::ImageLuma8(gray) if self.palette.is_none() && self.colours.is_none() => self.apply_gray(gray).map(DynamicImage::ImageLuma8),
+            DynamicImage::ImageRgb8(rgb) => self.apply(rgb).map(DynamicImage::ImageRgb8),
+            DynamicImage::ImageRgba8(rgba) => self.apply_rgba(rgba).map(DynamicImage::ImageRgba8),
+            img if img.color().has_alpha() => self.apply_rgba(&img.to_rgba8()).map(DynamicImage::ImageRgba8),
+            img => self.apply(&img.to_rgb8()).map(DynamicImage::ImageRgb8),
+        }
+    }
+
+    /// `img` dithered to black and white with these settings, on its single
+    /// channel; a grey pixel gets the same result as through `apply`.
+    pub fn apply_gray(&self, img: &GrayImage) -> Result<GrayImage, DitherError> {
+        self.check()?;
+        if self.palette.is_some() || self.colours.is_some() {
+            return Err(DitherError::InvalidParameter("une image en niveaux de gris n’est tramée qu’en noir et blanc".to_string()));
+        }
+        let img = img.clone();
+        match &self.method {
+            Method::Ordered(source) => {
+                let rgb = DynamicImage::ImageLuma8(img).to_rgb8();
+                Ok(DynamicImage::ImageRgb8(modify_image_tramage(rgb, source, self.linear)).to_luma8())
+            }
+            Method::Diffusion(algo) => modify_image_dithering_gray(img, *algo, None, &self.options(self.alpha)),
+            Method::Kernel(kernel) => modify_image_dithering_gray(img, Algo::FloydSteinberg, Some(kernel), &self.options(self.alpha)),
+        }
+    }
+
+    /// A `Streamer` dithering, with these settings, an image fed row by row
+    /// whose rows are `width` pixels wide. Riemersma, which does not scan the
+    /// image row by row, is not available, nor are `alpha` and
+    /// `full_precision`, which hold a whole image.
+    pub fn streamer(&self, width: u32) -> Result<Streamer<'_>, DitherError> {
+        self.check()?;
+        if self.alpha.is_some() || self.source.is_some() {
+            return Err(DitherError::InvalidParameter("le plan alpha et la pleine précision d’une image entière n’ont pas de sens ligne par ligne".to_string()));
+        }
+        let options = self.options(None);
+        check_strength(&options)?;
+        let rows = match &self.method {
+            Method::Ordered(source) => {
+                let force = self.force.unwrap_or(DEFAULT_FORCE);
+                check_force(force)?;
+                Rows::Ordered { source, force }
+            }
+            Method::Diffusion(Algo::Random) => Rows::Random { source: ThresholdSource::Random(options.seed), force: DEFAULT_FORCE },
+            Method::Diffusion(algo) => match algo.diffusion() {
+                Some(diffusion) => Rows::Diffusion(RowDiffusion::new(diffusion, width, &options)),
+                None => return Err(DitherError::InvalidParameter("riemersma parcourt l’image le long d’une courbe, pas ligne par ligne".to_string())),
+            },
+            Method::Kernel(kernel) => Rows::Diffusion(RowDiffusion::new(Diffusion::Fixed(kernel), width, &options)),
+        };
+        Ok(Streamer::new(width, self.linear, self.quantizer(&options)?, rows))
+    }
+
+    /// A `Tiler` dithering, with these settings, a `width` × `height` image
+    /// fed in the tiles of `side` pixels of `tiles::tiles`. As with
+    /// `streamer`, Riemersma is not available, nor are `alpha` and
+    /// `full_precision`, each tile coming with its own. Error diffusion needs
+    /// tiles at least as large as the reach of its kernel.
+    pub fn tiler(&self, width: u32, height: u32, side: u32) -> Result<Tiler<'_>, DitherError> {
+        self.check()?;
+        if self.alpha.is_some() || self.source.is_some() {
+            return Err(DitherError::InvalidParameter("le plan alpha et la pleine précision d’une image entière n’ont pas de sens tuile par tuile".to_string()));
+        }
+        let options = self.options(None);
+        check_strength(&options)?;
+        let diffusion = match &self.method {
+            Method::Ordered(_) | Method::Diffusion(Algo::Random) => None,
+            Method::Diffusion(algo) => match algo.diffusion() {
+                Some(diffusion) => Some(diffusion),
+                None => return Err(DitherError::InvalidParameter("riemersma parcourt l’image le long d’une courbe, pas tuile par tuile".to_string())),
+            },
+            Method::Kernel(kernel) => Some(Diffusion::Fixed(kernel)),
+        };
+        let min_side = diffusion.map_or(1, TileDiffusion::min_side);
+        if side < min_side {
+            return Err(DitherError::InvalidParameter(format!("les tuiles doivent mesurer au moins {} pixels de côté pour cette diffusion", min_side)));
+        }
+        let method = match (&self.method, diffusion) {
+            (_, Some(diffusion)) => TileMethod::Diffusion(TileDiffusion::new(diffusion, width, &options)),
+            (Method::Ordered(source), None) => {
+                let force = self.force.unwrap_or(DEFAULT_FORCE);
+                check_force(force)?;
+                TileMethod::Ordered { source, force }
+            }
+            (_, None) => TileMethod::Random { source: ThresholdSource::Random(options.seed), force: DEFAULT_FORCE },
+        };
+        Ok(Tiler::new(width, height, side, self.linear, self.quantizer(&options)?, method))
+    }
+
+    // What pixels are quantized to, one pixel at a time, for `streamer` and
+    // `tiler`
+    fn quantizer(&self, options: &DitherOptions) -> Result<Box<dyn PixelQuantizer>, DitherError> {
+        Ok(match &self.palette {
+            Some(palette) if palette.is_empty() => return Err(DitherError::EmptyPalette),
+            Some(palette) => Box::new(palette.matcher(Distance::Rgb, self.linear)),
+            None => Box::new(self.black_and_white(options)),
+        })
+    }
+
+    // Black and white, or the two colours in their place
+    fn black_and_white(&self, options: &DitherOptions) -> BlackAndWhite {
+        match self.colours {
+            Some((dark, light)) => BlackAndWhite::with_colours(options.threshold, dark, light),
+            None => BlackAndWhite::new(options.threshold),
+        }
+    }
+
+    // `img` dithered, once the settings are checked, with `alpha` as its plane
+    fn run(&self, img: RgbImage, alpha: Option<&GrayImage>) -> Result<RgbImage, DitherError> {
+        if self.colours.is_some() {
+            return self.run_two_colours(img, alpha);
+        }
+        match &self.method {
+            Method::Ordered(source) => match &self.palette {
+                Some(palette) => modify_image_tramage_palette(img, source, palette, self.force.unwrap_or(DEFAULT_FORCE), self.linear),
+                None => Ok(modify_image_tramage(img, source, self.linear)),
+            },
+            Method::Diffusion(algo) => modify_image_dithering(img, *algo, None, self.palette.as_ref(), &self.options(alpha)),
+            Method::Kernel(kernel) => modify_image_dithering(img, Algo::FloydSteinberg, Some(kernel), self.palette.as_ref(), &self.options(alpha)),
+        }
+    }
+
+    // `run` towards the two colours in the place of black and white, which
+    // every method quantizes to as it would to them
+    fn run_two_colours(&self, img: RgbImage, alpha: Option<&GrayImage>) -> Result<RgbImage, DitherError> {
+        let options = self.options(alpha);
+        check_strength(&options)?;
+        let quantizer = self.black_and_white(&options);
+        Ok(match &self.method {
+            Method::Ordered(source) => Ordered::new(source, DEFAULT_FORCE, self.linear).dither(img, &quantizer),
+            Method::Diffusion(algo) => algo.ditherer(&options).dither(img, &quantizer),
+            Method::Kernel(kernel) => ErrorDiffusion::with_kernel(kernel, &options).dither(img, &quantizer),
+        })
+    }
+
+    // Rejects the settings that the method ignores
+    fn check(&self) -> Result<(), DitherError> {
+        let invalid = |message: &str| Err(DitherError::InvalidParameter(message.to_string()));
+        let random = matches!(self.method, Method::Diffusion(Algo::Random));
+        match &self.method {
+            Method::Ordered(_) if self.strength.is_some() || self.serpentine || self.threshold.is_some() => {
+                invalid("la force de diffusion, le parcours en serpentin et le seuil n’ont pas de sens avec le tramage ordonné")
+            }
+            Method::Ordered(_) if self.force.is_some() && self.palette.is_none() => {
+                invalid("l’amplitude de la perturbation n’a de sens qu’avec une palette")
+            }
+            Method::Diffusion(_) | Method::Kernel(_) if self.force.is_some() => {
+                invalid("l’amplitude de la perturbation n’a de sens qu’avec le tramage ordonné")
+            }
+            _ if random && self.strength.is_some() => invalid("la force de diffusion n’a pas de sens avec le seuillage aléatoire"),
+            _ if self.colours.is_some() && self.palette.is_some() => invalid("les couleurs claire et foncée n’ont pas de sens avec une palette"),
+            _ if self.threshold.is_some() && (self.palette.is_some() || random) => {
+                invalid("le seuil n’a pas de sens avec une palette ou le seuillage aléatoire")
+            }
+            _ if self.seed.is_some() && !random => invalid("la graine n’a de sens qu’avec le seuillage aléatoire"),
+            _ => Ok(()),
+        }
+    }
+
+    fn options<'b>(&'b self, alpha: Option<&'b GrayImage>) -> DitherOptions<'b> {
+        DitherOptions {
+            serpentin: self.serpentine,
+            seed: self.seed.unwrap_or_else(|| Rng::from_entropy().next_u64()),
+            linear: self.linear,
+            strength: self.strength.unwrap_or(1.0),
+            threshold: self.threshold.unwrap_or(DEFAULT_THRESHOLD),
+            alpha,
+            source: self.source,
+            progress: self.progress,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mean_luma(img: &RgbImage) -> f64 {
+        img.pixels().map(|pixel| crate::srgb::rec709_luma(pixel.0.map(f64::from))).sum::<f64>() / (img.width() * img.height()) as f64
+    }
+
+    #[test]
+    fn two_colours_take_the_error_they_leave() {
+        // A grey of 100 between greys of 64 and 192 is 28 % light: dithered
+        // to black and white then recoloured, it would be 39 %
+        let (dark, light) = (Rgb([64; 3]), Rgb([192; 3]));
+        let img = RgbImage::from_pixel(64, 64, Rgb([100; 3]));
+        for dither in [Dither::new(), Dither::new().algorithm(Algo::JarvisJudiceNinke), Dither::new().serpentine(true)] {
+            let result = dither.colours(dark, light).apply(&img).unwrap();
+            assert!(result.pixels().all(|&pixel| pixel == dark || pixel == light));
+            assert!((mean_luma(&result) - 100.0).abs() < 2.0, "luma moyenne {}", mean_luma(&result));
+        }
+        // Row by row as well
+        let dither = Dither::new().colours(dark, light);
+        let mut streamer = dither.streamer(64).unwrap();
+        let rows: Vec<u8> = (0..64).flat_map(|_| streamer.push_row(&[100; 64 * 3])).collect();
+        assert_eq!(rows, dither.apply(&img).unwrap().into_raw());
+    }
+
+    #[test]
+    fn two_colours_go_without_a_palette_and_without_a_single_channel() {
+        let img = GrayImage::from_pixel(4, 4, image::Luma([100]));
+        let dither = Dither::new().colours(Rgb([64; 3]), Rgb([192; 3]));
+        assert!(dither.apply_gray(&img).is_err());
+        assert!(dither.apply_dynamic(&DynamicImage::ImageLuma8(img)).unwrap().as_rgb8().is_some());
+        assert!(dither.palette(Palette::builtin(3)).apply(&RgbImage::new(4, 4)).is_err());
+    }
+
+    #[test]
+    fn a_sixteen_bit_gradient_dithers_smoother_than_its_eight_bits() {
+        // A dark ramp over the first eight 8-bit levels, which truncation
+        // turns into a staircase
+        let (width, height) = (512, 256);
+        let ramp = |x: u32| x as f64 * 8.0 / width as f64 / 255.0;
+        let deep = DynamicImage::ImageRgb16(image::ImageBuffer::from_fn(width, height, |x, _| Rgb([(ramp(x) * 65535.0).round() as u16; 3])));
+        let (source, truncated) = (deep.to_rgb32f(), deep.to_rgb8());
+        // The squared gap between the share of white pixels of each band of
+        // 16 columns and the mean level of the ramp over it
+        let gap = |img: &RgbImage| {
+            (0..width / 16).map(|band| {
+                let columns = band * 16..band * 16 + 16;
+                let white = columns.clone().flat_map(|x| (0..height).map(move |y| (x, y))).filter(|&(x, y)| img.get_pixel(x, y)[0] == 255).count();
+                let level = columns.map(ramp).sum::<f64>() / 16.0;
+                (white as f64 / (16 * height) as f64 - level).powi(2)
+            }).sum::<f64>()
+        };
+        let full = Dither::new().full_precision(&source).apply(&truncated).unwrap();
+        let eight_bits = Dither::new().apply(&truncated).unwrap();
+        assert!(gap(&full) * 2.0 < gap(&eight_bits), "{} en 16 bits, {} en 8 bits", gap(&full), gap(&eight_bits));
+    }
+
+    #[test]
+    fn a_grayscale_image_gets_the_pixels_of_the_rgb_path() {
+        let mut rng = Rng::new(60);
+        let gray = GrayImage::from_fn(37, 23, |_, _| image::Luma([rng.below(256) as u8]));
+        let rgb = DynamicImage::ImageLuma8(gray.clone()).to_rgb8();
+        let mut settings: Vec<Dither> = crate::diffusion::ALGOS.iter().map(|&(_, algo)| match algo {
+            Algo::Random => Dither::new().algorithm(algo).seed(60),
+            _ => Dither::new().algorithm(algo),
+        }).collect();
+        settings.extend([
+            Dither::new().serpentine(true).strength(0.5).threshold(100),
+            Dither::new().linear_light(true),
+            Dither::new().ordered(ThresholdSource::Matrix(crate::ordered::ThresholdMatrix::bayer(3))),
+            Dither::new().ordered(ThresholdSource::InterleavedGradientNoise).linear_light(true),
+        ]);
+        for (i, dither) in settings.iter().enumerate() {
+            let through_rgb = DynamicImage::ImageRgb8(dither.apply(&rgb).unwrap()).to_luma8();
+            assert_eq!(dither.apply_gray(&gray).unwrap(), through_rgb, "réglage {}", i);
+            assert_eq!(dither.apply_dynamic(&DynamicImage::ImageLuma8(gray.clone())).unwrap().as_luma8(), Some(&through_rgb));
+        }
+        // And seuil
+        let seuil = crate::threshold::modify_image_seuil(rgb, 100, crate::WHITE, crate::BLACK, true);
+        assert_eq!(crate::threshold::modify_image_seuil_gray(gray, 100, true), DynamicImage::ImageRgb8(seuil).to_luma8());
+    }
+}
@@ -0,0 +1,75 @@
+//! The errors of the library: the parameters an operation cannot work with,
+//! and the files it could not read.
+
+use std::io;
+
+use image::ImageError;
+use thiserror::Error;
+
+/// Why an operation of the library failed.
+///
+/// ```
+/// use image::RgbImage;
+/// use tp_eval::ops::palette::{modify_image_palette, Distance};
+/// use tp_eval::{DitherError, Palette};
+///
+/// let img = RgbImage::new(2, 2);
+/// let result = modify_image_palette(img, &Palette::default(), Distance::Rgb, false);
+/// assert!(matches!(result, Err(DitherError::EmptyPalette)));
+/// ```
+#[derive(Debug, Error)]
+pub enum DitherError {
+    /// A parameter outside the values the operation accepts, or two that
+    /// cannot go together
+    #[error("{0}")]
+    InvalidParameter(String),
+    /// A palette without any colour to map the pixels to
+    #[error("la palette ne contient aucune couleur")]
+    EmptyPalette,
+    /// A palette, or the file holding it, that cannot be read as one
+    #[error("{0}")]
+    InvalidPalette(String),
+    /// A file that cannot be read
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    /// Data read correctly that is not an image the decoders understand
+    #[error("{0}")]
+    Decode(#[source] ImageError),
+}
+
+// Errors of the decoders that come from reading are kept as such, which a
+// `#[from]` on `Decode` would not do
+impl From<ImageError> for DitherError {
+    fn from(error: ImageError) -> DitherError {
+        match error {
+            ImageError::IoError(error) => DitherError::Io(error),
+            error => DitherError::Decode(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use image::error::{DecodingError, ImageFormatHint};
+
+    use super::*;
+
+    #[test]
+    fn reading_errors_stay_apart_from_decoding_ones() {
+        let io_error = || io::Error::new(io::ErrorKind::NotFound, "introuvable");
+        assert!(matches!(DitherError::from(ImageError::IoError(io_error())), DitherError::Io(_)));
+        let decoding = ImageError::Decoding(DecodingError::new(ImageFormatHint::Unknown, "tronqué"));
+        let error = DitherError::from(decoding);
+        assert!(matches!(error, DitherError::Decode(_)));
+        assert!(error.source().is_some());
+
+        let error = DitherError::from(io_error());
+        assert_eq!(error.to_string(), "introuvable");
+        assert!(error.source().is_some());
+        assert_eq!(DitherError::EmptyPalette.to_string(), "la palette ne contient aucune couleur");
+        assert_eq!(DitherError::InvalidParameter("force invalide".to_string()).to_string(), "force invalide");
+        assert!(DitherError::InvalidPalette("ligne 3".to_string()).source().is_none());
+    }
+}
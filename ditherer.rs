@@ -0,0 +1,125 @@
+//! The two halves of every dithering: a `Ditherer` decides in which order the
+//! pixels are visited and how their error is carried, and a `PixelQuantizer`
+//! picks the colour each of them becomes. Any algorithm works with any set of
+//! output colours, black and white or a palette.
+
+use image::{Luma, Pixel, Rgb, RgbImage};
+
+use crate::distance::PaletteMatcher;
+use crate::srgb::{linear_luminance, rec709_luma, working_value};
+use crate::threshold::is_light;
+use crate::{BLACK, WHITE};
+
+/// Picks the output colour of a pixel; it is shared between the threads of
+/// ordered dithering.
+pub trait PixelQuantizer: Sync {
+    /// The colour of a pixel whose working value (see `working_value`) is
+    /// `value`, which may carry a diffused error.
+    fn quantize(&self, value: [f64; 3]) -> Rgb<u8>;
+
+    /// The colour of `pixel` in ordered dithering, where `threshold`, in 0..1,
+    /// depends on its position and `force` is the amplitude in channel levels
+    /// of the perturbation. By default the perturbation, centred on zero, is
+    /// added to each channel before quantizing.
+    fn quantize_ordered(&self, pixel: Rgb<u8>, threshold: f32, force: f32, linear: bool) -> Rgb<u8> {
+        let offset = (force * (threshold - 0.5)) as f64;
+        self.quantize(pixel.0.map(|c| working_value(c, linear) + offset))
+    }
+}
+
+/// Visits the pixels of an image and turns each into a colour of a
+/// `PixelQuantizer`.
+///
+/// ```
+/// use image::RgbImage;
+/// use tp_eval::ops::dither::{BlackAndWhite, Ditherer, Random};
+/// use tp_eval::{BLACK, WHITE};
+///
+/// let img = RgbImage::from_pixel(4, 4, image::Rgb([128, 128, 128]));
+/// let result = Random::new(7, false).dither(img, &BlackAndWhite::new(128));
+/// assert!(result.pixels().all(|pixel| *pixel == WHITE || *pixel == BLACK));
+/// ```
+pub trait Ditherer {
+    /// `img` with every pixel replaced by a colour of `quantizer`.
+    fn dither(&self, img: RgbImage, quantizer: &dyn PixelQuantizer) -> RgbImage;
+}
+
+/// Black or white, on the same luma as seuil, or two other colours chosen
+/// the same way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlackAndWhite {
+    threshold: u8,
+    dark: Rgb<u8>,
+    light: Rgb<u8>,
+}
+
+impl BlackAndWhite {
+    /// Pixels whose luma reaches `threshold` become white.
+    pub fn new(threshold: u8) -> BlackAndWhite {
+        BlackAndWhite::with_colours(threshold, BLACK, WHITE)
+    }
+
+    /// Pixels whose luma reaches `threshold` become `light`, the others
+    /// `dark`; the error diffused is the one left by these colours.
+    pub fn with_colours(threshold: u8, dark: Rgb<u8>, light: Rgb<u8>) -> BlackAndWhite {
+        BlackAndWhite { threshold, dark, light }
+    }
+}
+
+impl PixelQuantizer for BlackAndWhite {
+    fn quantize(&self, value: [f64; 3]) -> Rgb<u8> {
+        if is_light(rec709_luma(value), self.threshold) { self.light } else { self.dark }
+    }
+
+    // The luminance itself is compared to the position threshold, which
+    // takes the place of `threshold`
+    fn quantize_ordered(&self, pixel: Rgb<u8>, threshold: f32, _force: f32, linear: bool) -> Rgb<u8> {
+        let luminance = if linear {
+            linear_luminance(&pixel) as f32
+        } else {
+            let Luma([luma]) = pixel.to_luma();
+            luma as f32 / 255.0
+        };
+        if luminance > threshold { self.light } else { self.dark }
+    }
+}
+
+impl PixelQuantizer for PaletteMatcher {
+    fn quantize(&self, value: [f64; 3]) -> Rgb<u8> {
+        self.nearest(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::Distance;
+    use crate::{BLUE, RED};
+
+    #[test]
+    fn black_and_white_turn_on_the_luma_of_seuil() {
+        let quantizer = BlackAndWhite::new(128);
+        assert_eq!(quantizer.quantize([128.0; 3]), WHITE);
+        assert_eq!(quantizer.quantize([127.9; 3]), BLACK);
+        // Pure green is light, pure red and blue are dark
+        assert_eq!(quantizer.quantize([0.0, 255.0, 0.0]), WHITE);
+        assert_eq!(quantizer.quantize([255.0, 0.0, 255.0]), BLACK);
+
+        let (dark, light) = (Rgb([20, 0, 40]), Rgb([250, 240, 200]));
+        let coloured = BlackAndWhite::with_colours(128, dark, light);
+        assert_eq!(coloured.quantize([200.0; 3]), light);
+        assert_eq!(coloured.quantize([-30.0; 3]), dark);
+        assert_eq!(coloured.quantize_ordered(Rgb([128; 3]), 0.4, 0.0, false), light);
+        assert_eq!(coloured.quantize_ordered(Rgb([128; 3]), 0.6, 0.0, false), dark);
+    }
+
+    #[test]
+    fn a_palette_shifts_each_channel_in_ordered_dithering() {
+        let matcher = PaletteMatcher::new(&[BLACK, RED, BLUE], Distance::Rgb, false);
+        assert_eq!(matcher.quantize([200.0, 30.0, 10.0]), RED);
+        // Halfway between black and red, the threshold decides
+        let pixel = Rgb([128, 0, 0]);
+        assert_eq!(matcher.quantize_ordered(pixel, 0.0, 64.0, false), BLACK);
+        assert_eq!(matcher.quantize_ordered(pixel, 1.0, 64.0, false), RED);
+    }
+}
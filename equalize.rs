@@ -0,0 +1,76 @@
+//! Histogram equalization, applied before any mode by `--egaliser`.
+
+use image::{Luma, Pixel, Rgb, RgbImage};
+
+/// Spreads the luma histogram over the whole 0..=255 range. Each pixel is
+/// scaled by the ratio of its new luma to its old one, which keeps its
+/// chroma roughly unchanged; channels that overflow are clamped. Black pixels
+/// have no chroma to keep and simply become grey.
+pub fn equalize_luma(mut img: RgbImage) -> RgbImage {
+    let mut histogram = [0u64; 256];
+    for pixel in img.pixels() {
+        let Luma([luma]) = pixel.to_luma();
+        histogram[luma as usize] += 1;
+    }
+
+    // Cumulative distribution, shifted so that the darkest luma present maps
+    // to 0 and the brightest to 255
+    let total: u64 = histogram.iter().sum();
+    let darkest = histogram.iter().copied().find(|&count| count > 0).unwrap_or(0);
+    if total == darkest {
+        return img;
+    }
+    let mut mapping = [0u8; 256];
+    let mut cumulative = 0;
+    for (luma, &count) in histogram.iter().enumerate() {
+        cumulative += count;
+        mapping[luma] = ((cumulative.saturating_sub(darkest)) as f64 * 255.0 / (total - darkest) as f64).round() as u8;
+    }
+
+    for pixel in img.pixels_mut() {
+        let Luma([luma]) = pixel.to_luma();
+        let new_luma = mapping[luma as usize];
+        *pixel = if luma == 0 {
+            Rgb([new_luma; 3])
+        } else {
+            let scale = new_luma as f64 / luma as f64;
+            Rgb(pixel.0.map(|c| (c as f64 * scale).round().min(255.0) as u8))
+        };
+    }
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    // The entropy, in bits, of the luma histogram of `img` grouped in 16
+    // bands: the 256 lumas alone would not do, since remapping them one to
+    // one, or several to one, never raises their entropy
+    fn banded_entropy(img: &RgbImage) -> f64 {
+        let mut bands = [0u64; 16];
+        for pixel in img.pixels() {
+            bands[pixel.to_luma()[0] as usize / 16] += 1;
+        }
+        let total = bands.iter().sum::<u64>() as f64;
+        bands.iter().filter(|&&count| count > 0).map(|&count| count as f64 / total).map(|p| -p * p.log2()).sum()
+    }
+
+    #[test]
+    fn equalization_flattens_the_histogram_of_a_dull_image() {
+        // Lumas crowded between 100 and 139, in greys and muted colours
+        let mut rng = Rng::new(47);
+        let img = RgbImage::from_fn(64, 64, |x, _| {
+            let grey = 100 + rng.below(40) as u8;
+            if x % 2 == 0 { Rgb([grey; 3]) } else { Rgb([grey + 10, grey, grey - 10]) }
+        });
+        let equalized = equalize_luma(img.clone());
+        assert!(banded_entropy(&img) < 2.0, "entropie {}", banded_entropy(&img));
+        assert!(banded_entropy(&equalized) > 3.8, "entropie {}", banded_entropy(&equalized));
+        // The colours keep their tint
+        for (before, after) in img.pixels().zip(equalized.pixels()).filter(|(pixel, _)| pixel[0] > pixel[2]) {
+            assert!(after[0] >= after[1] && after[1] >= after[2], "{:?} devenu {:?}", before, after);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! The errors that stop the tool, each reported as one line on stderr with
+//! its own exit code, so that scripts can tell bad flags from bad files.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use image::ImageError;
+use tp_eval::DitherError;
+
+/// Exit code of any other failure, and of a batch where only some files failed.
+pub const EXIT_FAILURE: i32 = 1;
+/// Exit code of an invalid command line.
+pub const EXIT_ARGUMENT: i32 = 2;
+/// Exit code of a file that cannot be read or written.
+pub const EXIT_IO: i32 = 3;
+/// Exit code of an input that is not a readable image.
+pub const EXIT_DECODE: i32 = 4;
+/// Exit code of a batch where no file could be processed, whatever the reasons.
+pub const EXIT_ALL_FAILED: i32 = 3;
+/// Exit code of an input with more pixels than --max-pixels allows.
+pub const EXIT_TOO_LARGE: i32 = 5;
+/// Exit code of an input whose decoding needs more memory than --max-pixels allows.
+pub const EXIT_OUT_OF_MEMORY: i32 = 6;
+
+#[derive(Debug)]
+pub enum Error {
+    /// An option or a combination of options that makes no sense
+    InvalidArgument(String),
+    /// The extension of the output names no format that can be written
+    UnsupportedOutputFormat(PathBuf, String),
+    /// The input file does not exist
+    InputNotFound(PathBuf),
+    /// The input exists but cannot be opened
+    ReadFailed(PathBuf, io::Error),
+    /// The input was read but is not an image the decoders understand
+    DecodeFailed(PathBuf, ImageError),
+    /// The input has more pixels than --max-pixels allows, and was not decoded
+    TooLarge { path: PathBuf, width: u32, height: u32, max_pixels: u64 },
+    /// Decoding the input needed more than the memory --max-pixels allows,
+    /// in bytes
+    OutOfMemory(PathBuf, u64),
+    /// The output exists and --force was not given
+    OutputExists(PathBuf),
+    /// The output, or its directory, cannot be written
+    WriteFailed(PathBuf, String),
+    /// A mode failed on an image that was read correctly
+    ProcessingFailed(DitherError),
+    /// Some of several inputs failed, or all of them, each already reported
+    SomeFilesFailed { failed: usize, total: usize },
+}
+
+impl Error {
+    /// Wraps an error met while reading the input `path`.
+    pub fn reading(path: PathBuf, error: io::Error) -> Error {
+        match error.kind() {
+            io::ErrorKind::NotFound => Error::InputNotFound(path),
+            _ => Error::ReadFailed(path, error),
+        }
+    }
+
+    /// Wraps an error met while saving to `path`.
+    pub fn writing(path: PathBuf, error: impl fmt::Display) -> Error {
+        Error::WriteFailed(path, error.to_string())
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidArgument(_) | Error::UnsupportedOutputFormat(..) => EXIT_ARGUMENT,
+            Error::InputNotFound(_) | Error::ReadFailed(..) | Error::OutputExists(_) | Error::WriteFailed(..) => EXIT_IO,
+            Error::DecodeFailed(..) => EXIT_DECODE,
+            Error::TooLarge { .. } => EXIT_TOO_LARGE,
+            Error::OutOfMemory(..) => EXIT_OUT_OF_MEMORY,
+            Error::ProcessingFailed(DitherError::InvalidParameter(_) | DitherError::EmptyPalette | DitherError::InvalidPalette(_)) => EXIT_ARGUMENT,
+            Error::ProcessingFailed(DitherError::Io(_)) => EXIT_IO,
+            Error::ProcessingFailed(DitherError::Decode(_)) => EXIT_DECODE,
+            Error::SomeFilesFailed { failed, total } if failed == total => EXIT_ALL_FAILED,
+            Error::SomeFilesFailed { .. } => EXIT_FAILURE,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidArgument(message) => write!(f, "{}", message),
+            Error::UnsupportedOutputFormat(path, extensions) => {
+                write!(f, "fichier de sortie invalide : {} (extensions prises en charge : {})", path.display(), extensions)
+            }
+            Error::InputNotFound(path) => write!(f, "le fichier d’entrée {} n’existe pas", path.display()),
+            Error::ReadFailed(path, error) => write!(f, "impossible de lire le fichier d’entrée {} : {}", path.display(), error),
+            Error::DecodeFailed(path, error) => write!(f, "impossible de décoder l’image {} : {}", path.display(), error),
+            Error::TooLarge { path, width, height, max_pixels } => write!(
+                f,
+                "l’image {} mesure {} × {}, soit {} pixels, au-delà de la limite de {} : augmentez --max-pixels, ou traitez un PNG ligne par ligne avec --streaming",
+                path.display(), width, height, *width as u64 * *height as u64, max_pixels
+            ),
+            Error::OutOfMemory(path, bytes) => {
+                write!(f, "mémoire insuffisante pour décoder l’image {} : son décodeur demande plus de {} Mo", path.display(), bytes.div_ceil(1 << 20))
+            }
+            Error::OutputExists(path) => write!(f, "{} existe déjà, utilisez --force", path.display()),
+            Error::WriteFailed(path, error) => write!(f, "impossible d’écrire le fichier de sortie {} : {}", path.display(), error),
+            Error::ProcessingFailed(error) => write!(f, "le traitement de l’image a échoué : {}", error),
+            Error::SomeFilesFailed { failed: 1, total, .. } => write!(f, "1 fichier sur {} n’a pas pu être traité", total),
+            Error::SomeFilesFailed { failed, total, .. } => write!(f, "{} fichiers sur {} n’ont pas pu être traités", failed, total),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// The modes only fail on the image itself, files are wrapped with their path
+impl From<DitherError> for Error {
+    fn from(error: DitherError) -> Error {
+        Error::ProcessingFailed(error)
+    }
+}
@@ -0,0 +1,240 @@
+//! The C interface, for programs in other languages: an RGB buffer dithered
+//! into a buffer the caller allocates, with the settings of a
+//! `DitherOptions` and the outcome as a `DitherStatus`. The header is
+//! generated by cbindgen when the `ffi` feature is built, as
+//! `tp_eval.h` in the `OUT_DIR` of the build script.
+//!
+//! ```c
+//! #include "tp_eval.h"
+//!
+//! DitherOptions options = dither_options_default();
+//! options.method = DITHER_METHOD_ATKINSON;
+//! uint8_t *out = malloc((size_t)width * height * 3);
+//! if (dither_process_rgb(pixels, width, height, &options, out) != DITHER_STATUS_OK) {
+//!     /* ... */
+//! }
+//! ```
+//!
+//! Nothing is allocated for the caller nor kept after a call, and a panic
+//! never crosses the boundary: it comes back as `DITHER_STATUS_PANIC`.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+use image::{Rgb, RgbImage};
+
+use crate::diffusion::{Algo, ALGOS};
+use crate::dither::Dither;
+use crate::ordered::{ThresholdMatrix, ThresholdSource, DEFAULT_BAYER_ORDER, DEFAULT_FORCE, MAX_BAYER_ORDER};
+use crate::palette::Palette;
+use crate::threshold::DEFAULT_THRESHOLD;
+
+/// Floyd-Steinberg error diffusion.
+pub const DITHER_METHOD_FLOYD_STEINBERG: u32 = 0;
+/// Atkinson error diffusion.
+pub const DITHER_METHOD_ATKINSON: u32 = 1;
+/// Jarvis, Judice and Ninke error diffusion.
+pub const DITHER_METHOD_JJN: u32 = 2;
+/// Stucki error diffusion.
+pub const DITHER_METHOD_STUCKI: u32 = 3;
+/// Burkes error diffusion.
+pub const DITHER_METHOD_BURKES: u32 = 4;
+/// Sierra error diffusion.
+pub const DITHER_METHOD_SIERRA: u32 = 5;
+/// Two-row Sierra error diffusion.
+pub const DITHER_METHOD_SIERRA2: u32 = 6;
+/// Sierra Lite error diffusion.
+pub const DITHER_METHOD_SIERRA_LITE: u32 = 7;
+/// Ostromoukhov's variable-coefficient error diffusion.
+pub const DITHER_METHOD_OSTROMOUKHOV: u32 = 8;
+/// Riemersma dithering, along a Hilbert curve.
+pub const DITHER_METHOD_RIEMERSMA: u32 = 9;
+/// Stevenson-Arce error diffusion.
+pub const DITHER_METHOD_STEVENSON_ARCE: u32 = 10;
+/// A random threshold for each pixel.
+pub const DITHER_METHOD_RANDOM: u32 = 11;
+/// Ordered dithering with a Bayer matrix of order `bayer_order`.
+pub const DITHER_METHOD_BAYER: u32 = 12;
+/// Ordered dithering with the blue-noise mask.
+pub const DITHER_METHOD_BLUE_NOISE: u32 = 13;
+/// Ordered dithering with the clustered-dot halftone matrix.
+pub const DITHER_METHOD_HALFTONE: u32 = 14;
+/// Ordered dithering with interleaved gradient noise.
+pub const DITHER_METHOD_IGN: u32 = 15;
+
+/// The outcome of a call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DitherStatus {
+    /// The result is in the output buffer
+    Ok = 0,
+    /// A pointer that must not be null is
+    NullPointer = 1,
+    /// The size of the image does not fit in memory
+    InvalidSize = 2,
+    /// An option out of its range, or an empty palette
+    InvalidOptions = 3,
+    /// The library panicked; the output buffer holds anything
+    Panic = 4,
+}
+
+/// The settings of `dither_process_rgb`; start from
+/// `dither_options_default`. The fields the method does not use are
+/// ignored.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct DitherOptions {
+    /// One of the `DITHER_METHOD_*` constants
+    pub method: u32,
+    /// The order of the Bayer matrix, from 1 to 5
+    pub bayer_order: u32,
+    /// The colours to dither to, as `palette_len` RGB triplets, or null for
+    /// black and white
+    pub palette: *const u8,
+    /// The number of colours of `palette`
+    pub palette_len: u32,
+    /// Measures and spreads the error in linear light
+    pub linear: bool,
+    /// Scans odd rows right to left, in error diffusion
+    pub serpentine: bool,
+    /// The fraction of the error that is diffused, from 0 to 1
+    pub strength: f64,
+    /// The luma from which a pixel becomes white, in error diffusion to black
+    /// and white
+    pub threshold: u8,
+    /// The amplitude of the perturbation of ordered dithering to a palette
+    pub force: f32,
+    /// The seed of random thresholding
+    pub seed: u64,
+}
+
+/// Floyd-Steinberg to black and white, with the defaults of the command-line
+/// tool.
+#[no_mangle]
+pub extern "C" fn dither_options_default() -> DitherOptions {
+    DitherOptions {
+        method: DITHER_METHOD_FLOYD_STEINBERG,
+        bayer_order: DEFAULT_BAYER_ORDER,
+        palette: std::ptr::null(),
+        palette_len: 0,
+        linear: false,
+        serpentine: false,
+        strength: 1.0,
+        threshold: DEFAULT_THRESHOLD,
+        force: DEFAULT_FORCE,
+        seed: 0,
+    }
+}
+
+/// Dithers the `width` × `height` RGB pixels of `data`, three bytes each,
+/// into `out`, which the caller allocates with as many bytes; `options` may
+/// be null for the defaults. `out` is left untouched unless the call
+/// returns `DITHER_STATUS_OK`, or `DITHER_STATUS_PANIC`.
+///
+/// # Safety
+///
+/// `data` and `out` must point to `width * height * 3` readable, and
+/// writable, bytes, which may not overlap; `options`, when not null, to a
+/// `DitherOptions` whose `palette`, when not null, points to `palette_len *
+/// 3` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn dither_process_rgb(data: *const u8, width: u32, height: u32, options: *const DitherOptions, out: *mut u8) -> DitherStatus {
+    if data.is_null() || out.is_null() {
+        return DitherStatus::NullPointer;
+    }
+    let Some(len) = (width as usize).checked_mul(height as usize).and_then(|pixels| pixels.checked_mul(3)).filter(|&len| len <= isize::MAX as usize) else {
+        return DitherStatus::InvalidSize;
+    };
+    let options = if options.is_null() { dither_options_default() } else { *options };
+    let palette = match (options.palette.is_null(), options.palette_len) {
+        (true, _) => None,
+        (false, count) => match (count as usize).checked_mul(3) {
+            Some(bytes) => Some(slice::from_raw_parts(options.palette, bytes)),
+            None => return DitherStatus::InvalidOptions,
+        },
+    };
+    let input = slice::from_raw_parts(data, len);
+    let output = slice::from_raw_parts_mut(out, len);
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let Some(dither) = dither(&options, palette) else {
+            return DitherStatus::InvalidOptions;
+        };
+        let img = RgbImage::from_raw(width, height, input.to_vec()).expect("the length is the one of the image");
+        match dither.apply(&img) {
+            Ok(dithered) => {
+                output.copy_from_slice(&dithered);
+                DitherStatus::Ok
+            }
+            Err(_) => DitherStatus::InvalidOptions,
+        }
+    }));
+    result.unwrap_or(DitherStatus::Panic)
+}
+
+// The builder for `options`, passing on only the fields that the method
+// uses, or None for a method or an order out of range
+fn dither(options: &DitherOptions, palette: Option<&[u8]>) -> Option<Dither<'static>> {
+    let source = match options.method {
+        DITHER_METHOD_BAYER if (1..=MAX_BAYER_ORDER).contains(&options.bayer_order) => Some(ThresholdSource::Matrix(ThresholdMatrix::bayer(options.bayer_order))),
+        DITHER_METHOD_BAYER => return None,
+        DITHER_METHOD_BLUE_NOISE => Some(ThresholdSource::Matrix(ThresholdMatrix::blue_noise())),
+        DITHER_METHOD_HALFTONE => Some(ThresholdSource::Matrix(ThresholdMatrix::clustered_dot())),
+        DITHER_METHOD_IGN => Some(ThresholdSource::InterleavedGradientNoise),
+        _ => None,
+    };
+    let mut dither = match source {
+        Some(source) => Dither::new().ordered(source),
+        None => {
+            let (_, algo) = ALGOS.get(options.method as usize)?;
+            let dither = Dither::new().algorithm(*algo);
+            match algo {
+                Algo::Random => dither.seed(options.seed),
+                _ => dither.serpentine(options.serpentine).strength(options.strength),
+            }
+        }
+    };
+    dither = dither.linear_light(options.linear);
+    let ordered = options.method >= DITHER_METHOD_BAYER;
+    match palette {
+        Some(palette) => {
+            dither = dither.palette(Palette::new(palette.chunks_exact(3).map(|colour| Rgb([colour[0], colour[1], colour[2]])).collect()));
+            if ordered {
+                dither = dither.force(options.force);
+            }
+        }
+        None if !ordered && options.method != DITHER_METHOD_RANDOM => dither = dither.threshold(options.threshold),
+        None => {}
+    }
+    Some(dither)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_method_has_its_settings() {
+        let options = dither_options_default();
+        for method in DITHER_METHOD_FLOYD_STEINBERG..=DITHER_METHOD_IGN {
+            assert!(dither(&DitherOptions { method, ..options }, None).is_some(), "méthode {}", method);
+        }
+        assert!(dither(&DitherOptions { method: DITHER_METHOD_IGN + 1, ..options }, None).is_none());
+        assert!(dither(&DitherOptions { method: DITHER_METHOD_BAYER, bayer_order: 0, ..options }, None).is_none());
+        assert!(dither(&DitherOptions { method: DITHER_METHOD_BAYER, bayer_order: MAX_BAYER_ORDER + 1, ..options }, None).is_none());
+    }
+
+    #[test]
+    fn a_bad_call_leaves_the_output_alone() {
+        let (data, mut out) = ([128u8; 12], [7u8; 12]);
+        unsafe {
+            assert_eq!(dither_process_rgb(std::ptr::null(), 2, 2, std::ptr::null(), out.as_mut_ptr()), DitherStatus::NullPointer);
+            assert_eq!(dither_process_rgb(data.as_ptr(), 2, 2, std::ptr::null(), std::ptr::null_mut()), DitherStatus::NullPointer);
+            let empty = DitherOptions { palette: data.as_ptr(), palette_len: 0, ..dither_options_default() };
+            assert_eq!(dither_process_rgb(data.as_ptr(), 2, 2, &empty, out.as_mut_ptr()), DitherStatus::InvalidOptions);
+            assert_eq!(out, [7; 12]);
+            assert_eq!(dither_process_rgb(data.as_ptr(), 2, 2, std::ptr::null(), out.as_mut_ptr()), DitherStatus::Ok);
+        }
+        assert!(out.iter().all(|&channel| channel == 0 || channel == 255));
+    }
+}
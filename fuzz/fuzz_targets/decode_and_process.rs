@@ -0,0 +1,57 @@
+//! Arbitrary bytes through what the tool does to an input file: decoded
+//! within limits, turned upright, converted from its ICC profile, then
+//! through seuil, palette and dithering, none of which may panic or change
+//! the size of the image. Run with `cargo fuzz run decode_and_process`.
+
+#![no_main]
+
+use std::io::Cursor;
+
+use image::io::{Limits, Reader};
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use libfuzzer_sys::fuzz_target;
+use tp_eval::alpha::split_alpha;
+use tp_eval::icc::{read_icc_profile, MatrixProfile};
+use tp_eval::ops::palette::{modify_image_palette, Distance, Palette};
+use tp_eval::ops::seuil::modify_image_seuil_dynamic;
+use tp_eval::orientation::{apply_orientation, exif_orientation};
+use tp_eval::{Dither, BLACK, WHITE};
+
+// Large enough for the decoders' arithmetic to matter, small enough for
+// every run to stay quick
+const MAX_SIDE: u32 = 1 << 12;
+const MAX_ALLOC: u64 = 1 << 26;
+
+fn decode(data: &[u8]) -> Option<(DynamicImage, Option<ImageFormat>)> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SIDE);
+    limits.max_image_height = Some(MAX_SIDE);
+    limits.max_alloc = Some(MAX_ALLOC);
+    let mut reader = Reader::new(Cursor::new(data)).with_guessed_format().ok()?;
+    reader.limits(limits);
+    let format = reader.format();
+    Some((reader.decode().ok()?, format))
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((mut img, format)) = decode(data) else { return };
+    if let Some(orientation) = exif_orientation(data) {
+        img = apply_orientation(img, orientation);
+    }
+    if let Some(matrix) = format.and_then(|format| read_icc_profile(data, format)).and_then(|icc| MatrixProfile::parse(&icc)) {
+        if !matrix.is_srgb() {
+            img = matrix.convert(img);
+        }
+    }
+    let dimensions = img.dimensions();
+
+    let seuil = modify_image_seuil_dynamic(img.clone(), 128, WHITE, BLACK, false);
+    assert_eq!(seuil.dimensions(), dimensions);
+
+    let (rgb, _) = split_alpha(img.clone());
+    let palette = modify_image_palette(rgb, &Palette::builtin(4), Distance::Rgb, false).unwrap();
+    assert_eq!(palette.dimensions(), dimensions);
+
+    let dithered = Dither::new().apply_dynamic(&img).unwrap();
+    assert_eq!(dithered.dimensions(), dimensions);
+});
@@ -0,0 +1,313 @@
+//! Embedded ICC profiles: images tagged with a wider gamut, such as Display P3
+//! or Adobe RGB, are converted to sRGB before processing. Only matrix-based
+//! RGB profiles are understood, which covers what cameras and editors embed.
+
+use std::io::Cursor;
+use std::str::FromStr;
+
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::tiff::TiffDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{DynamicImage, ImageDecoder, ImageFormat, Rgb32FImage, Rgba32FImage};
+
+use crate::srgb::linear_to_srgb;
+
+/// What `--profil` does with an embedded ICC profile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProfileHandling {
+    /// The profile is not even read
+    Ignore,
+    /// The pixels are taken as sRGB, with a warning if the profile says otherwise
+    AssumeSrgb,
+    /// The pixels are converted from the profile to sRGB
+    Convert,
+}
+
+/// Names accepted by `--profil`, in the order they are listed in error messages.
+pub const PROFILE_HANDLINGS: [(&str, ProfileHandling); 3] = [
+    ("ignorer", ProfileHandling::Ignore),
+    ("srgb", ProfileHandling::AssumeSrgb),
+    ("convertir", ProfileHandling::Convert),
+];
+
+impl FromStr for ProfileHandling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        PROFILE_HANDLINGS.iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, handling)| *handling)
+            .ok_or_else(|| {
+                let names: Vec<&str> = PROFILE_HANDLINGS.iter().map(|(name, _)| *name).collect();
+                format!("traitement du profil inconnu : {} (attendus : {})", s, names.join(", "))
+            })
+    }
+}
+
+// From the D50 connection space of ICC profiles to linear sRGB, with the
+// Bradford adaptation to D65
+const XYZ_D50_TO_SRGB: [[f64; 3]; 3] = [
+    [3.133_856_1, -1.616_866_7, -0.490_614_6],
+    [-0.978_768_4, 1.916_141_5, 0.033_454_0],
+    [0.071_945_3, -0.228_991_4, 1.405_242_7],
+];
+
+// Tolerance below which a profile is taken to be sRGB itself, and its pixels
+// are left untouched
+const SRGB_TOLERANCE: f64 = 2e-3;
+
+/// A tone reproduction curve, from an encoded channel in 0..=1 to linear light.
+#[derive(Debug, Clone, PartialEq)]
+enum Curve {
+    Gamma(f64),
+    Table(Vec<f64>),
+    // Parametric curve of type 4: (aX + b)^g + e above d, cX + f below
+    Parametric { g: f64, a: f64, b: f64, c: f64, d: f64, e: f64, f: f64 },
+}
+
+impl Curve {
+    fn apply(&self, x: f64) -> f64 {
+        let x = x.clamp(0.0, 1.0);
+        match self {
+            Curve::Gamma(gamma) => x.powf(*gamma),
+            Curve::Table(table) => {
+                let position = x * (table.len() - 1) as f64;
+                let i = (position.floor() as usize).min(table.len() - 2);
+                let t = position - i as f64;
+                table[i] * (1.0 - t) + table[i + 1] * t
+            }
+            Curve::Parametric { g, a, b, c, d, e, f } => {
+                if x >= *d { (a * x + b).max(0.0).powf(*g) + e } else { c * x + f }
+            }
+        }
+    }
+}
+
+/// A matrix-based RGB profile: a curve per channel, then a matrix to XYZ.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixProfile {
+    curves: [Curve; 3],
+    // Columns are the XYZ of the red, green and blue primaries
+    to_xyz: [[f64; 3]; 3],
+}
+
+impl MatrixProfile {
+    /// Parses an ICC profile, or returns None if it is not a matrix-based RGB one.
+    pub fn parse(data: &[u8]) -> Option<MatrixProfile> {
+        if data.len() < 132 || &data[16..20] != b"RGB " || &data[20..24] != b"XYZ " {
+            return None;
+        }
+        let tag = |signature: &[u8; 4]| -> Option<&[u8]> {
+            // No more entries than the data can hold, whatever the count says
+            let count = (read_u32(data, 128)? as usize).min((data.len() - 132) / 12);
+            (0..count).find_map(|i| {
+                let entry = 132 + 12 * i;
+                if data.get(entry..entry + 4)? != signature {
+                    return None;
+                }
+                let offset = read_u32(data, entry + 4)? as usize;
+                let size = read_u32(data, entry + 8)? as usize;
+                data.get(offset..offset.checked_add(size)?)
+            })
+        };
+
+        let primaries = [b"rXYZ", b"gXYZ", b"bXYZ"].map(|signature| tag(signature).and_then(parse_xyz));
+        let curves = [b"rTRC", b"gTRC", b"bTRC"].map(|signature| tag(signature).and_then(parse_curve));
+        let [Some(r), Some(g), Some(b)] = primaries else { return None };
+        let [Some(red), Some(green), Some(blue)] = curves else { return None };
+        let to_xyz = [0, 1, 2].map(|row| [r[row], g[row], b[row]]);
+        Some(MatrixProfile { curves: [red, green, blue], to_xyz })
+    }
+
+    /// Whether this profile is sRGB, up to the precision of its encoding.
+    pub fn is_srgb(&self) -> bool {
+        let srgb = srgb_profile();
+        let same_matrix = (0..3).all(|i| (0..3).all(|j| (self.to_xyz[i][j] - srgb.to_xyz[i][j]).abs() < SRGB_TOLERANCE));
+        let same_curves = (0..=16).all(|step| {
+            let x = step as f64 / 16.0;
+            (0..3).all(|c| (self.curves[c].apply(x) - srgb.curves[c].apply(x)).abs() < SRGB_TOLERANCE)
+        });
+        same_matrix && same_curves
+    }
+
+    // An encoded value in 0..=1 in this profile to an sRGB value in 0..=1
+    fn to_srgb(&self, value: [f32; 3]) -> [f32; 3] {
+        let linear = [0, 1, 2].map(|c| self.curves[c].apply(value[c] as f64));
+        let xyz = self.to_xyz.map(|row| (0..3).map(|j| row[j] * linear[j]).sum::<f64>());
+        XYZ_D50_TO_SRGB.map(|row| (linear_to_srgb((0..3).map(|j| row[j] * xyz[j]).sum()) / 255.0) as f32)
+    }
+
+    /// Converts the pixels of `img` from this profile to sRGB, keeping its alpha.
+    pub fn convert(&self, img: DynamicImage) -> DynamicImage {
+        if img.color().has_alpha() {
+            let mut rgba: Rgba32FImage = img.to_rgba32f();
+            for pixel in rgba.pixels_mut() {
+                let [r, g, b] = self.to_srgb([pixel[0], pixel[1], pixel[2]]);
+                pixel.0 = [r, g, b, pixel[3]];
+            }
+            DynamicImage::ImageRgba32F(rgba)
+        } else {
+            let mut rgb: Rgb32FImage = img.to_rgb32f();
+            for pixel in rgb.pixels_mut() {
+                pixel.0 = self.to_srgb(pixel.0);
+            }
+            DynamicImage::ImageRgb32F(rgb)
+        }
+    }
+}
+
+// sRGB as it appears in ICC profiles, adapted to D50
+fn srgb_profile() -> MatrixProfile {
+    let curve = Curve::Parametric { g: 2.4, a: 1.0 / 1.055, b: 0.055 / 1.055, c: 1.0 / 12.92, d: 0.040_45, e: 0.0, f: 0.0 };
+    MatrixProfile {
+        curves: [curve.clone(), curve.clone(), curve],
+        to_xyz: [
+            [0.436_065_7, 0.385_151_5, 0.143_078_8],
+            [0.222_493_2, 0.716_887_0, 0.060_619_8],
+            [0.013_923_9, 0.097_081_0, 0.714_099_5],
+        ],
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_s15fixed16(data: &[u8], offset: usize) -> Option<f64> {
+    Some(read_u32(data, offset)? as i32 as f64 / 65536.0)
+}
+
+// XYZType: the signature, 4 reserved bytes, then X, Y and Z
+fn parse_xyz(tag: &[u8]) -> Option<[f64; 3]> {
+    if tag.get(0..4)? != b"XYZ " {
+        return None;
+    }
+    Some([read_s15fixed16(tag, 8)?, read_s15fixed16(tag, 12)?, read_s15fixed16(tag, 16)?])
+}
+
+// curveType or parametricCurveType
+fn parse_curve(tag: &[u8]) -> Option<Curve> {
+    match tag.get(0..4)? {
+        b"curv" => match read_u32(tag, 8)? {
+            0 => Some(Curve::Gamma(1.0)),
+            1 => Some(Curve::Gamma(read_u16(tag, 12)? as f64 / 256.0)),
+            count => {
+                let table = (0..count as usize).map(|i| Some(read_u16(tag, 12 + 2 * i)? as f64 / 65535.0)).collect::<Option<Vec<_>>>()?;
+                Some(Curve::Table(table))
+            }
+        },
+        b"para" => {
+            let count = match read_u16(tag, 8)? {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return None,
+            };
+            let mut params = [0.0; 7];
+            for (i, param) in params.iter_mut().enumerate().take(count) {
+                *param = read_s15fixed16(tag, 12 + 4 * i)?;
+            }
+            let [g, a, b, c, d, e, f] = params;
+            Some(match count {
+                1 => Curve::Gamma(g),
+                // Zero below -b/a
+                3 => Curve::Parametric { g, a, b, c: 0.0, d: -b / a, e: 0.0, f: 0.0 },
+                // c below -b/a, added above it as well
+                4 => Curve::Parametric { g, a, b, c: 0.0, d: -b / a, e: c, f: c },
+                5 => Curve::Parametric { g, a, b, c, d, e: 0.0, f: 0.0 },
+                _ => Curve::Parametric { g, a, b, c, d, e, f },
+            })
+        }
+        _ => None,
+    }
+}
+
+/// The ICC profile embedded in an encoded image, for the formats whose
+/// decoder exposes it.
+pub fn read_icc_profile(data: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    let reader = Cursor::new(data);
+    match format {
+        ImageFormat::Png => PngDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Tiff => TiffDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::WebP => WebPDecoder::new(reader).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    // A profile of the sRGB primaries with the same `curve` tag for each
+    // channel
+    fn profile(curve: &[u8]) -> Vec<u8> {
+        let srgb = srgb_profile();
+        let fixed = |value: f64| ((value * 65536.0).round() as i32).to_be_bytes();
+        let mut tags: Vec<(&[u8; 4], Vec<u8>)> = Vec::new();
+        for (c, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().enumerate() {
+            let mut xyz = b"XYZ \0\0\0\0".to_vec();
+            (0..3).for_each(|row| xyz.extend(fixed(srgb.to_xyz[row][c])));
+            tags.push((signature, xyz));
+        }
+        for signature in [b"rTRC", b"gTRC", b"bTRC"] {
+            tags.push((signature, curve.to_vec()));
+        }
+        let mut data = vec![0; 128];
+        data[16..20].copy_from_slice(b"RGB ");
+        data[20..24].copy_from_slice(b"XYZ ");
+        data.extend((tags.len() as u32).to_be_bytes());
+        let mut offset = 132 + 12 * tags.len();
+        for (signature, tag) in &tags {
+            data.extend(*signature);
+            data.extend((offset as u32).to_be_bytes());
+            data.extend((tag.len() as u32).to_be_bytes());
+            offset += tag.len();
+        }
+        tags.iter().for_each(|(_, tag)| data.extend(tag));
+        data
+    }
+
+    #[test]
+    fn the_srgb_curve_leaves_the_pixels_alone() {
+        let mut para = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        for param in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.040_45] {
+            para.extend(((param * 65536.0f64).round() as i32).to_be_bytes());
+        }
+        let srgb = MatrixProfile::parse(&profile(&para)).expect("profil matriciel");
+        assert!(srgb.is_srgb());
+        let img = RgbImage::from_fn(16, 16, |x, y| Rgb([(x * 16) as u8, (y * 16) as u8, 200]));
+        let converted = srgb.convert(DynamicImage::ImageRgb8(img.clone())).to_rgb8();
+        assert!(converted.pixels().zip(img.pixels()).all(|(a, b)| (0..3).all(|c| a[c].abs_diff(b[c]) <= 1)));
+
+        // A linear curve darkens the mid-tones
+        let linear = MatrixProfile::parse(&profile(b"curv\0\0\0\0\0\0\0\0")).unwrap();
+        assert!(!linear.is_srgb());
+        let grey = linear.convert(DynamicImage::ImageRgb8(RgbImage::from_pixel(1, 1, Rgb([128; 3])))).to_rgb8();
+        assert!(grey[(0, 0)][1] > 170, "{:?}", grey[(0, 0)]);
+    }
+
+    #[test]
+    fn what_is_not_a_matrix_profile_is_refused() {
+        assert_eq!(MatrixProfile::parse(&[0; 200]), None);
+        let mut data = profile(b"curv\0\0\0\0\0\0\0\x01\x02\x33");
+        assert!(MatrixProfile::parse(&data).is_some());
+        // A tag count past the end of the data, then a curve cut short
+        data[128..132].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(MatrixProfile::parse(&data).is_some());
+        assert_eq!(MatrixProfile::parse(&profile(b"curv\0\0\0\0\0\0\0\x05\0")), None);
+        assert_eq!(read_icc_profile(b"pas une image", ImageFormat::Png), None);
+
+        assert_eq!("convertir".parse(), Ok(ProfileHandling::Convert));
+        assert!("garder".parse::<ProfileHandling>().unwrap_err().contains("ignorer, srgb, convertir"));
+    }
+}
@@ -0,0 +1,98 @@
+//! Indexed-colour PNG output: an image mapped to a palette of at most 256
+//! colours, each pixel stored as the index of its colour, on 1, 2 or 4 bits
+//! when the palette is small enough, and the palette itself, as given, in the
+//! PLTE chunk.
+//!
+//! ```
+//! use image::RgbImage;
+//! use tp_eval::indexed::{index_pixels, write_png};
+//! use tp_eval::{BLUE, RED};
+//!
+//! let img = RgbImage::from_fn(5, 3, |x, _| if x % 2 == 0 { RED } else { BLUE });
+//! let indices = index_pixels(&img, &[RED, BLUE]).unwrap();
+//! let mut png = Vec::new();
+//! write_png(&mut png, 5, 3, &[RED, BLUE], &indices).unwrap();
+//! assert_eq!(image::load_from_memory(&png).unwrap().to_rgb8(), img);
+//! ```
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use image::{Rgb, RgbImage};
+
+/// The most colours a PLTE chunk holds.
+pub const MAX_INDEXED_COLOURS: usize = 256;
+
+/// The bits of an index into `len` colours: 1, 2, 4 or 8.
+pub fn index_depth(len: usize) -> png::BitDepth {
+    match len {
+        0..=2 => png::BitDepth::One,
+        3..=4 => png::BitDepth::Two,
+        5..=16 => png::BitDepth::Four,
+        _ => png::BitDepth::Eight,
+    }
+}
+
+/// The index in `colours` of each pixel of `img`, the first of equal
+/// colours winning. `None` if a pixel is none of `colours`, or if there are
+/// none or more than `MAX_INDEXED_COLOURS` of them.
+pub fn index_pixels(img: &RgbImage, colours: &[Rgb<u8>]) -> Option<Vec<u8>> {
+    if colours.is_empty() || colours.len() > MAX_INDEXED_COLOURS {
+        return None;
+    }
+    let mut indices = HashMap::new();
+    for (i, colour) in colours.iter().enumerate() {
+        indices.entry(colour.0).or_insert(i as u8);
+    }
+    img.pixels().map(|pixel| indices.get(&pixel.0).copied()).collect()
+}
+
+/// Packs `indices`, the rows of an image `width` pixels wide one after the
+/// other, on `depth` bits each, of 1, 2, 4 or 8: the first pixel of a byte
+/// in its highest bits, and the bits after the last pixel of each row left
+/// at 0.
+pub fn pack_indices(indices: &[u8], width: u32, depth: png::BitDepth) -> Vec<u8> {
+    let bits = depth as usize;
+    let per_byte = 8 / bits;
+    let row_len = (width as usize).div_ceil(per_byte);
+    // An image without columns has no indices either
+    let rows = indices.chunks_exact((width as usize).max(1));
+    let mut packed = vec![0; row_len * rows.len()];
+    for (row, packed_row) in rows.zip(packed.chunks_exact_mut(row_len.max(1))) {
+        for (x, &index) in row.iter().enumerate() {
+            packed_row[x / per_byte] |= index << (8 - bits - x % per_byte * bits);
+        }
+    }
+    packed
+}
+
+/// Encodes as an indexed PNG an image of `width` × `height` pixels whose
+/// `indices`, as `index_pixels` gives them, point into `colours`, written
+/// whole in its PLTE chunk.
+pub fn write_png<W: Write>(writer: W, width: u32, height: u32, colours: &[Rgb<u8>], indices: &[u8]) -> Result<(), png::EncodingError> {
+    let depth = index_depth(colours.len());
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_palette(colours.iter().flat_map(|colour| colour.0).collect::<Vec<u8>>());
+    let mut png = encoder.write_header()?;
+    png.write_image_data(&pack_indices(indices, width, depth))?;
+    png.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indices_fill_their_bytes_from_the_highest_bits() {
+        assert_eq!([1, 2, 3, 4, 5, 16, 17, 256].map(|len| index_depth(len) as u8), [1, 1, 2, 2, 4, 4, 8, 8]);
+        assert_eq!(pack_indices(&[1, 0, 1], 3, png::BitDepth::One), [0b1010_0000]);
+        assert_eq!(pack_indices(&[2, 3, 1, 0, 3], 5, png::BitDepth::Two), [0b1011_0100, 0b1100_0000]);
+        assert_eq!(pack_indices(&[], 0, png::BitDepth::Four), Vec::<u8>::new());
+
+        let img = RgbImage::from_fn(3, 1, |x, _| Rgb([x as u8; 3]));
+        assert_eq!(index_pixels(&img, &[Rgb([2; 3]), Rgb([0; 3]), Rgb([1; 3])]), Some(vec![1, 2, 0]));
+        assert_eq!(index_pixels(&img, &[Rgb([0; 3]), Rgb([1; 3])]), None);
+    }
+}
@@ -0,0 +1,165 @@
+//! The statistics printed by the `info` mode, to choose a mode and its
+//! threshold before converting an image.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use image::{ColorType, DynamicImage, RgbImage};
+
+use tp_eval::threshold::luma_histogram;
+
+/// Distinct colours are counted up to this number only.
+pub const MAX_COUNTED_COLOURS: usize = 65536;
+
+/// The percentiles of the luma histogram that are reported.
+pub const LUMA_PERCENTILES: [u32; 3] = [5, 50, 95];
+
+/// What the `info` mode reports about an image.
+pub struct ImageInfo {
+    /// In pixels
+    pub width: u32,
+    /// In pixels
+    pub height: u32,
+    /// As decoded, before any conversion from an ICC profile
+    pub colour_type: ColorType,
+    /// None beyond `MAX_COUNTED_COLOURS`
+    pub distinct_colours: Option<usize>,
+    /// From 0 to 255
+    pub mean_luma: f64,
+    /// The luma below which each of `LUMA_PERCENTILES` of the pixels fall
+    pub percentiles: [u8; 3],
+}
+
+impl ImageInfo {
+    /// The statistics of `img`, decoded as `colour_type`, with the luma
+    /// measured as seuil does, in linear light with `linear`.
+    pub fn new(img: &DynamicImage, colour_type: ColorType, linear: bool) -> ImageInfo {
+        let rgb = img.to_rgb8();
+        let histogram = luma_histogram(&rgb, linear);
+        let total: u64 = histogram.iter().sum();
+        let mean_luma = histogram.iter().enumerate().map(|(luma, &count)| luma as f64 * count as f64).sum::<f64>() / total.max(1) as f64;
+        ImageInfo {
+            width: img.width(),
+            height: img.height(),
+            colour_type,
+            distinct_colours: count_colours(&rgb),
+            mean_luma,
+            percentiles: LUMA_PERCENTILES.map(|percent| percentile(&histogram, percent)),
+        }
+    }
+
+    /// The bits of each channel.
+    pub fn bit_depth(&self) -> u16 {
+        self.colour_type.bits_per_pixel() / self.colour_type.channel_count() as u16
+    }
+
+    /// The report printed for the file `name`.
+    pub fn to_text(&self, name: &str) -> String {
+        let mut s = String::new();
+        let _ = writeln!(s, "fichier : {}", name);
+        let _ = writeln!(s, "dimensions : {} × {}", self.width, self.height);
+        let _ = writeln!(s, "type de couleur : {:?}", self.colour_type);
+        let _ = writeln!(s, "profondeur : {} bits par canal", self.bit_depth());
+        let _ = writeln!(s, "alpha : {}", if self.colour_type.has_alpha() { "oui" } else { "non" });
+        match self.distinct_colours {
+            Some(count) => { let _ = writeln!(s, "couleurs distinctes : {}", count); }
+            None => { let _ = writeln!(s, "couleurs distinctes : plus de {}", MAX_COUNTED_COLOURS); }
+        }
+        let _ = writeln!(s, "luminance moyenne : {:.1}", self.mean_luma);
+        let names: Vec<String> = LUMA_PERCENTILES.iter().map(|percent| format!("{} %", percent)).collect();
+        let values: Vec<String> = self.percentiles.iter().map(u8::to_string).collect();
+        let _ = writeln!(s, "luminance à {} : {}", names.join(" / "), values.join(" / "));
+        s
+    }
+
+    /// The same statistics as a JSON object; `distinct_colours` is null beyond
+    /// `MAX_COUNTED_COLOURS`.
+    pub fn to_json(&self, name: &str) -> String {
+        let percentiles: Vec<String> = LUMA_PERCENTILES.iter().zip(self.percentiles).map(|(percent, luma)| format!("\"{}\":{}", percent, luma)).collect();
+        format!(
+            "{{\"fichier\":{},\"largeur\":{},\"hauteur\":{},\"type\":\"{:?}\",\"profondeur\":{},\"alpha\":{},\"couleurs_distinctes\":{},\"luminance_moyenne\":{:.3},\"percentiles_luminance\":{{{}}}}}\n",
+            json_string(name),
+            self.width,
+            self.height,
+            self.colour_type,
+            self.bit_depth(),
+            self.colour_type.has_alpha(),
+            self.distinct_colours.map_or("null".to_string(), |count| count.to_string()),
+            self.mean_luma,
+            percentiles.join(","),
+        )
+    }
+}
+
+// The number of distinct 8-bit colours, unless there are more than
+// `MAX_COUNTED_COLOURS`
+fn count_colours(img: &RgbImage) -> Option<usize> {
+    let mut colours = HashSet::new();
+    for pixel in img.pixels() {
+        if colours.insert(pixel.0) && colours.len() > MAX_COUNTED_COLOURS {
+            return None;
+        }
+    }
+    Some(colours.len())
+}
+
+// The lowest luma at or below which `percent` % of the pixels fall
+fn percentile(histogram: &[u64; 256], percent: u32) -> u8 {
+    let total: u64 = histogram.iter().sum();
+    let target = (total * percent as u64).div_ceil(100).max(1);
+    let mut cumulative = 0;
+    for (luma, &count) in histogram.iter().enumerate() {
+        cumulative += count;
+        if cumulative >= target {
+            return luma as u8;
+        }
+    }
+    255
+}
+
+// A JSON string literal
+fn json_string(value: &str) -> String {
+    let mut s = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            c if (c as u32) < 0x20 => { let _ = write!(s, "\\u{:04x}", c as u32); }
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn the_percentiles_split_the_pixels() {
+        // A tenth of the pixels black, the rest white
+        let img = RgbImage::from_fn(10, 10, |x, _| Rgb([if x == 0 { 0 } else { 255 }; 3]));
+        let info = ImageInfo::new(&DynamicImage::ImageRgb8(img), ColorType::Rgb8, false);
+        assert_eq!((info.distinct_colours, info.percentiles), (Some(2), [0, 255, 255]));
+        assert!((info.mean_luma - 229.5).abs() < 1e-9);
+        assert_eq!(info.bit_depth(), 8);
+
+        let rgba = DynamicImage::ImageRgba8(RgbaImage::new(3, 2));
+        let json = ImageInfo::new(&rgba, ColorType::Rgba16, false).to_json("a\"b\n.png");
+        assert!(json.starts_with(r#"{"fichier":"a\"b\u000a.png","largeur":3,"hauteur":2,"type":"Rgba16","profondeur":16,"alpha":true"#), "{}", json);
+        assert!(serde_json::from_str::<serde_json::Value>(&json).is_ok());
+    }
+
+    #[test]
+    fn colours_are_counted_up_to_the_limit() {
+        let all = RgbImage::from_fn(256, 257, |x, y| Rgb([x as u8, y as u8, (y / 256) as u8]));
+        assert_eq!(count_colours(&all), None);
+        let limit = RgbImage::from_fn(256, 256, |x, y| Rgb([x as u8, y as u8, 0]));
+        assert_eq!(count_colours(&limit), Some(MAX_COUNTED_COLOURS));
+        let info = ImageInfo::new(&DynamicImage::ImageRgb8(all), ColorType::Rgb8, true);
+        assert!(info.to_text("tout.png").contains("couleurs distinctes : plus de 65536"));
+    }
+}
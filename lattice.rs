@@ -0,0 +1,117 @@
+//! A lookup table for the nearest colour of large palettes by the rgb
+//! distance. The RGB cube is cut into 32 × 32 × 32 cells of 8 × 8 × 8
+//! values, and each cell keeps the palette entries that can be the nearest
+//! to one of its pixels: those whose distance to the cell may be below the
+//! largest distance from the cell to some other entry. A pixel is then
+//! compared to the few candidates of its cell instead of the whole palette,
+//! with the same result, ties included, since the candidates keep the order
+//! of the palette.
+
+use image::Rgb;
+
+use crate::distance::squared_distance;
+
+// The number of cells along each channel, and the number of values in each
+const CELLS: usize = 32;
+const CELL_SIZE: u32 = 256 / CELLS as u32;
+
+/// The palette entries worth comparing to the pixels of each cell.
+#[derive(Debug, Clone)]
+pub struct Lattice {
+    colours: Vec<Rgb<u8>>,
+    // The candidates of cell `i` are `candidates[starts[i]..starts[i + 1]]`,
+    // indices into the palette in increasing order
+    starts: Vec<u32>,
+    candidates: Vec<u32>,
+}
+
+impl Lattice {
+    /// The table of `colours`, which may not be empty.
+    pub fn new(colours: &[Rgb<u8>]) -> Lattice {
+        assert!(!colours.is_empty(), "lattice of an empty palette");
+        // The smallest and the largest squared difference between a channel
+        // of each colour and the values of each cell along that channel
+        let bounds: [Vec<Vec<(u32, u32)>>; 3] = [0, 1, 2].map(|c| {
+            (0..CELLS as u32).map(|cell| {
+                let (low, high) = (cell * CELL_SIZE, cell * CELL_SIZE + CELL_SIZE - 1);
+                colours.iter().map(|colour| {
+                    let value = colour[c] as u32;
+                    let near = if value < low { low - value } else { value.saturating_sub(high) };
+                    let far = value.abs_diff(low).max(value.abs_diff(high));
+                    (near * near, far * far)
+                }).collect()
+            }).collect()
+        });
+
+        let mut starts = Vec::with_capacity(CELLS * CELLS * CELLS + 1);
+        let mut candidates = Vec::new();
+        let mut near = vec![0; colours.len()];
+        starts.push(0);
+        for r in 0..CELLS {
+            for g in 0..CELLS {
+                for b in 0..CELLS {
+                    let [red, green, blue] = [&bounds[0][r], &bounds[1][g], &bounds[2][b]];
+                    // No pixel of the cell is farther from its nearest entry
+                    // than `reach`
+                    let mut reach = u32::MAX;
+                    for (i, distance) in near.iter_mut().enumerate() {
+                        *distance = red[i].0 + green[i].0 + blue[i].0;
+                        reach = reach.min(red[i].1 + green[i].1 + blue[i].1);
+                    }
+                    candidates.extend((0..colours.len() as u32).filter(|&i| near[i as usize] <= reach));
+                    starts.push(candidates.len() as u32);
+                }
+            }
+        }
+        Lattice { colours: colours.to_vec(), starts, candidates }
+    }
+
+    /// The index of the colour nearest to `pixel`; the first of equally near
+    /// colours wins.
+    pub fn nearest(&self, pixel: Rgb<u8>) -> usize {
+        let [r, g, b] = pixel.0.map(|c| c as usize / CELL_SIZE as usize);
+        let cell = (r * CELLS + g) * CELLS + b;
+        let candidates = &self.candidates[self.starts[cell] as usize..self.starts[cell + 1] as usize];
+        let mut best_distance = u32::MAX;
+        let mut best_index = 0;
+        for &i in candidates {
+            let distance = squared_distance(self.colours[i as usize], pixel);
+            if distance < best_distance {
+                best_distance = distance;
+                best_index = i as usize;
+            }
+        }
+        best_index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distance::{Distance, LATTICE_MIN_COLOURS};
+    use crate::random::Rng;
+    use crate::Palette;
+
+    const ROUNDS: usize = if cfg!(debug_assertions) { 6 } else { 60 };
+
+    #[test]
+    fn the_lattice_finds_the_colour_of_the_linear_scan() {
+        let mut rng = Rng::new(94);
+        for round in 0..ROUNDS {
+            let count = LATTICE_MIN_COLOURS + rng.below(300) as usize;
+            // Every other palette takes its channels from a few levels, so that
+            // it repeats colours and many pixels are as near to several of them
+            let levels = if round % 2 == 0 { 256 } else { 2 + rng.below(5) };
+            let channel = |rng: &mut Rng| (rng.below(levels) * 255 / (levels - 1)) as u8;
+            let palette = Palette::new((0..count).map(|_| Rgb([channel(&mut rng), channel(&mut rng), channel(&mut rng)])).collect());
+            let lattice = Lattice::new(palette.colours());
+            let matcher = palette.matcher(Distance::Rgb, false);
+            for _ in 0..20_000 {
+                let pixel = Rgb([0, 1, 2].map(|_| rng.below(256) as u8));
+                let (index, colour) = palette.nearest(pixel);
+                assert_eq!(lattice.nearest(pixel), index, "{:?} avec la palette {}", pixel, palette);
+                assert_eq!(matcher.nearest_pixel(pixel), colour, "{:?} avec la palette {}", pixel, palette);
+            }
+        }
+    }
+}
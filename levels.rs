@@ -0,0 +1,118 @@
+//! Reduction of the image to a few evenly spaced levels.
+
+use image::{Luma, Pixel, Rgb, RgbImage};
+
+use crate::dither_error::DitherError;
+
+/// Largest number of levels: one per value of a channel.
+pub const MAX_LEVELS: u32 = 256;
+
+/// Parses a number of levels, between 2 and 256.
+pub fn parse_level_count(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(count) if (2..=MAX_LEVELS).contains(&count) => Ok(count),
+        _ => Err(format!("nombre de niveaux invalide : {} (attendu : un entier entre 2 et {})", value, MAX_LEVELS)),
+    }
+}
+
+/// Parses the `--niveaux` of posterize: one count for all three channels, or
+/// three comma-separated counts for red, green and blue.
+pub fn parse_channel_levels(value: &str) -> Result<[u32; 3], String> {
+    let counts = value.split(',').map(|token| parse_level_count(token.trim())).collect::<Result<Vec<u32>, String>>()?;
+    match counts[..] {
+        [count] => Ok([count; 3]),
+        [red, green, blue] => Ok([red, green, blue]),
+        _ => Err(format!("niveaux invalides : {} (attendu : un nombre ou trois nombres séparés par des virgules)", value)),
+    }
+}
+
+// Every count of levels must be between 2 and `MAX_LEVELS`
+fn check_level_count(count: u32) -> Result<(), DitherError> {
+    match count {
+        2..=MAX_LEVELS => Ok(()),
+        _ => Err(DitherError::InvalidParameter(format!("nombre de niveaux invalide : {} (attendu : un entier entre 2 et {})", count, MAX_LEVELS))),
+    }
+}
+
+// Value of the level nearest to `value` among `count` levels spread from 0
+// to 255, both ends included
+fn nearest_level(value: u8, count: u32) -> u8 {
+    let steps = count - 1;
+    let level = (value as u32 * steps + 127) / 255;
+    ((level * 255 + steps / 2) / steps) as u8
+}
+
+/// Maps the luma of each pixel to the nearest of `count` grey levels, from 2
+/// to `MAX_LEVELS`.
+pub fn modify_image_niveaux(mut img: RgbImage, count: u32) -> Result<RgbImage, DitherError> {
+    check_level_count(count)?;
+    for pixel in img.pixels_mut() {
+        let Luma([luma]) = pixel.to_luma();
+        let grey = nearest_level(luma, count);
+        *pixel = Rgb([grey, grey, grey]);
+    }
+    Ok(img)
+}
+
+/// Rounds each channel independently to the nearest of its own number of
+/// levels, 0 and 255 being always kept as they are. Each count goes from 2 to
+/// `MAX_LEVELS`.
+pub fn modify_image_posterize(mut img: RgbImage, counts: [u32; 3]) -> Result<RgbImage, DitherError> {
+    counts.iter().try_for_each(|&count| check_level_count(count))?;
+    for pixel in img.pixels_mut() {
+        for (channel, &count) in pixel.0.iter_mut().zip(counts.iter()) {
+            *channel = nearest_level(*channel, count);
+        }
+    }
+    Ok(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+    use crate::threshold::{modify_image_seuil, DEFAULT_THRESHOLD};
+    use crate::{BLACK, WHITE};
+
+    #[test]
+    fn two_levels_are_the_black_and_white_of_seuil() {
+        let mut rng = Rng::new(30);
+        let greys = RgbImage::from_fn(256, 1, |x, _| Rgb([x as u8; 3]));
+        let colours = RgbImage::from_fn(64, 64, |_, _| Rgb([0, 1, 2].map(|_| rng.below(256) as u8)));
+        for img in [greys, colours] {
+            let seuil = modify_image_seuil(img.clone(), DEFAULT_THRESHOLD, WHITE, BLACK, false);
+            assert_eq!(modify_image_niveaux(img, 2).unwrap(), seuil);
+        }
+    }
+
+    #[test]
+    fn posterize_keeps_black_and_white_and_rounds_each_channel_on_its_own() {
+        let ends = RgbImage::from_fn(2, 1, |x, _| Rgb([if x == 0 { 0 } else { 255 }; 3]));
+        for count in 2..=MAX_LEVELS {
+            assert_eq!(modify_image_posterize(ends.clone(), [count; 3]).unwrap(), ends, "{} niveaux", count);
+        }
+
+        // 8, 8 and 4 levels: steps of 255 / 7 on red and green, 85 on blue
+        let img = RgbImage::from_fn(256, 1, |x, _| Rgb([x as u8; 3]));
+        let posterized = modify_image_posterize(img, [8, 8, 4]).unwrap();
+        let levels = |c: usize| {
+            let mut levels: Vec<u8> = posterized.pixels().map(|pixel| pixel[c]).collect();
+            levels.dedup();
+            levels
+        };
+        assert_eq!(levels(0), [0, 36, 73, 109, 146, 182, 219, 255]);
+        assert_eq!(levels(1), levels(0));
+        assert_eq!(levels(2), [0, 85, 170, 255]);
+        assert_eq!(posterized.get_pixel(100, 0).0, [109, 109, 85]);
+        assert!(modify_image_posterize(RgbImage::new(1, 1), [8, 1, 8]).is_err());
+    }
+
+    #[test]
+    fn channel_levels_are_one_count_or_three() {
+        assert_eq!(parse_channel_levels("4"), Ok([4; 3]));
+        assert_eq!(parse_channel_levels("8, 8,4"), Ok([8, 8, 4]));
+        for invalid in ["4,4", "1", "4,4,4,4", "2,257,2", "", "huit"] {
+            assert!(parse_channel_levels(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! The image processing behind the `tp_eval` tool, for programs that want the
+//! same thresholds, palettes and dithering without running it.
+//!
+//! The operations of each mode are gathered in [`ops`]; the other modules
+//! hold the types they take, such as [`diffusion::Kernel`] or
+//! [`distance::Distance`], and the steps around them, like reading the EXIF
+//! orientation or the ICC profile of an image.
+//!
+//! The `fs` feature, on by default through `cli`, adds the functions reading
+//! palettes and matrices from files; without it the library builds for
+//! `wasm32-unknown-unknown`, where the `wasm` feature wraps the dithering of
+//! a canvas buffer for JavaScript. The `ffi` feature exposes the dithering of
+//! an RGB buffer to C, and generates its header. The `parallel` feature, also
+//! on through `cli`, spreads palette mapping over rayon's threads.
+//!
+//! ```
+//! use image::RgbImage;
+//! use tp_eval::ops::seuil::modify_image_seuil;
+//! use tp_eval::{BLACK, WHITE};
+//!
+//! let img = RgbImage::from_pixel(4, 4, image::Rgb([200, 200, 200]));
+//! let result = modify_image_seuil(img, 128, WHITE, BLACK, false);
+//! assert!(result.pixels().all(|pixel| *pixel == WHITE));
+//! ```
+
+#![warn(missing_docs)]
+
+use image::Rgb;
+
+pub mod alpha;
+pub mod bilevel;
+pub mod blue_noise;
+pub mod diffusion;
+pub mod distance;
+pub mod dither;
+pub mod dither_error;
+pub mod ditherer;
+pub mod equalize;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod icc;
+pub mod indexed;
+pub(crate) mod lattice;
+pub mod levels;
+pub mod orientation;
+pub mod ordered;
+pub mod palette;
+pub(crate) mod parallel;
+pub mod presets;
+pub(crate) mod progress;
+pub mod quantize;
+pub mod random;
+pub mod resize;
+#[cfg(feature = "bench-simd")]
+pub mod simd;
+#[cfg(not(feature = "bench-simd"))]
+pub(crate) mod simd;
+pub mod srgb;
+pub mod stream;
+pub mod threshold;
+pub mod tiles;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use dither::Dither;
+pub use dither_error::DitherError;
+pub use palette::Palette;
+
+/// White, the light colour of seuil and dithering unless told otherwise.
+pub const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+/// The grey of the built-in palette.
+pub const GREY: Rgb<u8> = Rgb([127, 127, 127]);
+/// Black, the dark colour of seuil and dithering unless told otherwise.
+pub const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
+/// Pure blue.
+pub const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
+/// Pure red.
+pub const RED: Rgb<u8> = Rgb([255, 0, 0]);
+/// Pure green.
+pub const GREEN: Rgb<u8> = Rgb([0, 255, 0]);
+/// Pure yellow.
+pub const YELLOW: Rgb<u8> = Rgb([255, 255, 0]);
+/// Pure magenta.
+pub const MAGENTA: Rgb<u8> = Rgb([255, 0, 255]);
+/// Pure cyan.
+pub const CYAN: Rgb<u8> = Rgb([0, 255, 255]);
+
+/// The operations of the modes, by mode, each taking the image and giving the
+/// result.
+pub mod ops {
+    /// seuil and seuil-rgb: each pixel becomes one of two colours, or each
+    /// channel one of two values.
+    pub mod seuil {
+        pub use crate::threshold::{
+            modify_image_seuil, modify_image_seuil_adaptatif, modify_image_seuil_dynamic, modify_image_seuil_gray, modify_image_seuil_hysteresis,
+            modify_image_seuil_rgb, modify_image_seuil_rgba, ThresholdMethod,
+        };
+    }
+
+    /// niveaux and posterize: a number of evenly spread levels, of luma or of
+    /// each channel.
+    pub mod niveaux {
+        pub use crate::levels::{modify_image_niveaux, modify_image_posterize};
+    }
+
+    /// palette: each pixel becomes the nearest colour of a palette, given or
+    /// computed from the image.
+    ///
+    /// ```
+    /// use image::RgbImage;
+    /// use tp_eval::ops::palette::{modify_image_palette, Distance, Palette};
+    /// use tp_eval::{BLUE, RED};
+    ///
+    /// let img = RgbImage::from_pixel(2, 2, image::Rgb([200, 30, 40]));
+    /// let palette = Palette::new(vec![RED, BLUE]);
+    /// let result = modify_image_palette(img, &palette, Distance::Rgb, false).unwrap();
+    /// assert!(result.pixels().all(|pixel| *pixel == RED));
+    /// ```
+    pub mod palette {
+        pub use crate::distance::Distance;
+        pub use crate::palette::{modify_image_palette, modify_image_palette_matcher, Palette};
+        pub use crate::quantize::{QuantizeOptions, Quantizer};
+    }
+
+    /// dithering: the error of each pixel spread over its neighbours, or a
+    /// random threshold.
+    ///
+    /// ```
+    /// use image::RgbImage;
+    /// use tp_eval::ops::dither::{modify_image_dithering, Algo, DitherOptions};
+    /// use tp_eval::{BLACK, WHITE};
+    ///
+    /// let img = RgbImage::from_pixel(8, 8, image::Rgb([128, 128, 128]));
+    /// let options = DitherOptions { serpentin: false, seed: 0, linear: false, strength: 1.0, threshold: 128, alpha: None, source: None, progress: None };
+    /// let result = modify_image_dithering(img, Algo::FloydSteinberg, None, None, &options).unwrap();
+    /// let white = result.pixels().filter(|pixel| **pixel == WHITE).count();
+    /// assert!(result.pixels().all(|pixel| *pixel == WHITE || *pixel == BLACK));
+    /// assert!((24..=40).contains(&white));
+    /// ```
+    pub mod dither {
+        pub use crate::dither::Dither;
+        pub use crate::diffusion::{modify_image_dithering, modify_image_dithering_gray, Algo, DitherOptions, ErrorDiffusion, Kernel, Riemersma};
+        pub use crate::ditherer::{BlackAndWhite, Ditherer, PixelQuantizer};
+        pub use crate::ordered::{Ordered, Random};
+        pub use crate::progress::Progress;
+        pub use crate::stream::Streamer;
+        pub use crate::tiles::Tiler;
+    }
+
+    /// tramage: each pixel compared to a threshold matrix tiled over the image.
+    pub mod tramage {
+        pub use crate::ordered::{modify_image_tramage, modify_image_tramage_palette, Ordered, ThresholdMatrix, ThresholdSource};
+    }
+}
@@ -0,0 +1,40 @@
+//! The messages of the tool, written to stderr through the `log` crate, so
+//! that the standard output only ever holds the result. A program reusing the
+//! modes can collect the same messages with its own logger.
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match record.level() {
+            Level::Warn => eprintln!("attention : {}", record.args()),
+            _ => eprintln!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Sends the messages to stderr: only the errors with `quiet`, the details
+/// of the processing as well with `verbose`.
+pub fn init(verbose: bool, quiet: bool) {
+    let level = match (verbose, quiet) {
+        (_, true) => LevelFilter::Error,
+        (true, false) => LevelFilter::Debug,
+        (false, false) => LevelFilter::Info,
+    };
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
@@ -1,183 +1,1719 @@
-use argh::FromArgs;
-use image::{ImageError, Luma, Rgb, RgbImage, Pixel};
+mod completions;
+mod config;
+mod error;
+mod info;
+mod logger;
+mod output;
+mod palettes;
+mod preview;
+mod prompt;
+mod stats;
+mod streaming;
+mod walk;
+mod watch;
 
-#[derive(Debug, Clone, PartialEq, FromArgs)]
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Cursor, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use argh::{ArgsInfo, EarlyExit, FlagInfoKind, FromArgs};
+use image::error::LimitErrorKind;
+use image::io::Limits;
+use image::{DynamicImage, GenericImageView, GrayImage, ImageError, ImageFormat, Rgb, RgbImage};
+use tp_eval::alpha::{merge_alpha, split_alpha};
+use tp_eval::blue_noise::{parse_mask_size, parse_sigma, ranks_to_image, ranks_to_text, void_and_cluster};
+use tp_eval::diffusion::{parse_divisor, parse_strength, Algo, Kernel, ALGOS};
+use tp_eval::distance::{parse_hsv_weights, Distance, DISTANCES};
+use tp_eval::equalize::equalize_luma;
+use tp_eval::icc::{read_icc_profile, MatrixProfile, ProfileHandling, PROFILE_HANDLINGS};
+use tp_eval::levels::{modify_image_niveaux, modify_image_posterize, parse_channel_levels, parse_level_count};
+use tp_eval::ordered::{parse_bayer_order, parse_force, ThresholdMatrix, ThresholdSource, DEFAULT_BAYER_ORDER};
+use tp_eval::ops::dither::Progress;
+use tp_eval::orientation::{apply_orientation, exif_orientation};
+use tp_eval::palette::{NAMED_COLOURS, modify_image_palette, modify_image_palette_matcher, parse_colour, parse_colour_count, parse_colour_list, parse_colour_names, parse_gpl_file, Palette};
+use tp_eval::presets::{parse_preset, PRESETS};
+use tp_eval::quantize::{parse_quality, QuantizeOptions, Quantizer, QUANTIZERS, DEFAULT_AUTO_COLOURS, DEFAULT_ITERATIONS, DEFAULT_QUALITY};
+use tp_eval::random::Rng;
+use tp_eval::resize::{parse_size, Size};
+use tp_eval::threshold::{modify_image_seuil, modify_image_seuil_gray, modify_image_seuil_adaptatif, modify_image_seuil_hysteresis, modify_image_seuil_rgb, parse_bias, parse_channel_thresholds, parse_hysteresis, parse_threshold, parse_window, ThresholdMethod, DEFAULT_BIAS, DEFAULT_THRESHOLD, DEFAULT_WINDOW, THRESHOLD_METHODS};
+use tp_eval::tiles::{map_tiles, parse_tile_size, Tile};
+use tp_eval::{Dither, BLACK, WHITE};
+
+use completions::{completion_script, names, Shell, ValueHint};
+use config::{find_config, Config};
+use error::{Error, EXIT_ARGUMENT};
+use info::ImageInfo;
+use output::{is_standard_stream, output_extensions, parse_bits, parse_output_format, Bits, Output};
+use palettes::{palettes_json, palettes_text};
+use preview::{parse_preview_width, render_preview, supports_truecolor, terminal_width};
+use prompt::confirmation;
+use stats::{colour_counts, mean_error, stats_json, RunStats, StageDurations};
+use streaming::{stream_png, StreamOptions};
+use walk::walk;
+use watch::Watcher;
+
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
 /// Convertit une image en monochrome ou vers une palette réduite de couleurs.
 struct DitherArgs {
 
-    /// le fichier d’entrée
+    /// le fichier d’entrée puis le fichier de sortie (optionnel, par défaut le nom de l’entrée suivi du mode, par exemple photo_seuil.png à côté de photo.jpg), - désignant l’entrée ou la sortie standard ; avec --sortie-dossier, uniquement des fichiers d’entrée ; aucun pour genere-masque
     #[argh(positional)]
-    input: String,
+    fichiers: Vec<PathBuf>,
 
-    /// le fichier de sortie (optionnel)
-    #[argh(positional)]
-    output: Option<String>,
+    /// traite tous les fichiers donnés comme des entrées et écrit leurs sorties dans ce dossier, sous leur nom par défaut
+    #[argh(option)]
+    sortie_dossier: Option<PathBuf>,
+
+    /// traite toutes les images des dossiers donnés en entrée et de leurs sous-dossiers, avec --sortie-dossier, où leur arborescence est reproduite
+    #[argh(switch)]
+    recursif: bool,
+
+    /// affiche des informations sur le traitement : dimensions de l’entrée, paramètres du mode, palette, durée de chaque étape
+    #[argh(switch)]
+    verbose: bool,
+
+    /// n’affiche que les erreurs, sans avertissements ni progression
+    #[argh(switch)]
+    quiet: bool,
+
+    /// travaille en lumière linéaire plutôt que sur les valeurs sRGB (seuil, seuil-rgb, palette, dithering et tramage)
+    #[argh(switch)]
+    lineaire: bool,
+
+    /// égalise l’histogramme de la luminance de l’image avant de la traiter
+    #[argh(switch)]
+    egaliser: bool,
+
+    /// ne tourne pas l’image selon son orientation EXIF
+    #[argh(switch)]
+    ignorer_exif: bool,
+
+    /// le format de la sortie, par son extension (png, jpg…) ; obligatoire quand la sortie est - (la sortie standard)
+    #[argh(option, from_str_fn(parse_output_format))]
+    format: Option<String>,
+
+    /// les bits de chaque pixel de la sortie : 1 (noir et blanc, en PNG seulement), 8 (niveaux de gris) ou 24 (RGB), l’alpha éventuel en plus ; par défaut, le PNG de palette est en couleurs indexées sur sa palette s’il n’a pas d’alpha et qu’elle n’a pas plus de 256 couleurs, et un PNG fait uniquement de noir et de blanc est écrit sur 1 bit
+    #[argh(option, from_str_fn(parse_bits))]
+    bits: Option<Bits>,
+
+    /// remplace le fichier de sortie s’il existe déjà
+    #[argh(switch)]
+    force: bool,
+
+    /// ne pose jamais de question : sans --force, une sortie qui existe déjà est une erreur ; sinon, dans un terminal, le remplacement est demandé
+    #[argh(switch)]
+    no_input: bool,
+
+    /// reste actif et refait la sortie chaque fois que l’entrée, ou le fichier de palette ou de matrice, est modifié, jusqu’à Ctrl-C
+    #[argh(switch)]
+    watch: bool,
+
+    /// affiche le résultat dans le terminal ; sans fichier de sortie, il n’est alors pas écrit
+    #[argh(switch)]
+    apercu: bool,
+
+    /// la largeur de --apercu, en colonnes (par défaut celle du terminal)
+    #[argh(option, from_str_fn(parse_preview_width))]
+    apercu_largeur: Option<u32>,
+
+    /// écrit en JSON, dans ce fichier ou sur la sortie standard avec -, des statistiques du traitement : chemins, dimensions, mode et options, durée de chaque étape, nombre de pixels de chaque couleur du résultat et erreur moyenne de quantification
+    #[argh(option)]
+    stats: Option<PathBuf>,
+
+    /// le traitement du profil ICC de l’image : convertir vers le sRGB (par défaut), srgb (la considérer comme sRGB) ou ignorer
+    #[argh(option)]
+    profil: Option<ProfileHandling>,
+
+    /// traite une entrée PNG ligne par ligne, sans jamais la garder entière en mémoire, vers une sortie PNG (dithering et tramage) ; les PNG 16 bits sont ramenés à 8 bits, l’orientation EXIF et le profil ICC ne sont pas appliqués
+    #[argh(switch)]
+    streaming: bool,
+
+    /// traite l’image par tuiles carrées de ce côté en pixels, pour borner la mémoire des calculs en 16 bits et en lumière linéaire : seuil, palette et tramage donnent le même résultat qu’en une fois, la diffusion d’erreur reporte son erreur sur les tuiles suivantes mais perd celle qui reviendrait vers les précédentes
+    #[argh(option, from_str_fn(parse_tile_size))]
+    tuiles: Option<u32>,
+
+    /// le nombre de pixels au-delà duquel une image est refusée avant d’être décodée (par défaut 268435456, soit 16384 × 16384) ; --streaming, qui ne garde qu’une ligne, n’y est pas soumis
+    #[argh(option, from_str_fn(parse_max_pixels))]
+    max_pixels: Option<u64>,
+
+    /// le nombre de fils d’exécution des traitements parallèles (par défaut la variable RAYON_NUM_THREADS, sinon un par cœur)
+    #[argh(option, from_str_fn(parse_threads))]
+    threads: Option<usize>,
+
+    /// ignore le fichier dither.toml, du dossier courant ou de $XDG_CONFIG_HOME/dither/, qui donne des valeurs par défaut aux options
+    #[argh(switch)]
+    no_config: bool,
 
     /// le mode d’opération
     #[argh(subcommand)]
     mode: Mode
 }
 
-#[derive(Debug, Clone, PartialEq, FromArgs)]
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
 #[argh(subcommand)]
 enum Mode {
     Seuil(OptsSeuil),
+    SeuilRgb(OptsSeuilRgb),
+    Niveaux(OptsNiveaux),
+    Posterize(OptsPosterize),
     Palette(OptsPalette),
     Dithering(OptsDithering),
+    Tramage(OptsTramage),
+    GenereMasque(OptsGenereMasque),
+    Palettes(OptsPalettes),
+    Info(OptsInfo),
+    Pipeline(OptsPipeline),
+}
+
+impl Mode {
+    // As typed on the command line
+    fn name(&self) -> &'static str {
+        match self {
+            Mode::Seuil(_) => "seuil",
+            Mode::SeuilRgb(_) => "seuil-rgb",
+            Mode::Niveaux(_) => "niveaux",
+            Mode::Posterize(_) => "posterize",
+            Mode::Palette(_) => "palette",
+            Mode::Dithering(_) => "dithering",
+            Mode::Tramage(_) => "tramage",
+            Mode::GenereMasque(_) => "genere-masque",
+            Mode::Palettes(_) => "palettes",
+            Mode::Info(_) => "info",
+            Mode::Pipeline(_) => "pipeline",
+        }
+    }
+
+    // Added to the name of the input to make the default output name
+    fn output_suffix(&self) -> String {
+        match self {
+            Mode::Seuil(_) => "seuil".to_string(),
+            Mode::SeuilRgb(_) => "seuil-rgb".to_string(),
+            Mode::Niveaux(opts) => format!("niveaux{}", opts.n_niveaux),
+            Mode::Posterize(_) => "posterize".to_string(),
+            Mode::Palette(opts) => format!("palette{}", opts.colour_count()),
+            Mode::Dithering(_) => "dithering".to_string(),
+            Mode::Tramage(_) => "tramage".to_string(),
+            Mode::GenereMasque(_) => "masque".to_string(),
+            Mode::Palettes(_) => "palettes".to_string(),
+            Mode::Info(_) => "info".to_string(),
+            Mode::Pipeline(_) => "pipeline".to_string(),
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, FromArgs)]
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
 #[argh(subcommand, name="seuil")]
 /// Rendu de l’image par seuillage monochrome.
-struct OptsSeuil {}
+struct OptsSeuil {
+
+    /// la luminance, de 0 à 255, à partir de laquelle un pixel est clair (128 par défaut)
+    #[argh(option, from_str_fn(parse_threshold))]
+    valeur: Option<u8>,
+
+    /// choisit le seuil d’après l’histogramme de l’image plutôt que --valeur : otsu
+    #[argh(option)]
+    auto: Option<ThresholdMethod>,
+
+    /// compare chaque pixel à la luminance moyenne de son voisinage plutôt qu’à un seuil global
+    #[argh(switch)]
+    adaptatif: bool,
+
+    /// le côté en pixels du voisinage de --adaptatif (31 par défaut)
+    #[argh(option, from_str_fn(parse_window))]
+    fenetre: Option<u32>,
+
+    /// la valeur retranchée à la moyenne du voisinage de --adaptatif (5 par défaut)
+    #[argh(option, from_str_fn(parse_bias))]
+    biais: Option<f64>,
+
+    /// seuillage par hystérésis avec un seuil bas et un seuil haut, par exemple "100,180" : entre les deux, un pixel n’est clair que s’il touche une région claire
+    #[argh(option, from_str_fn(parse_hysteresis))]
+    hysteresis: Option<(u8, u8)>,
+
+    /// la couleur des pixels clairs, en hexadécimal ou par son nom (blanc par défaut)
+    #[argh(option, from_str_fn(parse_colour))]
+    couleur_claire: Option<Rgb<u8>>,
+
+    /// la couleur des pixels foncés, en hexadécimal ou par son nom (noir par défaut)
+    #[argh(option, from_str_fn(parse_colour))]
+    couleur_foncee: Option<Rgb<u8>>
+}
+
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
+#[argh(subcommand, name="seuil-rgb")]
+/// Rendu de l’image en seuillant chaque canal séparément, vers les huit couleurs primaires et secondaires.
+struct OptsSeuilRgb {
+
+    /// le seuil de 0 à 255 : un seul pour les trois canaux ou trois séparés par des virgules, par exemple "128,100,140" (128 par défaut)
+    #[argh(option, from_str_fn(parse_channel_thresholds))]
+    valeurs: Option<[u8; 3]>
+}
+
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
+#[argh(subcommand, name="niveaux")]
+/// Rendu de l’image en niveaux de gris régulièrement espacés.
+struct OptsNiveaux {
 
-#[derive(Debug, Clone, PartialEq, FromArgs)]
+    /// le nombre de niveaux de gris, entre 2 et 256 (2 donne le même résultat que seuil)
+    #[argh(option, from_str_fn(parse_level_count))]
+    n_niveaux: u32
+}
+
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
+#[argh(subcommand, name="posterize")]
+/// Rendu de l’image en réduisant séparément chaque canal à quelques niveaux.
+struct OptsPosterize {
+
+    /// le nombre de niveaux par canal, entre 2 et 256 : un seul pour les trois canaux ou trois séparés par des virgules (par exemple "8,8,4")
+    #[argh(option, from_str_fn(parse_channel_levels))]
+    niveaux: [u32; 3]
+}
+
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
 #[argh(subcommand, name="palette")]
 /// Rendu de l’image avec une palette contenant un nombre limité de couleurs
 struct OptsPalette {
 
-    /// le nombre de couleurs à utiliser, dans la liste [NOIR, BLANC, ROUGE, VERT, BLEU, JAUNE, CYAN, MAGENTA]
+    /// le nombre de couleurs à utiliser, dans la liste [NOIR, BLANC, ROUGE, VERT, BLEU, JAUNE, CYAN, MAGENTA, GRIS] ou avec --auto (16 par défaut)
+    #[argh(option, from_str_fn(parse_colour_count))]
+    n_couleurs: Option<usize>,
+
+    /// les couleurs de la palette en hexadécimal, séparées par des virgules (par exemple "#000000,#fff,ff8800")
+    #[argh(option, from_str_fn(parse_colour_list))]
+    couleurs: Option<Palette>,
+
+    /// les noms des couleurs de la palette, séparés par des virgules, parmi noir, blanc, gris, rouge, vert, bleu, jaune, cyan et magenta
+    #[argh(option, from_str_fn(parse_colour_names))]
+    noms: Option<Palette>,
+
+    /// un fichier de palette GIMP (.gpl) dont les couleurs forment la palette
+    #[argh(option, from_str_fn(parse_gpl_file))]
+    fichier: Option<Palette>,
+
+    /// calcule une palette de --n-couleurs couleurs adaptée à l’image : median-cut, kmeans, octree ou neuquant
+    #[argh(option)]
+    auto: Option<Quantizer>,
+
+    /// le nombre maximal d’itérations de --auto kmeans (20 par défaut)
+    #[argh(option)]
+    iterations: Option<usize>,
+
+    /// la graine de l’initialisation de --auto kmeans, pour obtenir toujours la même palette
+    #[argh(option)]
+    seed: Option<u64>,
+
+    /// le facteur d’échantillonnage de --auto neuquant, de 1 (le plus fidèle) à 30 (le plus rapide), 10 par défaut
+    #[argh(option, from_str_fn(parse_quality))]
+    qualite: Option<usize>,
+
+    /// une image dont la palette, calculée par --auto (median-cut par défaut), est appliquée à l’image d’entrée
     #[argh(option)]
-    n_couleurs: usize
+    reference: Option<PathBuf>,
+
+    /// une palette prédéfinie : gameboy, cga, ega, nes, zx ou websafe
+    #[argh(option, from_str_fn(parse_preset))]
+    preset: Option<Palette>,
+
+    /// les noms des couleurs à retirer de la liste de --n-couleurs, séparés par des virgules (par exemple "gris,magenta")
+    #[argh(option, from_str_fn(parse_colour_names))]
+    exclure: Option<Palette>,
+
+    /// la distance qui choisit la couleur la plus proche : rgb (par défaut), redmean (rgb pondéré selon le rouge), lab (ΔE76 dans CIELAB), ciede2000 ou hsv
+    #[argh(option, default = "Distance::Rgb")]
+    distance: Distance,
+
+    /// les poids de la teinte, de la saturation et de la valeur pour --distance hsv, séparés par des virgules (1,1,1 par défaut)
+    #[argh(option, from_str_fn(parse_hsv_weights))]
+    poids_hsv: Option<[f64; 3]>
 }
 
-#[derive(Debug, Clone, PartialEq, FromArgs)]
+impl OptsPalette {
+    // The palette must come from exactly one source; --n-couleurs is either
+    // a source on its own or the size of the --auto palette, and --exclure
+    // only applies to the built-in list
+    fn source_error(&self) -> Option<&'static str> {
+        let computed = self.auto.is_some() || self.reference.is_some();
+        let sources = [self.couleurs.is_some(), self.noms.is_some(), self.fichier.is_some(), self.preset.is_some(), computed];
+        match sources.iter().filter(|&&set| set).count() {
+            0 if self.n_couleurs.is_none() && self.exclure.is_none() => Some("l’une des options --n-couleurs, --couleurs, --noms, --fichier, --preset, --auto ou --reference est obligatoire"),
+            0 if self.builtin().is_empty() => Some("la palette ne contient aucune couleur : --exclure les retire toutes"),
+            0 => None,
+            _ if self.exclure.is_some() => Some("--exclure ne s’applique qu’à la liste de couleurs de --n-couleurs"),
+            1 if self.n_couleurs.is_some() && !computed => Some("--n-couleurs ne peut être combiné qu’avec --auto ou --reference"),
+            1 => None,
+            _ => Some("--couleurs, --noms, --fichier, --preset et --auto ou --reference ne peuvent pas être utilisés ensemble"),
+        }
+    }
+
+    // The number of colours of the palette, as far as it is known before
+    // reading the image
+    fn colour_count(&self) -> usize {
+        let given = [&self.couleurs, &self.noms, &self.fichier, &self.preset].into_iter().find_map(|list| list.as_ref());
+        match given {
+            Some(list) => list.len(),
+            None if self.auto.is_some() || self.reference.is_some() => self.n_couleurs.unwrap_or(DEFAULT_AUTO_COLOURS),
+            None => self.builtin().len(),
+        }
+    }
+
+    // The built-in palette selected by --n-couleurs and --exclure
+    fn builtin(&self) -> Palette {
+        Palette::builtin_excluding(self.n_couleurs.unwrap_or(usize::MAX), self.exclure.as_ref().map_or(&[], Palette::colours))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
 #[argh(subcommand, name="dithering")]
 /// Rendu de l’image en dithering.
-struct OptsDithering {}
-
-const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
-const GREY: Rgb<u8> = Rgb([127, 127, 127]);
-const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
-const BLUE: Rgb<u8> = Rgb([0, 0, 255]);
-const RED: Rgb<u8> = Rgb([255, 0, 0]);
-const GREEN: Rgb<u8> = Rgb([0, 255, 0]);
-const YELLOW: Rgb<u8> = Rgb([255, 255, 0]);
-const MAGENTA: Rgb<u8> = Rgb([255, 0, 255]);
-const CYAN: Rgb<u8> = Rgb([0, 255, 255]);
-
-fn get_image(path: String) -> Result<RgbImage, ImageError> {
-    let img = image::open(path)?;
-    let img = img.to_rgb8();
-    Ok(img)
-}
-
-fn modify_image_seuil(mut img: RgbImage) -> Result<RgbImage, ImageError> {
-    let (width, height) = img.dimensions();
-    for x in 0..width {
-        for y in 0..height {
-            let Luma(luminosite_) = img.get_pixel(x, y).to_luma();
-            if luminosite_[0] > 127 {
-                img.put_pixel(x, y, WHITE);
-            } else {
-                img.put_pixel(x, y, BLACK);
+struct OptsDithering {
+
+    /// l’algorithme de diffusion d’erreur : floyd-steinberg (par défaut), atkinson, jjn, stucki, burkes, sierra, sierra2, sierra-lite, ostromoukhov, riemersma, stevenson-arce ou aleatoire
+    #[argh(option, default = "Algo::FloydSteinberg")]
+    algo: Algo,
+
+    /// un noyau de diffusion personnalisé qui remplace --algo, par exemple "0 * 7; 3 5 1" où * désigne le pixel courant
+    #[argh(option)]
+    noyau: Option<Kernel>,
+
+    /// le diviseur des coefficients de --noyau (par défaut leur somme)
+    #[argh(option, from_str_fn(parse_divisor))]
+    diviseur: Option<u32>,
+
+    /// parcourt les lignes alternativement de gauche à droite et de droite à gauche
+    #[argh(switch)]
+    serpentin: bool,
+
+    /// la graine de --algo aleatoire, pour obtenir toujours le même résultat
+    #[argh(option)]
+    seed: Option<u64>,
+
+    /// diffuse l’erreur vers les N premières couleurs de la liste de palette --n-couleurs au lieu du noir et blanc
+    #[argh(option, from_str_fn(parse_colour_count))]
+    palette: Option<usize>,
+
+    /// diffuse l’erreur vers ces couleurs en hexadécimal, séparées par des virgules, au lieu du noir et blanc
+    #[argh(option, from_str_fn(parse_colour_list))]
+    couleurs: Option<Palette>,
+
+    /// diffuse l’erreur vers ces couleurs nommées, séparées par des virgules, parmi noir, blanc, gris, rouge, vert, bleu, jaune, cyan et magenta, au lieu du noir et blanc
+    #[argh(option, from_str_fn(parse_colour_names))]
+    noms: Option<Palette>,
+
+    /// diffuse l’erreur vers une palette prédéfinie au lieu du noir et blanc : gameboy, cga, ega, nes, zx ou websafe
+    #[argh(option, from_str_fn(parse_preset))]
+    preset: Option<Palette>,
+
+    /// la couleur tramée au lieu du blanc, en hexadécimal ou par son nom
+    #[argh(option, from_str_fn(parse_colour))]
+    couleur_claire: Option<Rgb<u8>>,
+
+    /// la couleur tramée au lieu du noir, en hexadécimal ou par son nom
+    #[argh(option, from_str_fn(parse_colour))]
+    couleur_foncee: Option<Rgb<u8>>,
+
+    /// la part de l’erreur qui est diffusée, de 0 (simple seuillage) à 1 (par défaut)
+    #[argh(option, from_str_fn(parse_strength))]
+    force: Option<f64>,
+
+    /// la luminance, de 0 à 255, à partir de laquelle un pixel devient blanc, comme pour seuil (128 par défaut)
+    #[argh(option, from_str_fn(parse_threshold))]
+    valeur: Option<u8>
+}
+
+impl OptsDithering {
+    // The custom kernel, with its divisor
+    fn kernel(&self) -> Option<Kernel> {
+        self.noyau.clone().map(|noyau| match self.diviseur {
+            Some(diviseur) => noyau.with_divisor(diviseur),
+            None => noyau,
+        })
+    }
+
+    // The settings of the library matching these options
+    fn dither(&self, linear: bool) -> Dither<'static> {
+        let mut dither = match (self.kernel(), self.seed) {
+            (Some(kernel), _) => Dither::new().kernel(kernel),
+            (None, Some(seed)) => Dither::new().algorithm(self.algo).seed(seed),
+            (None, None) => Dither::new().algorithm(self.algo),
+        };
+        dither = dither.linear_light(linear).serpentine(self.serpentin);
+        if let Some(strength) = self.force {
+            dither = dither.strength(strength);
+        }
+        if let Some(threshold) = self.valeur {
+            dither = dither.threshold(threshold);
+        }
+        if let Some(palette) = self.couleurs.clone().or_else(|| self.noms.clone()).or_else(|| self.preset.clone()).or_else(|| self.palette.map(|n| builtin_prefix("--palette", n, &[]))) {
+            log::debug!("palette : {}", palette);
+            dither = dither.palette(palette);
+        }
+        if self.couleur_claire.is_some() || self.couleur_foncee.is_some() {
+            dither = dither.colours(self.couleur_foncee.unwrap_or(BLACK), self.couleur_claire.unwrap_or(WHITE));
+        }
+        dither
+    }
+
+    // The number of options giving the palette, of which at most one is allowed
+    fn palette_options(&self) -> usize {
+        [self.palette.is_some(), self.couleurs.is_some(), self.noms.is_some(), self.preset.is_some()].iter().filter(|&&set| set).count()
+    }
+
+    // Whether the result is only black and white
+    fn is_monochrome(&self) -> bool {
+        self.palette_options() == 0 && self.couleur_claire.is_none() && self.couleur_foncee.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
+#[argh(subcommand, name="tramage")]
+/// Rendu de l’image par tramage ordonné (matrice de Bayer par défaut).
+struct OptsTramage {
+
+    /// l’ordre de la matrice de Bayer, de 1 (2×2) à 5 (32×32), 3 (8×8) par défaut
+    #[argh(option, from_str_fn(parse_bayer_order))]
+    ordre: Option<u32>,
+
+    /// un fichier texte contenant la matrice de seuils à utiliser, une ligne d’entiers par rangée
+    #[argh(option, from_str_fn(ThresholdMatrix::from_file))]
+    matrice: Option<ThresholdMatrix>,
+
+    /// utilise un masque de bruit bleu 64×64 plutôt qu’une matrice de Bayer
+    #[argh(switch)]
+    bruit_bleu: bool,
+
+    /// utilise une trame à points groupés 8×8 qui grossissent depuis le centre de chaque cellule
+    #[argh(switch)]
+    halftone: bool,
+
+    /// utilise le bruit à gradient entrelacé (IGN), calculé pour chaque pixel sans matrice
+    #[argh(switch)]
+    ign: bool,
+
+    /// trame vers les N premières couleurs de la liste de palette --n-couleurs au lieu du noir et blanc
+    #[argh(option, from_str_fn(parse_colour_count))]
+    palette: Option<usize>,
+
+    /// trame vers ces couleurs en hexadécimal, séparées par des virgules, au lieu du noir et blanc
+    #[argh(option, from_str_fn(parse_colour_list))]
+    couleurs: Option<Palette>,
+
+    /// l’amplitude, en niveaux de canal, de la perturbation ajoutée avant de choisir la couleur de --palette ou --couleurs (64 par défaut)
+    #[argh(option, from_str_fn(parse_force))]
+    force: Option<f32>
+}
+
+impl OptsTramage {
+    // The settings of the library matching these options
+    fn dither(&self, linear: bool) -> Dither<'static> {
+        let source = match &self.matrice {
+            Some(matrix) => ThresholdSource::Matrix(matrix.clone()),
+            None if self.bruit_bleu => ThresholdSource::Matrix(ThresholdMatrix::blue_noise()),
+            None if self.halftone => ThresholdSource::Matrix(ThresholdMatrix::clustered_dot()),
+            None if self.ign => ThresholdSource::InterleavedGradientNoise,
+            None => ThresholdSource::Matrix(ThresholdMatrix::bayer(self.ordre.unwrap_or(DEFAULT_BAYER_ORDER))),
+        };
+        let mut dither = Dither::new().ordered(source).linear_light(linear);
+        if let Some(palette) = self.couleurs.clone().or_else(|| self.palette.map(|n| builtin_prefix("--palette", n, &[]))) {
+            log::debug!("palette : {}", palette);
+            dither = dither.palette(palette);
+        }
+        if let Some(force) = self.force {
+            dither = dither.force(force);
+        }
+        dither
+    }
+
+    // Whether the result is only black and white
+    fn is_monochrome(&self) -> bool {
+        self.palette.is_none() && self.couleurs.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
+#[argh(subcommand, name="genere-masque")]
+/// Génère un masque de bruit bleu par l’algorithme void-and-cluster.
+struct OptsGenereMasque {
+
+    /// le fichier du masque : une image en niveaux de gris, ou un fichier .txt lisible par tramage --matrice
+    #[argh(positional)]
+    sortie: PathBuf,
+
+    /// le côté du masque, une puissance de deux (64 par défaut)
+    #[argh(option, default = "64", from_str_fn(parse_mask_size))]
+    taille: usize,
+
+    /// l’écart type du filtre gaussien qui mesure la densité des points (1.5 par défaut)
+    #[argh(option, default = "1.5", from_str_fn(parse_sigma))]
+    sigma: f64,
+
+    /// la graine du générateur aléatoire, pour obtenir toujours le même masque
+    #[argh(option)]
+    seed: Option<u64>
+}
+
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
+#[argh(subcommand, name="palettes")]
+/// Liste les couleurs nommées, les presets et les distances disponibles.
+struct OptsPalettes {
+
+    /// affiche un aperçu de chaque couleur, quand la sortie est un terminal
+    #[argh(switch)]
+    couleur: bool,
+
+    /// écrit la liste en JSON
+    #[argh(switch)]
+    json: bool
+}
+
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
+#[argh(subcommand, name="info")]
+/// Affiche les dimensions, le type de couleur et les statistiques de luminance d’une image.
+struct OptsInfo {
+
+    /// l’image à décrire, - désignant l’entrée standard
+    #[argh(positional)]
+    fichier: PathBuf,
+
+    /// écrit les statistiques en JSON
+    #[argh(switch)]
+    json: bool
+}
+
+#[derive(Debug, Clone, PartialEq, FromArgs, ArgsInfo)]
+#[argh(subcommand, name="pipeline")]
+/// Enchaîne plusieurs opérations sur l’image, sans fichier intermédiaire.
+struct OptsPipeline {
+
+    /// les étapes, séparées par des virgules, chacune NOM:PARAMÈTRES : redim:800x600 (ou 800x, x600), puis un mode suivi de la valeur de sa première option (palette:8, dithering:atkinson) ou de ses options (palette:--preset cga --distance lab)
+    #[argh(option, from_str_fn(parse_stages))]
+    etapes: Stages
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Stages(Vec<Stage>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Stage {
+    Resize(Size),
+    Mode(Box<Mode>),
+}
+
+// The modes that a pipeline can chain, besides redim
+const STAGE_MODES: [&str; 7] = ["seuil", "seuil-rgb", "niveaux", "posterize", "palette", "dithering", "tramage"];
+
+// Parses `--etapes`. The commas inside a stage, as in a list of colours, are
+// told apart by what follows them, which is not the name of a stage
+fn parse_stages(value: &str) -> Result<Stages, String> {
+    let starts_stage = |piece: &str| {
+        let name = piece.split(':').next().unwrap_or_default();
+        name == "redim" || STAGE_MODES.contains(&name)
+    };
+    let mut texts: Vec<String> = Vec::new();
+    for piece in value.split(',') {
+        match texts.last_mut() {
+            Some(text) if !starts_stage(piece.trim()) => {
+                text.push(',');
+                text.push_str(piece);
             }
+            _ => texts.push(piece.trim().to_string()),
         }
     }
-    Ok(img)
-}
-
-fn modify_image_palette(mut img: RgbImage, n_couleurs: usize) -> Result<RgbImage, ImageError> {
-    let (width, height) = img.dimensions();
-    
-    // Original palette with 9 colors
-    let mut palette = vec![BLACK, GREY, WHITE, RED, GREEN, BLUE, YELLOW, CYAN, MAGENTA];
-    
-    // Clamp n_couleurs to the size of the palette
-    let n_couleurs = n_couleurs.min(palette.len());
-    
-    // Reduce the palette to n_couleurs colors
-    palette = palette.drain(0..n_couleurs).collect::<Vec<Rgb<u8>>>();
-    
-    for x in 0..width {
-        for y in 0..height {
-            let pixel = img.get_pixel(x, y);
-            let mut best_distance = f64::INFINITY;
-            let mut best_color = BLACK;
-            for color in palette.iter() {
-                let distance = (color[0] as f64 - pixel[0] as f64).powi(2) + (color[1] as f64 - pixel[1] as f64).powi(2) + (color[2] as f64 - pixel[2] as f64).powi(2);
-                if distance < best_distance {
-                    best_distance = distance;
-                    best_color = *color;
-                }
+    texts.iter()
+        .enumerate()
+        .map(|(index, text)| parse_stage(text).map_err(|message| format!("étape {} ({}) : {}", index + 1, text, message)))
+        .collect::<Result<_, _>>()
+        .map(Stages)
+}
+
+fn parse_stage(text: &str) -> Result<Stage, String> {
+    let (name, params) = text.split_once(':').unwrap_or((text, ""));
+    let params = params.trim();
+    if name == "redim" {
+        return parse_size(params).map(Stage::Resize);
+    }
+    if !STAGE_MODES.contains(&name) {
+        return Err(format!("étape inconnue : {} (attendues : redim, {})", name, STAGE_MODES.join(", ")));
+    }
+    let tokens: Vec<&str> = if params.is_empty() || params.starts_with("--") {
+        params.split_whitespace().collect()
+    } else {
+        // A bare value is the one of the first option of the mode
+        let info = DitherArgs::get_args_info();
+        let option = info.commands.iter()
+            .find(|command| command.name == name)
+            .and_then(|command| command.command.flags.iter().find(|flag| matches!(flag.kind, FlagInfoKind::Option { .. })))
+            .map(|flag| flag.long)
+            .unwrap_or_default();
+        vec![option, params]
+    };
+    Mode::from_args(&[name], &tokens).map(|mode| Stage::Mode(Box::new(mode))).map_err(|early_exit| {
+        let lines: Vec<&str> = early_exit.output.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+        lines.join(" ")
+    })
+}
+
+// The image turned upright from its EXIF orientation, unless `ignore_exif` is
+// set, and converted to sRGB from its ICC profile as `profile` says
+fn get_image(path: &Path, ignore_exif: bool, profile: ProfileHandling, max_pixels: u64) -> Result<DynamicImage, Error> {
+    let (img, data, format) = read_image(path, ignore_exif, max_pixels)?;
+    Ok(apply_profile(img, &data, format, profile, path))
+}
+
+// Parses --threads, at least 1
+fn parse_threads(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(threads) if threads >= 1 => Ok(threads),
+        _ => Err(format!("nombre de fils invalide : {} (attendu : un entier strictement positif)", value)),
+    }
+}
+
+// The --max-pixels of 16384 × 16384, 768 Mo of 8-bit RGB
+const DEFAULT_MAX_PIXELS: u64 = 16384 * 16384;
+
+// Parses --max-pixels, at least 1
+fn parse_max_pixels(value: &str) -> Result<u64, String> {
+    match value.parse::<u64>() {
+        Ok(pixels) if pixels >= 1 => Ok(pixels),
+        _ => Err(format!("nombre de pixels invalide : {} (attendu : un entier strictement positif)", value)),
+    }
+}
+
+// The decoded image, turned upright unless `ignore_exif` is set, with the
+// encoded data and its format. Images of more than `max_pixels` pixels are
+// refused from their header, before the file is even read whole
+fn read_image(path: &Path, ignore_exif: bool, max_pixels: u64) -> Result<(DynamicImage, Vec<u8>, Option<ImageFormat>), Error> {
+    let data = if is_standard_stream(path) {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data).map(|_| data)
+    } else {
+        // A header the decoders do not understand is reported by the decoding
+        if let Ok(dimensions) = image::image_dimensions(path) {
+            check_size(path, dimensions, max_pixels)?;
+        }
+        fs::read(path)
+    };
+    let data = data.map_err(|error| Error::reading(path.to_path_buf(), error))?;
+
+    // The content tells the format, the extension only when it cannot
+    let mut reader = image::io::Reader::new(Cursor::new(&data));
+    if let Ok(format) = ImageFormat::from_path(path) {
+        reader.set_format(format);
+    }
+    let mut reader = reader.with_guessed_format().map_err(|error| Error::reading(path.to_path_buf(), error))?;
+    let format = reader.format();
+    // The standard input has no header to read on its own
+    if let (true, Some(format)) = (is_standard_stream(path), format) {
+        if let Ok(dimensions) = image::io::Reader::with_format(Cursor::new(&data), format).into_dimensions() {
+            check_size(path, dimensions, max_pixels)?;
+        }
+    }
+    // --max-pixels replaces the limits of the decoders on their sides and
+    // their allocations
+    let limits = decoding_limits(max_pixels);
+    let max_alloc = limits.max_alloc.unwrap_or(u64::MAX);
+    reader.limits(limits);
+    let mut img = reader.decode().map_err(|error| match error {
+        ImageError::Limits(limit) if limit.kind() == LimitErrorKind::DimensionError => {
+            match format.and_then(|format| image::io::Reader::with_format(Cursor::new(&data), format).into_dimensions().ok()) {
+                Some((width, height)) => Error::TooLarge { path: path.to_path_buf(), width, height, max_pixels },
+                None => Error::DecodeFailed(path.to_path_buf(), ImageError::Limits(limit)),
             }
-            img.put_pixel(x, y, best_color);
+        }
+        ImageError::Limits(limit) if limit.kind() == LimitErrorKind::InsufficientMemory => Error::OutOfMemory(path.to_path_buf(), max_alloc),
+        error => Error::DecodeFailed(path.to_path_buf(), error),
+    })?;
+    if !ignore_exif {
+        if let Some(orientation) = exif_orientation(&data) {
+            img = apply_orientation(img, orientation);
         }
     }
+    Ok((img, data, format))
+}
 
-    Ok(img)
+// Refuses an image of `dimensions` with more than `max_pixels` pixels
+fn check_size(path: &Path, (width, height): (u32, u32), max_pixels: u64) -> Result<(), Error> {
+    if width as u64 * height as u64 > max_pixels {
+        return Err(Error::TooLarge { path: path.to_path_buf(), width, height, max_pixels });
+    }
+    Ok(())
 }
 
-fn modify_image_dithering(mut img: RgbImage) -> Result<RgbImage, ImageError> {
-    let (width, height) = img.dimensions();
+// The limits of the decoders for --max-pixels: no side longer than the
+// pixels allowed, and no more memory than they take in 32-bit float RGBA,
+// the widest pixels a decoder gives
+fn decoding_limits(max_pixels: u64) -> Limits {
+    let mut limits = Limits::default();
+    let side = u32::try_from(max_pixels).unwrap_or(u32::MAX);
+    limits.max_image_width = Some(side);
+    limits.max_image_height = Some(side);
+    limits.max_alloc = Some(max_pixels.saturating_mul(16));
+    limits
+}
 
-    for y in 0..height {
-        for x in 0..width {
-            let pixel = img.get_pixel(x, y);
-            let avg_color = (pixel[0] as f64 + pixel[1] as f64 + pixel[2] as f64) / 3.0;
-            let new_color = if avg_color > 128.0 { WHITE } else { BLACK };
+// `img` converted to sRGB from the ICC profile embedded in `data`, as
+// `profile` says
+fn apply_profile(mut img: DynamicImage, data: &[u8], format: Option<ImageFormat>, profile: ProfileHandling, path: &Path) -> DynamicImage {
+    let icc = match (profile, format) {
+        (ProfileHandling::Ignore, _) | (_, None) => None,
+        (_, Some(format)) => read_icc_profile(data, format),
+    };
+    if let Some(icc) = icc {
+        match (MatrixProfile::parse(&icc), profile) {
+            (Some(matrix), _) if matrix.is_srgb() => {}
+            (Some(matrix), ProfileHandling::Convert) => img = matrix.convert(img),
+            (Some(_), _) => log::warn!("le profil ICC de {} n’est pas sRGB, ses couleurs sont traitées comme du sRGB", path.display()),
+            (None, _) => log::warn!("le profil ICC de {} n’est pas pris en charge, ses couleurs sont traitées comme du sRGB", path.display()),
+        }
+    }
+    img
+}
 
-            let error = [
-                pixel[0] as f64 - new_color[0] as f64,
-                pixel[1] as f64 - new_color[1] as f64,
-                pixel[2] as f64 - new_color[2] as f64,
-            ];
+// The name of the output when none is given: photo.jpg turned black and
+// white by seuil becomes photo_seuil.png
+fn default_output_name(input: &Path, mode: &Mode) -> OsString {
+    let mut name = input.file_stem().unwrap_or_default().to_os_string();
+    name.push("_");
+    name.push(mode.output_suffix());
+    // Appended rather than set, the stem may itself contain dots
+    name.push(".png");
+    name
+}
+
+// Runs the modes that turn a grayscale image black and white on its single
+// channel, with the same result as through RGB; the image is given back for
+// the other modes
+fn modify_gray(gray: GrayImage, mode: &Mode, linear: bool, progress: &Progress) -> Result<Result<GrayImage, GrayImage>, Error> {
+    match mode {
+        Mode::Seuil(opts) if !opts.adaptatif && opts.auto.is_none() && opts.hysteresis.is_none() && opts.couleur_claire.is_none() && opts.couleur_foncee.is_none() => {
+            let threshold = opts.valeur.unwrap_or(DEFAULT_THRESHOLD);
+            log::debug!("seuil : {}", threshold);
+            Ok(Ok(modify_image_seuil_gray(gray, threshold, linear)))
+        }
+        Mode::Dithering(opts) if opts.is_monochrome() => Ok(Ok(opts.dither(linear).progress(progress).apply_gray(&gray)?)),
+        _ => Ok(Err(gray)),
+    }
+}
+
+// The first `count` colours of the built-in list, with a warning when `option`
+// asks for more colours than the list has left
+fn builtin_prefix(option: &str, count: usize, excluded: &[Rgb<u8>]) -> Palette {
+    let palette = Palette::builtin_excluding(count, excluded);
+    if count > palette.len() {
+        log::warn!("{} {} ramené à {}, le nombre de couleurs de la liste", option, count, palette.len());
+    }
+    palette
+}
+
+fn invalid_argument(message: &str) -> Error {
+    Error::InvalidArgument(message.to_string())
+}
 
-            img.put_pixel(x, y, new_color);
+// Stands for a `-` argument while argh parses the command line, since it would
+// take it for an option; no real argument can contain a NUL byte
+const STANDARD_STREAM_PLACEHOLDER: &str = "\0-";
 
-            // Floyd-Steinberg error diffusion
-            if x + 1 < width {
-                let neighbor = img.get_pixel_mut(x + 1, y);
-                neighbor[0] = (neighbor[0] as f64 + error[0] * 7.0 / 16.0) as u8;
-                neighbor[1] = (neighbor[1] as f64 + error[1] * 7.0 / 16.0) as u8;
-                neighbor[2] = (neighbor[2] as f64 + error[2] * 7.0 / 16.0) as u8;
+// Followed by its rank, stands for an argument that is not UTF-8 while argh
+// parses the command line: only a path given as a positional argument can be
+// one
+const NON_UTF8_PLACEHOLDER: &str = "\0os";
+
+// The command line once merged with the configuration file
+struct CommandLine {
+    // Of the program, for the messages
+    name: String,
+    // The arguments after the name, with the defaults of the file
+    tokens: Vec<String>,
+    // The arguments that are not UTF-8, in place of their placeholders
+    non_utf8: Vec<OsString>,
+    config: Option<PathBuf>,
+}
+
+// Parses the command line like `argh::from_env`, with the defaults of the
+// configuration file, if any, but exits with `EXIT_ARGUMENT`
+fn parse_args() -> (DitherArgs, CommandLine) {
+    let mut args_os = std::env::args_os();
+    let program = args_os.next().map(PathBuf::from).unwrap_or_default();
+    let name = program.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let (mut strings, non_utf8) = utf8_tokens(args_os);
+
+    // Left out of the help, since it is set up once rather than typed
+    match strings.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["--completions", shell] => match shell.parse::<Shell>() {
+            Ok(shell) => {
+                print!("{}", completion_script(shell, &name, &DitherArgs::get_args_info(), value_hint));
+                std::process::exit(0)
+            }
+            Err(message) => argh_error(&message, &name),
+        },
+        ["--completions"] => argh_error("--completions attend un shell : bash, zsh ou fish", &name),
+        _ => {}
+    }
+
+    let config = find_config().filter(|_| !strings.iter().any(|arg| arg == "--no-config"));
+    if let Some(path) = &config {
+        match Config::load(path).and_then(|config| config.apply(&strings, &DitherArgs::get_args_info(), &EXCLUSIVE_OPTIONS, value_hint)) {
+            Ok(merged) => strings = merged,
+            Err(message) => argh_error(&message, &name),
+        }
+    }
+
+    match parse_tokens(&name, &strings, &non_utf8) {
+        Ok(args) => (args, CommandLine { name, tokens: strings, non_utf8, config }),
+        Err(early_exit) => match early_exit.status {
+            Ok(()) => {
+                println!("{}", early_exit.output);
+                std::process::exit(0)
             }
-            if x > 0 && y + 1 < height {
-                let neighbor = img.get_pixel_mut(x - 1, y + 1);
-                neighbor[0] = (neighbor[0] as f64 + error[0] * 3.0 / 16.0) as u8;
-                neighbor[1] = (neighbor[1] as f64 + error[1] * 3.0 / 16.0) as u8;
-                neighbor[2] = (neighbor[2] as f64 + error[2] * 3.0 / 16.0) as u8;
+            Err(()) => argh_error(&early_exit.output, &name),
+        },
+    }
+}
+
+// The arguments as argh takes them, each one that is not UTF-8 replaced by
+// its placeholder, and the arguments replaced
+fn utf8_tokens(args: impl IntoIterator<Item = OsString>) -> (Vec<String>, Vec<OsString>) {
+    let mut non_utf8 = Vec::new();
+    let tokens = args.into_iter()
+        .map(|arg| arg.into_string().unwrap_or_else(|arg| {
+            non_utf8.push(arg);
+            format!("{}{}", NON_UTF8_PLACEHOLDER, non_utf8.len() - 1)
+        }))
+        .collect();
+    (tokens, non_utf8)
+}
+
+// Parses the arguments after the name of the program, where - stands for a
+// standard stream and the placeholders of `utf8_tokens` for the `non_utf8`
+// arguments, which only positional paths may be
+fn parse_tokens(name: &str, tokens: &[String], non_utf8: &[OsString]) -> Result<DitherArgs, EarlyExit> {
+    let lossy = |text: &str| non_utf8.iter().enumerate().rev().fold(text.to_string(), |text, (i, arg)| {
+        text.replace(&format!("{}{}", NON_UTF8_PLACEHOLDER, i), &arg.to_string_lossy())
+    });
+    let rest: Vec<&str> = tokens.iter().map(|arg| if arg == "-" { STANDARD_STREAM_PLACEHOLDER } else { arg.as_str() }).collect();
+    let mut args = DitherArgs::from_args(&[name], &rest).map_err(|early_exit| EarlyExit { output: lossy(&early_exit.output), ..early_exit })?;
+    let mut restored = vec![false; non_utf8.len()];
+    let mut restore = |path: &mut PathBuf| {
+        if path.as_os_str() == STANDARD_STREAM_PLACEHOLDER {
+            *path = PathBuf::from("-");
+        } else if let Some(i) = path.to_str().and_then(|path| path.strip_prefix(NON_UTF8_PLACEHOLDER)).and_then(|rank| rank.parse::<usize>().ok()) {
+            *path = PathBuf::from(&non_utf8[i]);
+            restored[i] = true;
+        }
+    };
+    args.fichiers.iter_mut().for_each(&mut restore);
+    match &mut args.mode {
+        Mode::GenereMasque(opts) => restore(&mut opts.sortie),
+        Mode::Info(opts) => restore(&mut opts.fichier),
+        _ => {}
+    }
+    if let Some(stats) = &mut args.stats {
+        if stats.as_os_str() == STANDARD_STREAM_PLACEHOLDER {
+            *stats = PathBuf::from("-");
+        }
+    }
+    match restored.iter().position(|&restored| !restored) {
+        Some(i) => Err(EarlyExit { output: format!("argument invalide, qui n’est pas de l’UTF-8 : {}", non_utf8[i].to_string_lossy()), status: Err(()) }),
+        None => Ok(args),
+    }
+}
+
+// The options of each mode, or of the program for an empty name, that cannot
+// be used together, so that one given on the command line replaces any other
+// one from the configuration file
+const EXCLUSIVE_OPTIONS: [(&str, &[&str]); 6] = [
+    ("", &["verbose", "quiet"]),
+    ("seuil", &["valeur", "auto", "adaptatif", "hysteresis"]),
+    ("palette", &["couleurs", "noms", "fichier", "preset", "auto", "reference"]),
+    ("dithering", &["palette", "couleurs", "noms", "preset"]),
+    ("tramage", &["ordre", "matrice", "bruit-bleu", "halftone", "ign"]),
+    ("tramage", &["palette", "couleurs"]),
+];
+
+// How the completion scripts complete the value of `option` in `command`,
+// empty for the options that come before the mode
+fn value_hint(command: &str, option: &str) -> ValueHint {
+    match (command, option) {
+        ("", "--format") => ValueHint::Values(output_extensions(true)),
+        ("", "--bits") => ValueHint::Values(vec!["1", "8", "24"]),
+        ("", "--profil") => names(&PROFILE_HANDLINGS),
+        ("", "--sortie-dossier") => ValueHint::Directory,
+        ("", "--stats") => ValueHint::File,
+        ("seuil", "--auto") => names(&THRESHOLD_METHODS),
+        ("palette", "--auto") => names(&QUANTIZERS),
+        ("palette" | "dithering", "--preset") => names(&PRESETS),
+        ("palette", "--distance") => names(&DISTANCES),
+        ("palette", "--noms" | "--exclure") | ("dithering", "--noms") => names(&NAMED_COLOURS),
+        ("palette", "--fichier" | "--reference") | ("tramage", "--matrice") | ("genere-masque", "--sortie") => ValueHint::File,
+        ("dithering", "--algo") => names(&ALGOS),
+        _ => ValueHint::Any,
+    }
+}
+
+// Reports an invalid command line the way argh does
+fn argh_error(message: &str, name: &str) -> ! {
+    eprintln!("{}\nRun {} --help for more information.", message, name);
+    std::process::exit(EXIT_ARGUMENT)
+}
+
+fn write_mask(opts: &OptsGenereMasque, output: Output) -> Result<(), Error> {
+    let mut rng = opts.seed.map_or_else(Rng::from_entropy, Rng::new);
+    let ranks = void_and_cluster(opts.taille, opts.sigma, &mut rng);
+
+    if output.is_text() {
+        output.write_text(&ranks_to_text(&ranks, opts.taille))
+    } else {
+        output.write_image(&DynamicImage::ImageLuma8(ranks_to_image(&ranks, opts.taille)), None, None)
+    }
+}
+
+fn main() {
+    let (args, command_line) = parse_args();
+    logger::init(args.verbose, args.quiet);
+    if let Some(path) = &command_line.config {
+        log::debug!("options par défaut : {}", path.display());
+    }
+    // The pool is made once, before the first image, and kept under --watch
+    if let Some(threads) = args.threads {
+        if let Err(error) = rayon::ThreadPoolBuilder::new().num_threads(threads).build_global() {
+            log::warn!("--threads {} n’est pas appliqué : {}", threads, error);
+        }
+    }
+    let result = if args.watch { watch(args, &command_line) } else { run(args) };
+    if let Err(error) = result {
+        match error {
+            Error::InvalidArgument(_) => eprintln!("{}\n\nRun {} --help for more information.", error, command_line.name),
+            _ => eprintln!("{}", error),
+        }
+        std::process::exit(error.exit_code());
+    }
+}
+
+// Rejects the options of `mode` that cannot be used together
+fn check_mode(mode: &Mode, lineaire: bool) -> Result<(), Error> {
+    match mode {
+        Mode::Seuil(opts) if [opts.valeur.is_some(), opts.auto.is_some(), opts.adaptatif, opts.hysteresis.is_some()].iter().filter(|&&set| set).count() > 1 => {
+            Err(invalid_argument("--valeur, --auto, --adaptatif et --hysteresis ne peuvent pas être utilisés ensemble"))
+        }
+        Mode::Seuil(opts) if (opts.fenetre.is_some() || opts.biais.is_some()) && !opts.adaptatif => {
+            Err(invalid_argument("--fenetre et --biais n’ont de sens qu’avec --adaptatif"))
+        }
+        Mode::Niveaux(_) | Mode::Posterize(_) if lineaire => {
+            Err(invalid_argument("--lineaire n’est pas disponible avec niveaux et posterize"))
+        }
+        Mode::Dithering(opts) if opts.diviseur.is_some() && opts.noyau.is_none() => {
+            Err(invalid_argument("--diviseur n’a de sens qu’avec --noyau"))
+        }
+        Mode::Dithering(opts) if opts.seed.is_some() && opts.algo != Algo::Random => {
+            Err(invalid_argument("--seed n’a de sens qu’avec --algo aleatoire"))
+        }
+        Mode::Dithering(opts) if opts.force.is_some() && opts.algo == Algo::Random && opts.noyau.is_none() => {
+            Err(invalid_argument("--force n’a pas de sens avec --algo aleatoire, qui ne diffuse pas d’erreur"))
+        }
+        Mode::Dithering(opts) if opts.valeur.is_some() && (opts.palette_options() > 0 || (opts.algo == Algo::Random && opts.noyau.is_none())) => {
+            Err(invalid_argument("--valeur n’a pas de sens avec --palette, --couleurs, --noms, --preset ou --algo aleatoire"))
+        }
+        Mode::Dithering(opts) if opts.palette_options() > 1 => {
+            Err(invalid_argument("--palette, --couleurs, --noms et --preset ne peuvent pas être utilisés ensemble"))
+        }
+        Mode::Dithering(opts) if opts.palette_options() > 0 && opts.noyau.is_none() && !opts.algo.has_kernel() => {
+            Err(invalid_argument("--palette, --couleurs, --noms et --preset ne sont pas disponibles avec --algo riemersma ou aleatoire"))
+        }
+        Mode::Dithering(opts) if opts.palette_options() > 0 && (opts.couleur_claire.is_some() || opts.couleur_foncee.is_some()) => {
+            Err(invalid_argument("--couleur-claire et --couleur-foncee ne peuvent pas être combinés avec --palette, --couleurs, --noms ou --preset"))
+        }
+        Mode::Palette(opts) if opts.source_error().is_some() => {
+            Err(invalid_argument(opts.source_error().unwrap()))
+        }
+        Mode::Palette(opts) if (opts.iterations.is_some() || opts.seed.is_some()) && opts.auto != Some(Quantizer::KMeans) => {
+            Err(invalid_argument("--iterations et --seed n’ont de sens qu’avec --auto kmeans"))
+        }
+        Mode::Palette(opts) if opts.poids_hsv.is_some() && !matches!(opts.distance, Distance::Hsv(_)) => {
+            Err(invalid_argument("--poids-hsv n’a de sens qu’avec --distance hsv"))
+        }
+        Mode::Palette(opts) if opts.qualite.is_some() && opts.auto != Some(Quantizer::NeuQuant) => {
+            Err(invalid_argument("--qualite n’a de sens qu’avec --auto neuquant"))
+        }
+        Mode::Tramage(opts) if [opts.ordre.is_some(), opts.matrice.is_some(), opts.bruit_bleu, opts.halftone, opts.ign].iter().filter(|&&set| set).count() > 1 => {
+            Err(invalid_argument("--ordre, --matrice, --bruit-bleu, --halftone et --ign ne peuvent pas être utilisés ensemble"))
+        }
+        Mode::Tramage(opts) if opts.palette.is_some() && opts.couleurs.is_some() => {
+            Err(invalid_argument("--palette et --couleurs ne peuvent pas être utilisés ensemble"))
+        }
+        Mode::Tramage(opts) if opts.force.is_some() && opts.palette.is_none() && opts.couleurs.is_none() => {
+            Err(invalid_argument("--force n’a de sens qu’avec --palette ou --couleurs"))
+        }
+        Mode::Pipeline(opts) => opts.etapes.0.iter().enumerate().try_for_each(|(index, stage)| match stage {
+            Stage::Mode(mode) => check_mode(mode, lineaire).map_err(|error| Error::InvalidArgument(format!("étape {} : {}", index + 1, error))),
+            Stage::Resize(_) => Ok(()),
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Rejects with --tuiles the modes that need a whole image to decide each pixel
+fn check_tiles(mode: &Mode) -> Result<(), Error> {
+    match mode {
+        Mode::Seuil(opts) if opts.adaptatif || opts.hysteresis.is_some() => {
+            Err(invalid_argument("--tuiles n’est pas disponible avec --adaptatif et --hysteresis, qui regardent autour de chaque pixel"))
+        }
+        Mode::Dithering(opts) if opts.algo == Algo::Riemersma && opts.noyau.is_none() => {
+            Err(invalid_argument("--tuiles n’est pas disponible avec --algo riemersma, qui parcourt l’image le long d’une courbe"))
+        }
+        Mode::Pipeline(opts) => opts.etapes.0.iter().enumerate().try_for_each(|(index, stage)| match stage {
+            Stage::Mode(mode) => check_tiles(mode).map_err(|error| Error::InvalidArgument(format!("étape {} : {}", index + 1, error))),
+            Stage::Resize(_) => Ok(()),
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Runs the command line again whenever the input, or a file the mode reads,
+// changes, until Ctrl-C. It is parsed again each time, since the palette and
+// matrix files are read while parsing
+fn watch(mut args: DitherArgs, command_line: &CommandLine) -> Result<(), Error> {
+    if matches!(args.mode, Mode::GenereMasque(_) | Mode::Palettes(_) | Mode::Info(_)) {
+        return Err(invalid_argument("--watch ne s’applique qu’aux modes qui convertissent une image"));
+    }
+    if args.sortie_dossier.is_some() || args.recursif {
+        return Err(invalid_argument("--watch ne suit qu’une image, il n’a pas de sens avec --sortie-dossier ou --recursif"));
+    }
+    let Some(input) = args.fichiers.first().filter(|input| !is_standard_stream(input)).cloned() else {
+        return Err(invalid_argument("--watch demande un fichier d’entrée, l’entrée standard ne peut pas être relue"));
+    };
+    if args.fichiers.get(1).is_some_and(|output| is_standard_stream(output)) {
+        return Err(invalid_argument("--watch n’a pas de sens quand la sortie est la sortie standard"));
+    }
+
+    let mut paths = vec![input.clone()];
+    let tokens = &command_line.tokens;
+    paths.extend(tokens.iter().zip(tokens.iter().skip(1))
+        .filter(|(option, _)| value_hint(args.mode.name(), option) == ValueHint::File)
+        .map(|(_, path)| PathBuf::from(path)));
+    let mut watcher = Watcher::new(paths);
+    for path in watcher.paths() {
+        log::debug!("surveillé : {}", path.display());
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = Arc::clone(&stop);
+    if let Err(error) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed)) {
+        log::warn!("Ctrl-C ne pourra pas attendre la fin de la conversion en cours : {}", error);
+    }
+
+    let timestamp = || chrono::Local::now().format("%H:%M:%S");
+    let mut first = true;
+    loop {
+        let start = Instant::now();
+        match run(args.clone()) {
+            Ok(()) => {
+                log::info!("[{}] {} converti en {:.1?}", timestamp(), input.display(), start.elapsed());
+                // The output is the one written just before
+                args.force = true;
             }
-            if y + 1 < height {
-                let neighbor = img.get_pixel_mut(x, y + 1);
-                neighbor[0] = (neighbor[0] as f64 + error[0] * 5.0 / 16.0) as u8;
-                neighbor[1] = (neighbor[1] as f64 + error[1] * 5.0 / 16.0) as u8;
-                neighbor[2] = (neighbor[2] as f64 + error[2] * 5.0 / 16.0) as u8;
+            // The command line itself is wrong, there is nothing to wait for
+            Err(error @ Error::InvalidArgument(_)) if first => return Err(error),
+            Err(error) => log::error!("[{}] {}", timestamp(), error),
+        }
+        first = false;
+
+        loop {
+            if !watcher.wait(&stop) {
+                return Ok(());
             }
-            if x + 1 < width && y + 1 < height {
-                let neighbor = img.get_pixel_mut(x + 1, y + 1);
-                neighbor[0] = (neighbor[0] as f64 + error[0] * 1.0 / 16.0) as u8;
-                neighbor[1] = (neighbor[1] as f64 + error[1] * 1.0 / 16.0) as u8;
-                neighbor[2] = (neighbor[2] as f64 + error[2] * 1.0 / 16.0) as u8;
+            match parse_tokens(&command_line.name, tokens, &command_line.non_utf8) {
+                Ok(new_args) => {
+                    args = DitherArgs { force: args.force, ..new_args };
+                    break;
+                }
+                Err(early_exit) => log::error!("[{}] {}", timestamp(), early_exit.output.trim_end()),
             }
         }
     }
-
-    Ok(img)
 }
 
-fn main() -> Result<(), ImageError>{
-    let args: DitherArgs = argh::from_env();
-    let path_in = args.input;
-    let path_out = args.output.unwrap_or_else(|| "out.png".to_string());
-    let mode = args.mode;
-    
-    let img = get_image(path_in)?;
+fn run(args: DitherArgs) -> Result<(), Error> {
+    let mode = &args.mode;
+    if args.verbose && args.quiet {
+        return Err(invalid_argument("--verbose et --quiet ne peuvent pas être utilisés ensemble"));
+    }
+
+    if let Mode::GenereMasque(opts) = mode {
+        if !args.fichiers.is_empty() {
+            return Err(invalid_argument("genere-masque ne prend pas de fichier d’entrée"));
+        }
+        if args.lineaire {
+            return Err(invalid_argument("--lineaire n’a pas de sens avec genere-masque"));
+        }
+        if args.egaliser {
+            return Err(invalid_argument("--egaliser n’a pas de sens avec genere-masque"));
+        }
+        if args.ignorer_exif || args.profil.is_some() || args.sortie_dossier.is_some() || args.recursif || args.apercu || args.stats.is_some() || args.streaming || args.tuiles.is_some() || args.max_pixels.is_some() || args.bits.is_some() {
+            return Err(invalid_argument("--ignorer-exif, --profil, --sortie-dossier, --recursif, --apercu, --stats, --streaming, --tuiles, --max-pixels et --bits n’ont pas de sens avec genere-masque"));
+        }
+        let output = Output::open_or_ask(&opts.sortie, args.format.as_deref(), true, args.force, confirmation(args.no_input).as_mut())?;
+        return write_mask(opts, output);
+    }
+
+    if let Mode::Palettes(opts) = mode {
+        let processing = [args.lineaire, args.egaliser, args.ignorer_exif, args.profil.is_some(), args.format.is_some(), args.force, args.no_input, args.sortie_dossier.is_some(), args.recursif, args.apercu, args.stats.is_some(), args.streaming, args.tuiles.is_some(), args.max_pixels.is_some(), args.bits.is_some()];
+        if !args.fichiers.is_empty() || processing.contains(&true) {
+            return Err(invalid_argument("palettes ne prend ni fichier ni option de traitement"));
+        }
+        if opts.couleur && opts.json {
+            return Err(invalid_argument("--couleur n’a pas de sens avec --json"));
+        }
+        let listing = if opts.json { palettes_json() } else { palettes_text(opts.couleur && io::stdout().is_terminal()) };
+        print!("{}", listing);
+        return Ok(());
+    }
+
+    check_mode(mode, args.lineaire)?;
+
+    log::debug!("mode : {:?}", mode);
+    if let Mode::Info(opts) = mode {
+        let processing = [args.egaliser, args.format.is_some(), args.force, args.no_input, args.sortie_dossier.is_some(), args.recursif, args.apercu, args.apercu_largeur.is_some(), args.stats.is_some(), args.streaming, args.tuiles.is_some(), args.bits.is_some()];
+        if !args.fichiers.is_empty() || processing.contains(&true) {
+            return Err(invalid_argument("info ne prend que son fichier, et parmi les options de traitement --lineaire, --ignorer-exif, --profil et --max-pixels"));
+        }
+        // The type is the one of the file, not of its conversion from a profile
+        let (img, data, format) = read_image(&opts.fichier, args.ignorer_exif, args.max_pixels.unwrap_or(DEFAULT_MAX_PIXELS))?;
+        let colour_type = img.color();
+        let img = apply_profile(img, &data, format, args.profil.unwrap_or(ProfileHandling::Convert), &opts.fichier);
+        let info = ImageInfo::new(&img, colour_type, args.lineaire);
+        let name = opts.fichier.display().to_string();
+        print!("{}", if opts.json { info.to_json(&name) } else { info.to_text(&name) });
+        return Ok(());
+    }
 
     match mode {
-        Mode::Seuil(_) => {
-            let image = modify_image_seuil(img)?;
-            image.save(path_out)?;
+        Mode::Dithering(opts) if args.streaming && opts.algo == Algo::Riemersma && opts.noyau.is_none() => {
+            return Err(invalid_argument("--streaming n’est pas disponible avec --algo riemersma, qui ne parcourt pas l’image ligne par ligne"));
+        }
+        Mode::Dithering(_) | Mode::Tramage(_) => {}
+        _ if args.streaming => return Err(invalid_argument("--streaming n’est disponible qu’avec dithering et tramage")),
+        _ => {}
+    }
+    if args.streaming && args.tuiles.is_some() {
+        return Err(invalid_argument("--streaming et --tuiles ne peuvent pas être utilisés ensemble"));
+    }
+    if args.tuiles.is_some() {
+        check_tiles(mode)?;
+    }
+    if args.streaming && (args.egaliser || args.apercu || args.stats.is_some()) {
+        return Err(invalid_argument("--egaliser, --apercu et --stats demandent l’image entière, ils n’ont pas de sens avec --streaming"));
+    }
+    if args.apercu_largeur.is_some() && !args.apercu {
+        return Err(invalid_argument("--apercu-largeur n’a de sens qu’avec --apercu"));
+    }
+    if args.apercu && args.sortie_dossier.is_some() {
+        return Err(invalid_argument("--apercu n’affiche qu’une image, il n’a pas de sens avec --sortie-dossier"));
+    }
+    if args.apercu && args.fichiers.get(1).is_some_and(|output| is_standard_stream(output)) {
+        return Err(invalid_argument("--apercu n’a pas de sens quand la sortie est la sortie standard"));
+    }
+    let stats_to_stdout = args.stats.as_deref().is_some_and(is_standard_stream);
+    if stats_to_stdout && (args.apercu || args.fichiers.get(1).is_some_and(|output| is_standard_stream(output))) {
+        return Err(invalid_argument("--stats - écrit sur la sortie standard, qui ne peut pas aussi recevoir l’image ou --apercu"));
+    }
+    // Shown in the terminal only, without an output
+    if let ([input], true, false) = (args.fichiers.as_slice(), args.apercu, args.recursif) {
+        let stats = process(&args, input, None, true)?;
+        return write_stats(&args, stats.as_slice(), false);
+    }
+
+    let (jobs, skipped) = jobs(&args)?;
+    if let ([(path_in, path_out)], false) = (jobs.as_slice(), args.recursif) {
+        let stats = process(&args, path_in, Some(path_out), true)?;
+        return write_stats(&args, stats.as_slice(), args.sortie_dossier.is_some());
+    }
+    // One file that fails does not stop the others. The bar counts the files,
+    // it is left out under --verbose, which names each one
+    let progress = Progress::new("fichiers", jobs.len() as u64, args.quiet || args.verbose);
+    let mut failed = 0;
+    let mut stats = Vec::new();
+    for (done, (path_in, path_out)) in jobs.iter().enumerate() {
+        progress.update(done as u64);
+        log::debug!("{} → {}", path_in.display(), path_out.display());
+        // The directories of the tree reproduced by --recursif are made as needed
+        let parent = path_out.parent().filter(|_| args.recursif);
+        let created = parent.map_or(Ok(()), |parent| fs::create_dir_all(parent).map_err(|error| Error::writing(parent.to_path_buf(), error)));
+        match created.and_then(|()| process(&args, path_in, Some(path_out), false)) {
+            Ok(run_stats) => stats.extend(run_stats),
+            Err(error) => {
+                progress.clear();
+                // The other errors name the file already
+                match error {
+                    Error::ProcessingFailed(_) => log::error!("{} : {}", path_in.display(), error),
+                    _ => log::error!("{}", error),
+                }
+                failed += 1;
+            }
+        }
+    }
+    progress.clear();
+    write_stats(&args, &stats, true)?;
+    // Only the walk of --recursif skips files
+    let skipped = if args.recursif { format!(", fichiers ignorés : {}", skipped) } else { String::new() };
+    log::info!("images traitées : {}{}, échecs : {}", jobs.len() - failed, skipped, failed);
+    match failed {
+        0 => Ok(()),
+        failed => Err(Error::SomeFilesFailed { failed, total: jobs.len() }),
+    }
+}
+
+// The input and output of each file to process: the input then the optional
+// output, or only inputs written into `--sortie-dossier`, with the images
+// found in directories under --recursif; also the number of files skipped
+// there for not being images
+fn jobs(args: &DitherArgs) -> Result<(Vec<(PathBuf, PathBuf)>, usize), Error> {
+    let (fichiers, mode) = (&args.fichiers, &args.mode);
+    let Some(directory) = args.sortie_dossier.as_deref() else {
+        let jobs = match fichiers.as_slice() {
+            _ if args.recursif => return Err(invalid_argument("--recursif demande --sortie-dossier, où écrire les sorties")),
+            [input] if is_standard_stream(input) => {
+                return Err(invalid_argument("le fichier de sortie est obligatoire quand l’entrée est lue sur l’entrée standard"));
+            }
+            [input] => vec![(input.clone(), input.with_file_name(default_output_name(input, mode)))],
+            [input, output] => vec![(input.clone(), output.clone())],
+            [] => return Err(invalid_argument("le fichier d’entrée est obligatoire")),
+            _ => return Err(invalid_argument("trop de fichiers : seuls l’entrée et la sortie sont attendues, utilisez --sortie-dossier pour en traiter plusieurs")),
+        };
+        return Ok((jobs, 0));
+    };
+
+    if fichiers.is_empty() {
+        return Err(invalid_argument("au moins un fichier d’entrée est obligatoire"));
+    }
+    if fichiers.iter().any(|input| is_standard_stream(input)) {
+        return Err(invalid_argument("l’entrée standard n’a pas de nom d’où tirer celui de la sortie, - n’est pas accepté avec --sortie-dossier"));
+    }
+    if args.recursif {
+        fs::create_dir_all(directory).map_err(|error| Error::writing(directory.to_path_buf(), error))?;
+    } else if let Some(input) = fichiers.iter().find(|input| input.is_dir()) {
+        return Err(Error::InvalidArgument(format!("{} est un dossier, utilisez --recursif pour traiter les images qu’il contient", input.display())));
+    }
+    if !directory.is_dir() {
+        return Err(Error::writing(directory.to_path_buf(), "le dossier n’existe pas"));
+    }
+
+    // Each input with the directory its output goes in
+    let mut inputs: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut skipped = 0;
+    for input in fichiers {
+        if input.is_dir() {
+            let found = walk(input, Some(directory))?;
+            skipped += found.skipped;
+            for image in found.images {
+                let relative = image.strip_prefix(input).ok().and_then(Path::parent).unwrap_or(Path::new(""));
+                inputs.push((image.clone(), directory.join(relative)));
+            }
+        } else {
+            inputs.push((input.clone(), directory.to_path_buf()));
+        }
+    }
+
+    let mut jobs: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let mut inputs_by_output: HashMap<PathBuf, PathBuf> = HashMap::new();
+    for (input, output_directory) in inputs {
+        let output = output_directory.join(default_output_name(&input, mode));
+        // photo.png and photo.jpg would both be written to photo_seuil.png
+        if let Some(other) = inputs_by_output.insert(output.clone(), input.clone()) {
+            return Err(Error::InvalidArgument(format!("{} et {} donneraient tous deux {}", other.display(), input.display(), output.display())));
+        }
+        jobs.push((input, output));
+    }
+    Ok((jobs, skipped))
+}
+
+// Runs the mode on one input, which has been checked with the options
+// already, then writes the result to `path_out` and with --apercu shows it;
+// dithering shows the rows done with `show_rows`. The statistics are only
+// gathered for --stats
+fn process(args: &DitherArgs, path_in: &Path, path_out: Option<&Path>, show_rows: bool) -> Result<Option<RunStats>, Error> {
+    let output = path_out.map(|path| Output::open_or_ask(path, args.format.as_deref(), false, args.force, confirmation(args.no_input).as_mut())).transpose()?;
+    if let Some(output) = &output {
+        output.check_bits(args.bits)?;
+    }
+    let output = match (args.streaming, output) {
+        (true, Some(output)) => return process_streaming(args, path_in, output, show_rows).map(|()| None),
+        (_, output) => output,
+    };
+    let start = Instant::now();
+    let input = get_image(path_in, args.ignorer_exif, args.profil.unwrap_or(ProfileHandling::Convert), args.max_pixels.unwrap_or(DEFAULT_MAX_PIXELS))?;
+    log::debug!("{} : {} × {}, {:?}", path_in.display(), input.width(), input.height(), input.color());
+    let reading = start.elapsed();
+    log::debug!("lecture : {:.1?}", reading);
+
+    // The error is measured against the input as the mode receives it
+    let original = args.stats.is_some().then(|| input.to_rgb8());
+    let start = Instant::now();
+    let progress = Progress::new("lignes", input.height() as u64, args.quiet || !show_rows);
+    let (image, palette) = modify(args, &args.mode, input, output.as_ref().and_then(Output::format), &progress)?;
+    progress.clear();
+    let processing = start.elapsed();
+    log::debug!("traitement : {:.1?}", processing);
+
+    let writing = match output {
+        Some(output) => {
+            let start = Instant::now();
+            output.write_image(&image, args.bits, palette.as_ref())?;
+            let writing = start.elapsed();
+            log::debug!("écriture : {:.1?}", writing);
+            Some(writing)
+        }
+        None => None,
+    };
+    if args.apercu {
+        let width = args.apercu_largeur.unwrap_or_else(terminal_width);
+        print!("{}", render_preview(&image, width, supports_truecolor()));
+    }
+
+    Ok(original.map(|original| {
+        let result = image.to_rgb8();
+        RunStats {
+            input: path_in.display().to_string(),
+            output: path_out.map(|path| path.display().to_string()),
+            width: image.width(),
+            height: image.height(),
+            mode: args.mode.name().to_string(),
+            arguments: std::env::args().skip(1).collect(),
+            durations: StageDurations {
+                lecture: reading.as_secs_f64(),
+                traitement: processing.as_secs_f64(),
+                ecriture: writing.map(|writing| writing.as_secs_f64()),
+            },
+            colours: colour_counts(&result),
+            mean_error: mean_error(&original, &result),
+        }
+    }))
+}
+
+// Runs the mode of --streaming, dithering or tramage, on the PNG `path_in`
+// row by row into `output`, a PNG too
+fn process_streaming(args: &DitherArgs, path_in: &Path, output: Output, show_rows: bool) -> Result<(), Error> {
+    if output.format() != Some(ImageFormat::Png) {
+        return Err(invalid_argument("--streaming n’écrit que des PNG"));
+    }
+    let start = Instant::now();
+    let options = StreamOptions {
+        bilevel: false,
+        bits: args.bits,
+        ignore_exif: args.ignorer_exif,
+        profile: args.profil.unwrap_or(ProfileHandling::Convert),
+        quiet: args.quiet || !show_rows,
+    };
+    let result = match &args.mode {
+        Mode::Dithering(opts) => {
+            stream_png(path_in, output, &opts.dither(args.lineaire), &StreamOptions { bilevel: opts.is_monochrome(), ..options })
+        }
+        Mode::Tramage(opts) => stream_png(path_in, output, &opts.dither(args.lineaire), &StreamOptions { bilevel: opts.is_monochrome(), ..options }),
+        _ => unreachable!("--streaming is checked against the mode"),
+    };
+    log::debug!("traitement : {:.1?}", start.elapsed());
+    result
+}
+
+// Writes the statistics of the inputs processed to the file of --stats, if any
+fn write_stats(args: &DitherArgs, stats: &[RunStats], batch: bool) -> Result<(), Error> {
+    let Some(path) = args.stats.as_deref() else {
+        return Ok(());
+    };
+    let json = stats_json(stats, batch);
+    if is_standard_stream(path) {
+        print!("{}", json);
+        Ok(())
+    } else {
+        fs::write(path, json).map_err(|error| Error::writing(path.to_path_buf(), error))
+    }
+}
+
+// `img` processed by `f` in the tiles of --tuiles, or as a single tile
+fn by_tiles(mut img: RgbImage, side: Option<u32>, mut f: impl FnMut(Tile, RgbImage) -> Result<RgbImage, Error>) -> Result<RgbImage, Error> {
+    match side {
+        Some(side) => {
+            map_tiles(&mut img, side, f)?;
+            Ok(img)
+        }
+        None => f(Tile { x: 0, y: 0, width: img.width(), height: img.height() }, img),
+    }
+}
+
+// The result of `mode` on `input`, with its alpha plane put back if `format`
+// can store it, and the palette of palette mode, which an indexed PNG takes
+fn modify(args: &DitherArgs, mode: &Mode, input: DynamicImage, format: Option<ImageFormat>, progress: &Progress) -> Result<(DynamicImage, Option<Palette>), Error> {
+    if let Mode::Pipeline(opts) = mode {
+        let (mut image, mut palette) = (input, None);
+        // Only the first mode equalizes, the others get its result
+        let mut args = args.clone();
+        for stage in &opts.etapes.0 {
+            // Resizing blends the colours of a palette
+            (image, palette) = match stage {
+                Stage::Resize(size) => (size.resize(&image), None),
+                Stage::Mode(mode) => {
+                    log::debug!("étape : {:?}", mode);
+                    let result = modify(&args, mode, image, format, progress)?;
+                    args.egaliser = false;
+                    result
+                }
+            };
+        }
+        return Ok((image, palette));
+    }
+
+    // Grayscale inputs turned black and white skip the conversion to RGB
+    let input = match input {
+        DynamicImage::ImageLuma8(gray) if !args.egaliser => match modify_gray(gray, mode, args.lineaire, progress)? {
+            Ok(image) => return Ok((DynamicImage::ImageLuma8(image), None)),
+            Err(gray) => DynamicImage::ImageLuma8(gray),
+        },
+        input => input,
+    };
+    // Dithering starts from the full precision of 16-bit inputs, unless they
+    // were equalized on 8 bits first. With --tuiles, it is taken tile by tile
+    // from the input, kept for that
+    let color = input.color();
+    let wide = matches!(mode, Mode::Dithering(_)) && color.bytes_per_pixel() > color.channel_count() && !args.egaliser;
+    let precise = (wide && args.tuiles.is_none()).then(|| input.to_rgb32f());
+    let (mut img, alpha) = split_alpha(&input);
+    let wide_input = (wide && args.tuiles.is_some()).then_some(input);
+    if args.egaliser {
+        img = equalize_luma(img);
+    }
+
+    let mut mapped = None;
+    let image = match mode {
+        Mode::Seuil(opts) => {
+            let (light, dark) = (opts.couleur_claire.unwrap_or(WHITE), opts.couleur_foncee.unwrap_or(BLACK));
+            if opts.adaptatif {
+                let window = opts.fenetre.unwrap_or(DEFAULT_WINDOW);
+                modify_image_seuil_adaptatif(img, window, opts.biais.unwrap_or(DEFAULT_BIAS), light, dark, args.lineaire)?
+            } else if let Some((low, high)) = opts.hysteresis {
+                modify_image_seuil_hysteresis(img, low, high, light, dark, args.lineaire)?
+            } else {
+                let threshold = match opts.auto {
+                    Some(method) => method.threshold(&img, args.lineaire),
+                    None => opts.valeur.unwrap_or(DEFAULT_THRESHOLD),
+                };
+                log::debug!("seuil : {}", threshold);
+                by_tiles(img, args.tuiles, |_, tile| Ok(modify_image_seuil(tile, threshold, light, dark, args.lineaire)))?
+            }
+        }
+        Mode::SeuilRgb(opts) => {
+            let thresholds = opts.valeurs.unwrap_or([DEFAULT_THRESHOLD; 3]);
+            by_tiles(img, args.tuiles, |_, tile| Ok(modify_image_seuil_rgb(tile, thresholds, args.lineaire)))?
         }
+        Mode::Niveaux(opts) => by_tiles(img, args.tuiles, |_, tile| Ok(modify_image_niveaux(tile, opts.n_niveaux)?))?,
+        Mode::Posterize(opts) => by_tiles(img, args.tuiles, |_, tile| Ok(modify_image_posterize(tile, opts.niveaux)?))?,
         Mode::Palette(opts) => {
-            let image = modify_image_palette(img, opts.n_couleurs)?;
-            image.save(path_out)?;
+            let palette = match opts.couleurs.clone().or_else(|| opts.noms.clone()).or_else(|| opts.fichier.clone()).or_else(|| opts.preset.clone()) {
+                Some(palette) => palette,
+                None if opts.auto.is_some() || opts.reference.is_some() => {
+                    let reference = match &opts.reference {
+                        Some(path) => split_alpha(&get_image(path, args.ignorer_exif, args.profil.unwrap_or(ProfileHandling::Convert), args.max_pixels.unwrap_or(DEFAULT_MAX_PIXELS))?).0,
+                        None => img.clone(),
+                    };
+                    let options = QuantizeOptions {
+                        iterations: opts.iterations.unwrap_or(DEFAULT_ITERATIONS),
+                        seed: opts.seed.unwrap_or_else(|| Rng::from_entropy().next_u64()),
+                        quality: opts.qualite.unwrap_or(DEFAULT_QUALITY),
+                    };
+                    let quantizer = opts.auto.unwrap_or(Quantizer::MedianCut);
+                    quantizer.palette(&reference, opts.n_couleurs.unwrap_or(DEFAULT_AUTO_COLOURS), &options)
+                }
+                None => match opts.n_couleurs {
+                    Some(count) => builtin_prefix("--n-couleurs", count, opts.exclure.as_ref().map_or(&[], Palette::colours)),
+                    None => opts.builtin(),
+                },
+            };
+            log::debug!("palette : {}", palette);
+            let distance = match opts.poids_hsv {
+                Some(weights) => Distance::Hsv(weights),
+                None => opts.distance,
+            };
+            let image = match args.tuiles {
+                // The matcher of large palettes is built once for all the tiles
+                Some(side) if !palette.is_empty() => {
+                    let matcher = palette.matcher(distance, args.lineaire);
+                    by_tiles(img, Some(side), |_, tile| Ok(modify_image_palette_matcher(tile, &matcher)))?
+                }
+                _ => modify_image_palette(img, &palette, distance, args.lineaire)?,
+            };
+            mapped = Some(palette);
+            image
         }
-        Mode::Dithering(_) => {
-            let image = modify_image_dithering(img)?;
-            image.save(path_out)?;
+        Mode::Dithering(opts) => {
+            let image = match args.tuiles {
+                Some(side) => {
+                    let dither = opts.dither(args.lineaire);
+                    let (width, height) = img.dimensions();
+                    let mut tiler = dither.tiler(width, height, side)?;
+                    by_tiles(img, Some(side), |tile, part| {
+                        let alpha = alpha.as_ref().map(|alpha| alpha.view(tile.x, tile.y, tile.width, tile.height).to_image());
+                        let precise = wide_input.as_ref().map(|input| input.crop_imm(tile.x, tile.y, tile.width, tile.height).to_rgb32f());
+                        let part = tiler.push_tile(part, alpha.as_ref(), precise.as_ref());
+                        if tile.x + tile.width == width {
+                            progress.update((tile.y + tile.height) as u64);
+                        }
+                        Ok(part)
+                    })?
+                }
+                None => {
+                    let mut dither = opts.dither(args.lineaire).progress(progress);
+                    if let Some(alpha) = &alpha {
+                        dither = dither.alpha(alpha);
+                    }
+                    if let Some(precise) = &precise {
+                        dither = dither.full_precision(precise);
+                    }
+                    dither.apply(&img)?
+                }
+            };
+            image
         }
+        Mode::Tramage(opts) => match args.tuiles {
+            Some(side) => {
+                let dither = opts.dither(args.lineaire);
+                let mut tiler = dither.tiler(img.width(), img.height(), side)?;
+                by_tiles(img, Some(side), |_, tile| Ok(tiler.push_tile(tile, None, None)))?
+            }
+            None => opts.dither(args.lineaire).apply(&img)?,
+        },
+        Mode::GenereMasque(_) | Mode::Palettes(_) | Mode::Info(_) | Mode::Pipeline(_) => unreachable!(),
+    };
+    Ok((merge_alpha(image, alpha.as_ref(), format), mapped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(tokens: &[&str]) -> DitherArgs {
+        parse_tokens("tp_eval", &tokens.iter().map(|token| token.to_string()).collect::<Vec<String>>(), &[]).unwrap()
     }
 
-    Ok(())
+    #[test]
+    fn dithering_takes_the_colours_of_its_palette_by_name() {
+        let args = parse(&["in.png", "out.png", "dithering", "--noms", "noir,blanc,rouge,noir"]);
+        let Mode::Dithering(opts) = &args.mode else { panic!("{:?}", args.mode) };
+        assert_eq!(opts.noms, Some(Palette::new(vec![BLACK, WHITE, tp_eval::RED])));
+        assert!(!opts.is_monochrome());
+        assert!(check_mode(&args.mode, false).is_ok());
+
+        for other in [["--couleurs", "#ff0000"], ["--palette", "3"], ["--preset", "cga"], ["--couleur-claire", "rouge"], ["--algo", "riemersma"]] {
+            let args = parse(&["in.png", "out.png", "dithering", "--noms", "noir,blanc", other[0], other[1]]);
+            assert!(check_mode(&args.mode, false).is_err(), "{:?}", other);
+        }
+        assert!(parse_tokens("tp_eval", &["in.png", "out.png", "dithering", "--noms", "noir,ocre"].map(String::from), &[]).is_err());
+    }
+
+    #[test]
+    fn dithering_takes_a_preset_palette_on_its_own() {
+        let args = parse(&["in.png", "out.png", "dithering", "--preset", "gameboy"]);
+        let Mode::Dithering(opts) = &args.mode else { panic!("{:?}", args.mode) };
+        assert_eq!(opts.preset.as_ref().map(Palette::len), Some(4));
+        assert!(check_mode(&args.mode, false).is_ok());
+        assert!(opts.dither(false).apply(&RgbImage::from_pixel(4, 4, Rgb([0x30, 0x62, 0x30]))).unwrap().pixels().all(|pixel| *pixel == Rgb([0x30, 0x62, 0x30])));
+
+        for other in [["--palette", "3"], ["--couleurs", "#ff0000"], ["--noms", "noir"], ["--algo", "aleatoire"]] {
+            let args = parse(&["in.png", "out.png", "dithering", "--preset", "nes", other[0], other[1]]);
+            assert!(check_mode(&args.mode, false).is_err(), "{:?}", other);
+        }
+        assert!(parse_tokens("tp_eval", &["in.png", "out.png", "dithering", "--preset", "amiga"].map(String::from), &[]).is_err());
+    }
+
+    #[test]
+    fn the_bash_completion_script_parses() {
+        use std::io::Write;
+        use std::process::{Command, Stdio};
+
+        let script = completion_script(Shell::Bash, "tp_eval", &DitherArgs::get_args_info(), value_hint);
+        for word in ["seuil", "palette", "dithering", "tramage", "--algo", "floyd-steinberg", "--preset"] {
+            assert!(script.contains(word), "{} absent du script", word);
+        }
+        let mut bash = Command::new("bash").arg("-n").stdin(Stdio::piped()).stderr(Stdio::piped()).spawn().expect("bash");
+        bash.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+        let output = bash.wait_with_output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_path_that_is_not_utf8_is_kept_as_it_is() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let input = OsString::from_vec(b"photo_\xe9t\xe9.png".to_vec());
+        let (tokens, non_utf8) = utf8_tokens([input.clone(), OsString::from("-"), OsString::from("seuil")]);
+        let args = parse_tokens("tp_eval", &tokens, &non_utf8).unwrap();
+        assert_eq!(args.fichiers, [PathBuf::from(&input), PathBuf::from("-")]);
+        assert_eq!(default_output_name(&args.fichiers[0], &args.mode), OsString::from_vec(b"photo_\xe9t\xe9_seuil.png".to_vec()));
+
+        // Options and their values stay UTF-8
+        for tokens in [[OsString::from("in.png"), OsString::from("--stats"), input.clone(), OsString::from("seuil")], [OsString::from("in.png"), OsString::from("seuil"), OsString::from("--valeur"), input.clone()]] {
+            let (tokens, non_utf8) = utf8_tokens(tokens);
+            let error = parse_tokens("tp_eval", &tokens, &non_utf8).unwrap_err();
+            assert!(!error.output.contains('\0') && error.output.contains("photo_\u{fffd}t\u{fffd}.png"), "{}", error.output);
+        }
+    }
+
+    #[test]
+    fn max_pixels_limits_the_decoders() {
+        let png = |width, height| {
+            let mut png = Cursor::new(Vec::new());
+            DynamicImage::ImageRgb8(RgbImage::new(width, height)).write_to(&mut png, ImageFormat::Png).unwrap();
+            png.into_inner()
+        };
+        let decode = |data: &[u8], max_pixels| {
+            let mut reader = image::io::Reader::with_format(Cursor::new(data), ImageFormat::Png);
+            reader.limits(decoding_limits(max_pixels));
+            reader.decode()
+        };
+        assert!(decode(&png(8, 8), 64).is_ok());
+        let error = decode(&png(65, 1), 64).unwrap_err();
+        assert!(matches!(&error, ImageError::Limits(limit) if limit.kind() == LimitErrorKind::DimensionError), "{:?}", error);
+        assert!(decode(&png(64, 1), 64).is_ok());
+        // No limit past what a side can measure
+        assert_eq!(decoding_limits(u64::MAX).max_image_width, Some(u32::MAX));
+
+        let path = std::env::temp_dir().join(format!("tp_eval_max_pixels_{}.png", std::process::id()));
+        fs::write(&path, png(8, 8)).unwrap();
+        let too_large = read_image(&path, true, 63).map(|_| ());
+        let fits = read_image(&path, true, 64).map(|(img, _, _)| img.dimensions());
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(too_large, Err(Error::TooLarge { width: 8, height: 8, max_pixels: 63, .. })), "{:?}", too_large);
+        assert_eq!(fits.unwrap(), (8, 8));
+    }
+
+    #[test]
+    fn a_failing_run_leaves_the_output_it_would_replace() {
+        let dir = std::env::temp_dir().join(format!("tp_eval_echec_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (bad, same, kept) = (dir.join("abimee.png"), dir.join("meme.png"), dir.join("gardee.png"));
+        fs::write(&bad, b"pas une image").unwrap();
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::from_fn(8, 8, |x, y| Rgb([(x * 32) as u8, (y * 32) as u8, 128]))).write_to(&mut png, ImageFormat::Png).unwrap();
+        let png = png.into_inner();
+        fs::write(&same, &png).unwrap();
+        fs::write(&kept, &png).unwrap();
+
+        // As --watch runs again once the output is written
+        let args = |input: &Path, output: &Path| parse(&["--force", input.to_str().unwrap(), output.to_str().unwrap(), "seuil"]);
+        let failed = process(&args(&bad, &kept), &bad, Some(&kept), false).map(|_| ());
+        let kept_bytes = fs::read(&kept).unwrap();
+        // Read whole before it is replaced
+        let in_place = process(&args(&same, &same), &same, Some(&same), false).map(|_| ());
+        let seuil = image::open(&same).map(|img| img.to_rgb8());
+        let entries = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(failed, Err(Error::DecodeFailed(..))), "{:?}", failed);
+        assert_eq!(kept_bytes, png);
+        assert!(in_place.is_ok(), "{:?}", in_place);
+        assert!(seuil.unwrap().pixels().all(|pixel| *pixel == BLACK || *pixel == WHITE));
+        assert_eq!(entries, 3);
+    }
 }
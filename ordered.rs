@@ -0,0 +1,371 @@
+//! Ordered dithering: every pixel is compared against a threshold that only
+//! depends on its position, so no error is carried from one pixel to the next.
+
+use image::RgbImage;
+
+use crate::blue_noise::void_and_cluster;
+use crate::dither_error::DitherError;
+use crate::ditherer::{BlackAndWhite, Ditherer, PixelQuantizer};
+use crate::distance::Distance;
+use crate::palette::Palette;
+use crate::parallel::map_pixels;
+use crate::random::{position_noise, Rng};
+use crate::threshold::DEFAULT_THRESHOLD;
+
+/// Largest accepted order for Bayer matrices (32×32).
+pub const MAX_BAYER_ORDER: u32 = 5;
+
+/// Order of the Bayer matrix used when no other matrix is given (8×8).
+pub const DEFAULT_BAYER_ORDER: u32 = 3;
+
+/// Default amplitude, in channel levels, of the perturbation added before
+/// snapping to a palette.
+pub const DEFAULT_FORCE: f32 = 64.0;
+
+// Parameters of the built-in blue-noise mask; the fixed seed makes it the same
+// on every run
+const BLUE_NOISE_SIDE: usize = 64;
+const BLUE_NOISE_SIGMA: f64 = 1.5;
+const BLUE_NOISE_SEED: u64 = 0x5eed;
+
+// Clustered-dot halftone cell: the highest thresholds sit in the centre, so
+// black dots grow outwards from it as the image gets darker
+const CLUSTERED_DOT: [[u32; 8]; 8] = [
+    [ 3, 10, 18, 29, 28, 17,  9,  2],
+    [11, 30, 38, 46, 45, 37, 27,  8],
+    [19, 39, 51, 58, 57, 50, 36, 16],
+    [31, 47, 59, 63, 62, 56, 44, 26],
+    [20, 40, 52, 60, 61, 55, 43, 25],
+    [12, 32, 48, 53, 54, 49, 35, 15],
+    [ 4, 21, 33, 41, 42, 34, 24,  7],
+    [ 0,  5, 13, 22, 23, 14,  6,  1],
+];
+
+/// Where the per-pixel thresholds of ordered dithering come from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThresholdSource {
+    /// A matrix tiled over the image
+    Matrix(ThresholdMatrix),
+    /// Jimenez's interleaved gradient noise, computed from the coordinates
+    InterleavedGradientNoise,
+    /// Uniform white noise drawn from the seed and the coordinates
+    Random(u64),
+}
+
+impl ThresholdSource {
+    pub(crate) fn threshold(&self, x: u32, y: u32) -> f32 {
+        match self {
+            ThresholdSource::Matrix(matrix) => matrix.threshold(x, y),
+            ThresholdSource::InterleavedGradientNoise => interleaved_gradient_noise(x, y),
+            ThresholdSource::Random(seed) => position_noise(*seed, x, y),
+        }
+    }
+}
+
+// Computed in f32 only, so that every platform gives the same thresholds
+fn interleaved_gradient_noise(x: u32, y: u32) -> f32 {
+    let fract = |v: f32| v - v.floor();
+    fract(52.982_918 * fract(0.067_110_56 * x as f32 + 0.005_837_15 * y as f32))
+}
+
+/// A threshold matrix tiled over the image, with values in 0..1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThresholdMatrix {
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+}
+
+impl ThresholdMatrix {
+    /// The 2^order × 2^order Bayer matrix, built recursively from the 2×2 one.
+    pub fn bayer(order: u32) -> ThresholdMatrix {
+        let mut indices = vec![0u32];
+        let mut side = 1;
+        for _ in 0..order {
+            let next_side = side * 2;
+            let mut next = vec![0; next_side * next_side];
+            for y in 0..side {
+                for x in 0..side {
+                    let m = 4 * indices[y * side + x];
+                    next[y * next_side + x] = m;
+                    next[y * next_side + x + side] = m + 2;
+                    next[(y + side) * next_side + x] = m + 3;
+                    next[(y + side) * next_side + x + side] = m + 1;
+                }
+            }
+            indices = next;
+            side = next_side;
+        }
+
+        ThresholdMatrix::from_levels(side, side, &indices, (side * side) as u32)
+    }
+
+    /// A 64×64 blue-noise mask, which tiles seamlessly and avoids the
+    /// cross-hatch structure of Bayer matrices.
+    pub fn blue_noise() -> ThresholdMatrix {
+        let ranks = void_and_cluster(BLUE_NOISE_SIDE, BLUE_NOISE_SIGMA, &mut Rng::new(BLUE_NOISE_SEED));
+        let count = ranks.len() as u32;
+        ThresholdMatrix::from_levels(BLUE_NOISE_SIDE, BLUE_NOISE_SIDE, &ranks, count)
+    }
+
+    /// The 8×8 clustered-dot matrix, rendering tones as dots of varying size
+    /// like a laser printer does.
+    pub fn clustered_dot() -> ThresholdMatrix {
+        let levels: Vec<u32> = CLUSTERED_DOT.iter().flatten().copied().collect();
+        ThresholdMatrix::from_levels(8, 8, &levels, 64)
+    }
+
+    // Centering each level in its interval keeps pure black and pure white intact
+    fn from_levels(width: usize, height: usize, levels: &[u32], count: u32) -> ThresholdMatrix {
+        let values = levels.iter().map(|&m| (m as f32 + 0.5) / count as f32).collect();
+        ThresholdMatrix { width, height, values }
+    }
+
+    /// Reads a matrix written as rows of non-negative integers, one row per
+    /// line; values are normalized by the largest one plus one.
+    #[cfg(feature = "fs")]
+    pub fn from_file(path: &str) -> Result<ThresholdMatrix, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("impossible de lire {} : {}", path, e))?;
+        ThresholdMatrix::from_text(&contents, path)
+    }
+
+    /// Parses the `contents` of a matrix file, as `from_file` reads it, the
+    /// messages naming it `path`.
+    pub fn from_text(contents: &str, path: &str) -> Result<ThresholdMatrix, String> {
+        let mut width = 0;
+        let mut height = 0;
+        let mut levels = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let row = line.split_whitespace()
+                .map(|token| token.parse::<u32>().map_err(|_| format!("{}, ligne {} : valeur invalide : {}", path, number + 1, token)))
+                .collect::<Result<Vec<u32>, String>>()?;
+            if row.is_empty() {
+                continue;
+            }
+            if height == 0 {
+                width = row.len();
+            } else if row.len() != width {
+                return Err(format!("{}, ligne {} : {} valeurs au lieu de {}", path, number + 1, row.len(), width));
+            }
+            levels.extend(row);
+            height += 1;
+        }
+
+        let max = levels.iter().max().ok_or_else(|| format!("{} ne contient aucune valeur", path))?;
+        let count = max.checked_add(1).ok_or_else(|| format!("{} : la valeur {} est trop grande", path, max))?;
+        Ok(ThresholdMatrix::from_levels(width, height, &levels, count))
+    }
+
+    fn threshold(&self, x: u32, y: u32) -> f32 {
+        let x = x as usize % self.width;
+        let y = y as usize % self.height;
+        self.values[y * self.width + x]
+    }
+}
+
+/// Parses the `--ordre` of a Bayer matrix.
+pub fn parse_bayer_order(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(order) if (1..=MAX_BAYER_ORDER).contains(&order) => Ok(order),
+        _ => Err(format!("l’ordre doit être un entier entre 1 et {}", MAX_BAYER_ORDER)),
+    }
+}
+
+/// Parses the `--force` of ordered dithering towards a palette.
+pub fn parse_force(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(force) if force >= 0.0 && force.is_finite() => Ok(force),
+        _ => Err(format!("force invalide : {} (attendu : un réel positif ou nul)", value)),
+    }
+}
+
+/// Ordered dithering: each pixel is quantized against the threshold at its
+/// place, with no error carried to its neighbours.
+pub struct Ordered<'a> {
+    source: &'a ThresholdSource,
+    force: f32,
+    linear: bool,
+}
+
+impl<'a> Ordered<'a> {
+    /// Thresholds from `source`, perturbing palette lookups by up to `force`
+    /// channel levels, in linear light with `linear`.
+    pub fn new(source: &'a ThresholdSource, force: f32, linear: bool) -> Ordered<'a> {
+        Ordered { source, force, linear }
+    }
+}
+
+impl Ditherer for Ordered<'_> {
+    // Each pixel depends only on its place, so the rows are shared between
+    // threads with the `parallel` feature
+    fn dither(&self, mut img: RgbImage, quantizer: &dyn PixelQuantizer) -> RgbImage {
+        map_pixels(&mut img, |x, y, pixel| quantizer.quantize_ordered(pixel, self.source.threshold(x, y), self.force, self.linear));
+        img
+    }
+}
+
+/// Random thresholding: ordered dithering against white noise drawn from the
+/// seed and the coordinates, the same for the same seed.
+pub struct Random {
+    seed: u64,
+    linear: bool,
+}
+
+impl Random {
+    /// Noise from `seed`, compared in linear light with `linear`.
+    pub fn new(seed: u64, linear: bool) -> Random {
+        Random { seed, linear }
+    }
+}
+
+impl Ditherer for Random {
+    fn dither(&self, img: RgbImage, quantizer: &dyn PixelQuantizer) -> RgbImage {
+        let source = ThresholdSource::Random(self.seed);
+        Ordered::new(&source, DEFAULT_FORCE, self.linear).dither(img, quantizer)
+    }
+}
+
+/// Each pixel turned black or white against the threshold at its place in
+/// the tiled matrix of `source`. With `linear`, the thresholds apply to the
+/// luminance in linear light.
+pub fn modify_image_tramage(img: RgbImage, source: &ThresholdSource, linear: bool) -> RgbImage {
+    Ordered::new(source, DEFAULT_FORCE, linear).dither(img, &BlackAndWhite::new(DEFAULT_THRESHOLD))
+}
+
+/// Ordered dithering towards a palette: each channel is shifted by the
+/// threshold, centred on zero and scaled by `force`, before the pixel is
+/// snapped to the nearest palette colour, in linear light with `linear`.
+/// `force` must be positive or zero.
+pub fn modify_image_tramage_palette(img: RgbImage, source: &ThresholdSource, palette: &Palette, force: f32, linear: bool) -> Result<RgbImage, DitherError> {
+    if palette.is_empty() {
+        return Err(DitherError::EmptyPalette);
+    }
+    check_force(force)?;
+    let matcher = palette.matcher(Distance::Rgb, linear);
+    Ok(Ordered::new(source, force, linear).dither(img, &matcher))
+}
+
+// The perturbation of ordered dithering to a palette is positive or zero, as
+// --force
+pub(crate) fn check_force(force: f32) -> Result<(), DitherError> {
+    if force >= 0.0 && force.is_finite() {
+        Ok(())
+    } else {
+        Err(DitherError::InvalidParameter(format!("force invalide : {} (attendu : un réel positif ou nul)", force)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+    use crate::{BLACK, WHITE};
+
+    #[test]
+    fn bayer_renders_mid_grey_as_a_checkerboard() {
+        let grey = RgbImage::from_pixel(16, 16, Rgb([128; 3]));
+        for order in 1..=3 {
+            let result = modify_image_tramage(grey.clone(), &ThresholdSource::Matrix(ThresholdMatrix::bayer(order)), false);
+            for (x, y, pixel) in result.enumerate_pixels() {
+                let expected = if (x + y) % 2 == 0 { WHITE } else { BLACK };
+                assert_eq!(*pixel, expected, "({}, {}) à l’ordre {}", x, y, order);
+            }
+        }
+        // 128 is a little over half of 255: from 16×16 on, the matrices have
+        // levels between the two, whitening a few more pixels per tile
+        for order in 4..=MAX_BAYER_ORDER {
+            let side = 1 << order;
+            let grey = RgbImage::from_pixel(side, side, Rgb([128; 3]));
+            let result = modify_image_tramage(grey, &ThresholdSource::Matrix(ThresholdMatrix::bayer(order)), false);
+            let levels = side * side;
+            let below = (0..levels).filter(|&level| (level as f32 + 0.5) / (levels as f32) < 128.0 / 255.0).count();
+            assert!(below > (levels / 2) as usize);
+            assert_eq!(result.pixels().filter(|pixel| **pixel == WHITE).count(), below, "ordre {}", order);
+        }
+    }
+
+    #[test]
+    fn the_order_of_bayer_matrices_goes_from_1_to_5() {
+        for order in 1..=5 {
+            assert_eq!(parse_bayer_order(&order.to_string()), Ok(order));
+        }
+        for value in ["0", "6", "-1", "trois", ""] {
+            assert!(parse_bayer_order(value).is_err(), "{:?}", value);
+        }
+    }
+
+    // The number of 4-connected groups of the pixels of `colour` in a square
+    // image, tiled over the plane
+    fn clusters(img: &RgbImage, colour: Rgb<u8>) -> usize {
+        let side = img.width();
+        let mut seen = vec![false; (side * side) as usize];
+        let mut count = 0;
+        for start in 0..side * side {
+            if seen[start as usize] || *img.get_pixel(start % side, start / side) != colour {
+                continue;
+            }
+            count += 1;
+            seen[start as usize] = true;
+            let mut stack = vec![start];
+            while let Some(index) = stack.pop() {
+                let (x, y) = (index % side, index / side);
+                for (nx, ny) in [((x + 1) % side, y), ((x + side - 1) % side, y), (x, (y + 1) % side), (x, (y + side - 1) % side)] {
+                    let next = ny * side + nx;
+                    if !seen[next as usize] && *img.get_pixel(nx, ny) == colour {
+                        seen[next as usize] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn halftone_dots_grow_as_single_clusters() {
+        let cell = |level: u8| modify_image_tramage(RgbImage::from_pixel(8, 8, Rgb([level; 3])), &ThresholdSource::Matrix(ThresholdMatrix::clustered_dot()), false);
+        let darkest = (0..=255).map(cell).find(|cell| cell.pixels().any(|pixel| *pixel == WHITE)).unwrap();
+        assert_eq!(clusters(&darkest, BLACK), 1);
+        assert_eq!(clusters(&darkest, WHITE), 1);
+
+        // Over a ramp, each cell holds one dot, which only grows as the grey darkens
+        let mut dot = 0;
+        for level in (0..=255).rev() {
+            let cell = cell(level);
+            let black = cell.pixels().filter(|pixel| **pixel == BLACK).count();
+            assert!(black >= dot, "niveau {}", level);
+            assert!(black == 0 || clusters(&cell, BLACK) == 1, "niveau {}", level);
+            dot = black;
+        }
+        assert_eq!(dot, 64);
+    }
+
+    #[test]
+    fn interleaved_gradient_noise_keeps_the_tone_of_bayer_without_its_tiles() {
+        let ramp = RgbImage::from_fn(256, 32, |x, _| Rgb([x as u8; 3]));
+        let ign = modify_image_tramage(ramp.clone(), &ThresholdSource::InterleavedGradientNoise, false);
+        let bayer = modify_image_tramage(ramp, &ThresholdSource::Matrix(ThresholdMatrix::bayer(DEFAULT_BAYER_ORDER)), false);
+        let white = |img: &RgbImage| img.pixels().filter(|pixel| **pixel == WHITE).count();
+        assert!(white(&ign).abs_diff(white(&bayer)) <= 256 * 32 / 100, "{} pixels blancs contre {} avec Bayer", white(&ign), white(&bayer));
+        assert!(ign.pixels().zip(bayer.pixels()).filter(|(a, b)| a != b).count() > 256 * 32 / 10);
+
+        // Down each column of the ramp, Bayer repeats every 8 rows, the noise
+        // does not
+        let shifted = |img: &RgbImage| (0..256).flat_map(|x| (0..24).map(move |y| (x, y))).filter(|&(x, y)| img.get_pixel(x, y) != img.get_pixel(x, y + 8)).count();
+        assert_eq!(shifted(&bayer), 0);
+        assert!(shifted(&ign) > 256 * 24 / 10);
+    }
+
+    #[test]
+    fn interleaved_gradient_noise_is_a_fixed_spread_of_thresholds() {
+        assert_eq!(interleaved_gradient_noise(0, 0), 0.0);
+        let thresholds: Vec<f32> = (0..64).flat_map(|y| (0..64).map(move |x| interleaved_gradient_noise(x, y))).collect();
+        assert_eq!(thresholds, (0..64).flat_map(|y| (0..64).map(move |x| ThresholdSource::InterleavedGradientNoise.threshold(x, y))).collect::<Vec<f32>>());
+        let mut bins = [0; 16];
+        for threshold in thresholds {
+            assert!((0.0..1.0).contains(&threshold));
+            bins[(threshold * 16.0) as usize] += 1;
+        }
+        assert!(bins.iter().all(|&count: &usize| count.abs_diff(256) <= 64), "{:?}", bins);
+    }
+}
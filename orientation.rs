@@ -0,0 +1,85 @@
+//! EXIF orientation: cameras store photos as shot and only tag how they
+//! should be turned for display.
+
+use std::io::Cursor;
+
+use exif::{Exif, In, Reader, Tag};
+use image::DynamicImage;
+
+/// The EXIF orientation of an encoded image, from 1 to 8, if it has a valid one.
+pub fn exif_orientation(data: &[u8]) -> Option<u32> {
+    orientation(&Reader::new().read_from_container(&mut Cursor::new(data)).ok()?)
+}
+
+/// The orientation of raw EXIF data, as a PNG holds it in its eXIf chunk.
+pub fn raw_exif_orientation(data: &[u8]) -> Option<u32> {
+    orientation(&Reader::new().read_raw(data.to_vec()).ok()?)
+}
+
+fn orientation(exif: &Exif) -> Option<u32> {
+    let orientation = exif.get_field(Tag::Orientation, In::PRIMARY)?.value.get_uint(0)?;
+    (1..=8).contains(&orientation).then_some(orientation)
+}
+
+/// Turns an image stored with the given EXIF orientation upright.
+pub fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        // Transposed along the top-left to bottom-right diagonal
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        // Transposed along the other diagonal
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    // Big-endian TIFF data with a single Orientation entry
+    fn raw_exif(orientation: u16) -> Vec<u8> {
+        let mut data = b"MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        data.extend(orientation.to_be_bytes());
+        data.extend([0; 6]);
+        data
+    }
+
+    #[test]
+    fn each_of_the_eight_orientations_is_read_and_turned_upright() {
+        // Every pixel of the upright image is different
+        let (width, height) = (3, 2);
+        let upright = RgbImage::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 0]));
+        let stored = |orientation, (w, h): (u32, u32), at: &dyn Fn(u32, u32) -> (u32, u32)| {
+            let img = RgbImage::from_fn(w, h, |x, y| {
+                let (x, y) = at(x, y);
+                *upright.get_pixel(x, y)
+            });
+            (orientation, img)
+        };
+        let (w, h) = (width - 1, height - 1);
+        let cases = [
+            stored(1, (width, height), &|x, y| (x, y)),
+            stored(2, (width, height), &|x, y| (w - x, y)),
+            stored(3, (width, height), &|x, y| (w - x, h - y)),
+            stored(4, (width, height), &|x, y| (x, h - y)),
+            stored(5, (height, width), &|x, y| (y, x)),
+            stored(6, (height, width), &|x, y| (w - y, x)),
+            stored(7, (height, width), &|x, y| (w - y, h - x)),
+            stored(8, (height, width), &|x, y| (y, h - x)),
+        ];
+        for (orientation, img) in cases {
+            assert_eq!(raw_exif_orientation(&raw_exif(orientation as u16)), Some(orientation));
+            assert_eq!(apply_orientation(DynamicImage::ImageRgb8(img), orientation).to_rgb8(), upright, "orientation {}", orientation);
+        }
+        for invalid in [0, 9] {
+            assert_eq!(raw_exif_orientation(&raw_exif(invalid)), None);
+        }
+    }
+}
@@ -0,0 +1,487 @@
+//! The output file, opened before any processing: a wrong extension or an
+//! unwritable path is reported at once rather than after the work is done.
+//! An existing file that is replaced is left as it is until the result is
+//! written whole, into a temporary file next to it that is then renamed over
+//! it. `-` stands for the standard output, in the format given by `--format`.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Cursor, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use image::codecs::pnm::{PnmSubtype, SampleEncoding};
+use image::{DynamicImage, GrayAlphaImage, GrayImage, ImageFormat, ImageOutputFormat};
+use tp_eval::bilevel::{self, pack_bilevel};
+use tp_eval::indexed::{self, index_pixels};
+use tp_eval::Palette;
+
+use crate::error::Error;
+use crate::prompt::Confirm;
+
+/// The formats whose encoder accepts the 8-bit images written by the modes.
+pub const OUTPUT_FORMATS: [ImageFormat; 10] = [
+    ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Bmp, ImageFormat::Gif, ImageFormat::Ico,
+    ImageFormat::Tiff, ImageFormat::Tga, ImageFormat::Pnm, ImageFormat::WebP, ImageFormat::Qoi,
+];
+
+/// Whether `path` is `-`, which stands for the standard input or output.
+pub fn is_standard_stream(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// Parses `--format`: the extension of one of `OUTPUT_FORMATS`, or txt.
+pub fn parse_output_format(value: &str) -> Result<String, String> {
+    let extension = value.trim_start_matches('.').to_ascii_lowercase();
+    let known = ImageFormat::from_extension(&extension).is_some_and(|format| OUTPUT_FORMATS.contains(&format));
+    if known || extension == "txt" {
+        Ok(extension)
+    } else {
+        Err(format!("format inconnu : {} (attendus : {})", value, supported_extensions(true)))
+    }
+}
+
+/// The extensions of `OUTPUT_FORMATS`, and txt when `text_allowed`.
+pub fn output_extensions(text_allowed: bool) -> Vec<&'static str> {
+    let mut extensions: Vec<&str> = OUTPUT_FORMATS.iter().flat_map(|format| format.extensions_str().iter().copied()).collect();
+    if text_allowed {
+        extensions.push("txt");
+    }
+    extensions
+}
+
+fn supported_extensions(text_allowed: bool) -> String {
+    output_extensions(text_allowed).join(", ")
+}
+
+/// The bits of each pixel of the result, chosen with `--bits`; without it, a
+/// PNG mapped to a palette is indexed on it, one made only of black and white
+/// is written on 1 bit, and other images as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bits {
+    /// Black and white, in a PNG only
+    One,
+    /// Levels of grey, with the alpha plane if any
+    Eight,
+    /// RGB, with the alpha plane if any
+    TwentyFour,
+}
+
+impl Bits {
+    /// The error of a result whose colours do not fit in these bits.
+    pub fn not_fitting(self) -> Error {
+        Error::InvalidArgument(match self {
+            Bits::One => "--bits 1 : le résultat n’est pas fait que de noir et de blanc opaques".to_string(),
+            Bits::Eight => "--bits 8 : le résultat a d’autres couleurs que des gris".to_string(),
+            Bits::TwentyFour => unreachable!("every colour fits in 24 bits"),
+        })
+    }
+}
+
+/// Parses `--bits`: 1, 8 or 24.
+pub fn parse_bits(value: &str) -> Result<Bits, String> {
+    match value {
+        "1" => Ok(Bits::One),
+        "8" => Ok(Bits::Eight),
+        "24" => Ok(Bits::TwentyFour),
+        _ => Err(format!("nombre de bits invalide : {} (attendus : 1, 8 ou 24)", value)),
+    }
+}
+
+/// The grey of each pixel of `pixels`, of 3 channels or of 4 with alpha,
+/// followed by its alpha; `None` if a pixel is not grey.
+pub fn grey_pixels(pixels: &[u8], channels: usize) -> Option<Vec<u8>> {
+    let mut grey = Vec::with_capacity(pixels.len() / channels * (channels - 2));
+    for pixel in pixels.chunks_exact(channels) {
+        if pixel[0] != pixel[1] || pixel[1] != pixel[2] {
+            return None;
+        }
+        grey.push(pixel[0]);
+        grey.extend_from_slice(&pixel[3..]);
+    }
+    Some(grey)
+}
+
+// `img` in `bits`, or `None` when it is in them already
+fn convert(img: &DynamicImage, bits: Bits) -> Result<Option<DynamicImage>, Error> {
+    let (width, height) = (img.width(), img.height());
+    Ok(match (bits, img) {
+        (Bits::Eight, DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_)) => None,
+        (Bits::Eight, _) => {
+            let (pixels, alpha) = match img {
+                DynamicImage::ImageRgba8(rgba) => (grey_pixels(rgba, 4), true),
+                img if img.color().has_alpha() => (grey_pixels(&img.to_rgba8(), 4), true),
+                img => (grey_pixels(&img.to_rgb8(), 3), false),
+            };
+            let pixels = pixels.ok_or_else(|| bits.not_fitting())?;
+            // The buffers have the size of the image
+            Some(if alpha {
+                DynamicImage::ImageLumaA8(GrayAlphaImage::from_raw(width, height, pixels).unwrap())
+            } else {
+                DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, pixels).unwrap())
+            })
+        }
+        (Bits::TwentyFour, DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_)) => None,
+        (Bits::TwentyFour, img) if img.color().has_alpha() => Some(DynamicImage::ImageRgba8(img.to_rgba8())),
+        (Bits::TwentyFour, img) => Some(DynamicImage::ImageRgb8(img.to_rgb8())),
+        (Bits::One, _) => unreachable!("1-bit results are packed, not converted"),
+    })
+}
+
+enum Target {
+    // Created empty until the result is written into it, and removed again if
+    // the tool stops before
+    File(File),
+    // Written in place of the output, renamed over it once the result is
+    // whole and removed if the tool stops before, which leaves the output as
+    // it was
+    Temporary { file: File, path: PathBuf },
+    Stdout,
+}
+
+/// Where the result is written.
+pub struct Output {
+    path: PathBuf,
+    extension: String,
+    target: Target,
+    written: bool,
+}
+
+impl Output {
+    /// Opens `path` for writing, in the format named by its extension or, for
+    /// the standard output, by `format`: one of `OUTPUT_FORMATS`, or txt when
+    /// `text_allowed`. An existing file is only replaced with `overwrite`,
+    /// once the result is written; otherwise the file is created with
+    /// `create_new`, so that two runs cannot both write it.
+    pub fn open(path: &Path, format: Option<&str>, text_allowed: bool, overwrite: bool) -> Result<Output, Error> {
+        let extension = match (format, is_standard_stream(path)) {
+            (Some(format), true) => format.to_string(),
+            (None, false) => path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_ascii_lowercase(),
+            (None, true) => return Err(Error::InvalidArgument("--format est obligatoire quand la sortie est -".to_string())),
+            (Some(_), false) => return Err(Error::InvalidArgument("--format n’a de sens qu’avec la sortie -, le format d’un fichier vient de son extension".to_string())),
+        };
+        let supported = match ImageFormat::from_extension(&extension) {
+            Some(format) => OUTPUT_FORMATS.contains(&format),
+            None => text_allowed && extension == "txt",
+        };
+        if !supported {
+            return Err(Error::UnsupportedOutputFormat(path.to_path_buf(), supported_extensions(text_allowed)));
+        }
+
+        if is_standard_stream(path) {
+            if io::stdout().is_terminal() {
+                return Err(Error::InvalidArgument("la sortie standard est un terminal : redirigez-la vers un fichier ou un programme".to_string()));
+            }
+            return Ok(Output { path: path.to_path_buf(), extension, target: Target::Stdout, written: false });
+        }
+
+        let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+        if parent.is_some_and(|parent| !parent.is_dir()) {
+            return Err(Error::writing(path.to_path_buf(), "le dossier n’existe pas"));
+        }
+        if overwrite {
+            if path.is_dir() {
+                return Err(Error::writing(path.to_path_buf(), "c’est un dossier"));
+            }
+            return match create_temporary(path) {
+                Ok((file, temporary)) => Ok(Output { path: path.to_path_buf(), extension, target: Target::Temporary { file, path: temporary }, written: false }),
+                Err(error) => Err(Error::writing(path.to_path_buf(), error)),
+            };
+        }
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(file) => Ok(Output { path: path.to_path_buf(), extension, target: Target::File(file), written: false }),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => Err(Error::OutputExists(path.to_path_buf())),
+            Err(error) => Err(Error::writing(path.to_path_buf(), error)),
+        }
+    }
+
+    /// Like `open`, but an existing file is also replaced when `confirm`
+    /// agrees to it.
+    pub fn open_or_ask(path: &Path, format: Option<&str>, text_allowed: bool, overwrite: bool, confirm: &mut dyn Confirm) -> Result<Output, Error> {
+        match Output::open(path, format, text_allowed, overwrite) {
+            Err(Error::OutputExists(path)) if confirm.confirm(&format!("{} existe déjà — écraser ?", path.display())) => {
+                Output::open(&path, format, text_allowed, true)
+            }
+            result => result,
+        }
+    }
+
+    /// The image format that is written.
+    pub fn format(&self) -> Option<ImageFormat> {
+        ImageFormat::from_extension(&self.extension)
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.extension == "txt"
+    }
+
+    /// Rejects `--bits 1` unless the output is a PNG, before the image is
+    /// processed.
+    pub fn check_bits(&self, bits: Option<Bits>) -> Result<(), Error> {
+        if bits == Some(Bits::One) && self.format() != Some(ImageFormat::Png) {
+            return Err(Error::InvalidArgument("--bits 1 n’écrit que des PNG".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Encodes `img` in the format of the output, in `bits` if given. Without
+    /// them, a PNG of RGB pixels that are all colours of `palette`, the one
+    /// `img` was mapped to, is indexed on it when it has at most 256 colours.
+    /// A PNG made only of opaque black and white is written on 1 bit unless
+    /// `bits` asks for 8 or 24.
+    pub fn write_image(self, img: &DynamicImage, bits: Option<Bits>, palette: Option<&Palette>) -> Result<(), Error> {
+        self.check_bits(bits)?;
+        let (width, height) = (img.width(), img.height());
+        let png = self.format() == Some(ImageFormat::Png);
+        if let (None, true, Some(palette), DynamicImage::ImageRgb8(rgb)) = (bits, png, palette, img) {
+            if let Some(indices) = index_pixels(rgb, palette.colours()) {
+                return self.write_png(|writer| indexed::write_png(writer, width, height, palette.colours(), &indices));
+            }
+        }
+        if matches!(bits, None | Some(Bits::One)) && png {
+            match pack_bilevel(img) {
+                Some(packed) => return self.write_png(|writer| bilevel::write_png(writer, width, height, &packed)),
+                None if bits.is_some() => return Err(Bits::One.not_fitting()),
+                None => {}
+            }
+        }
+        let converted = bits.map(|bits| convert(img, bits)).transpose()?.flatten();
+        let img = converted.as_ref().unwrap_or(img);
+        let format = output_format(&self.extension);
+        match &self.target {
+            Target::File(file) | Target::Temporary { file, .. } => {
+                let mut writer = BufWriter::new(file);
+                img.write_to(&mut writer, format).map_err(|error| Error::writing(self.path.clone(), error))?;
+                writer.flush().map_err(|error| Error::writing(self.path.clone(), error))?;
+            }
+            Target::Stdout => {
+                // The encoders need to seek, which the standard output cannot
+                let mut encoded = Cursor::new(Vec::new());
+                img.write_to(&mut encoded, format).map_err(|error| Error::writing(self.path.clone(), error))?;
+                return self.write_bytes(&encoded.into_inner());
+            }
+        }
+        self.done()
+    }
+
+    // Writes the PNG that `encode` gives; the PNG encoder does not seek, the
+    // standard output is written as it goes
+    fn write_png(self, encode: impl FnOnce(&mut dyn Write) -> Result<(), png::EncodingError>) -> Result<(), Error> {
+        let failed = |error: png::EncodingError| Error::writing(self.path.clone(), error);
+        let flushed = match &self.target {
+            Target::File(file) | Target::Temporary { file, .. } => {
+                let mut writer = BufWriter::new(file);
+                encode(&mut writer).map_err(failed)?;
+                writer.flush()
+            }
+            Target::Stdout => {
+                let mut stdout = io::stdout().lock();
+                encode(&mut stdout).map_err(failed)?;
+                stdout.flush()
+            }
+        };
+        flushed.map_err(|error| Error::writing(self.path.clone(), error))?;
+        self.done()
+    }
+
+    /// Encodes as PNG an image of `width` × `height` pixels of `color` on
+    /// `depth` bits, whose rows `next_row` gives from top to bottom: each one
+    /// is written before the next is asked for, so that the image is never
+    /// whole in memory.
+    pub fn write_png_rows(self, width: u32, height: u32, color: png::ColorType, depth: png::BitDepth, next_row: impl FnMut() -> Result<Vec<u8>, Error>) -> Result<(), Error> {
+        let flushed = match &self.target {
+            Target::File(file) | Target::Temporary { file, .. } => {
+                let mut writer = BufWriter::new(file);
+                encode_png_rows(&self.path, &mut writer, width, height, color, depth, next_row)?;
+                writer.flush()
+            }
+            Target::Stdout => {
+                let mut stdout = io::stdout().lock();
+                encode_png_rows(&self.path, &mut stdout, width, height, color, depth, next_row)?;
+                stdout.flush()
+            }
+        };
+        flushed.map_err(|error| Error::writing(self.path.clone(), error))?;
+        self.done()
+    }
+
+    pub fn write_text(self, text: &str) -> Result<(), Error> {
+        self.write_bytes(text.as_bytes())
+    }
+
+    fn write_bytes(self, bytes: &[u8]) -> Result<(), Error> {
+        let result = match &self.target {
+            Target::File(file) | Target::Temporary { file, .. } => {
+                let mut writer = BufWriter::new(file);
+                writer.write_all(bytes).and_then(|()| writer.flush())
+            }
+            Target::Stdout => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(bytes).and_then(|()| stdout.flush())
+            }
+        };
+        result.map_err(|error| Error::writing(self.path.clone(), error))?;
+        self.done()
+    }
+
+    fn done(mut self) -> Result<(), Error> {
+        self.written = true;
+        // The temporary file is closed before it is renamed, as Windows
+        // requires
+        if let Target::Temporary { path, .. } = std::mem::replace(&mut self.target, Target::Stdout) {
+            if let Err(error) = fs::rename(&path, &self.path) {
+                let _ = fs::remove_file(&path);
+                return Err(Error::writing(self.path.clone(), error));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if self.written {
+            return;
+        }
+        match &self.target {
+            Target::File(_) => { let _ = fs::remove_file(&self.path); }
+            Target::Temporary { path, .. } => { let _ = fs::remove_file(path); }
+            Target::Stdout => {}
+        }
+    }
+}
+
+// A new hidden file next to `path`, with the permissions of `path` if it
+// exists, for the result to be written into before it replaces `path`
+fn create_temporary(path: &Path) -> io::Result<(File, PathBuf)> {
+    // Outputs of the same name in one run get files of their own
+    static COUNT: AtomicU32 = AtomicU32::new(0);
+    loop {
+        let mut name = OsString::from(".");
+        name.push(path.file_name().unwrap_or_default());
+        name.push(format!(".{}-{}.tmp", process::id(), COUNT.fetch_add(1, Ordering::Relaxed)));
+        let temporary = path.with_file_name(name);
+        match OpenOptions::new().write(true).create_new(true).open(&temporary) {
+            Ok(file) => {
+                if let Ok(metadata) = fs::metadata(path) {
+                    let _ = file.set_permissions(metadata.permissions());
+                }
+                return Ok((file, temporary));
+            }
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+// The encoder for the extension, with the PNM variant it names as `save` does
+fn output_format(extension: &str) -> ImageOutputFormat {
+    match ImageFormat::from_extension(extension) {
+        Some(ImageFormat::Pnm) => ImageOutputFormat::Pnm(match extension {
+            "pbm" => PnmSubtype::Bitmap(SampleEncoding::Binary),
+            "pgm" => PnmSubtype::Graymap(SampleEncoding::Binary),
+            "ppm" => PnmSubtype::Pixmap(SampleEncoding::Binary),
+            _ => PnmSubtype::ArbitraryMap,
+        }),
+        Some(format) => format.into(),
+        None => ImageOutputFormat::Png,
+    }
+}
+
+// The PNG encoder does not seek, the standard output is written as it goes
+fn encode_png_rows<W: Write>(path: &Path, writer: W, width: u32, height: u32, color: png::ColorType, depth: png::BitDepth, mut next_row: impl FnMut() -> Result<Vec<u8>, Error>) -> Result<(), Error> {
+    let failed = |error: png::EncodingError| Error::writing(path.to_path_buf(), error);
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    let mut png = encoder.write_header().map_err(failed)?;
+    let mut stream = png.stream_writer().map_err(failed)?;
+    for _ in 0..height {
+        let row = next_row()?;
+        stream.write_all(&row).map_err(|error| Error::writing(path.to_path_buf(), error))?;
+    }
+    stream.finish().map_err(failed)?;
+    png.finish().map_err(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    // A directory of its own for each test, removed at the end
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let dir = std::env::temp_dir().join(format!("tp_eval_{}_{}", name, process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir(&dir).unwrap();
+            Scratch(dir)
+        }
+
+        fn entries(&self) -> Vec<OsString> {
+            let mut entries: Vec<OsString> = fs::read_dir(&self.0).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+            entries.sort();
+            entries
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn an_output_replaced_is_kept_until_the_result_is_written() {
+        let scratch = Scratch::new("remplacement");
+        let path = scratch.0.join("sortie.png");
+        fs::write(&path, b"ancienne image").unwrap();
+
+        // Stopped before writing: the file stays as it was, and alone
+        drop(Output::open(&path, None, false, true).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), b"ancienne image");
+        assert_eq!(scratch.entries(), ["sortie.png"]);
+
+        let img = DynamicImage::ImageRgb8(RgbImage::new(3, 2));
+        Output::open(&path, None, false, true).unwrap().write_image(&img, Some(Bits::TwentyFour), None).unwrap();
+        assert_eq!(image::open(&path).unwrap().to_rgb8(), img.to_rgb8());
+        assert_eq!(scratch.entries(), ["sortie.png"]);
+
+        assert!(matches!(Output::open(&path, None, false, false), Err(Error::OutputExists(_))));
+        assert!(Output::open(&scratch.0, None, false, true).is_err());
+    }
+
+    #[test]
+    fn an_output_created_is_removed_unless_written() {
+        let scratch = Scratch::new("creation");
+        let path = scratch.0.join("sortie.txt");
+        for overwrite in [false, true] {
+            drop(Output::open(&path, None, true, overwrite).unwrap());
+            assert!(scratch.entries().is_empty());
+        }
+        Output::open(&path, None, true, true).unwrap().write_text("texte").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "texte");
+    }
+
+    struct Yes;
+
+    impl Confirm for Yes {
+        fn confirm(&mut self, _question: &str) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn a_confirmed_overwrite_keeps_the_output_until_the_result_is_written() {
+        let scratch = Scratch::new("confirmation");
+        let path = scratch.0.join("sortie.png");
+        fs::write(&path, b"ancienne image").unwrap();
+        drop(Output::open_or_ask(&path, None, false, false, &mut Yes).unwrap());
+        assert_eq!(fs::read(&path).unwrap(), b"ancienne image");
+        assert_eq!(scratch.entries(), ["sortie.png"]);
+    }
+}
@@ -0,0 +1,312 @@
+//! Nearest-colour mapping onto a reduced palette, and the ways of building it.
+
+use std::fmt;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::slice;
+
+use image::{Rgb, RgbImage};
+
+use crate::dither_error::DitherError;
+use crate::distance::{squared_distance, Distance, PaletteMatcher};
+use crate::parallel::{map_pixels, map_rows};
+use crate::presets::{nearest_websafe, WEBSAFE};
+use crate::{BLACK, BLUE, CYAN, GREEN, GREY, MAGENTA, RED, WHITE, YELLOW};
+
+/// French names of the built-in colours, as accepted by `--noms`.
+pub const NAMED_COLOURS: [(&str, Rgb<u8>); 9] = [
+    ("noir", BLACK),
+    ("blanc", WHITE),
+    ("gris", GREY),
+    ("rouge", RED),
+    ("vert", GREEN),
+    ("bleu", BLUE),
+    ("jaune", YELLOW),
+    ("cyan", CYAN),
+    ("magenta", MAGENTA),
+];
+
+/// The built-in list that `--n-couleurs` takes a prefix of. Its order is the
+/// one given in the help of `--n-couleurs`, which must be kept in sync.
+pub const BUILTIN_PALETTE: [Rgb<u8>; 9] = [BLACK, WHITE, RED, GREEN, BLUE, YELLOW, CYAN, MAGENTA, GREY];
+
+/// The colours an image is reduced to, in the order they were given.
+///
+/// ```
+/// use tp_eval::ops::palette::Palette;
+/// use tp_eval::{BLACK, RED, WHITE};
+///
+/// let palette = Palette::from_hex_list("#000,#fff,#ff0000").unwrap();
+/// assert_eq!(palette.len(), 3);
+/// assert_eq!(palette.nearest(image::Rgb([200, 40, 30])), (2, RED));
+/// assert_eq!(palette, Palette::builtin(3));
+/// assert_eq!(palette.iter().take(2).copied().collect::<Vec<_>>(), [BLACK, WHITE]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Palette {
+    colours: Vec<Rgb<u8>>,
+}
+
+impl Palette {
+    /// The palette of `colours`, which may repeat or be empty.
+    pub fn new(colours: Vec<Rgb<u8>>) -> Palette {
+        Palette { colours }
+    }
+
+    /// The first `n_couleurs` colours of the built-in list, or all of them.
+    pub fn builtin(n_couleurs: usize) -> Palette {
+        Palette::builtin_excluding(n_couleurs, &[])
+    }
+
+    /// The first `n_couleurs` colours of the built-in list once the
+    /// `excluded` ones are removed, or all the remaining ones.
+    pub fn builtin_excluding(n_couleurs: usize, excluded: &[Rgb<u8>]) -> Palette {
+        let mut colours = BUILTIN_PALETTE.to_vec();
+        colours.retain(|colour| !excluded.contains(colour));
+
+        // Clamp n_couleurs to the size of the palette
+        let n_couleurs = n_couleurs.min(colours.len());
+
+        // Reduce the palette to n_couleurs colors
+        colours.truncate(n_couleurs);
+        Palette::new(colours)
+    }
+
+    /// Parses hex colours separated by commas, as `--couleurs`.
+    pub fn from_hex_list(value: &str) -> Result<Palette, DitherError> {
+        parse_colour_list(value).map_err(DitherError::InvalidPalette)
+    }
+
+    /// Reads a GIMP palette (.gpl): a `GIMP Palette` header, optional `Name:`
+    /// and `Columns:` lines, then one `R G B [name]` row per colour. Lines
+    /// starting with `#` are comments.
+    #[cfg(feature = "fs")]
+    pub fn from_gpl(path: impl AsRef<Path>) -> Result<Palette, DitherError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        Palette::from_gpl_text(&contents, &path.display().to_string())
+    }
+
+    /// Parses the `contents` of a GIMP palette, as `from_gpl` reads it, the
+    /// messages naming it `name`.
+    pub fn from_gpl_text(contents: &str, name: &str) -> Result<Palette, DitherError> {
+        let mut lines = contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+
+        match lines.next() {
+            Some((_, header)) if header.trim() == "GIMP Palette" => {}
+            _ => return Err(DitherError::InvalidPalette(format!("{} n’est pas une palette GIMP : la première ligne doit être \"GIMP Palette\"", name))),
+        }
+
+        let mut colours = Vec::new();
+        for (number, line) in lines {
+            let line = line.trim();
+            if line.starts_with('#') || line.starts_with("Name:") || line.starts_with("Columns:") {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let mut channel = || {
+                let field = fields.next().ok_or_else(|| DitherError::InvalidPalette(format!("{}, ligne {} : trois composantes R G B attendues", name, number + 1)))?;
+                field.parse::<u8>().map_err(|_| DitherError::InvalidPalette(format!("{}, ligne {} : composante invalide : {}", name, number + 1, field)))
+            };
+            colours.push(Rgb([channel()?, channel()?, channel()?]));
+        }
+
+        if colours.is_empty() {
+            return Err(DitherError::InvalidPalette(format!("{} ne contient aucune couleur", name)));
+        }
+        Ok(Palette::new(colours))
+    }
+
+    /// The index and the colour of the entry nearest to `colour`, by
+    /// Euclidean distance between sRGB values; the first of equally near
+    /// entries wins.
+    ///
+    /// # Panics
+    ///
+    /// If the palette is empty.
+    pub fn nearest(&self, colour: Rgb<u8>) -> (usize, Rgb<u8>) {
+        self.colours.iter()
+            .copied()
+            .enumerate()
+            .min_by_key(|(_, entry)| squared_distance(*entry, colour))
+            .expect("nearest colour of an empty palette")
+    }
+
+    /// The `PaletteMatcher` comparing colours to this palette by `distance`,
+    /// in linear light with `linear`.
+    pub fn matcher(&self, distance: Distance, linear: bool) -> PaletteMatcher {
+        PaletteMatcher::new(&self.colours, distance, linear)
+    }
+
+    /// The number of colours.
+    pub fn len(&self) -> usize {
+        self.colours.len()
+    }
+
+    /// Whether the palette has no colour at all.
+    pub fn is_empty(&self) -> bool {
+        self.colours.is_empty()
+    }
+
+    /// The colours, in order.
+    pub fn colours(&self) -> &[Rgb<u8>] {
+        &self.colours
+    }
+
+    /// An iterator over the colours, in order.
+    pub fn iter(&self) -> slice::Iter<'_, Rgb<u8>> {
+        self.colours.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Palette {
+    type Item = &'a Rgb<u8>;
+    type IntoIter = slice::Iter<'a, Rgb<u8>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl FromIterator<Rgb<u8>> for Palette {
+    fn from_iter<I: IntoIterator<Item = Rgb<u8>>>(iter: I) -> Palette {
+        Palette::new(iter.into_iter().collect())
+    }
+}
+
+/// The `--couleurs` list that reproduces the palette.
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let colours: Vec<String> = self.iter().map(|c| format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2])).collect();
+        write!(f, "{}", colours.join(","))
+    }
+}
+
+/// Parses a number of colours such as `--n-couleurs`, which must be at least 1.
+pub fn parse_colour_count(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(count) if count >= 1 => Ok(count),
+        _ => Err(format!("nombre de couleurs invalide : {} (attendu : un entier supérieur ou égal à 1)", value)),
+    }
+}
+
+/// Parses `#rgb` or `#rrggbb`, the `#` being optional.
+pub fn parse_hex_colour(token: &str) -> Option<Rgb<u8>> {
+    let digits = token.strip_prefix('#').unwrap_or(token);
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |range: std::ops::Range<usize>| u8::from_str_radix(&digits[range], 16).ok();
+    match digits.len() {
+        3 => Some(Rgb([channel(0..1)? * 17, channel(1..2)? * 17, channel(2..3)? * 17])),
+        6 => Some(Rgb([channel(0..2)?, channel(2..4)?, channel(4..6)?])),
+        _ => None,
+    }
+}
+
+// The built-in colour called `token`, whatever its case
+fn named_colour(token: &str) -> Option<Rgb<u8>> {
+    NAMED_COLOURS.iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(token))
+        .map(|(_, colour)| *colour)
+}
+
+/// Parses a single colour, given either by its built-in name or in hex.
+pub fn parse_colour(value: &str) -> Result<Rgb<u8>, String> {
+    let token = value.trim();
+    named_colour(token)
+        .or_else(|| parse_hex_colour(token))
+        .ok_or_else(|| format!("couleur invalide : \"{}\" (attendu : #rgb, #rrggbb ou un nom parmi noir, blanc, gris, rouge, vert, bleu, jaune, cyan et magenta)", token))
+}
+
+/// Parses the `--couleurs` list: hex colours separated by commas.
+pub fn parse_colour_list(value: &str) -> Result<Palette, String> {
+    value.split(',')
+        .enumerate()
+        .map(|(i, token)| {
+            let token = token.trim();
+            parse_hex_colour(token).ok_or_else(|| format!("couleur n°{} invalide : \"{}\" (attendu : #rgb ou #rrggbb)", i + 1, token))
+        })
+        .collect()
+}
+
+/// Parses the `--noms` list: built-in colour names separated by commas. A
+/// colour named several times appears only once in the palette.
+pub fn parse_colour_names(value: &str) -> Result<Palette, String> {
+    let mut palette = Vec::new();
+    for token in value.split(',') {
+        let token = token.trim();
+        let colour = named_colour(token).ok_or_else(|| {
+            let names: Vec<&str> = NAMED_COLOURS.iter().map(|(name, _)| *name).collect();
+            format!("couleur inconnue : \"{}\" (attendues : {})", token, names.join(", "))
+        })?;
+        if !palette.contains(&colour) {
+            palette.push(colour);
+        }
+    }
+    Ok(Palette::new(palette))
+}
+
+/// Reads the GIMP palette of `--fichier`, see `Palette::from_gpl`.
+#[cfg(feature = "fs")]
+pub fn parse_gpl_file(path: &str) -> Result<Palette, String> {
+    Palette::from_gpl(path).map_err(|error| match error {
+        DitherError::Io(error) => format!("impossible de lire {} : {}", path, error),
+        error => error.to_string(),
+    })
+}
+
+/// Each pixel replaced by the colour of `palette` nearest to it by
+/// `distance`. With `linear`, colours are compared in linear light. With the
+/// `parallel` feature, the rows are shared between rayon's threads.
+pub fn modify_image_palette(mut img: RgbImage, palette: &Palette, distance: Distance, linear: bool) -> Result<RgbImage, DitherError> {
+    if palette.is_empty() {
+        return Err(DitherError::EmptyPalette);
+    }
+    if distance == Distance::Rgb && !linear && palette.colours() == WEBSAFE {
+        map_pixels(&mut img, |_, _, pixel| nearest_websafe(pixel));
+        return Ok(img);
+    }
+
+    Ok(modify_image_palette_matcher(img, &palette.matcher(distance, linear)))
+}
+
+/// Each pixel replaced by the colour that `matcher` finds nearest to it, as
+/// `modify_image_palette` does once the matcher is built: the tiles of an
+/// image can share one.
+pub fn modify_image_palette_matcher(mut img: RgbImage, matcher: &PaletteMatcher) -> RgbImage {
+    map_rows(&mut img, |_, row| matcher.nearest_row(row));
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Rng;
+
+    #[test]
+    fn the_websafe_fast_path_agrees_with_the_search_of_the_nearest_colour() {
+        let mut rng = Rng::new(29);
+        let img = RgbImage::from_fn(64, 64, |_, _| Rgb([0, 1, 2].map(|_| rng.below(256) as u8)));
+        let websafe = Palette::new(WEBSAFE.to_vec());
+        let fast = modify_image_palette(img.clone(), &websafe, Distance::Rgb, false).unwrap();
+        assert_eq!(fast, modify_image_palette_matcher(img.clone(), &websafe.matcher(Distance::Rgb, false)));
+        let squared = |a: &Rgb<u8>, b: &Rgb<u8>| a.0.iter().zip(b.0).map(|(&a, b)| (a as i32 - b as i32).pow(2)).sum::<i32>();
+        for (pixel, result) in img.pixels().zip(fast.pixels()) {
+            let nearest = WEBSAFE.iter().min_by_key(|colour| squared(colour, pixel)).unwrap();
+            assert_eq!(result, nearest, "{:?}", pixel);
+        }
+    }
+
+    #[test]
+    fn the_builtin_palette_grows_in_the_documented_order() {
+        let documented = [BLACK, WHITE, RED, GREEN, BLUE, YELLOW, CYAN, MAGENTA, GREY];
+        for n in 1..=9 {
+            assert_eq!(Palette::builtin(n).colours(), &documented[..n], "{} couleurs", n);
+        }
+        // Two colours are black and white, eight leave only grey out
+        assert_eq!(Palette::builtin(2).colours(), [BLACK, WHITE]);
+        assert!(!Palette::builtin(8).colours().contains(&GREY));
+        assert_eq!(Palette::builtin(10).colours(), documented);
+    }
+}
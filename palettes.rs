@@ -0,0 +1,81 @@
+//! The listing printed by the `palettes` mode: the colour names, the presets
+//! and the distances that palette and dithering accept.
+
+use std::fmt::Write;
+
+use image::Rgb;
+
+use tp_eval::distance::DISTANCES;
+use tp_eval::palette::NAMED_COLOURS;
+use tp_eval::presets::PRESETS;
+
+// Presets with more colours are shown by their first swatches only
+const MAX_SWATCHES: usize = 32;
+
+fn hex(colour: Rgb<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", colour[0], colour[1], colour[2])
+}
+
+// `width` spaces on a background of `colour`, in 24-bit ANSI colour
+fn swatch(colour: Rgb<u8>, width: usize) -> String {
+    format!("\x1b[48;2;{};{};{}m{}\x1b[0m", colour[0], colour[1], colour[2], " ".repeat(width))
+}
+
+/// The listing as text, with a swatch of each colour when `swatches` is set.
+pub fn palettes_text(swatches: bool) -> String {
+    let mut s = String::new();
+    s.push_str("couleurs nommées (--noms, --exclure) :\n");
+    for (name, colour) in NAMED_COLOURS {
+        let swatch = if swatches { format!("{} ", swatch(colour, 2)) } else { String::new() };
+        let _ = writeln!(s, "  {:<10} {}{}", name, swatch, hex(colour));
+    }
+
+    s.push_str("\npresets (--preset) :\n");
+    for (name, colours) in PRESETS {
+        let mut line = format!("  {:<10} {} couleurs", name, colours.len());
+        if swatches {
+            line.push(' ');
+            line.extend(colours.iter().take(MAX_SWATCHES).map(|&colour| swatch(colour, 1)));
+            if colours.len() > MAX_SWATCHES {
+                line.push('…');
+            }
+        }
+        let _ = writeln!(s, "{}", line);
+    }
+
+    s.push_str("\ndistances (--distance) :\n");
+    let names: Vec<&str> = DISTANCES.iter().map(|(name, _)| *name).collect();
+    let _ = writeln!(s, "  {}", names.join(", "));
+    s
+}
+
+/// The listing as a JSON object, for programs that offer these choices.
+pub fn palettes_json() -> String {
+    // The names and colours are plain ASCII, nothing needs escaping
+    let colours: Vec<String> = NAMED_COLOURS.iter().map(|(name, colour)| format!("{{\"nom\":\"{}\",\"hex\":\"{}\"}}", name, hex(*colour))).collect();
+    let presets: Vec<String> = PRESETS.iter()
+        .map(|(name, colours)| {
+            let hexes: Vec<String> = colours.iter().map(|colour| format!("\"{}\"", hex(*colour))).collect();
+            format!("{{\"nom\":\"{}\",\"couleurs\":[{}]}}", name, hexes.join(","))
+        })
+        .collect();
+    let distances: Vec<String> = DISTANCES.iter().map(|(name, _)| format!("\"{}\"", name)).collect();
+    format!("{{\"couleurs\":[{}],\"presets\":[{}],\"distances\":[{}]}}\n", colours.join(","), presets.join(","), distances.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_listing_holds_every_name() {
+        let (text, coloured) = (palettes_text(false), palettes_text(true));
+        assert!(!text.contains('\x1b') && coloured.contains("\x1b[48;2;"));
+        let json: serde_json::Value = serde_json::from_str(&palettes_json()).unwrap();
+        for (name, _) in NAMED_COLOURS {
+            assert!(text.contains(name) && json["couleurs"].as_array().unwrap().iter().any(|colour| colour["nom"] == name));
+        }
+        assert_eq!(json["presets"].as_array().map(Vec::len), Some(PRESETS.len()));
+        assert_eq!(json["distances"].as_array().map(Vec::len), Some(DISTANCES.len()));
+    }
+}
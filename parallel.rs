@@ -0,0 +1,62 @@
+//! Loops over the rows of an image, shared between rayon's threads with the
+//! `parallel` feature and run on the calling thread without it, as in the
+//! wasm build; either way each pixel gets the same result. They serve the
+//! operations where a pixel depends on nothing but itself and its place:
+//! palette mapping, seuil and ordered dithering. Error diffusion, which
+//! carries the error of each pixel to the next ones, stays on one thread.
+
+use image::{ImageBuffer, Pixel};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+// Replaces each pixel of `img` by `f` of its coordinates and itself, row by
+// row
+pub(crate) fn map_pixels<P>(img: &mut ImageBuffer<P, Vec<u8>>, f: impl Fn(u32, u32, P) -> P + Sync)
+where
+    P: Pixel<Subpixel = u8> + Send + Sync,
+{
+    map_rows(img, |y, row| {
+        for (x, pixel) in row.chunks_exact_mut(P::CHANNEL_COUNT as usize).enumerate() {
+            let result = f(x as u32, y, *P::from_slice(pixel));
+            pixel.copy_from_slice(result.channels());
+        }
+    });
+}
+
+// Runs `f` on the index and the bytes of each row of `img`
+pub(crate) fn map_rows<P>(img: &mut ImageBuffer<P, Vec<u8>>, f: impl Fn(u32, &mut [u8]) + Sync)
+where
+    P: Pixel<Subpixel = u8> + Send + Sync,
+{
+    let width = img.width() as usize;
+    // An image without columns has no row to split
+    if width == 0 {
+        return;
+    }
+    let map_row = |(y, row): (usize, &mut [u8])| f(y as u32, row);
+    let row_len = width * P::CHANNEL_COUNT as usize;
+    #[cfg(feature = "parallel")]
+    img.par_chunks_mut(row_len).enumerate().for_each(map_row);
+    #[cfg(not(feature = "parallel"))]
+    img.chunks_mut(row_len).enumerate().for_each(map_row);
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma, Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn each_pixel_gets_its_place() {
+        let mut img = RgbImage::new(37, 23);
+        map_pixels(&mut img, |x, y, _| Rgb([x as u8, y as u8, (x * y) as u8]));
+        assert!(img.enumerate_pixels().all(|(x, y, pixel)| pixel.0 == [x as u8, y as u8, (x * y) as u8]));
+
+        let mut gray = GrayImage::from_pixel(5, 4, Luma([10]));
+        map_rows(&mut gray, |y, row| row.iter_mut().for_each(|value| *value += y as u8));
+        assert!(gray.enumerate_pixels().all(|(_, y, pixel)| pixel[0] == 10 + y as u8));
+        // Nor rows nor columns: nothing to call
+        map_rows(&mut GrayImage::new(0, 3), |_, _| panic!("aucune ligne"));
+    }
+}
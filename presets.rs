@@ -0,0 +1,131 @@
+//! Palettes of retro hardware, selectable with `--preset` in palette and
+//! dithering.
+
+use image::Rgb;
+
+use crate::palette::Palette;
+
+/// Names accepted by `--preset` and their colours.
+pub const PRESETS: [(&str, &[Rgb<u8>]); 6] = [
+    ("gameboy", &GAMEBOY),
+    ("cga", &CGA),
+    ("ega", &EGA),
+    ("nes", &NES),
+    ("zx", &ZX_SPECTRUM),
+    ("websafe", &WEBSAFE),
+];
+
+// The four shades of green of the original Game Boy (DMG) screen
+const GAMEBOY: [Rgb<u8>; 4] = [
+    Rgb([0x0f, 0x38, 0x0f]), Rgb([0x30, 0x62, 0x30]), Rgb([0x8b, 0xac, 0x0f]), Rgb([0x9b, 0xbc, 0x0f]),
+];
+
+// The 16 colours of the CGA text modes, in RGBI order
+const CGA: [Rgb<u8>; 16] = [
+    Rgb([0x00, 0x00, 0x00]), Rgb([0x00, 0x00, 0xaa]), Rgb([0x00, 0xaa, 0x00]), Rgb([0x00, 0xaa, 0xaa]),
+    Rgb([0xaa, 0x00, 0x00]), Rgb([0xaa, 0x00, 0xaa]), Rgb([0xaa, 0x55, 0x00]), Rgb([0xaa, 0xaa, 0xaa]),
+    Rgb([0x55, 0x55, 0x55]), Rgb([0x55, 0x55, 0xff]), Rgb([0x55, 0xff, 0x55]), Rgb([0x55, 0xff, 0xff]),
+    Rgb([0xff, 0x55, 0x55]), Rgb([0xff, 0x55, 0xff]), Rgb([0xff, 0xff, 0x55]), Rgb([0xff, 0xff, 0xff]),
+];
+
+// The 64 colours of the EGA, in the order of its rgbRGB palette registers
+const EGA: [Rgb<u8>; 64] = [
+    Rgb([0x00, 0x00, 0x00]), Rgb([0x00, 0x00, 0xaa]), Rgb([0x00, 0xaa, 0x00]), Rgb([0x00, 0xaa, 0xaa]),
+    Rgb([0xaa, 0x00, 0x00]), Rgb([0xaa, 0x00, 0xaa]), Rgb([0xaa, 0xaa, 0x00]), Rgb([0xaa, 0xaa, 0xaa]),
+    Rgb([0x00, 0x00, 0x55]), Rgb([0x00, 0x00, 0xff]), Rgb([0x00, 0xaa, 0x55]), Rgb([0x00, 0xaa, 0xff]),
+    Rgb([0xaa, 0x00, 0x55]), Rgb([0xaa, 0x00, 0xff]), Rgb([0xaa, 0xaa, 0x55]), Rgb([0xaa, 0xaa, 0xff]),
+    Rgb([0x00, 0x55, 0x00]), Rgb([0x00, 0x55, 0xaa]), Rgb([0x00, 0xff, 0x00]), Rgb([0x00, 0xff, 0xaa]),
+    Rgb([0xaa, 0x55, 0x00]), Rgb([0xaa, 0x55, 0xaa]), Rgb([0xaa, 0xff, 0x00]), Rgb([0xaa, 0xff, 0xaa]),
+    Rgb([0x00, 0x55, 0x55]), Rgb([0x00, 0x55, 0xff]), Rgb([0x00, 0xff, 0x55]), Rgb([0x00, 0xff, 0xff]),
+    Rgb([0xaa, 0x55, 0x55]), Rgb([0xaa, 0x55, 0xff]), Rgb([0xaa, 0xff, 0x55]), Rgb([0xaa, 0xff, 0xff]),
+    Rgb([0x55, 0x00, 0x00]), Rgb([0x55, 0x00, 0xaa]), Rgb([0x55, 0xaa, 0x00]), Rgb([0x55, 0xaa, 0xaa]),
+    Rgb([0xff, 0x00, 0x00]), Rgb([0xff, 0x00, 0xaa]), Rgb([0xff, 0xaa, 0x00]), Rgb([0xff, 0xaa, 0xaa]),
+    Rgb([0x55, 0x00, 0x55]), Rgb([0x55, 0x00, 0xff]), Rgb([0x55, 0xaa, 0x55]), Rgb([0x55, 0xaa, 0xff]),
+    Rgb([0xff, 0x00, 0x55]), Rgb([0xff, 0x00, 0xff]), Rgb([0xff, 0xaa, 0x55]), Rgb([0xff, 0xaa, 0xff]),
+    Rgb([0x55, 0x55, 0x00]), Rgb([0x55, 0x55, 0xaa]), Rgb([0x55, 0xff, 0x00]), Rgb([0x55, 0xff, 0xaa]),
+    Rgb([0xff, 0x55, 0x00]), Rgb([0xff, 0x55, 0xaa]), Rgb([0xff, 0xff, 0x00]), Rgb([0xff, 0xff, 0xaa]),
+    Rgb([0x55, 0x55, 0x55]), Rgb([0x55, 0x55, 0xff]), Rgb([0x55, 0xff, 0x55]), Rgb([0x55, 0xff, 0xff]),
+    Rgb([0xff, 0x55, 0x55]), Rgb([0xff, 0x55, 0xff]), Rgb([0xff, 0xff, 0x55]), Rgb([0xff, 0xff, 0xff]),
+];
+
+// The 54 usable colours of the 64 entries of the NES picture processing unit:
+// its repeated blacks are kept only once, and the grey of entry 0x2D, next to
+// indistinguishable from that of entry 0x00, is left out
+const NES: [Rgb<u8>; 54] = [
+    Rgb([0x7c, 0x7c, 0x7c]), Rgb([0x00, 0x00, 0xfc]), Rgb([0x00, 0x00, 0xbc]), Rgb([0x44, 0x28, 0xbc]),
+    Rgb([0x94, 0x00, 0x84]), Rgb([0xa8, 0x00, 0x20]), Rgb([0xa8, 0x10, 0x00]), Rgb([0x88, 0x14, 0x00]),
+    Rgb([0x50, 0x30, 0x00]), Rgb([0x00, 0x78, 0x00]), Rgb([0x00, 0x68, 0x00]), Rgb([0x00, 0x58, 0x00]),
+    Rgb([0x00, 0x40, 0x58]), Rgb([0x00, 0x00, 0x00]), Rgb([0xbc, 0xbc, 0xbc]), Rgb([0x00, 0x78, 0xf8]),
+    Rgb([0x00, 0x58, 0xf8]), Rgb([0x68, 0x44, 0xfc]), Rgb([0xd8, 0x00, 0xcc]), Rgb([0xe4, 0x00, 0x58]),
+    Rgb([0xf8, 0x38, 0x00]), Rgb([0xe4, 0x5c, 0x10]), Rgb([0xac, 0x7c, 0x00]), Rgb([0x00, 0xb8, 0x00]),
+    Rgb([0x00, 0xa8, 0x00]), Rgb([0x00, 0xa8, 0x44]), Rgb([0x00, 0x88, 0x88]), Rgb([0xf8, 0xf8, 0xf8]),
+    Rgb([0x3c, 0xbc, 0xfc]), Rgb([0x68, 0x88, 0xfc]), Rgb([0x98, 0x78, 0xf8]), Rgb([0xf8, 0x78, 0xf8]),
+    Rgb([0xf8, 0x58, 0x98]), Rgb([0xf8, 0x78, 0x58]), Rgb([0xfc, 0xa0, 0x44]), Rgb([0xf8, 0xb8, 0x00]),
+    Rgb([0xb8, 0xf8, 0x18]), Rgb([0x58, 0xd8, 0x54]), Rgb([0x58, 0xf8, 0x98]), Rgb([0x00, 0xe8, 0xd8]),
+    Rgb([0xfc, 0xfc, 0xfc]), Rgb([0xa4, 0xe4, 0xfc]), Rgb([0xb8, 0xb8, 0xf8]), Rgb([0xd8, 0xb8, 0xf8]),
+    Rgb([0xf8, 0xb8, 0xf8]), Rgb([0xf8, 0xa4, 0xc0]), Rgb([0xf0, 0xd0, 0xb0]), Rgb([0xfc, 0xe0, 0xa8]),
+    Rgb([0xf8, 0xd8, 0x78]), Rgb([0xd8, 0xf8, 0x78]), Rgb([0xb8, 0xf8, 0xb8]), Rgb([0xb8, 0xf8, 0xd8]),
+    Rgb([0x00, 0xfc, 0xfc]), Rgb([0xf8, 0xd8, 0xf8]),
+];
+
+// The ZX Spectrum colours: eight normal ones then the bright ones, bright
+// black being the same as black
+const ZX_SPECTRUM: [Rgb<u8>; 15] = [
+    Rgb([0x00, 0x00, 0x00]), Rgb([0x00, 0x00, 0xd7]), Rgb([0xd7, 0x00, 0x00]), Rgb([0xd7, 0x00, 0xd7]),
+    Rgb([0x00, 0xd7, 0x00]), Rgb([0x00, 0xd7, 0xd7]), Rgb([0xd7, 0xd7, 0x00]), Rgb([0xd7, 0xd7, 0xd7]),
+    Rgb([0x00, 0x00, 0xff]), Rgb([0xff, 0x00, 0x00]), Rgb([0xff, 0x00, 0xff]), Rgb([0x00, 0xff, 0x00]),
+    Rgb([0x00, 0xff, 0xff]), Rgb([0xff, 0xff, 0x00]), Rgb([0xff, 0xff, 0xff]),
+];
+
+/// Spacing of the web-safe cube, whose levels are 0, 51, ..., 255.
+pub const WEBSAFE_STEP: u8 = 51;
+
+/// The 6×6×6 web-safe cube, red varying slowest.
+pub const WEBSAFE: [Rgb<u8>; 216] = websafe_cube();
+
+const fn websafe_cube() -> [Rgb<u8>; 216] {
+    let mut cube = [Rgb([0, 0, 0]); 216];
+    let mut i = 0;
+    while i < 216 {
+        let (r, g, b) = ((i / 36) as u8, (i / 6 % 6) as u8, (i % 6) as u8);
+        cube[i] = Rgb([r * WEBSAFE_STEP, g * WEBSAFE_STEP, b * WEBSAFE_STEP]);
+        i += 1;
+    }
+    cube
+}
+
+/// Nearest colour of the web-safe cube. The cube being a uniform lattice,
+/// each channel is simply rounded to the closest multiple of 51.
+pub fn nearest_websafe(pixel: Rgb<u8>) -> Rgb<u8> {
+    let step = WEBSAFE_STEP as u16;
+    Rgb(pixel.0.map(|channel| ((channel as u16 + step / 2) / step * step) as u8))
+}
+
+/// Parses the `--preset` name of a hardware palette.
+pub fn parse_preset(value: &str) -> Result<Palette, String> {
+    PRESETS.iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(value))
+        .map(|(_, colours)| Palette::new(colours.to_vec()))
+        .ok_or_else(|| {
+            let names: Vec<&str> = PRESETS.iter().map(|(name, _)| *name).collect();
+            format!("palette inconnue : {} (attendues : {})", value, names.join(", "))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_presets_have_their_hardware_counts_of_distinct_colours() {
+        for (name, count) in [("gameboy", 4), ("cga", 16), ("ega", 64), ("nes", 54), ("zx", 15), ("websafe", 216)] {
+            let mut colours = parse_preset(name).unwrap().colours().to_vec();
+            assert_eq!(colours.len(), count, "{}", name);
+            colours.sort_by_key(|colour| colour.0);
+            colours.dedup();
+            assert_eq!(colours.len(), count, "{}", name);
+        }
+        assert_eq!(parse_preset("NES").map(|palette| palette.len()), Ok(54));
+        assert!(parse_preset("amiga").unwrap_err().contains("gameboy, cga, ega, nes, zx, websafe"));
+    }
+}
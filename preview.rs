@@ -0,0 +1,91 @@
+//! The preview of `--apercu`: the result drawn in the terminal with half
+//! blocks, each character cell showing two pixels, one above the other.
+
+use std::fmt::Write;
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Rgb, RgbImage};
+
+/// Width of the preview when the terminal does not tell its own.
+pub const DEFAULT_PREVIEW_WIDTH: u32 = 80;
+
+// The upper half block: its foreground is the upper pixel, its background the lower one
+const UPPER_HALF: char = '▀';
+
+// The six levels of each channel in the colour cube of 256-colour terminals
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Parses `--apercu-largeur`, in columns.
+pub fn parse_preview_width(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(width) if width >= 1 => Ok(width),
+        _ => Err(format!("largeur d’aperçu invalide : {} (attendu : un entier strictement positif)", value)),
+    }
+}
+
+/// The width of the terminal in columns, or `DEFAULT_PREVIEW_WIDTH`.
+pub fn terminal_width() -> u32 {
+    terminal_size::terminal_size().map_or(DEFAULT_PREVIEW_WIDTH, |(width, _)| width.0 as u32)
+}
+
+/// Whether the terminal announces 24-bit colours, as most do through COLORTERM.
+pub fn supports_truecolor() -> bool {
+    std::env::var("COLORTERM").is_ok_and(|value| value == "truecolor" || value == "24bit")
+}
+
+/// `img` scaled down to at most `width` columns, as lines of half blocks in
+/// 24-bit colours, or in the 256-colour palette without `truecolor`. Images
+/// narrower than `width` keep their size, one column per pixel.
+pub fn render_preview(img: &DynamicImage, width: u32, truecolor: bool) -> String {
+    let rgb = img.to_rgb8();
+    let rgb = if rgb.width() > width {
+        let height = ((rgb.height() as u64 * width as u64).div_ceil(rgb.width() as u64) as u32).max(1);
+        imageops::resize(&rgb, width, height, FilterType::Triangle)
+    } else {
+        rgb
+    };
+
+    let colour = |layer: u8, pixel: &Rgb<u8>| -> String {
+        let [r, g, b] = pixel.0;
+        if truecolor { format!("\x1b[{};2;{};{};{}m", layer, r, g, b) } else { format!("\x1b[{};5;{}m", layer, ansi_256(pixel.0)) }
+    };
+    let mut s = String::new();
+    for y in (0..rgb.height()).step_by(2) {
+        for x in 0..rgb.width() {
+            s.push_str(&colour(38, rgb.get_pixel(x, y)));
+            match lower_pixel(&rgb, x, y + 1) {
+                Some(lower) => s.push_str(&colour(48, lower)),
+                // The last row of an image of odd height lies over the terminal background
+                None => s.push_str("\x1b[49m"),
+            }
+            s.push(UPPER_HALF);
+        }
+        let _ = writeln!(s, "\x1b[0m");
+    }
+    s
+}
+
+fn lower_pixel(img: &RgbImage, x: u32, y: u32) -> Option<&Rgb<u8>> {
+    (y < img.height()).then(|| img.get_pixel(x, y))
+}
+
+// The nearest colour of the 256-colour palette: a step of its 6×6×6 cube, or
+// one of its 24 greys
+fn ansi_256([r, g, b]: [u8; 3]) -> u8 {
+    let step = |c: u8| CUBE_LEVELS.iter().enumerate().min_by_key(|(_, &level)| (level as i32 - c as i32).abs()).unwrap().0;
+    let (ri, gi, bi) = (step(r), step(g), step(b));
+    let cube = [CUBE_LEVELS[ri], CUBE_LEVELS[gi], CUBE_LEVELS[bi]];
+
+    let mean = (r as u32 + g as u32 + b as u32) / 3;
+    let grey_index = (mean.saturating_sub(3) / 10).min(23);
+    let grey = (8 + 10 * grey_index) as u8;
+
+    let distance = |[cr, cg, cb]: [u8; 3]| {
+        [(cr, r), (cg, g), (cb, b)].iter().map(|&(a, b)| (a as i32 - b as i32).pow(2)).sum::<i32>()
+    };
+    if distance([grey; 3]) < distance(cube) {
+        232 + grey_index as u8
+    } else {
+        16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8
+    }
+}
@@ -0,0 +1,73 @@
+//! A progress bar on stderr, for the rows of a long dithering or the files
+//! of a batch. It stays hidden when stderr is not a terminal.
+
+use std::cell::Cell;
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+// Redrawn at most this often, so that updating after every row costs nothing
+const REFRESH: Duration = Duration::from_millis(50);
+const WIDTH: u64 = 30;
+
+/// A progress bar on stderr.
+pub struct Progress {
+    label: &'static str,
+    total: u64,
+    enabled: bool,
+    last_drawn: Cell<Option<Instant>>,
+}
+
+impl Progress {
+    /// A bar counting `total` items, drawn unless `quiet` is set or stderr
+    /// is not a terminal.
+    pub fn new(label: &'static str, total: u64, quiet: bool) -> Progress {
+        Progress { label, total, enabled: !quiet && io::stderr().is_terminal(), last_drawn: Cell::new(None) }
+    }
+
+    /// Shows that `done` items out of the total are finished.
+    pub fn update(&self, done: u64) {
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        if self.last_drawn.get().is_some_and(|last| now - last < REFRESH) && done < self.total {
+            return;
+        }
+        self.last_drawn.set(Some(now));
+        let filled = (done * WIDTH).checked_div(self.total).unwrap_or(WIDTH).min(WIDTH) as usize;
+        let bar = format!("{}{}", "#".repeat(filled), " ".repeat(WIDTH as usize - filled));
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r{} [{}] {}/{}", self.label, bar, done, self.total);
+        let _ = stderr.flush();
+    }
+
+    /// Erases the bar, before a message or once the work is over.
+    pub fn clear(&self) {
+        if self.last_drawn.take().is_some() {
+            let mut stderr = io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[K");
+            let _ = stderr.flush();
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_quiet_bar_draws_nothing() {
+        let progress = Progress::new("Tramage", 10, true);
+        assert!(!progress.enabled);
+        (0..=12).for_each(|done| progress.update(done));
+        assert_eq!(progress.last_drawn.get(), None);
+        progress.clear();
+        assert!(!Progress::new("Vide", 0, true).enabled);
+    }
+}
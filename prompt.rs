@@ -0,0 +1,44 @@
+//! The questions asked before replacing an existing output. They go through
+//! `Confirm`, so that the terminal can be replaced by scripted answers.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// Answers a yes-or-no question.
+pub trait Confirm {
+    fn confirm(&mut self, question: &str) -> bool;
+}
+
+/// Asks on stderr and reads the answer on stdin; anything but yes is no.
+pub struct TerminalPrompt;
+
+impl Confirm for TerminalPrompt {
+    fn confirm(&mut self, question: &str) -> bool {
+        // The line may hold a progress bar
+        eprint!("\r\x1b[K{} [o/N] ", question);
+        let _ = io::stderr().flush();
+        let mut answer = String::new();
+        match io::stdin().lock().read_line(&mut answer) {
+            Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "o" | "oui" | "y" | "yes"),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Answers no without asking, for scripts and `--no-input`.
+pub struct NoPrompt;
+
+impl Confirm for NoPrompt {
+    fn confirm(&mut self, _question: &str) -> bool {
+        false
+    }
+}
+
+/// The terminal when stdin and stderr both are one and `no_input` is not set,
+/// or else no questions at all.
+pub fn confirmation(no_input: bool) -> Box<dyn Confirm> {
+    if !no_input && io::stdin().is_terminal() && io::stderr().is_terminal() {
+        Box::new(TerminalPrompt)
+    } else {
+        Box::new(NoPrompt)
+    }
+}
@@ -0,0 +1,516 @@
+//! Automatic palette generation from the colours of an image.
+
+use std::str::FromStr;
+
+use image::{Rgb, RgbImage};
+
+use crate::palette::Palette;
+use crate::random::Rng;
+
+/// Size of the computed palettes when `--n-couleurs` isn't given.
+pub const DEFAULT_AUTO_COLOURS: usize = 16;
+
+/// Iteration limit of k-means when `--iterations` isn't given.
+pub const DEFAULT_ITERATIONS: usize = 20;
+
+/// NeuQuant sampling factor when `--qualite` isn't given.
+pub const DEFAULT_QUALITY: usize = 10;
+
+/// Largest number of pixels the quantizers look at; bigger images are sampled.
+const MAX_SAMPLES: usize = 1 << 20;
+
+/// The algorithms of `--auto`, which compute a palette from an image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quantizer {
+    /// Splits the box of colours at its median, longest side first
+    MedianCut,
+    /// Clusters the colours with k-means, seeded by k-means++
+    KMeans,
+    /// Merges the leaves of an octree of the colours
+    Octree,
+    /// Trains a self-organizing network on the colours
+    NeuQuant,
+}
+
+/// Names accepted by `--auto`, in the order they are listed in error messages.
+pub const QUANTIZERS: [(&str, Quantizer); 4] = [
+    ("median-cut", Quantizer::MedianCut),
+    ("kmeans", Quantizer::KMeans),
+    ("octree", Quantizer::Octree),
+    ("neuquant", Quantizer::NeuQuant),
+];
+
+/// Settings of the iterative quantizers.
+pub struct QuantizeOptions {
+    /// Maximum number of refinement passes
+    pub iterations: usize,
+    /// Seed of the random initialization
+    pub seed: u64,
+    /// NeuQuant sampling factor: 1 learns from every pixel, 30 from one in 30
+    pub quality: usize,
+}
+
+impl FromStr for Quantizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        QUANTIZERS.iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, quantizer)| *quantizer)
+            .ok_or_else(|| {
+                let names: Vec<&str> = QUANTIZERS.iter().map(|(name, _)| *name).collect();
+                format!("méthode inconnue : {} (attendues : {})", s, names.join(", "))
+            })
+    }
+}
+
+impl Quantizer {
+    /// Computes a palette of at most `n_couleurs` colours suited to `img`. An
+    /// image with no more distinct colours than that gets all of them.
+    pub fn palette(self, img: &RgbImage, n_couleurs: usize, options: &QuantizeOptions) -> Palette {
+        let pixels = sample_pixels(img);
+
+        let mut distinct = pixels.clone();
+        distinct.sort_unstable_by_key(|pixel| pixel.0);
+        distinct.dedup();
+        if distinct.len() <= n_couleurs {
+            return Palette::new(distinct);
+        }
+
+        let colours = match self {
+            Quantizer::MedianCut => median_cut(pixels, n_couleurs),
+            Quantizer::KMeans => kmeans(&pixels, n_couleurs, options.iterations, &mut Rng::new(options.seed)),
+            Quantizer::Octree => octree(&pixels, n_couleurs),
+            Quantizer::NeuQuant => neuquant(&pixels, n_couleurs, options.quality),
+        };
+        Palette::new(colours)
+    }
+}
+
+/// Parses the `--qualite` sampling factor of NeuQuant, between 1 and 30.
+pub fn parse_quality(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(quality) if (1..=30).contains(&quality) => Ok(quality),
+        _ => Err("la qualité doit être un entier entre 1 (la meilleure) et 30 (la plus rapide)".to_string()),
+    }
+}
+
+// Every pixel of small images, evenly spaced ones for the others
+fn sample_pixels(img: &RgbImage) -> Vec<Rgb<u8>> {
+    let count = img.pixels().len();
+    let step = count.div_ceil(MAX_SAMPLES).max(1);
+    img.pixels().step_by(step).copied().collect()
+}
+
+// Median cut: the box of colours spanning the widest range on one channel is
+// split at the median of that channel until there are enough boxes, and each
+// box is then represented by its mean colour.
+fn median_cut(pixels: Vec<Rgb<u8>>, n_couleurs: usize) -> Vec<Rgb<u8>> {
+    if pixels.is_empty() || n_couleurs == 0 {
+        return Vec::new();
+    }
+
+    let mut boxes = vec![pixels];
+    while boxes.len() < n_couleurs {
+        let widest = boxes.iter()
+            .enumerate()
+            .map(|(i, colours)| (i, widest_channel(colours)))
+            .max_by_key(|&(_, (_, range))| range);
+        let Some((i, (channel, range))) = widest else { break };
+        if range == 0 {
+            // Every box holds a single colour
+            break;
+        }
+
+        let mut colours = boxes.swap_remove(i);
+        colours.sort_unstable_by_key(|colour| colour[channel]);
+        let upper = colours.split_off(colours.len() / 2);
+        boxes.push(colours);
+        boxes.push(upper);
+    }
+
+    // Boxes split among equal colours can share the same mean
+    let mut palette: Vec<Rgb<u8>> = Vec::new();
+    for colour in boxes.iter().map(|colours| mean_colour(colours)) {
+        if !palette.contains(&colour) {
+            palette.push(colour);
+        }
+    }
+    palette
+}
+
+// The channel with the widest range of values, and that range
+fn widest_channel(colours: &[Rgb<u8>]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let min = colours.iter().map(|colour| colour[c]).min().unwrap_or(0);
+            let max = colours.iter().map(|colour| colour[c]).max().unwrap_or(0);
+            (c, max - min)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+fn mean_colour(colours: &[Rgb<u8>]) -> Rgb<u8> {
+    let count = colours.len() as u64;
+    let mut sums = [0u64; 3];
+    for colour in colours {
+        for c in 0..3 {
+            sums[c] += colour[c] as u64;
+        }
+    }
+    Rgb(sums.map(|sum| ((sum + count / 2) / count) as u8))
+}
+
+fn squared_distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+fn to_f64(colour: &Rgb<u8>) -> [f64; 3] {
+    colour.0.map(|c| c as f64)
+}
+
+// Index of the centroid closest to `point`
+fn nearest_centroid(centroids: &[[f64; 3]], point: [f64; 3]) -> usize {
+    let mut best = 0;
+    let mut best_distance = f64::INFINITY;
+    for (i, &centroid) in centroids.iter().enumerate() {
+        let distance = squared_distance(centroid, point);
+        if distance < best_distance {
+            best_distance = distance;
+            best = i;
+        }
+    }
+    best
+}
+
+// k-means in RGB, initialized with k-means++: each new centroid is a pixel
+// drawn with a probability proportional to its squared distance to the
+// closest centroid already chosen
+fn kmeans(pixels: &[Rgb<u8>], n_couleurs: usize, iterations: usize, rng: &mut Rng) -> Vec<Rgb<u8>> {
+    if pixels.is_empty() || n_couleurs == 0 {
+        return Vec::new();
+    }
+    let points: Vec<[f64; 3]> = pixels.iter().map(to_f64).collect();
+
+    let mut centroids = vec![points[rng.below(points.len() as u64) as usize]];
+    let mut distances: Vec<f64> = points.iter().map(|&p| squared_distance(p, centroids[0])).collect();
+    while centroids.len() < n_couleurs {
+        let total: f64 = distances.iter().sum();
+        if total == 0.0 {
+            // Fewer distinct colours than requested
+            break;
+        }
+        let mut target = rng.next_f64() * total;
+        let mut chosen = points.len() - 1;
+        for (i, &distance) in distances.iter().enumerate() {
+            if target < distance {
+                chosen = i;
+                break;
+            }
+            target -= distance;
+        }
+        let centroid = points[chosen];
+        centroids.push(centroid);
+        for (distance, &p) in distances.iter_mut().zip(&points) {
+            *distance = distance.min(squared_distance(p, centroid));
+        }
+    }
+
+    let mut assignments = vec![usize::MAX; points.len()];
+    for _ in 0..iterations {
+        let mut changed = false;
+        for (assignment, &p) in assignments.iter_mut().zip(&points) {
+            let nearest = nearest_centroid(&centroids, p);
+            if *assignment != nearest {
+                *assignment = nearest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![[0.0; 3]; centroids.len()];
+        let mut counts = vec![0usize; centroids.len()];
+        for (&assignment, &p) in assignments.iter().zip(&points) {
+            for c in 0..3 {
+                sums[assignment][c] += p[c];
+            }
+            counts[assignment] += 1;
+        }
+        for ((centroid, sum), &count) in centroids.iter_mut().zip(&sums).zip(&counts) {
+            // An empty cluster keeps its previous centroid
+            if count > 0 {
+                *centroid = sum.map(|s| s / count as f64);
+            }
+        }
+    }
+
+    let mut palette: Vec<Rgb<u8>> = Vec::new();
+    for centroid in centroids {
+        let colour = Rgb(centroid.map(|c| c.round() as u8));
+        if !palette.contains(&colour) {
+            palette.push(colour);
+        }
+    }
+    palette
+}
+
+// Depth of the octree: one level per bit of the channels
+const OCTREE_DEPTH: usize = 8;
+
+#[derive(Default)]
+struct OctreeNode {
+    // Sum and number of the pixels in the subtree
+    sums: [u64; 3],
+    count: u64,
+    children: [Option<usize>; 8],
+}
+
+impl OctreeNode {
+    fn is_leaf(&self) -> bool {
+        self.children.iter().all(Option::is_none)
+    }
+}
+
+// Octree quantization: each pixel goes down the tree following the bits of its
+// channels, from the most significant one, so that every distinct colour ends
+// in its own leaf. The lightest nodes of the deepest level are then merged
+// with their children until there are few enough leaves.
+fn octree(pixels: &[Rgb<u8>], n_couleurs: usize) -> Vec<Rgb<u8>> {
+    if pixels.is_empty() || n_couleurs == 0 {
+        return Vec::new();
+    }
+
+    let mut nodes = vec![OctreeNode::default()];
+    // Inner nodes of every level, the root being at level 0
+    let mut levels: Vec<Vec<usize>> = vec![Vec::new(); OCTREE_DEPTH];
+    levels[0].push(0);
+
+    for pixel in pixels {
+        let mut node = 0;
+        for depth in 0..=OCTREE_DEPTH {
+            for c in 0..3 {
+                nodes[node].sums[c] += pixel[c] as u64;
+            }
+            nodes[node].count += 1;
+            if depth == OCTREE_DEPTH {
+                break;
+            }
+
+            let bit = 7 - depth;
+            let index = (((pixel[0] >> bit) & 1) << 2 | ((pixel[1] >> bit) & 1) << 1 | ((pixel[2] >> bit) & 1)) as usize;
+            node = match nodes[node].children[index] {
+                Some(child) => child,
+                None => {
+                    let child = nodes.len();
+                    nodes.push(OctreeNode::default());
+                    nodes[node].children[index] = Some(child);
+                    if depth + 1 < OCTREE_DEPTH {
+                        levels[depth + 1].push(child);
+                    }
+                    child
+                }
+            };
+        }
+    }
+
+    // Once a level has been entirely reduced, the children of the level above
+    // are all leaves, so merging a node removes all of them but one
+    let mut leaves = nodes.iter().filter(|node| node.is_leaf()).count();
+    'reduction: for level in levels.iter_mut().rev() {
+        level.sort_by_key(|&i| nodes[i].count);
+        for &i in level.iter() {
+            if leaves <= n_couleurs {
+                break 'reduction;
+            }
+            let children = nodes[i].children.iter().flatten().count();
+            nodes[i].children = [None; 8];
+            leaves -= children - 1;
+        }
+    }
+
+    let mut palette = Vec::new();
+    let mut stack = vec![0];
+    while let Some(i) = stack.pop() {
+        let node = &nodes[i];
+        if node.is_leaf() {
+            palette.push(Rgb(node.sums.map(|sum| ((sum + node.count / 2) / node.count) as u8)));
+        } else {
+            stack.extend(node.children.iter().flatten());
+        }
+    }
+    palette
+}
+
+// Learning parameters of NeuQuant, from Dekker's reference implementation
+const NEUQUANT_CYCLES: usize = 100;
+const NEUQUANT_PRIMES: [usize; 4] = [499, 491, 487, 503];
+const NEUQUANT_BETA: f64 = 1.0 / 1024.0;
+const NEUQUANT_RADIUS_DECREASE: f64 = 30.0;
+
+// NeuQuant (Dekker, 1994): a one-dimensional self-organizing map of
+// `n_couleurs` neurons, trained on pixels sampled one in `quality`. Each sample
+// pulls its closest neuron and, less and less strongly as the training goes
+// on, the neurons next to it in the map; a frequency-based bias keeps rarely
+// winning neurons in the contest.
+fn neuquant(pixels: &[Rgb<u8>], n_couleurs: usize, quality: usize) -> Vec<Rgb<u8>> {
+    if pixels.is_empty() || n_couleurs == 0 {
+        return Vec::new();
+    }
+
+    let mut network: Vec<[f64; 3]> = (0..n_couleurs).map(|i| [(i * 256 / n_couleurs) as f64; 3]).collect();
+    let mut freq = vec![1.0 / n_couleurs as f64; n_couleurs];
+    let mut bias = vec![0.0; n_couleurs];
+
+    // Small images are learnt from every pixel
+    let quality = if pixels.len() < NEUQUANT_PRIMES[3] { 1 } else { quality };
+    let alpha_decrease = 30.0 + (quality - 1) as f64 / 3.0;
+    let samples = pixels.len() / quality;
+    let delta = (samples / NEUQUANT_CYCLES).max(1);
+    // Stepping through the pixels by a prime that doesn't divide their count
+    // visits them in a scattered order
+    let step = NEUQUANT_PRIMES.iter()
+        .copied()
+        .find(|&prime| !pixels.len().is_multiple_of(prime))
+        .unwrap_or(NEUQUANT_PRIMES[3]);
+
+    let mut alpha = 1.0;
+    let mut radius = (n_couleurs / 8) as f64;
+    let mut position = 0;
+    for i in 0..samples {
+        let pixel = to_f64(&pixels[position]);
+
+        // Contest: the winner is chosen on the biased distance, while the
+        // frequencies and biases follow the unbiased one
+        let mut best = (f64::INFINITY, 0);
+        let mut best_biased = (f64::INFINITY, 0);
+        for (j, neuron) in network.iter().enumerate() {
+            let distance: f64 = (0..3).map(|c| (neuron[c] - pixel[c]).abs()).sum();
+            if distance < best.0 {
+                best = (distance, j);
+            }
+            let biased = distance - bias[j];
+            if biased < best_biased.0 {
+                best_biased = (biased, j);
+            }
+            let beta_freq = NEUQUANT_BETA * freq[j];
+            freq[j] -= beta_freq;
+            bias[j] += beta_freq / NEUQUANT_BETA;
+        }
+        freq[best.1] += NEUQUANT_BETA;
+        bias[best.1] -= 1.0;
+        let winner = best_biased.1;
+
+        for c in 0..3 {
+            network[winner][c] -= alpha * (network[winner][c] - pixel[c]);
+        }
+        let rad = radius as usize;
+        if rad > 1 {
+            let lo = winner.saturating_sub(rad - 1);
+            let hi = (winner + rad).min(n_couleurs);
+            for (j, neuron) in network.iter_mut().enumerate().take(hi).skip(lo) {
+                if j == winner {
+                    continue;
+                }
+                let m = j.abs_diff(winner) as f64;
+                let r = rad as f64;
+                let a = alpha * (r * r - m * m) / (r * r);
+                for c in 0..3 {
+                    neuron[c] -= a * (neuron[c] - pixel[c]);
+                }
+            }
+        }
+
+        position = (position + step) % pixels.len();
+        if (i + 1) % delta == 0 {
+            alpha -= alpha / alpha_decrease;
+            radius -= radius / NEUQUANT_RADIUS_DECREASE;
+        }
+    }
+
+    let mut palette: Vec<Rgb<u8>> = Vec::new();
+    for neuron in network {
+        let colour = Rgb(neuron.map(|c| c.round().clamp(0.0, 255.0) as u8));
+        if !palette.contains(&colour) {
+            palette.push(colour);
+        }
+    }
+    palette
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kmeans_recovers_the_two_colours_of_a_two_colour_image() {
+        let (red, teal) = (Rgb([200, 30, 40]), Rgb([20, 150, 160]));
+        let img = RgbImage::from_fn(16, 16, |x, y| if (x * y) % 3 == 0 { red } else { teal });
+        for seed in 0..8 {
+            // The colours themselves, which `palette` would return without
+            // running k-means
+            let mut colours = kmeans(&sample_pixels(&img), 2, DEFAULT_ITERATIONS, &mut Rng::new(seed));
+            colours.sort_by_key(|colour| colour.0);
+            assert_eq!(colours, [teal, red], "graine {}", seed);
+        }
+    }
+
+    #[test]
+    fn octree_gives_at_most_the_colours_requested_and_greys_for_a_grey_image() {
+        let img = RgbImage::from_fn(64, 64, |x, y| Rgb([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8]));
+        let options = QuantizeOptions { iterations: DEFAULT_ITERATIONS, seed: 0, quality: DEFAULT_QUALITY };
+        for n in [1, 2, 3, 7, 8, 9, 16, 64, 255, 256] {
+            let palette = Quantizer::Octree.palette(&img, n, &options);
+            assert!(!palette.is_empty() && palette.len() <= n, "{} couleurs pour {} demandées", palette.len(), n);
+        }
+
+        let grey = RgbImage::from_fn(64, 64, |x, y| Rgb([((x * 64 + y) % 256) as u8; 3]));
+        for n in [2, 5, 16] {
+            let palette = Quantizer::Octree.palette(&grey, n, &options);
+            assert!(palette.colours().iter().all(|Rgb([r, g, b])| r == g && g == b), "{:?}", palette.colours());
+        }
+    }
+
+    // Peak signal-to-noise ratio of `result` against `original`, in decibels
+    fn psnr(original: &RgbImage, result: &RgbImage) -> f64 {
+        let squared: f64 = original.as_raw().iter().zip(result.as_raw()).map(|(&a, &b)| (a as f64 - b as f64).powi(2)).sum();
+        let mse = squared / original.as_raw().len() as f64;
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+
+    #[test]
+    fn neuquant_renders_a_photo_better_than_the_fixed_palette() {
+        let photo = image::load_from_memory(include_bytes!("myimage.jpeg")).unwrap().to_rgb8();
+        let map = |palette: &Palette| crate::palette::modify_image_palette(photo.clone(), palette, crate::distance::Distance::Rgb, false).unwrap();
+        let fixed = Palette::builtin(9);
+        let options = QuantizeOptions { iterations: DEFAULT_ITERATIONS, seed: 0, quality: DEFAULT_QUALITY };
+        let neuquant = Quantizer::NeuQuant.palette(&photo, fixed.len(), &options);
+        assert!(neuquant.len() <= fixed.len());
+        let (fixed_psnr, neuquant_psnr) = (psnr(&photo, &map(&fixed)), psnr(&photo, &map(&neuquant)));
+        assert!(neuquant_psnr > fixed_psnr + 3.0, "{:.2} dB contre {:.2} dB avec la palette fixe", neuquant_psnr, fixed_psnr);
+        // More colours only bring it closer
+        let larger = Quantizer::NeuQuant.palette(&photo, 64, &options);
+        assert!(psnr(&photo, &map(&larger)) > neuquant_psnr);
+    }
+
+    #[test]
+    fn kmeans_finds_the_centres_of_clusters_of_more_colours_than_requested() {
+        // Two clouds of colours around a red and a teal, each pixel a little
+        // off its centre
+        let (red, teal) = ([200, 30, 40], [20, 150, 160]);
+        let mut rng = Rng::new(27);
+        let img = RgbImage::from_fn(32, 32, |x, _| {
+            let centre = if x < 16 { red } else { teal };
+            Rgb(centre.map(|channel| channel + rng.below(9) as u8 - 4))
+        });
+        let options = QuantizeOptions { iterations: DEFAULT_ITERATIONS, seed: 3, quality: DEFAULT_QUALITY };
+        let mut palette = Quantizer::KMeans.palette(&img, 2, &options).colours().to_vec();
+        palette.sort_by_key(|colour| colour.0);
+        assert_eq!(palette.len(), 2);
+        for (colour, centre) in palette.iter().zip([teal, red]) {
+            assert!(colour.0.iter().zip(centre).all(|(&c, centre)| c.abs_diff(centre) <= 1), "{:?} au lieu de {:?}", colour, centre);
+        }
+    }
+}
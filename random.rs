@@ -0,0 +1,86 @@
+//! A small deterministic pseudo-random generator (SplitMix64), so that seeded
+//! outputs are identical on every run and platform.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// A SplitMix64 generator.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// A generator giving the same values for the same `seed`.
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// A generator seeded from the random keys std gets from the OS.
+    pub fn from_entropy() -> Rng {
+        Rng::new(RandomState::new().build_hasher().finish())
+    }
+
+    /// A uniformly distributed 64-bit value.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A uniformly distributed value in 0..1.
+    pub fn next_f64(&mut self) -> f64 {
+        // The 53 high bits fill exactly the mantissa of an f64
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A uniformly distributed integer in 0..bound.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        // Rejecting the last incomplete range avoids the modulo bias
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+}
+
+/// A uniform value in 0..1 that only depends on the seed and the position, so
+/// that it doesn't matter in which order the pixels are visited.
+pub fn position_noise(seed: u64, x: u32, y: u32) -> f32 {
+    let mut rng = Rng::new(seed ^ ((y as u64) << 32 | x as u64));
+    // The 24 high bits fill exactly the mantissa of an f32
+    (rng.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_seed_gives_the_same_values_spread_over_their_range() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+        assert!((0..100).all(|_| a.next_u64() == b.next_u64()));
+        assert_ne!(Rng::new(42).next_u64(), Rng::new(43).next_u64());
+
+        let mut rng = Rng::new(7);
+        let mut counts = [0u32; 6];
+        for _ in 0..60_000 {
+            counts[rng.below(6) as usize] += 1;
+        }
+        assert!(counts.iter().all(|&count| (9_500..10_500).contains(&count)), "{:?}", counts);
+        assert!((0..1000).map(|_| rng.next_f64()).all(|value| (0.0..1.0).contains(&value)));
+    }
+
+    #[test]
+    fn position_noise_depends_on_the_position_only() {
+        let forward: Vec<f32> = (0..64).map(|i| position_noise(3, i % 8, i / 8)).collect();
+        let backward: Vec<f32> = (0..64).rev().map(|i| position_noise(3, i % 8, i / 8)).collect();
+        assert!(forward.iter().eq(backward.iter().rev()));
+        assert!(forward.iter().all(|value| (0.0..1.0).contains(value)));
+        assert_ne!(position_noise(3, 1, 0), position_noise(3, 0, 1));
+    }
+}
@@ -0,0 +1,70 @@
+//! The `redim` stage of a pipeline, which scales the image before the
+//! operations that follow, to a size given as `800x600`, or by one side only,
+//! as `800x` or `x600`, the other following the proportions of the image.
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+/// The size of `redim`, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Size {
+    /// None to follow the height
+    pub width: Option<u32>,
+    /// None to follow the width
+    pub height: Option<u32>,
+}
+
+/// Parses a size such as `800x600`, `800x` or `x600`.
+pub fn parse_size(value: &str) -> Result<Size, String> {
+    let invalid = || format!("taille invalide : {} (attendu : LARGEURxHAUTEUR, LARGEURx ou xHAUTEUR, en pixels)", value);
+    let (width, height) = value.split_once(['x', '×']).ok_or_else(invalid)?;
+    let side = |side: &str| -> Result<Option<u32>, String> {
+        match side {
+            "" => Ok(None),
+            side => side.parse::<u32>().ok().filter(|&pixels| pixels >= 1).map(Some).ok_or_else(invalid),
+        }
+    };
+    let size = Size { width: side(width)?, height: side(height)? };
+    if size.width.is_none() && size.height.is_none() {
+        return Err(invalid());
+    }
+    Ok(size)
+}
+
+impl Size {
+    /// `img` scaled to this size, with a Lanczos filter.
+    pub fn resize(&self, img: &DynamicImage) -> DynamicImage {
+        let proportional = |side: u32, from: u32, to: u32| ((side as u64 * to as u64 + from as u64 / 2) / from.max(1) as u64).max(1) as u32;
+        let (width, height) = match (self.width, self.height) {
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, proportional(img.height(), img.width(), width)),
+            (None, Some(height)) => (proportional(img.width(), img.height(), height), height),
+            (None, None) => (img.width(), img.height()),
+        };
+        img.resize_exact(width, height, FilterType::Lanczos3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    #[test]
+    fn a_missing_side_follows_the_proportions() {
+        assert_eq!(parse_size("800x600"), Ok(Size { width: Some(800), height: Some(600) }));
+        assert_eq!(parse_size("800x"), Ok(Size { width: Some(800), height: None }));
+        assert_eq!(parse_size("×600"), Ok(Size { width: None, height: Some(600) }));
+        for invalid in ["x", "800", "0x600", "-1x", "axb"] {
+            assert!(parse_size(invalid).is_err(), "{}", invalid);
+        }
+
+        let img = DynamicImage::ImageRgb8(RgbImage::new(400, 300));
+        let resized = |size: &str| parse_size(size).unwrap().resize(&img);
+        assert_eq!(resized("200x").height(), 150);
+        assert_eq!(resized("x100").width(), 133);
+        assert_eq!((resized("50x50").width(), resized("50x50").height()), (50, 50));
+        assert_eq!(resized("x1").width(), 1);
+    }
+}
@@ -0,0 +1,298 @@
+//! Explicit SIMD for two loops that the compiler vectorizes poorly as
+//! written: the luma of seuil and the nearest colour of small palettes. On
+//! x86_64 processors with AVX2, detected at run time, they take eight pixels
+//! at a time, the channels of each pixel spread over a 32-bit lane. The
+//! scalar loops run everywhere else and on the pixels left over at the end
+//! of a row, with the same results.
+
+use image::Rgb;
+
+use crate::distance::squared_distance;
+
+// The weights of `Pixel::to_luma` on 8-bit RGB, whose luma is their sum
+// divided by LUMA_DIVISOR and rounded down: it reaches a threshold exactly
+// when the sum reaches LUMA_DIVISOR times it
+const LUMA_WEIGHTS: [i32; 3] = [2126, 7152, 722];
+const LUMA_DIVISOR: i32 = 10_000;
+
+/// The instructions the loops run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Isa {
+    /// One pixel at a time, on any processor
+    Scalar,
+    /// Eight pixels at a time, on x86_64 processors with AVX2; the scalar
+    /// loops run on the others
+    Avx2,
+}
+
+impl Isa {
+    /// The fastest instructions this processor has.
+    pub fn detect() -> Isa {
+        if avx2() { Isa::Avx2 } else { Isa::Scalar }
+    }
+}
+
+// Whether the processor has AVX2, which the standard library checks once
+#[cfg(target_arch = "x86_64")]
+fn avx2() -> bool {
+    is_x86_feature_detected!("avx2")
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn avx2() -> bool {
+    false
+}
+
+/// Sets the pixels of `rgb`, packed 8-bit RGB, whose luma reaches
+/// `threshold` to `light` and the others to `dark`, as `modify_image_seuil`
+/// does without linear light.
+pub fn seuil_row(isa: Isa, rgb: &mut [u8], threshold: u8, light: Rgb<u8>, dark: Rgb<u8>) {
+    let done = match isa {
+        // SAFETY: the processor has AVX2
+        #[cfg(target_arch = "x86_64")]
+        Isa::Avx2 if avx2() => unsafe { x86::seuil_row(rgb, threshold, light, dark) },
+        _ => 0,
+    };
+    for pixel in rgb[done..].chunks_exact_mut(3) {
+        let sum: i32 = pixel.iter().zip(LUMA_WEIGHTS).map(|(&channel, weight)| channel as i32 * weight).sum();
+        let colour = if sum >= LUMA_DIVISOR * threshold as i32 { light } else { dark };
+        pixel.copy_from_slice(&colour.0);
+    }
+}
+
+/// Replaces each pixel of `rgb`, packed 8-bit RGB, by the colour of
+/// `colours` nearest to it by the rgb distance, the first of equally near
+/// colours winning, as `PaletteMatcher::nearest_pixel` does. Each pixel is
+/// compared to every colour, which suits small palettes.
+///
+/// # Panics
+///
+/// If `colours` is empty.
+pub fn nearest_row(isa: Isa, rgb: &mut [u8], colours: &[Rgb<u8>]) {
+    assert!(!colours.is_empty(), "nearest colour of an empty palette");
+    let done = match isa {
+        // SAFETY: the processor has AVX2
+        #[cfg(target_arch = "x86_64")]
+        Isa::Avx2 if avx2() => unsafe { x86::nearest_row(rgb, colours) },
+        _ => 0,
+    };
+    for pixel in rgb[done..].chunks_exact_mut(3) {
+        let value = Rgb([pixel[0], pixel[1], pixel[2]]);
+        let mut best_distance = u32::MAX;
+        let mut best_colour = colours[0];
+        for &colour in colours {
+            let distance = squared_distance(colour, value);
+            if distance < best_distance {
+                best_distance = distance;
+                best_colour = colour;
+            }
+        }
+        pixel.copy_from_slice(&best_colour.0);
+    }
+}
+
+// Written with the intrinsics of std::arch rather than portable vectors:
+// std::simd only builds on nightly compilers, and a crate such as `wide` is
+// not among the dependencies, which offline builds could not fetch. Both
+// loops also lean on byte shuffles that portable vectors offer less directly.
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use image::Rgb;
+
+    use super::{LUMA_DIVISOR, LUMA_WEIGHTS};
+
+    // The bytes of the eight pixels taken at a time
+    const BLOCK: usize = 24;
+    // A shuffle index that gives a zero byte
+    const Z: i8 = -128;
+
+    // The eight pixels of `block` in 32-bit lanes: red in the low 16 bits
+    // and green in the high ones of the first vector, blue in the low 16 bits
+    // of the second, so that `_mm256_madd_epi16` weighs and adds red and
+    // green at once
+    #[target_feature(enable = "avx2")]
+    fn lanes(block: &[u8]) -> (__m256i, __m256i) {
+        assert_eq!(block.len(), BLOCK);
+        // SAFETY: both loads read 16 of the 24 bytes of the block, the first
+        // four pixels in bytes 0 to 11 of the first, the last four in bytes 4
+        // to 15 of the second
+        let (first, last) = unsafe { (_mm_loadu_si128(block.as_ptr().cast()), _mm_loadu_si128(block.as_ptr().add(8).cast())) };
+        let rg_first = _mm_setr_epi8(0, Z, 1, Z, 3, Z, 4, Z, 6, Z, 7, Z, 9, Z, 10, Z);
+        let rg_last = _mm_setr_epi8(4, Z, 5, Z, 7, Z, 8, Z, 10, Z, 11, Z, 13, Z, 14, Z);
+        let b_first = _mm_setr_epi8(2, Z, Z, Z, 5, Z, Z, Z, 8, Z, Z, Z, 11, Z, Z, Z);
+        let b_last = _mm_setr_epi8(6, Z, Z, Z, 9, Z, Z, Z, 12, Z, Z, Z, 15, Z, Z, Z);
+        (
+            _mm256_set_m128i(_mm_shuffle_epi8(last, rg_last), _mm_shuffle_epi8(first, rg_first)),
+            _mm256_set_m128i(_mm_shuffle_epi8(last, b_last), _mm_shuffle_epi8(first, b_first)),
+        )
+    }
+
+    // `colour` over the 24 bytes of a block, in two halves
+    #[target_feature(enable = "avx2")]
+    fn repeated(colour: Rgb<u8>) -> (__m128i, __m128i) {
+        let mut bytes = [0u8; 32];
+        for pixel in bytes[..BLOCK].chunks_exact_mut(3) {
+            pixel.copy_from_slice(&colour.0);
+        }
+        // SAFETY: both loads read 16 of the 32 bytes
+        unsafe { (_mm_loadu_si128(bytes.as_ptr().cast()), _mm_loadu_si128(bytes.as_ptr().add(16).cast())) }
+    }
+
+    // `super::seuil_row` on the whole blocks of `rgb`, giving the number of
+    // bytes done
+    #[target_feature(enable = "avx2")]
+    pub(super) fn seuil_row(rgb: &mut [u8], threshold: u8, light: Rgb<u8>, dark: Rgb<u8>) -> usize {
+        let rg_weights = _mm256_set1_epi32(LUMA_WEIGHTS[0] | LUMA_WEIGHTS[1] << 16);
+        let b_weights = _mm256_set1_epi32(LUMA_WEIGHTS[2]);
+        // Light when the weighted sum is above this
+        let dark_up_to = _mm256_set1_epi32(LUMA_DIVISOR * threshold as i32 - 1);
+        // The first 16 bytes of the block are pixels 0 to 4 and the red of
+        // pixel 5, whose lanes are 0 to 3 of the first half and 0 and 1 of the
+        // last; the other 8 bytes are the rest of pixels 5 to 7
+        let first_of_first = _mm_setr_epi8(0, 0, 0, 4, 4, 4, 8, 8, 8, 12, 12, 12, Z, Z, Z, Z);
+        let first_of_last = _mm_setr_epi8(Z, Z, Z, Z, Z, Z, Z, Z, Z, Z, Z, Z, 0, 0, 0, 4);
+        let last_of_last = _mm_setr_epi8(4, 4, 8, 8, 8, 12, 12, 12, Z, Z, Z, Z, Z, Z, Z, Z);
+        let light_bytes = repeated(light);
+        let dark_bytes = repeated(dark);
+        for block in rgb.chunks_exact_mut(BLOCK) {
+            let (rg, b) = lanes(block);
+            let sum = _mm256_add_epi32(_mm256_madd_epi16(rg, rg_weights), _mm256_madd_epi16(b, b_weights));
+            let light_lanes = _mm256_cmpgt_epi32(sum, dark_up_to);
+            let (first, last) = (_mm256_castsi256_si128(light_lanes), _mm256_extracti128_si256(light_lanes, 1));
+            // The lane of each pixel spread over its three bytes
+            let first_mask = _mm_or_si128(_mm_shuffle_epi8(first, first_of_first), _mm_shuffle_epi8(last, first_of_last));
+            let last_mask = _mm_shuffle_epi8(last, last_of_last);
+            let first_bytes = _mm_blendv_epi8(dark_bytes.0, light_bytes.0, first_mask);
+            let last_bytes = _mm_blendv_epi8(dark_bytes.1, light_bytes.1, last_mask);
+            // SAFETY: the stores write the 16 and then the 8 bytes of the block
+            unsafe {
+                _mm_storeu_si128(block.as_mut_ptr().cast(), first_bytes);
+                _mm_storel_epi64(block.as_mut_ptr().add(16).cast(), last_bytes);
+            }
+        }
+        rgb.len() / BLOCK * BLOCK
+    }
+
+    // `super::nearest_row` on the whole blocks of `rgb`, giving the number of
+    // bytes done
+    #[target_feature(enable = "avx2")]
+    pub(super) fn nearest_row(rgb: &mut [u8], colours: &[Rgb<u8>]) -> usize {
+        // Each colour in the lanes of `lanes`, in every lane
+        let entries: Vec<(__m256i, __m256i)> = colours.iter()
+            .map(|&Rgb([r, g, b])| (_mm256_set1_epi32(r as i32 | (g as i32) << 16), _mm256_set1_epi32(b as i32)))
+            .collect();
+        for block in rgb.chunks_exact_mut(BLOCK) {
+            let (rg, b) = lanes(block);
+            let mut best_distance = _mm256_set1_epi32(i32::MAX);
+            let mut best_index = _mm256_setzero_si256();
+            for (i, &(entry_rg, entry_b)) in entries.iter().enumerate() {
+                // The differences fit in 16 bits, their squares summed in 32
+                let (rg_difference, b_difference) = (_mm256_sub_epi16(rg, entry_rg), _mm256_sub_epi16(b, entry_b));
+                let distance = _mm256_add_epi32(_mm256_madd_epi16(rg_difference, rg_difference), _mm256_madd_epi16(b_difference, b_difference));
+                // Strictly nearer only, so that the first of ties stays
+                let nearer = _mm256_cmpgt_epi32(best_distance, distance);
+                best_distance = _mm256_min_epi32(best_distance, distance);
+                best_index = _mm256_blendv_epi8(best_index, _mm256_set1_epi32(i as i32), nearer);
+            }
+            let mut indices = [0i32; 8];
+            // SAFETY: `indices` holds the 32 bytes stored
+            unsafe { _mm256_storeu_si256(indices.as_mut_ptr().cast(), best_index) };
+            for (pixel, index) in block.chunks_exact_mut(3).zip(indices) {
+                pixel.copy_from_slice(&colours[index as usize].0);
+            }
+        }
+        rgb.len() / BLOCK * BLOCK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Luma, Pixel};
+
+    use super::*;
+    use crate::random::Rng;
+    use crate::{Palette, BLACK, WHITE};
+
+    const ROUNDS: usize = if cfg!(debug_assertions) { 300 } else { 3000 };
+
+    // `pixels` random pixels, packed; one in four a grey, near the ties of
+    // the luma and between colours
+    fn random_row(rng: &mut Rng, pixels: usize) -> Vec<u8> {
+        (0..pixels).flat_map(|_| match rng.below(4) {
+            0 => [rng.below(256) as u8; 3],
+            _ => [0, 1, 2].map(|_| rng.below(256) as u8),
+        }).collect()
+    }
+
+    fn random_palette(rng: &mut Rng) -> Palette {
+        let count = 1 + rng.below(31) as usize;
+        // Few levels, so that colours repeat and pixels lie halfway between some
+        let levels = [2, 3, 5, 256][rng.below(4) as usize];
+        let level = |rng: &mut Rng| (rng.below(levels) * 255 / (levels - 1).max(1)) as u8;
+        Palette::new((0..count).map(|_| Rgb([level(rng), level(rng), level(rng)])).collect())
+    }
+
+    // On processors without AVX2, both sides run the scalar loops; the row
+    // lengths leave every number of pixels over after the blocks of eight
+    #[test]
+    fn seuil_rows_give_the_pixels_of_the_scalar_loop() {
+        let mut rng = Rng::new(97);
+        let isa = Isa::detect();
+        for round in 0..ROUNDS {
+            let row = random_row(&mut rng, round % 41);
+            let threshold = match round % 5 {
+                0 => 0,
+                1 => 255,
+                _ => rng.below(256) as u8,
+            };
+            let (light, dark) = (Rgb([rng.below(256) as u8, 1, 2]), Rgb([3, rng.below(256) as u8, 5]));
+            let (mut fast, mut scalar) = (row.clone(), row.clone());
+            seuil_row(isa, &mut fast, threshold, light, dark);
+            seuil_row(Isa::Scalar, &mut scalar, threshold, light, dark);
+            assert_eq!(fast, scalar, "{:?}, seuil {} sur {:?}", isa, threshold, row);
+        }
+    }
+
+    #[test]
+    fn scalar_seuil_compares_the_luma_of_to_luma() {
+        let mut rng = Rng::new(970);
+        for _ in 0..ROUNDS {
+            let mut row = random_row(&mut rng, 16);
+            let threshold = rng.below(256) as u8;
+            let expected: Vec<u8> = row.chunks_exact(3).flat_map(|pixel| {
+                let Luma([luma]) = Rgb::from_slice(pixel).to_luma();
+                if luma >= threshold { WHITE.0 } else { BLACK.0 }
+            }).collect();
+            seuil_row(Isa::Scalar, &mut row, threshold, WHITE, BLACK);
+            assert_eq!(row, expected, "seuil {}", threshold);
+        }
+    }
+
+    #[test]
+    fn nearest_rows_give_the_colours_of_the_scalar_loop() {
+        let mut rng = Rng::new(971);
+        let isa = Isa::detect();
+        for round in 0..ROUNDS {
+            let palette = random_palette(&mut rng);
+            let row = random_row(&mut rng, round % 41);
+            let (mut fast, mut scalar) = (row.clone(), row.clone());
+            nearest_row(isa, &mut fast, palette.colours());
+            nearest_row(Isa::Scalar, &mut scalar, palette.colours());
+            assert_eq!(fast, scalar, "{:?}, palette {} sur {:?}", isa, palette, row);
+        }
+    }
+
+    #[test]
+    fn scalar_nearest_gives_the_first_nearest_colour() {
+        let mut rng = Rng::new(972);
+        for _ in 0..ROUNDS {
+            let palette = random_palette(&mut rng);
+            let mut row = random_row(&mut rng, 16);
+            let expected: Vec<u8> = row.chunks_exact(3).flat_map(|pixel| palette.nearest(*Rgb::from_slice(pixel)).1 .0).collect();
+            nearest_row(Isa::Scalar, &mut row, palette.colours());
+            assert_eq!(row, expected, "palette {}", palette);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! The sRGB transfer function, for processing in linear light.
+
+use image::Rgb;
+
+/// Decodes an sRGB channel in 0..=255 to linear light in 0..=1.
+pub fn srgb_to_linear(channel: f64) -> f64 {
+    let c = channel / 255.0;
+    if c <= 0.040_45 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Encodes linear light, clamped to 0..=1, back to an sRGB channel in 0..=255.
+pub fn linear_to_srgb(linear: f64) -> f64 {
+    let l = linear.clamp(0.0, 1.0);
+    let c = if l <= 0.003_130_8 { l * 12.92 } else { 1.055 * l.powf(1.0 / 2.4) - 0.055 };
+    c * 255.0
+}
+
+/// A channel in the space where the processing happens: sRGB itself, or
+/// linear light scaled to 0..=255 so that black and white keep their values.
+pub fn working_value(channel: u8, linear: bool) -> f64 {
+    working_value_f64(channel as f64, linear)
+}
+
+/// `working_value` of a channel in 0..=255 that need not be whole, such as
+/// one decoded from a 16-bit image.
+pub fn working_value_f64(channel: f64, linear: bool) -> f64 {
+    if linear { srgb_to_linear(channel) * 255.0 } else { channel }
+}
+
+/// The Rec. 709 luma of a value in 0..=255, with the weights of `to_luma`;
+/// unlike it, this works on values carrying a diffused error. Applied to
+/// working values in linear light, it gives the luminance on the same scale.
+pub fn rec709_luma(value: [f64; 3]) -> f64 {
+    // Integer weights keep the sum exact for whole values, so that the result
+    // reaches a threshold exactly when `to_luma` does
+    (2126.0 * value[0] + 7152.0 * value[1] + 722.0 * value[2]) / 10_000.0
+}
+
+/// The Rec. 709 relative luminance of an sRGB pixel, in 0..=1.
+pub fn linear_luminance(pixel: &Rgb<u8>) -> f64 {
+    rec709_luma(pixel.0.map(|c| srgb_to_linear(c as f64)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_channel_comes_back_from_linear_light() {
+        for channel in 0..=255u8 {
+            let back = linear_to_srgb(srgb_to_linear(channel as f64));
+            assert!((back - channel as f64).abs() < 1e-9, "{} devenu {}", channel, back);
+        }
+        assert_eq!((srgb_to_linear(0.0), srgb_to_linear(255.0)), (0.0, 1.0));
+        assert!((srgb_to_linear(128.0) - 0.2158605).abs() < 1e-6);
+        // Black and white keep their values in the working space
+        assert_eq!((working_value(0, true), working_value(255, true)), (0.0, 255.0));
+        assert_eq!(working_value(100, false), 100.0);
+    }
+
+    #[test]
+    fn the_luma_of_a_grey_is_the_grey() {
+        for grey in 0..=255u8 {
+            assert!((rec709_luma([grey as f64; 3]) - grey as f64).abs() < 1e-9);
+            let luminance = linear_luminance(&Rgb([grey; 3]));
+            assert!((luminance - srgb_to_linear(grey as f64)).abs() < 1e-12);
+        }
+        assert!(rec709_luma([0.0, 255.0, 0.0]) > rec709_luma([255.0, 0.0, 0.0]));
+    }
+}
@@ -0,0 +1,139 @@
+//! The statistics of `--stats`, written as JSON after the processing so that
+//! scripts can follow what a run did: where the time went, how the pixels were
+//! shared between the colours of the result, and how far they moved.
+
+use std::collections::HashMap;
+
+use image::RgbImage;
+use serde::Serialize;
+
+use crate::info::MAX_COUNTED_COLOURS;
+
+/// What one run did to one input.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunStats {
+    /// The path of the input
+    #[serde(rename = "entree")]
+    pub input: String,
+    /// None when the result was only previewed
+    #[serde(rename = "sortie")]
+    pub output: Option<String>,
+    /// In pixels
+    #[serde(rename = "largeur")]
+    pub width: u32,
+    /// In pixels
+    #[serde(rename = "hauteur")]
+    pub height: u32,
+    /// The mode, or pipeline, that was run
+    pub mode: String,
+    /// The command line after the program name, which holds every option used
+    pub arguments: Vec<String>,
+    /// The time taken by each stage
+    #[serde(rename = "durees")]
+    pub durations: StageDurations,
+    /// The colours of the result, the most used first; None beyond
+    /// `MAX_COUNTED_COLOURS`
+    #[serde(rename = "couleurs")]
+    pub colours: Option<Vec<ColourCount>>,
+    /// The mean distance between the colours of the input and of the result,
+    /// in sRGB values from 0 to 255
+    #[serde(rename = "erreur_moyenne")]
+    pub mean_error: f64,
+}
+
+/// The wall-clock time of each stage, in seconds.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StageDurations {
+    /// Reading and decoding the input
+    pub lecture: f64,
+    /// Converting the image
+    pub traitement: f64,
+    /// None when nothing was written
+    pub ecriture: Option<f64>,
+}
+
+/// The number of pixels of the result in one colour.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ColourCount {
+    /// As #rrggbb
+    #[serde(rename = "couleur")]
+    pub colour: String,
+    /// How many pixels have it
+    pub pixels: u64,
+}
+
+/// The pixels of `img` counted by colour, the most used first and the equal
+/// ones by colour, unless there are more than `MAX_COUNTED_COLOURS`.
+pub fn colour_counts(img: &RgbImage) -> Option<Vec<ColourCount>> {
+    let mut counts: HashMap<[u8; 3], u64> = HashMap::new();
+    for pixel in img.pixels() {
+        *counts.entry(pixel.0).or_default() += 1;
+        if counts.len() > MAX_COUNTED_COLOURS {
+            return None;
+        }
+    }
+    let mut counts: Vec<([u8; 3], u64)> = counts.into_iter().collect();
+    counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+    Some(counts.into_iter().map(|([r, g, b], pixels)| ColourCount { colour: format!("#{:02x}{:02x}{:02x}", r, g, b), pixels }).collect())
+}
+
+/// The mean Euclidean distance in RGB between the pixels of `input` and those
+/// of `output`, which has the same size.
+pub fn mean_error(input: &RgbImage, output: &RgbImage) -> f64 {
+    let total: f64 = input.pixels().zip(output.pixels())
+        .map(|(a, b)| a.0.iter().zip(b.0).map(|(&x, y)| (x as f64 - y as f64).powi(2)).sum::<f64>().sqrt())
+        .sum();
+    total / (input.width() as u64 * input.height() as u64).max(1) as f64
+}
+
+/// `stats` as a JSON document: an array with `batch`, for the runs over
+/// several inputs, even when only one of them succeeded, or else the object of
+/// the single input.
+pub fn stats_json(stats: &[RunStats], batch: bool) -> String {
+    let json = match stats {
+        [one] if !batch => serde_json::to_string_pretty(one),
+        _ => serde_json::to_string_pretty(stats),
+    };
+    // Every field is a string, a number or a list of them
+    json.expect("statistiques sérialisables") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+
+    #[test]
+    fn the_most_used_colours_come_first() {
+        let img = RgbImage::from_fn(4, 2, |x, y| Rgb(match (x + y * 4) % 4 { 0 | 1 => [255, 0, 16], 2 => [0, 0, 0], _ => [0, 0, 1] }));
+        let counts = colour_counts(&img).unwrap();
+        let counted: Vec<(&str, u64)> = counts.iter().map(|count| (count.colour.as_str(), count.pixels)).collect();
+        assert_eq!(counted, [("#ff0010", 4), ("#000000", 2), ("#000001", 2)]);
+
+        let black = RgbImage::new(4, 2);
+        assert_eq!(mean_error(&black, &black), 0.0);
+        let white = RgbImage::from_pixel(4, 2, Rgb([255; 3]));
+        assert!((mean_error(&black, &white) - 255.0 * 3f64.sqrt()).abs() < 1e-9);
+        assert_eq!(mean_error(&RgbImage::new(0, 0), &RgbImage::new(0, 0)), 0.0);
+    }
+
+    #[test]
+    fn a_batch_is_an_array_even_of_one_run() {
+        let run = RunStats {
+            input: "a.png".to_string(),
+            output: None,
+            width: 1,
+            height: 1,
+            mode: "seuil".to_string(),
+            arguments: vec![],
+            durations: StageDurations { lecture: 0.5, traitement: 0.25, ecriture: None },
+            colours: None,
+            mean_error: 0.0,
+        };
+        let single: serde_json::Value = serde_json::from_str(&stats_json(std::slice::from_ref(&run), false)).unwrap();
+        assert_eq!((single["entree"].as_str(), single["durees"]["traitement"].as_f64()), (Some("a.png"), Some(0.25)));
+        let batch: serde_json::Value = serde_json::from_str(&stats_json(&[run], true)).unwrap();
+        assert_eq!(batch.as_array().map(Vec::len), Some(1));
+    }
+}
@@ -0,0 +1,128 @@
+//! Dithering of an image fed one row at a time, from top to bottom, for the
+//! images too large to be held in memory: only the rows of error that the
+//! kernel reaches are kept between two calls.
+
+use image::Rgb;
+
+use crate::alpha::TRANSPARENT;
+use crate::diffusion::RowDiffusion;
+use crate::ditherer::PixelQuantizer;
+use crate::ordered::ThresholdSource;
+use crate::srgb::working_value;
+
+// How the pixels of each row are quantized
+pub(crate) enum Rows<'a> {
+    // The error carried to the rows below
+    Diffusion(RowDiffusion<'a, 3>),
+    // Against the threshold at each place, `force` perturbing palette lookups
+    Ordered { source: &'a ThresholdSource, force: f32 },
+    // Ordered dithering against white noise of its own
+    Random { source: ThresholdSource, force: f32 },
+}
+
+/// Dithers an image row by row, in the order of the rows, with the settings
+/// of the `Dither` it comes from (see `Dither::streamer`). A row fed through
+/// `push_row` gets the same colours as in the whole image dithered at once.
+///
+/// ```
+/// use image::RgbImage;
+/// use tp_eval::ops::dither::Algo;
+/// use tp_eval::Dither;
+///
+/// let img = RgbImage::from_fn(16, 8, |x, y| image::Rgb([(x * 16) as u8, (y * 32) as u8, 128]));
+/// let dither = Dither::new().algorithm(Algo::SierraLite);
+/// let mut streamer = dither.streamer(img.width()).unwrap();
+/// let rows: Vec<u8> = img.rows().flat_map(|row| streamer.push_row(&row.flat_map(|pixel| pixel.0).collect::<Vec<u8>>())).collect();
+/// assert_eq!(rows, dither.apply(&img).unwrap().into_raw());
+/// ```
+pub struct Streamer<'a> {
+    width: u32,
+    y: u32,
+    linear: bool,
+    quantizer: Box<dyn PixelQuantizer + 'a>,
+    rows: Rows<'a>,
+}
+
+impl<'a> Streamer<'a> {
+    pub(crate) fn new(width: u32, linear: bool, quantizer: Box<dyn PixelQuantizer + 'a>, rows: Rows<'a>) -> Streamer<'a> {
+        Streamer { width, y: 0, linear, quantizer, rows }
+    }
+
+    /// The width of the rows, in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The number of rows dithered so far.
+    pub fn rows_done(&self) -> u32 {
+        self.y
+    }
+
+    /// The next row of the image dithered, both as RGB bytes, three per
+    /// pixel.
+    ///
+    /// # Panics
+    ///
+    /// When `row` does not hold `width` pixels.
+    pub fn push_row(&mut self, row: &[u8]) -> Vec<u8> {
+        assert_eq!(row.len() as u64, self.width as u64 * 3, "une ligne de {} pixels RGB compte {} octets", self.width, self.width as u64 * 3);
+        let pixels: Vec<Rgb<u8>> = row.chunks_exact(3).map(|pixel| Rgb([pixel[0], pixel[1], pixel[2]])).collect();
+        self.dither_row(&pixels, |_| false).into_iter().flat_map(|pixel| pixel.0).collect()
+    }
+
+    /// The next row of the image dithered, both as RGBA bytes, four per
+    /// pixel: the alpha channel is kept as it is, and the fully transparent
+    /// pixels spread no error.
+    ///
+    /// # Panics
+    ///
+    /// When `row` does not hold `width` pixels.
+    pub fn push_row_rgba(&mut self, row: &[u8]) -> Vec<u8> {
+        assert_eq!(row.len() as u64, self.width as u64 * 4, "une ligne de {} pixels RGBA compte {} octets", self.width, self.width as u64 * 4);
+        let pixels: Vec<Rgb<u8>> = row.chunks_exact(4).map(|pixel| Rgb([pixel[0], pixel[1], pixel[2]])).collect();
+        let dithered = self.dither_row(&pixels, |x| row[x as usize * 4 + 3] == TRANSPARENT);
+        dithered.into_iter().zip(row.chunks_exact(4)).flat_map(|(Rgb([r, g, b]), pixel)| [r, g, b, pixel[3]]).collect()
+    }
+
+    fn dither_row(&mut self, pixels: &[Rgb<u8>], transparent: impl Fn(u32) -> bool) -> Vec<Rgb<u8>> {
+        let (y, linear) = (self.y, self.linear);
+        self.y += 1;
+        let quantizer = &*self.quantizer;
+        let (source, force) = match &mut self.rows {
+            Rows::Diffusion(rows) => {
+                let values: Vec<[f64; 3]> = pixels.iter().map(|pixel| pixel.0.map(|c| working_value(c, linear))).collect();
+                let mut dithered = pixels.to_vec();
+                rows.next_row(&values, transparent, |value| quantizer.quantize(value), |x, pixel| dithered[x as usize] = pixel);
+                return dithered;
+            }
+            Rows::Ordered { source, force } => (&**source, *force),
+            Rows::Random { source, force } => (&*source, *force),
+        };
+        (0..).zip(pixels).map(|(x, &pixel)| quantizer.quantize_ordered(pixel, source.threshold(x, y), force, linear)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use crate::alpha::TRANSPARENT;
+    use crate::Dither;
+
+    #[test]
+    fn rows_of_rgba_get_the_colours_of_the_whole_image() {
+        let img = RgbaImage::from_fn(13, 9, |x, y| Rgba([(x * 19) as u8, (y * 28) as u8, 90, if (x + y) % 5 == 0 { TRANSPARENT } else { 255 }]));
+        let dither = Dither::new();
+        let mut streamer = dither.streamer(img.width()).unwrap();
+        assert_eq!(streamer.width(), 13);
+        let rows: Vec<u8> = img.rows().flat_map(|row| streamer.push_row_rgba(&row.flat_map(|pixel| pixel.0).collect::<Vec<u8>>())).collect();
+        assert_eq!(streamer.rows_done(), 9);
+        assert_eq!(rows, dither.apply_rgba(&img).unwrap().into_raw());
+    }
+
+    #[test]
+    #[should_panic(expected = "une ligne de 4 pixels RGB compte 12 octets")]
+    fn a_row_of_another_width_is_refused() {
+        Dither::new().streamer(4).unwrap().push_row(&[0; 9]);
+    }
+}
@@ -0,0 +1,118 @@
+//! `--streaming`: a PNG dithered as its rows are decoded, each one written to
+//! the output once done, so that neither the input nor the result is ever
+//! whole in memory.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use image::error::{DecodingError, ImageFormatHint};
+use image::{ImageError, ImageFormat};
+use tp_eval::bilevel::pack_row;
+use tp_eval::icc::{MatrixProfile, ProfileHandling};
+use tp_eval::ops::dither::Progress;
+use tp_eval::orientation::raw_exif_orientation;
+use tp_eval::Dither;
+
+use crate::error::Error;
+use crate::output::{grey_pixels, is_standard_stream, Bits, Output};
+
+/// What is done around the dithering of each row.
+pub struct StreamOptions {
+    /// Whether the result is only black and white, written on 1 bit unless
+    /// `bits` says otherwise
+    pub bilevel: bool,
+    /// The bits of each pixel of the result, whose rows are checked to fit
+    /// in them as they come
+    pub bits: Option<Bits>,
+    pub ignore_exif: bool,
+    pub profile: ProfileHandling,
+    /// Hides the bar counting the rows
+    pub quiet: bool,
+}
+
+/// Dithers the PNG `path` with `dither` into `output`, a PNG too, one row at
+/// a time. Its 16-bit channels are rounded to 8 bits first, and its EXIF
+/// orientation and ICC profile are left aside, with a warning.
+pub fn stream_png(path: &Path, output: Output, dither: &Dither, options: &StreamOptions) -> Result<(), Error> {
+    let input: Box<dyn Read> = if is_standard_stream(path) {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(File::open(path).map_err(|error| Error::reading(path.to_path_buf(), error))?)
+    };
+    let mut decoder = png::Decoder::new(BufReader::new(input));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|error| decoding_failed(path, error))?;
+
+    let info = reader.info();
+    let (width, height) = (info.width, info.height);
+    log::debug!("{} : {} × {}, {:?} sur {:?}", path.display(), width, height, info.color_type, info.bit_depth);
+    if info.interlaced {
+        return Err(Error::InvalidArgument(format!("{} est un PNG entrelacé, dont les lignes ne se lisent pas une à une : retirez --streaming", path.display())));
+    }
+    let orientation = info.exif_metadata.as_deref().and_then(raw_exif_orientation);
+    if !options.ignore_exif && orientation.is_some_and(|orientation| orientation != 1) {
+        log::warn!("l’orientation EXIF de {} n’est pas appliquée avec --streaming", path.display());
+    }
+    let profile = info.icc_profile.as_deref().map(MatrixProfile::parse);
+    if options.profile == ProfileHandling::Convert && profile.is_some_and(|matrix| !matrix.is_some_and(|matrix| matrix.is_srgb())) {
+        log::warn!("le profil ICC de {} n’est pas appliqué avec --streaming, ses couleurs sont traitées comme du sRGB", path.display());
+    }
+
+    let (colour, depth) = reader.output_color_type();
+    let alpha = matches!(colour, png::ColorType::GrayscaleAlpha | png::ColorType::Rgba);
+    let bits = match options.bits {
+        Some(bits) => bits,
+        None if options.bilevel && !alpha => Bits::One,
+        None => Bits::TwentyFour,
+    };
+    let (output_colour, output_depth) = match (bits, alpha) {
+        (Bits::One, _) => (png::ColorType::Grayscale, png::BitDepth::One),
+        (Bits::Eight, false) => (png::ColorType::Grayscale, png::BitDepth::Eight),
+        (Bits::Eight, true) => (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight),
+        (Bits::TwentyFour, false) => (png::ColorType::Rgb, png::BitDepth::Eight),
+        (Bits::TwentyFour, true) => (png::ColorType::Rgba, png::BitDepth::Eight),
+    };
+
+    let mut streamer = dither.streamer(width)?;
+    let progress = Progress::new("lignes", height as u64, options.quiet);
+    output.write_png_rows(width, height, output_colour, output_depth, || {
+        let row = reader.next_row().map_err(|error| decoding_failed(path, error))?.ok_or_else(|| truncated(path))?;
+        let row = match depth {
+            png::BitDepth::Sixteen => row.data().chunks_exact(2).map(|sample| to_8_bits(u16::from_be_bytes([sample[0], sample[1]]))).collect(),
+            _ => row.data().to_vec(),
+        };
+        // Grey pixels go through RGB, as in the whole image
+        let row: Vec<u8> = match colour {
+            png::ColorType::Grayscale => row.iter().flat_map(|&grey| [grey; 3]).collect(),
+            png::ColorType::GrayscaleAlpha => row.chunks_exact(2).flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]]).collect(),
+            _ => row,
+        };
+        let dithered = if alpha { streamer.push_row_rgba(&row) } else { streamer.push_row(&row) };
+        let channels = if alpha { 4 } else { 3 };
+        progress.update(streamer.rows_done() as u64);
+        match bits {
+            Bits::One => pack_row(&dithered, channels).ok_or_else(|| bits.not_fitting()),
+            Bits::Eight => grey_pixels(&dithered, channels).ok_or_else(|| bits.not_fitting()),
+            Bits::TwentyFour => Ok(dithered),
+        }
+    })
+}
+
+// A 16-bit sample rounded to 8 bits, as the decoders of the whole image do
+fn to_8_bits(sample: u16) -> u8 {
+    ((sample as u32 + 128) / 257) as u8
+}
+
+// Errors of reading are kept as such, the others are the content's
+fn decoding_failed(path: &Path, error: png::DecodingError) -> Error {
+    match error {
+        png::DecodingError::IoError(error) => Error::reading(path.to_path_buf(), error),
+        error => Error::DecodeFailed(path.to_path_buf(), ImageError::Decoding(DecodingError::new(ImageFormatHint::Exact(ImageFormat::Png), error))),
+    }
+}
+
+fn truncated(path: &Path) -> Error {
+    let error = DecodingError::new(ImageFormatHint::Exact(ImageFormat::Png), "l’image s’arrête avant sa dernière ligne");
+    Error::DecodeFailed(path.to_path_buf(), ImageError::Decoding(error))
+}
@@ -0,0 +1,108 @@
+//! 1-bit PNG output: black and white images packed eight pixels to a byte,
+//! over widths that leave every number of pixels in the last byte of a row,
+//! decoded back by the png crate as they were packed and by `image` as the
+//! pixels they came from, in files the size of their bits.
+
+use std::io::Cursor;
+
+use image::{DynamicImage, GrayAlphaImage, GrayImage, ImageBuffer, ImageOutputFormat, Luma, Pixel, Rgb, RgbImage, Rgba, RgbaImage};
+use tp_eval::bilevel::{pack_bilevel, pack_row, packed_row_len, write_png};
+use tp_eval::ops::dither::Dither;
+use tp_eval::random::Rng;
+use tp_eval::{BLACK, WHITE};
+
+// Random black and white, one pixel in `1 / density` white
+fn noise(width: u32, height: u32, density: u32, seed: u64) -> RgbImage {
+    let mut rng = Rng::new(seed);
+    RgbImage::from_fn(width, height, |_, _| if rng.below(density as u64) == 0 { WHITE } else { BLACK })
+}
+
+fn encode(img: &DynamicImage) -> Vec<u8> {
+    let packed = pack_bilevel(img).expect("image en noir et blanc");
+    let mut png = Vec::new();
+    write_png(&mut png, img.width(), img.height(), &packed).unwrap();
+    png
+}
+
+#[test]
+fn packed_rows_hold_the_pixels_from_the_highest_bit() {
+    for width in 1..=17 {
+        let img = noise(width, 3, 2, width as u64);
+        let packed = pack_bilevel(&DynamicImage::ImageRgb8(img.clone())).unwrap();
+        let row_len = packed_row_len(width);
+        assert_eq!(packed.len(), row_len * 3, "largeur {}", width);
+        for (x, y, pixel) in img.enumerate_pixels() {
+            let byte = packed[y as usize * row_len + x as usize / 8];
+            assert_eq!(byte >> (7 - x % 8) & 1 == 1, *pixel == WHITE, "({}, {}) sur la largeur {}", x, y, width);
+        }
+        // The bits after the last pixel of each row are left at 0
+        let used = width as usize % 8;
+        if used != 0 {
+            assert!(packed.chunks_exact(row_len).all(|row| row[row_len - 1] & (0xff >> used) == 0), "largeur {}", width);
+        }
+    }
+}
+
+#[test]
+fn a_png_decodes_back_to_its_bits_and_its_pixels() {
+    for width in 1..=17 {
+        for height in [1, 2, 7] {
+            let img = noise(width, height, 2, (width * 10 + height) as u64);
+            let gray = GrayImage::from_fn(width, height, |x, y| img.get_pixel(x, y).to_luma());
+            let gray_alpha = GrayAlphaImage::from_fn(width, height, |x, y| img.get_pixel(x, y).to_luma_alpha());
+            let rgba = RgbaImage::from_fn(width, height, |x, y| img.get_pixel(x, y).to_rgba());
+            for img in [DynamicImage::ImageRgb8(img.clone()), DynamicImage::ImageLuma8(gray.clone()), DynamicImage::ImageLumaA8(gray_alpha), DynamicImage::ImageRgba8(rgba)] {
+                let png = encode(&img);
+
+                let mut reader = png::Decoder::new(Cursor::new(&png)).read_info().unwrap();
+                assert_eq!((reader.info().color_type, reader.info().bit_depth), (png::ColorType::Grayscale, png::BitDepth::One));
+                let mut bits = vec![0; reader.output_buffer_size()];
+                let frame = reader.next_frame(&mut bits).unwrap();
+                assert_eq!(&bits[..frame.buffer_size()], pack_bilevel(&img).unwrap(), "{} × {}", width, height);
+
+                let decoded = image::load_from_memory(&png).unwrap();
+                assert_eq!(decoded.to_luma8(), gray, "{} × {}, {:?}", width, height, img.color());
+            }
+        }
+    }
+}
+
+#[test]
+fn a_dithered_image_takes_a_bit_per_pixel() {
+    let img = RgbImage::from_fn(203, 97, |x, y| Rgb([(x + y) as u8, (x * 2) as u8, (y * 3) as u8]));
+    let dithered = DynamicImage::ImageRgb8(Dither::new().apply(&img).unwrap());
+    let png = encode(&dithered);
+    let mut rgb = Cursor::new(Vec::new());
+    dithered.write_to(&mut rgb, ImageOutputFormat::Png).unwrap();
+    assert!(png.len() * 3 < rgb.get_ref().len(), "{} octets sur 1 bit, {} en RGB", png.len(), rgb.get_ref().len());
+
+    // Noise does not compress: the file holds its packed rows, each after
+    // its filter byte, and a few chunks around them
+    for (width, height) in [(256, 64), (203, 97), (9, 300)] {
+        let packed = packed_row_len(width) * height as usize;
+        let png = encode(&DynamicImage::ImageRgb8(noise(width, height, 2, 99)));
+        assert!(png.len() >= packed && png.len() <= packed + height as usize + 200, "{} octets pour {} × {}", png.len(), width, height);
+    }
+}
+
+#[test]
+fn only_opaque_black_and_white_is_packed() {
+    assert_eq!(pack_row(&[0, 0, 0, 255, 255, 255], 3), Some(vec![0b0100_0000]));
+    assert_eq!(pack_row(&[255, 255, 0, 255], 2), Some(vec![0b1000_0000]));
+    // Grey, a colour, a black with an alpha and a channel of white
+    assert_eq!(pack_row(&[0, 0, 0, 127, 127, 127], 3), None);
+    assert_eq!(pack_row(&[255, 0, 0], 3), None);
+    assert_eq!(pack_row(&[0, 0, 0, 254], 4), None);
+    assert_eq!(pack_row(&[0, 255, 0], 3), None);
+
+    let img = noise(10, 10, 3, 5);
+    let mut grey = img.clone();
+    grey.put_pixel(9, 9, Rgb([128; 3]));
+    assert!(pack_bilevel(&DynamicImage::ImageRgb8(img.clone())).is_some());
+    assert!(pack_bilevel(&DynamicImage::ImageRgb8(grey)).is_none());
+    let transparent = RgbaImage::from_fn(10, 10, |x, y| if (x, y) == (3, 4) { Rgba([0, 0, 0, 0]) } else { img.get_pixel(x, y).to_rgba() });
+    assert!(pack_bilevel(&DynamicImage::ImageRgba8(transparent)).is_none());
+    // 16 bits to a channel are never taken for black and white
+    let wide: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_pixel(4, 4, Luma([0]));
+    assert!(pack_bilevel(&DynamicImage::ImageLuma16(wide)).is_none());
+}
@@ -0,0 +1,69 @@
+/* The C interface as a C program sees it: every check failing makes the
+ * program exit with the number of its line. */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "tp_eval.h"
+
+#define CHECK(condition) do { if (!(condition)) { fprintf(stderr, "ffi.c:%d: %s\n", __LINE__, #condition); return __LINE__; } } while (0)
+
+enum { WIDTH = 16, HEIGHT = 4, LEN = WIDTH * HEIGHT * 3 };
+
+static int only_colours(const uint8_t *pixels, const uint8_t *colours, size_t count) {
+    for (size_t i = 0; i < LEN; i += 3) {
+        int found = 0;
+        for (size_t c = 0; c < count; c++) {
+            found |= memcmp(pixels + i, colours + 3 * c, 3) == 0;
+        }
+        if (!found) {
+            return 0;
+        }
+    }
+    return 1;
+}
+
+int main(void) {
+    uint8_t ramp[LEN];
+    uint8_t out[LEN];
+    for (size_t i = 0; i < LEN; i++) {
+        ramp[i] = (uint8_t)((i / 3 % WIDTH) * 255 / (WIDTH - 1));
+    }
+
+    /* The defaults, with and without options */
+    static const uint8_t black_and_white[] = {0, 0, 0, 255, 255, 255};
+    CHECK(dither_process_rgb(ramp, WIDTH, HEIGHT, NULL, out) == DITHER_STATUS_OK);
+    CHECK(only_colours(out, black_and_white, 2));
+    DitherOptions options = dither_options_default();
+    uint8_t again[LEN];
+    CHECK(dither_process_rgb(ramp, WIDTH, HEIGHT, &options, again) == DITHER_STATUS_OK);
+    CHECK(memcmp(out, again, LEN) == 0);
+
+    /* Ordered dithering to a palette of the caller */
+    static const uint8_t palette[] = {0, 0, 0, 255, 136, 0, 255, 255, 255};
+    options.method = DITHER_METHOD_BAYER;
+    options.bayer_order = 2;
+    options.palette = palette;
+    options.palette_len = 3;
+    CHECK(dither_process_rgb(ramp, WIDTH, HEIGHT, &options, out) == DITHER_STATUS_OK);
+    CHECK(only_colours(out, palette, 3));
+
+    /* The errors, which leave the output alone */
+    memset(out, 42, LEN);
+    CHECK(dither_process_rgb(NULL, WIDTH, HEIGHT, NULL, out) == DITHER_STATUS_NULL_POINTER);
+    CHECK(dither_process_rgb(ramp, WIDTH, HEIGHT, NULL, NULL) == DITHER_STATUS_NULL_POINTER);
+    options = dither_options_default();
+    options.method = 99;
+    CHECK(dither_process_rgb(ramp, WIDTH, HEIGHT, &options, out) == DITHER_STATUS_INVALID_OPTIONS);
+    options = dither_options_default();
+    options.strength = 2.0;
+    CHECK(dither_process_rgb(ramp, WIDTH, HEIGHT, &options, out) == DITHER_STATUS_INVALID_OPTIONS);
+    options = dither_options_default();
+    options.palette = palette;
+    options.palette_len = 0;
+    CHECK(dither_process_rgb(ramp, WIDTH, HEIGHT, &options, out) == DITHER_STATUS_INVALID_OPTIONS);
+    CHECK(out[0] == 42 && out[LEN - 1] == 42);
+
+    return 0;
+}
@@ -0,0 +1,37 @@
+//! The C interface, through tests/ffi.c compiled with the system's C
+//! compiler (`$CC`, `cc` otherwise) against the cdylib and the header that
+//! cbindgen generates: run by `cargo test --features ffi`.
+
+#![cfg(all(feature = "ffi", unix))]
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// The directory of the cdylib, which `cargo test` builds along with the rlib
+// it links the tests to: the one holding the test itself
+fn cdylib_dir() -> PathBuf {
+    let test = env::current_exe().unwrap();
+    test.parent().unwrap().to_path_buf()
+}
+
+#[test]
+fn c_program_links_and_runs() {
+    let lib_dir = cdylib_dir();
+    let program = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(compiler)
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror"])
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/ffi.c"))
+        .arg("-I").arg(env!("OUT_DIR"))
+        .arg("-L").arg(&lib_dir)
+        .arg("-ltp_eval")
+        .arg("-o").arg(&program)
+        .status()
+        .expect("le compilateur C ne se lance pas");
+    assert!(status.success(), "tests/ffi.c ne compile pas");
+
+    let library_path = if cfg!(target_os = "macos") { "DYLD_LIBRARY_PATH" } else { "LD_LIBRARY_PATH" };
+    let status = Command::new(&program).env(library_path, &lib_dir).status().unwrap();
+    assert!(status.success(), "tests/ffi.c échoue à la ligne {:?}", status.code());
+}
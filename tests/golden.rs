@@ -0,0 +1,122 @@
+//! The results of seuil, palette and dithering on small images, compared
+//! pixel for pixel with the ones checked in under tests/golden. A change
+//! of output that is intended is recorded by running
+//! `TP_EVAL_REGENERATE_GOLDENS=1 cargo test --test golden`, then reviewing
+//! the images it rewrote.
+
+use std::env;
+use std::f64::consts::PI;
+use std::path::PathBuf;
+
+use image::{Rgb, RgbImage};
+use tp_eval::ops::dither::{Algo, Dither};
+use tp_eval::ops::palette::{modify_image_palette, Distance, Palette, QuantizeOptions, Quantizer};
+use tp_eval::ops::seuil::modify_image_seuil;
+use tp_eval::ops::tramage::{ThresholdMatrix, ThresholdSource};
+use tp_eval::{BLACK, WHITE};
+
+// The images every operation runs on, by name
+fn fixtures() -> Vec<(&'static str, RgbImage)> {
+    vec![
+        ("degrade-gris", RgbImage::from_fn(32, 8, |x, _| {
+            let grey = (x * 255 / 31) as u8;
+            Rgb([grey, grey, grey])
+        })),
+        ("degrade-rgb", RgbImage::from_fn(16, 16, |x, y| Rgb([(x * 17) as u8, (y * 17) as u8, 128]))),
+        ("roue", colour_wheel(24)),
+        ("1x1", RgbImage::from_pixel(1, 1, Rgb([200, 60, 90]))),
+        ("3x3", RgbImage::from_fn(3, 3, |x, y| Rgb([(x * 120) as u8, (y * 120) as u8, ((x + y) * 60) as u8]))),
+    ]
+}
+
+// Hues around the centre, saturated at the edge and white in the middle;
+// outside the disc, mid-grey
+fn colour_wheel(side: u32) -> RgbImage {
+    let centre = (side as f64 - 1.0) / 2.0;
+    RgbImage::from_fn(side, side, |x, y| {
+        let (dx, dy) = (x as f64 - centre, y as f64 - centre);
+        let radius = dx.hypot(dy);
+        if radius > centre {
+            return Rgb([128, 128, 128]);
+        }
+        let saturation = radius / centre;
+        let hue = (dy.atan2(dx) + PI) / (2.0 * PI) * 6.0;
+        let channel = |offset: f64| {
+            let k = (hue + offset) % 6.0;
+            let value = 1.0 - saturation * (k.min(4.0 - k).clamp(0.0, 1.0));
+            (value * 255.0).round() as u8
+        };
+        Rgb([channel(5.0), channel(3.0), channel(1.0)])
+    })
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+// Compares `result` with tests/golden/`name`.png, or rewrites it when
+// regenerating; the difference found, if any
+fn check(name: &str, result: &RgbImage) -> Option<String> {
+    let path = golden_dir().join(format!("{}.png", name));
+    if env::var_os("TP_EVAL_REGENERATE_GOLDENS").is_some() {
+        result.save(&path).unwrap_or_else(|error| panic!("impossible d’écrire {} : {}", path.display(), error));
+        return None;
+    }
+    let expected = match image::open(&path) {
+        Ok(expected) => expected.to_rgb8(),
+        Err(error) => return Some(format!("{} : {} (TP_EVAL_REGENERATE_GOLDENS=1 le crée)", path.display(), error)),
+    };
+    if expected.dimensions() != result.dimensions() {
+        return Some(format!("{} : {:?} au lieu de {:?}", name, result.dimensions(), expected.dimensions()));
+    }
+    let (x, y, got, want) = result.enumerate_pixels()
+        .zip(expected.pixels())
+        .find(|((_, _, got), want)| got != want)
+        .map(|((x, y, got), want)| (x, y, *got, *want))?;
+    Some(format!("{} : premier pixel différent en ({}, {}) : {:?} au lieu de {:?}", name, x, y, got.0, want.0))
+}
+
+// Runs `operation` on every fixture, reporting all the differences at once
+fn check_all(operation: &str, apply: impl Fn(&RgbImage) -> RgbImage) {
+    let failures: Vec<String> = fixtures()
+        .iter()
+        .filter_map(|(fixture, img)| check(&format!("{}-{}", operation, fixture), &apply(img)))
+        .collect();
+    assert!(failures.is_empty(), "{} image(s) différente(s) :\n{}", failures.len(), failures.join("\n"));
+}
+
+#[test]
+fn seuil() {
+    check_all("seuil", |img| modify_image_seuil(img.clone(), 128, WHITE, BLACK, false));
+    check_all("seuil-lineaire", |img| modify_image_seuil(img.clone(), 128, WHITE, BLACK, true));
+}
+
+#[test]
+fn palette() {
+    for n in [2, 4, 8] {
+        check_all(&format!("palette-{}", n), |img| modify_image_palette(img.clone(), &Palette::builtin(n), Distance::Rgb, false).unwrap());
+    }
+    check_all("palette-8-lab", |img| modify_image_palette(img.clone(), &Palette::builtin(8), Distance::Lab, false).unwrap());
+    let options = QuantizeOptions { iterations: 10, seed: 1, quality: 10 };
+    check_all("palette-median-cut-4", |img| {
+        let palette = Quantizer::MedianCut.palette(img, 4, &options);
+        modify_image_palette(img.clone(), &palette, Distance::Rgb, false).unwrap()
+    });
+}
+
+#[test]
+fn dithering() {
+    let cases = [
+        ("floyd-steinberg", Dither::new()),
+        ("atkinson-serpentin", Dither::new().algorithm(Algo::Atkinson).serpentine(true)),
+        ("jjn-lineaire", Dither::new().algorithm(Algo::JarvisJudiceNinke).linear_light(true)),
+        ("ostromoukhov", Dither::new().algorithm(Algo::Ostromoukhov)),
+        ("riemersma", Dither::new().algorithm(Algo::Riemersma)),
+        ("floyd-steinberg-palette-8", Dither::new().palette(Palette::builtin(8))),
+        ("bayer", Dither::new().ordered(ThresholdSource::Matrix(ThresholdMatrix::bayer(3)))),
+        ("bayer-palette-4", Dither::new().ordered(ThresholdSource::Matrix(ThresholdMatrix::bayer(2))).palette(Palette::builtin(4))),
+    ];
+    for (name, dither) in cases {
+        check_all(&format!("dithering-{}", name), |img| dither.apply(img).unwrap());
+    }
+}
@@ -0,0 +1,88 @@
+//! Indexed PNG output: images mapped to palettes of 1 to 256 colours,
+//! written with their palette in the PLTE chunk and their indices on as few
+//! bits as it allows, then decoded back to the same colours, over widths
+//! that leave every number of pixels in the last byte of a row.
+
+use std::io::Cursor;
+
+use image::{Rgb, RgbImage};
+use tp_eval::indexed::{index_depth, index_pixels, pack_indices, write_png, MAX_INDEXED_COLOURS};
+use tp_eval::ops::palette::{modify_image_palette, Distance, Palette};
+use tp_eval::random::Rng;
+
+fn random_palette(rng: &mut Rng, count: usize) -> Palette {
+    Palette::new((0..count).map(|_| Rgb([0, 1, 2].map(|_| rng.below(256) as u8))).collect())
+}
+
+fn random_image(rng: &mut Rng, width: u32, height: u32) -> RgbImage {
+    RgbImage::from_fn(width, height, |_, _| Rgb([0, 1, 2].map(|_| rng.below(256) as u8)))
+}
+
+fn encode(img: &RgbImage, palette: &Palette) -> Vec<u8> {
+    let indices = index_pixels(img, palette.colours()).expect("couleurs de la palette");
+    let mut png = Vec::new();
+    write_png(&mut png, img.width(), img.height(), palette.colours(), &indices).unwrap();
+    png
+}
+
+#[test]
+fn a_mapped_image_decodes_back_to_its_colours_and_its_palette() {
+    let mut rng = Rng::new(100);
+    for (count, depth) in [(1, 1), (2, 1), (3, 2), (4, 2), (5, 4), (16, 4), (17, 8), (200, 8), (256, 8)] {
+        let palette = random_palette(&mut rng, count);
+        for (width, height) in [(1, 1), (3, 5), (13, 7), (64, 2)] {
+            let img = modify_image_palette(random_image(&mut rng, width, height), &palette, Distance::Rgb, false).unwrap();
+            let png = encode(&img, &palette);
+
+            let reader = png::Decoder::new(Cursor::new(&png)).read_info().unwrap();
+            let info = reader.info();
+            assert_eq!((info.color_type, info.bit_depth as u8), (png::ColorType::Indexed, depth), "{} couleurs", count);
+            let plte: Vec<u8> = palette.colours().iter().flat_map(|colour| colour.0).collect();
+            assert_eq!(info.palette.as_deref(), Some(&plte[..]), "{} couleurs", count);
+
+            let decoded = image::load_from_memory(&png).unwrap().to_rgb8();
+            assert_eq!(decoded, img, "{} couleurs sur {} × {}", count, width, height);
+        }
+    }
+}
+
+#[test]
+fn indices_are_packed_from_the_highest_bits_of_each_row() {
+    // Two rows of five pixels
+    let indices = [1, 0, 1, 1, 0, 0, 1, 0, 0, 1];
+    assert_eq!(pack_indices(&indices, 5, png::BitDepth::One), [0b1011_0000, 0b0100_1000]);
+    let indices = [3, 0, 2, 1, 3, 1, 2, 0, 0, 3];
+    assert_eq!(pack_indices(&indices, 5, png::BitDepth::Two), [0b1100_1001, 0b1100_0000, 0b0110_0000, 0b1100_0000]);
+    let indices = [15, 1, 9, 0, 7, 2, 3, 4, 5, 6];
+    assert_eq!(pack_indices(&indices, 5, png::BitDepth::Four), [0xf1, 0x90, 0x70, 0x23, 0x45, 0x60]);
+    assert_eq!(pack_indices(&indices, 5, png::BitDepth::Eight), indices);
+    // The fewest bits that hold every index
+    for count in 1..=MAX_INDEXED_COLOURS {
+        let bits = index_depth(count) as u32;
+        assert!(count <= 1 << bits && (bits == 1 || count > 1 << (bits / 2)), "{} couleurs sur {} bits", count, bits);
+    }
+}
+
+#[test]
+fn the_first_of_equal_colours_is_the_index() {
+    let (red, blue) = (Rgb([255, 0, 0]), Rgb([0, 0, 255]));
+    let img = RgbImage::from_fn(4, 1, |x, _| if x < 2 { red } else { blue });
+    assert_eq!(index_pixels(&img, &[blue, red, blue, red]), Some(vec![1, 1, 0, 0]));
+    let palette = Palette::new(vec![blue, red, blue, red, red]);
+    assert_eq!(image::load_from_memory(&encode(&img, &palette)).unwrap().to_rgb8(), img);
+}
+
+#[test]
+fn only_images_of_the_colours_of_small_palettes_are_indexed() {
+    let mut rng = Rng::new(101);
+    let palette = random_palette(&mut rng, 8);
+    let mut img = modify_image_palette(random_image(&mut rng, 6, 6), &palette, Distance::Rgb, false).unwrap();
+    assert!(index_pixels(&img, palette.colours()).is_some());
+    assert_eq!(index_pixels(&img, &[]), None);
+    let large = random_palette(&mut rng, MAX_INDEXED_COLOURS + 1);
+    let mapped = modify_image_palette(img.clone(), &large, Distance::Rgb, false).unwrap();
+    assert_eq!(index_pixels(&mapped, large.colours()), None);
+    let outside = Rgb(palette.colours()[0].0.map(|channel| channel ^ 1));
+    img.put_pixel(5, 5, outside);
+    assert_eq!(index_pixels(&img, palette.colours()), None);
+}
@@ -0,0 +1,31 @@
+//! The nearest colour of an 8-bit pixel, found on integers by
+//! `PaletteMatcher::nearest_pixel`, against the one found on f64 by
+//! `nearest`, for a few palettes and every sRGB colour under
+//! `cargo test --release`; unoptimized builds, where the 16.7 million colours
+//! take minutes, check one in `STEP`.
+
+use image::Rgb;
+use tp_eval::ops::palette::{Distance, Palette};
+use tp_eval::random::Rng;
+
+const STEP: usize = if cfg!(debug_assertions) { 101 } else { 1 };
+
+fn palettes() -> Vec<Palette> {
+    let mut rng = Rng::new(93);
+    let mut random = |count: usize| Palette::new((0..count).map(|_| Rgb([0, 1, 2].map(|_| rng.below(256) as u8))).collect());
+    // Greys at equal distances from many pixels, to exercise the ties
+    let greys = Palette::new((0..=4).map(|i| Rgb([i * 63; 3])).collect());
+    vec![Palette::builtin(4), Palette::builtin(9), greys, random(16), random(64)]
+}
+
+#[test]
+fn integers_find_the_colour_of_floats_for_every_pixel() {
+    for palette in palettes() {
+        let matcher = palette.matcher(Distance::Rgb, false);
+        for value in (0..1u32 << 24).step_by(STEP) {
+            let pixel = Rgb([(value >> 16) as u8, (value >> 8) as u8, value as u8]);
+            let float = matcher.nearest(pixel.0.map(f64::from));
+            assert_eq!(matcher.nearest_pixel(pixel), float, "{:?} avec la palette {}", pixel, palette);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Invariants of the quantizing operations, checked by proptest on small
+//! random images, palettes and settings: whatever the input, the result has
+//! its size and only the colours it may have.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use image::{Rgb, RgbImage};
+use proptest::prelude::*;
+use tp_eval::diffusion::ALGOS;
+use tp_eval::ops::dither::{Algo, Dither};
+use tp_eval::ops::palette::{modify_image_palette, Distance, Palette};
+use tp_eval::ops::seuil::modify_image_seuil;
+use tp_eval::ops::tramage::{ThresholdMatrix, ThresholdSource};
+use tp_eval::{DitherError, BLACK, WHITE};
+
+fn colour() -> impl Strategy<Value = Rgb<u8>> {
+    any::<[u8; 3]>().prop_map(Rgb)
+}
+
+fn image() -> impl Strategy<Value = RgbImage> {
+    (1u32..12, 1u32..12).prop_flat_map(|(width, height)| {
+        proptest::collection::vec(any::<u8>(), (width * height * 3) as usize)
+            .prop_map(move |data| RgbImage::from_raw(width, height, data).unwrap())
+    })
+}
+
+fn palette() -> impl Strategy<Value = Palette> {
+    proptest::collection::vec(colour(), 1..8).prop_map(Palette::new)
+}
+
+fn distance() -> impl Strategy<Value = Distance> {
+    prop_oneof![
+        Just(Distance::Rgb),
+        Just(Distance::Redmean),
+        Just(Distance::Lab),
+        Just(Distance::Ciede2000),
+        Just(Distance::Hsv([1.0, 1.0, 1.0])),
+    ]
+}
+
+// The distances under which every colour is nearer to itself than to any
+// other; hsv compares a nearly grey pixel on its value alone
+fn distance_to_self() -> impl Strategy<Value = Distance> {
+    prop_oneof![
+        Just(Distance::Rgb),
+        Just(Distance::Redmean),
+        Just(Distance::Lab),
+        Just(Distance::Ciede2000),
+    ]
+}
+
+fn algo() -> impl Strategy<Value = Algo> {
+    (0..ALGOS.len()).prop_map(|i| ALGOS[i].1)
+}
+
+fn threshold_source() -> impl Strategy<Value = ThresholdSource> {
+    prop_oneof![
+        (1u32..=5).prop_map(|order| ThresholdSource::Matrix(ThresholdMatrix::bayer(order))),
+        Just(ThresholdSource::Matrix(ThresholdMatrix::clustered_dot())),
+        Just(ThresholdSource::InterleavedGradientNoise),
+        any::<u64>().prop_map(ThresholdSource::Random),
+    ]
+}
+
+fn only_colours(img: &RgbImage, colours: &[Rgb<u8>]) -> bool {
+    img.pixels().all(|pixel| colours.contains(pixel))
+}
+
+proptest! {
+    #[test]
+    fn palette_gives_only_its_colours(img in image(), palette in palette(), distance in distance(), linear in any::<bool>()) {
+        let result = modify_image_palette(img.clone(), &palette, distance, linear).unwrap();
+        prop_assert_eq!(result.dimensions(), img.dimensions());
+        prop_assert!(only_colours(&result, palette.colours()));
+    }
+
+    #[test]
+    fn palette_is_idempotent(img in image(), palette in palette(), distance in distance_to_self(), linear in any::<bool>()) {
+        let once = modify_image_palette(img, &palette, distance, linear).unwrap();
+        let twice = modify_image_palette(once.clone(), &palette, distance, linear).unwrap();
+        prop_assert_eq!(twice, once);
+    }
+
+    #[test]
+    fn palette_rejects_an_empty_palette(img in image(), distance in distance()) {
+        let result = modify_image_palette(img, &Palette::default(), distance, false);
+        prop_assert!(matches!(result, Err(DitherError::EmptyPalette)));
+    }
+
+    #[test]
+    fn seuil_gives_black_and_white(img in image(), threshold in any::<u8>(), linear in any::<bool>()) {
+        let result = modify_image_seuil(img.clone(), threshold, WHITE, BLACK, linear);
+        prop_assert_eq!(result.dimensions(), img.dimensions());
+        prop_assert!(only_colours(&result, &[BLACK, WHITE]));
+    }
+
+    #[test]
+    fn dithering_gives_black_and_white(img in image(), algo in algo(), serpentine in any::<bool>(), linear in any::<bool>(), strength in 0.0..=1.0f64) {
+        let dither = Dither::new().algorithm(algo).serpentine(serpentine).linear_light(linear);
+        let dither = if algo == Algo::Random { dither.seed(7) } else { dither.strength(strength) };
+        let result = dither.apply(&img).unwrap();
+        prop_assert_eq!(result.dimensions(), img.dimensions());
+        prop_assert!(only_colours(&result, &[BLACK, WHITE]));
+    }
+
+    #[test]
+    fn dithering_gives_only_palette_colours(img in image(), algo in algo(), palette in palette(), linear in any::<bool>()) {
+        prop_assume!(algo.has_kernel());
+        let result = Dither::new().algorithm(algo).palette(palette.clone()).linear_light(linear).apply(&img).unwrap();
+        prop_assert_eq!(result.dimensions(), img.dimensions());
+        prop_assert!(only_colours(&result, palette.colours()));
+    }
+
+    #[test]
+    fn ordered_dithering_gives_only_palette_colours(img in image(), source in threshold_source(), palette in proptest::option::of(palette()), force in 0.0..=255.0f32) {
+        let result = match &palette {
+            Some(palette) => Dither::new().ordered(source).palette(palette.clone()).force(force).apply(&img).unwrap(),
+            None => Dither::new().ordered(source).apply(&img).unwrap(),
+        };
+        prop_assert_eq!(result.dimensions(), img.dimensions());
+        let colours = palette.map_or(vec![BLACK, WHITE], |palette| palette.colours().to_vec());
+        prop_assert!(only_colours(&result, &colours));
+    }
+}
@@ -0,0 +1,130 @@
+//! The loops that walk the image buffers directly, on images that are wider
+//! than tall and taller than wide, where a row length mixed up with a column
+//! length or a pixel off by one shows: each result is compared to the one of
+//! the same operation on the single rows, columns or pixels it is made of.
+
+use image::{GenericImage, GenericImageView, GrayImage, ImageBuffer, Luma, Pixel, Rgb, RgbImage, Rgba, RgbaImage};
+use tp_eval::alpha::{join_rgba, split_rgba};
+use tp_eval::ops::dither::{Algo, Dither, Kernel};
+use tp_eval::ops::palette::{modify_image_palette, Distance, Palette};
+use tp_eval::ops::seuil::{modify_image_seuil, modify_image_seuil_adaptatif};
+use tp_eval::{BLACK, WHITE};
+
+const SIZES: [(u32, u32); 6] = [(7, 3), (3, 7), (1, 5), (5, 1), (13, 2), (2, 13)];
+
+// Every pixel different from its neighbours, and no row nor column alike
+fn image(width: u32, height: u32) -> RgbImage {
+    RgbImage::from_fn(width, height, |x, y| Rgb([(x * 37 + y * 91) as u8, (x * y * 13 + y * 7) as u8, ((x ^ y) * 53 + x) as u8]))
+}
+
+fn rgba(width: u32, height: u32) -> RgbaImage {
+    let rgb = image(width, height);
+    RgbaImage::from_fn(width, height, |x, y| {
+        let [r, g, b] = rgb.get_pixel(x, y).0;
+        Rgba([r, g, b, if (x + 2 * y) % 3 == 0 { 0 } else { 255 }])
+    })
+}
+
+// The `width` × `height` part of `img` whose top left corner is (x, y)
+fn part<P: Pixel<Subpixel = u8> + 'static>(img: &ImageBuffer<P, Vec<u8>>, x: u32, y: u32, width: u32, height: u32) -> ImageBuffer<P, Vec<u8>> {
+    img.view(x, y, width, height).to_image()
+}
+
+// `img` where each column is replaced by `f` of itself
+fn by_columns<P: Pixel<Subpixel = u8> + 'static>(img: &ImageBuffer<P, Vec<u8>>, f: impl Fn(ImageBuffer<P, Vec<u8>>) -> ImageBuffer<P, Vec<u8>>) -> ImageBuffer<P, Vec<u8>> {
+    let mut result = img.clone();
+    for x in 0..img.width() {
+        result.copy_from(&f(part(img, x, 0, 1, img.height())), x, 0).unwrap();
+    }
+    result
+}
+
+// `img` where each row is replaced by `f` of itself
+fn by_rows<P: Pixel<Subpixel = u8> + 'static>(img: &ImageBuffer<P, Vec<u8>>, f: impl Fn(ImageBuffer<P, Vec<u8>>) -> ImageBuffer<P, Vec<u8>>) -> ImageBuffer<P, Vec<u8>> {
+    let mut result = img.clone();
+    for y in 0..img.height() {
+        result.copy_from(&f(part(img, 0, y, img.width(), 1)), 0, y).unwrap();
+    }
+    result
+}
+
+// `img` where each pixel is replaced by `f` of itself, alone in its image
+fn by_pixels<P: Pixel<Subpixel = u8> + 'static>(img: &ImageBuffer<P, Vec<u8>>, f: impl Fn(ImageBuffer<P, Vec<u8>>) -> ImageBuffer<P, Vec<u8>>) -> ImageBuffer<P, Vec<u8>> {
+    by_rows(img, |row| by_columns(&row, &f))
+}
+
+#[test]
+fn a_downward_kernel_dithers_each_column_alone() {
+    let dither = Dither::new().kernel("*; 1".parse::<Kernel>().unwrap());
+    for (width, height) in SIZES {
+        let img = image(width, height);
+        assert_eq!(dither.apply(&img).unwrap(), by_columns(&img, |column| dither.apply(&column).unwrap()), "{} × {}", width, height);
+        let gray = GrayImage::from_fn(width, height, |x, y| Luma([img.get_pixel(x, y)[0]]));
+        assert_eq!(dither.apply_gray(&gray).unwrap(), by_columns(&gray, |column| dither.apply_gray(&column).unwrap()), "{} × {}", width, height);
+        let rgba = rgba(width, height);
+        assert_eq!(dither.apply_rgba(&rgba).unwrap(), by_columns(&rgba, |column| dither.apply_rgba(&column).unwrap()), "{} × {}", width, height);
+    }
+}
+
+#[test]
+fn a_rightward_kernel_dithers_each_row_alone() {
+    let dither = Dither::new().kernel("* 1".parse::<Kernel>().unwrap());
+    for (width, height) in SIZES {
+        let img = image(width, height);
+        assert_eq!(dither.apply(&img).unwrap(), by_rows(&img, |row| dither.apply(&row).unwrap()), "{} × {}", width, height);
+        let rgba = rgba(width, height);
+        assert_eq!(dither.apply_rgba(&rgba).unwrap(), by_rows(&rgba, |row| dither.apply_rgba(&row).unwrap()), "{} × {}", width, height);
+    }
+}
+
+#[test]
+fn riemersma_without_diffusion_quantizes_each_pixel_alone() {
+    let dither = Dither::new().algorithm(Algo::Riemersma).strength(0.0);
+    for (width, height) in SIZES {
+        let img = image(width, height);
+        assert_eq!(dither.apply(&img).unwrap(), by_pixels(&img, |pixel| dither.apply(&pixel).unwrap()), "{} × {}", width, height);
+    }
+}
+
+#[test]
+fn seuil_and_palette_map_each_pixel_alone() {
+    let palette = Palette::builtin(9);
+    for (width, height) in SIZES {
+        let img = image(width, height);
+        let seuil = |img: RgbImage| modify_image_seuil(img, 128, WHITE, BLACK, false);
+        assert_eq!(seuil(img.clone()), by_pixels(&img, seuil), "{} × {}", width, height);
+        let map = |img: RgbImage| modify_image_palette(img, &palette, Distance::Rgb, false).unwrap();
+        assert_eq!(map(img.clone()), by_pixels(&img, map), "{} × {}", width, height);
+    }
+}
+
+#[test]
+fn adaptive_seuil_takes_the_mean_of_the_window_around_each_pixel() {
+    let window = 3;
+    for (width, height) in SIZES {
+        let img = image(width, height);
+        let luma = |x: u32, y: u32| img.get_pixel(x, y).to_luma()[0] as f64;
+        // The lumas are integers, so their sums are exact in any order
+        let expected = RgbImage::from_fn(width, height, |x, y| {
+            let (left, top) = (x.saturating_sub(window / 2), y.saturating_sub(window / 2));
+            let (right, bottom) = ((x + window / 2 + 1).min(width), (y + window / 2 + 1).min(height));
+            let sum: f64 = (top..bottom).flat_map(|y| (left..right).map(move |x| (x, y))).map(|(x, y)| luma(x, y)).sum();
+            let mean = sum / ((right - left) * (bottom - top)) as f64;
+            if luma(x, y) >= mean - 2.0 { WHITE } else { BLACK }
+        });
+        assert_eq!(modify_image_seuil_adaptatif(img.clone(), window, 2.0, WHITE, BLACK, false).unwrap(), expected, "{} × {}", width, height);
+    }
+}
+
+#[test]
+fn the_alpha_plane_is_split_and_joined_in_place() {
+    for (width, height) in SIZES {
+        let img = rgba(width, height);
+        let (rgb, alpha) = split_rgba(&img);
+        for (x, y, pixel) in img.enumerate_pixels() {
+            let [r, g, b, a] = pixel.0;
+            assert_eq!((rgb.get_pixel(x, y).0, alpha.get_pixel(x, y)[0]), ([r, g, b], a), "({}, {}) sur {} × {}", x, y, width, height);
+        }
+        assert_eq!(join_rgba(&rgb, &alpha), img);
+    }
+}
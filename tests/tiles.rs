@@ -0,0 +1,195 @@
+//! Images processed tile by tile, as `--tuiles` does, against the same
+//! operation on the whole image. The modes where each pixel depends only on
+//! itself and its place give the same pixels whatever the tiles; error
+//! diffusion does when the tiles are as wide as the image, since the error
+//! then only crosses bottom edges, or when its kernel sends nothing back to
+//! the left, and otherwise still gives only palette colours, spread as on
+//! the whole image.
+
+use image::{GenericImageView, GrayImage, Luma, Rgb, Rgb32FImage, RgbImage};
+use tp_eval::ops::dither::{Algo, Dither, Kernel};
+use tp_eval::ops::niveaux::{modify_image_niveaux, modify_image_posterize};
+use tp_eval::ops::palette::{modify_image_palette, modify_image_palette_matcher, Distance, Palette};
+use tp_eval::ops::seuil::{modify_image_seuil, modify_image_seuil_rgb};
+use tp_eval::ops::tramage::{ThresholdMatrix, ThresholdSource};
+use tp_eval::random::Rng;
+use tp_eval::tiles::{map_tiles, tiles, Tile};
+use tp_eval::{BLACK, WHITE};
+
+const SIZES: [(u32, u32); 4] = [(37, 23), (23, 37), (40, 8), (5, 1)];
+const SIDES: [u32; 4] = [1, 4, 16, 64];
+
+// Gradients across both directions, with some noise
+fn image(width: u32, height: u32) -> RgbImage {
+    let mut rng = Rng::new(96);
+    RgbImage::from_fn(width, height, |x, y| {
+        let noise = rng.below(24) as u32;
+        Rgb([(x * 255 / width + noise).min(255) as u8, (y * 255 / height) as u8, ((x + y) * 7 + noise) as u8])
+    })
+}
+
+// `img` processed by `f` tile by tile
+fn tiled(img: &RgbImage, side: u32, mut f: impl FnMut(Tile, RgbImage) -> RgbImage) -> RgbImage {
+    let mut result = img.clone();
+    map_tiles(&mut result, side, |tile, part| Ok::<_, ()>(f(tile, part))).unwrap();
+    result
+}
+
+// `img` dithered by `dither` tile by tile, each tile with its part of the
+// alpha plane and of the full precision
+fn dithered(dither: &Dither, img: &RgbImage, side: u32, alpha: Option<&GrayImage>, source: Option<&Rgb32FImage>) -> RgbImage {
+    let mut tiler = dither.tiler(img.width(), img.height(), side).unwrap();
+    let result = tiled(img, side, |tile, part| {
+        assert_eq!(tiler.next_tile(), Some(tile));
+        let alpha = alpha.map(|alpha| alpha.view(tile.x, tile.y, tile.width, tile.height).to_image());
+        let source = source.map(|source| source.view(tile.x, tile.y, tile.width, tile.height).to_image());
+        tiler.push_tile(part, alpha.as_ref(), source.as_ref())
+    });
+    assert_eq!(tiler.next_tile(), None);
+    result
+}
+
+#[test]
+fn the_tiles_cover_the_image_once() {
+    for (width, height) in SIZES {
+        for side in SIDES {
+            let mut covered = vec![0; (width * height) as usize];
+            for tile in tiles(width, height, side) {
+                assert!(tile.width >= 1 && tile.width <= side && tile.height >= 1 && tile.height <= side, "{:?}", tile);
+                for y in tile.y..tile.y + tile.height {
+                    for x in tile.x..tile.x + tile.width {
+                        covered[(y * width + x) as usize] += 1;
+                    }
+                }
+            }
+            assert!(covered.iter().all(|&count| count == 1), "{} × {} en tuiles de {}", width, height, side);
+        }
+    }
+}
+
+#[test]
+fn seuil_and_levels_give_the_pixels_of_the_whole_image() {
+    for (width, height) in SIZES {
+        let img = image(width, height);
+        for side in SIDES {
+            let seuil = |img: RgbImage| modify_image_seuil(img, 100, WHITE, BLACK, true);
+            assert_eq!(tiled(&img, side, |_, tile| seuil(tile)), seuil(img.clone()), "{} × {} en tuiles de {}", width, height, side);
+            let seuil_rgb = |img: RgbImage| modify_image_seuil_rgb(img, [60, 128, 200], false);
+            assert_eq!(tiled(&img, side, |_, tile| seuil_rgb(tile)), seuil_rgb(img.clone()));
+            let niveaux = |img: RgbImage| modify_image_niveaux(img, 5).unwrap();
+            assert_eq!(tiled(&img, side, |_, tile| niveaux(tile)), niveaux(img.clone()));
+            let posterize = |img: RgbImage| modify_image_posterize(img, [2, 3, 4]).unwrap();
+            assert_eq!(tiled(&img, side, |_, tile| posterize(tile)), posterize(img.clone()));
+        }
+    }
+}
+
+#[test]
+fn palette_mapping_gives_the_pixels_of_the_whole_image() {
+    let mut rng = Rng::new(960);
+    // Large enough to be looked up through a lattice
+    let large = Palette::new((0..40).map(|_| Rgb([0, 1, 2].map(|_| rng.below(256) as u8))).collect());
+    let cases = [(Palette::builtin(9), Distance::Rgb, false), (Palette::builtin(5), Distance::Lab, true), (large, Distance::Rgb, false)];
+    for (width, height) in SIZES {
+        let img = image(width, height);
+        for (palette, distance, linear) in &cases {
+            let matcher = palette.matcher(*distance, *linear);
+            let whole = modify_image_palette(img.clone(), palette, *distance, *linear).unwrap();
+            for side in SIDES {
+                assert_eq!(tiled(&img, side, |_, tile| modify_image_palette_matcher(tile, &matcher)), whole, "{} × {} en tuiles de {}, {}", width, height, side, palette);
+            }
+        }
+    }
+}
+
+#[test]
+fn ordered_and_random_dithering_give_the_pixels_of_the_whole_image() {
+    let dithers = [
+        Dither::new().ordered(ThresholdSource::Matrix(ThresholdMatrix::bayer(3))),
+        Dither::new().ordered(ThresholdSource::Matrix(ThresholdMatrix::bayer(2))).palette(Palette::builtin(6)).force(0.4).linear_light(true),
+        Dither::new().ordered(ThresholdSource::InterleavedGradientNoise).palette(Palette::builtin(4)),
+        Dither::new().algorithm(Algo::Random).seed(7),
+    ];
+    for (width, height) in SIZES {
+        let img = image(width, height);
+        for (i, dither) in dithers.iter().enumerate() {
+            let whole = dither.apply(&img).unwrap();
+            for side in SIDES {
+                assert_eq!(dithered(dither, &img, side, None, None), whole, "tramage n°{} sur {} × {} en tuiles de {}", i + 1, width, height, side);
+            }
+        }
+    }
+}
+
+fn diffusions() -> Vec<Dither<'static>> {
+    vec![
+        Dither::new(),
+        Dither::new().algorithm(Algo::Stucki).serpentine(true),
+        Dither::new().algorithm(Algo::JarvisJudiceNinke).palette(Palette::builtin(6)).linear_light(true),
+        Dither::new().algorithm(Algo::Ostromoukhov).strength(0.8).serpentine(true),
+        // Three columns to the right and three rows below
+        Dither::new().kernel("0 0 * 3 1 2; 1 2 1 1 0 2; 0 1 0 0 0 0; 1 0 0 0 1 0".parse::<Kernel>().unwrap()),
+    ]
+}
+
+#[test]
+fn diffusion_over_tiles_as_wide_as_the_image_gives_the_pixels_of_the_whole_image() {
+    let (width, height) = (12, 41);
+    let img = image(width, height);
+    let alpha = GrayImage::from_fn(width, height, |x, y| Luma([if (x * 3 + y) % 7 == 0 { 0 } else { 255 }]));
+    let source = Rgb32FImage::from_fn(width, height, |x, y| Rgb(img.get_pixel(x, y).0.map(|c| (c as f32 + 0.37) / 256.0)));
+    for (i, dither) in diffusions().iter().enumerate() {
+        let whole = dither.apply(&img).unwrap();
+        let with_alpha = diffusions().swap_remove(i).alpha(&alpha).apply(&img).unwrap();
+        let precise = diffusions().swap_remove(i).full_precision(&source).apply(&img).unwrap();
+        // A single tile, then rows of tiles whose height does not divide the image
+        for side in [41, 64, 12, 13, 20] {
+            assert_eq!(dithered(dither, &img, side, None, None), whole, "diffusion n°{} en tuiles de {}", i + 1, side);
+            assert_eq!(dithered(dither, &img, side, Some(&alpha), None), with_alpha, "diffusion n°{} en tuiles de {}, avec alpha", i + 1, side);
+            assert_eq!(dithered(dither, &img, side, None, Some(&source)), precise, "diffusion n°{} en tuiles de {}, en pleine précision", i + 1, side);
+        }
+    }
+}
+
+#[test]
+fn diffusion_forward_and_down_only_gives_the_pixels_of_the_whole_image() {
+    // Each pixel gets the error of the one above it, then of the one on its
+    // left, in the same order whatever the tiles: none of it goes back
+    let dither = Dither::new().kernel("* 1; 1 0".parse::<Kernel>().unwrap());
+    for (width, height) in SIZES {
+        let img = image(width, height);
+        let whole = dither.apply(&img).unwrap();
+        for side in SIDES {
+            assert_eq!(dithered(&dither, &img, side, None, None), whole, "{} × {} en tuiles de {}", width, height, side);
+        }
+    }
+}
+
+#[test]
+fn diffusion_over_small_tiles_keeps_the_palette_and_the_tone() {
+    let palette = Palette::builtin(4);
+    for (width, height) in [(64, 64), (50, 37)] {
+        let grey = RgbImage::from_pixel(width, height, Rgb([100; 3]));
+        for side in [2, 5, 16] {
+            let result = dithered(&Dither::new().algorithm(Algo::Stucki), &grey, side, None, None);
+            let white = result.pixels().filter(|pixel| **pixel == WHITE).count() as f64 / (width * height) as f64;
+            assert!(result.pixels().all(|pixel| *pixel == WHITE || *pixel == BLACK));
+            assert!((white - 100.0 / 255.0).abs() < 0.03, "{} de blanc sur {} × {} en tuiles de {}", white, width, height, side);
+
+            let img = image(width, height);
+            let result = dithered(&Dither::new().palette(palette.clone()).serpentine(true), &img, side, None, None);
+            assert!(result.pixels().all(|pixel| palette.colours().contains(pixel)));
+        }
+    }
+}
+
+#[test]
+fn the_tiler_rejects_riemersma_and_tiles_smaller_than_the_kernel() {
+    assert!(Dither::new().algorithm(Algo::Riemersma).tiler(10, 10, 4).is_err());
+    // Stucki reaches two columns on each side and two rows below
+    assert!(Dither::new().algorithm(Algo::Stucki).tiler(10, 10, 1).is_err());
+    assert!(Dither::new().algorithm(Algo::Stucki).tiler(10, 10, 2).is_ok());
+    assert!(Dither::new().tiler(10, 10, 1).is_ok());
+    assert!(Dither::new().kernel("* 0 0 1".parse::<Kernel>().unwrap()).tiler(10, 10, 2).is_err());
+    assert!(Dither::new().ordered(ThresholdSource::Matrix(ThresholdMatrix::bayer(2))).tiler(10, 10, 1).is_ok());
+}
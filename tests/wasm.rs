@@ -0,0 +1,45 @@
+//! The wasm-bindgen wrapper, run in Node by
+//! `wasm-pack test --node -- --no-default-features --features wasm`.
+
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use tp_eval::wasm::dither_rgba;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+// A horizontal grey ramp, opaque except for its first column
+fn ramp(width: u32, height: u32) -> Vec<u8> {
+    (0..height).flat_map(|_| (0..width).flat_map(|x| {
+        let grey = (x * 255 / (width - 1)) as u8;
+        [grey, grey, grey, if x == 0 { 0 } else { 255 }]
+    })).collect()
+}
+
+#[wasm_bindgen_test]
+fn dithers_to_black_and_white_by_default() {
+    let data = ramp(16, 4);
+    let result = dither_rgba(&data, 16, 4, "").unwrap();
+    assert_eq!(result.len(), data.len());
+    for (pixel, input) in result.chunks_exact(4).zip(data.chunks_exact(4)) {
+        assert!(pixel[..3] == [0, 0, 0] || pixel[..3] == [255, 255, 255]);
+        assert_eq!(pixel[3], input[3]);
+    }
+}
+
+#[wasm_bindgen_test]
+fn maps_to_the_colours_given() {
+    let data = ramp(16, 4);
+    let result = dither_rgba(&data, 16, 4, r##"{"tramage": "bayer", "ordre": 2, "couleurs": "#000,#f80,#fff"}"##).unwrap();
+    let colours = [[0, 0, 0], [255, 136, 0], [255, 255, 255]];
+    assert!(result.chunks_exact(4).all(|pixel| colours.iter().any(|colour| pixel[..3] == colour[..])));
+}
+
+#[wasm_bindgen_test]
+fn rejects_a_buffer_of_the_wrong_size() {
+    assert!(dither_rgba(&[0; 10], 2, 2, "").is_err());
+}
+
+#[wasm_bindgen_test]
+fn rejects_unknown_options() {
+    assert!(dither_rgba(&ramp(4, 4), 4, 4, r#"{"algorithme": "atkinson"}"#).is_err());
+    assert!(dither_rgba(&ramp(4, 4), 4, 4, r#"{"algo": "inconnu"}"#).is_err());
+}
@@ -0,0 +1,377 @@
+//! Binarization of the image against a luma threshold.
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use image::{DynamicImage, GrayImage, Luma, Pixel, Rgb, RgbImage, Rgba, RgbaImage};
+
+use crate::dither_error::DitherError;
+use crate::parallel::{map_pixels, map_rows};
+use crate::simd::{seuil_row, Isa};
+use crate::srgb::{rec709_luma, working_value};
+use crate::{BLACK, BLUE, CYAN, GREEN, MAGENTA, RED, WHITE, YELLOW};
+
+/// Luma from which a pixel is light when no `--valeur` is given.
+pub const DEFAULT_THRESHOLD: u8 = 128;
+
+/// Ways of choosing the threshold from the image itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThresholdMethod {
+    /// Otsu's method: the threshold that best separates the luma histogram
+    /// into two classes
+    Otsu,
+}
+
+/// Names accepted by `seuil --auto`, in the order they are listed in error messages.
+pub const THRESHOLD_METHODS: [(&str, ThresholdMethod); 1] = [
+    ("otsu", ThresholdMethod::Otsu),
+];
+
+impl FromStr for ThresholdMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        THRESHOLD_METHODS.iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, method)| *method)
+            .ok_or_else(|| {
+                let names: Vec<&str> = THRESHOLD_METHODS.iter().map(|(name, _)| *name).collect();
+                format!("méthode inconnue : {} (attendues : {})", s, names.join(", "))
+            })
+    }
+}
+
+impl ThresholdMethod {
+    /// The threshold chosen for `img`, on the scale of `modify_image_seuil`.
+    pub fn threshold(self, img: &RgbImage, linear: bool) -> u8 {
+        match self {
+            ThresholdMethod::Otsu => otsu_threshold(img, linear),
+        }
+    }
+}
+
+/// Side of the window of adaptive thresholding when no `--fenetre` is given.
+pub const DEFAULT_WINDOW: u32 = 31;
+
+/// Amount subtracted from the local mean when no `--biais` is given.
+pub const DEFAULT_BIAS: f64 = 5.0;
+
+/// The two-level decision shared by seuil and dithering: a luma on the
+/// 0..=255 scale is light when it reaches `threshold`, so that the default
+/// of 128 splits the 256 values in two halves.
+pub fn is_light(luma: f64, threshold: u8) -> bool {
+    luma >= threshold as f64
+}
+
+/// Parses the `--valeur` of the threshold, between 0 and 255.
+pub fn parse_threshold(value: &str) -> Result<u8, String> {
+    value.parse::<u8>().map_err(|_| format!("seuil invalide : {} (attendu : un entier entre 0 et 255)", value))
+}
+
+/// Parses the `--valeurs` of seuil-rgb: one threshold for all three channels,
+/// or three comma-separated ones for red, green and blue.
+pub fn parse_channel_thresholds(value: &str) -> Result<[u8; 3], String> {
+    let thresholds = value.split(',').map(|token| parse_threshold(token.trim())).collect::<Result<Vec<u8>, String>>()?;
+    match thresholds[..] {
+        [threshold] => Ok([threshold; 3]),
+        [red, green, blue] => Ok([red, green, blue]),
+        _ => Err(format!("seuils invalides : {} (attendu : un nombre ou trois nombres séparés par des virgules)", value)),
+    }
+}
+
+/// Parses the `--hysteresis` thresholds: the low one then the high one,
+/// separated by a comma.
+pub fn parse_hysteresis(value: &str) -> Result<(u8, u8), String> {
+    let thresholds = value.split(',').map(|token| parse_threshold(token.trim())).collect::<Result<Vec<u8>, String>>()?;
+    match thresholds[..] {
+        [low, high] if low <= high => Ok((low, high)),
+        _ => Err(format!("seuils invalides : {} (attendu : le seuil bas puis le seuil haut, séparés par une virgule)", value)),
+    }
+}
+
+/// Parses the `--fenetre` side of adaptive thresholding, in pixels.
+pub fn parse_window(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(side) if side >= 1 => Ok(side),
+        _ => Err(format!("fenêtre invalide : {} (attendu : un entier strictement positif)", value)),
+    }
+}
+
+/// Parses the `--biais` of adaptive thresholding, which may be negative.
+pub fn parse_bias(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(bias) if bias.is_finite() => Ok(bias),
+        _ => Err(format!("biais invalide : {} (attendu : un réel)", value)),
+    }
+}
+
+/// Pixels whose luma reaches `threshold` become `light`, the others `dark`,
+/// several at a time where the processor allows it (see `simd::seuil_row`).
+/// With `linear`, the luminance in linear light is compared instead, on the
+/// same 0..=255 scale.
+pub fn modify_image_seuil(mut img: RgbImage, threshold: u8, light: Rgb<u8>, dark: Rgb<u8>, linear: bool) -> RgbImage {
+    if linear {
+        map_pixels(&mut img, |_, _, pixel| if is_light(luma(&pixel, linear), threshold) { light } else { dark });
+    } else {
+        let isa = Isa::detect();
+        map_rows(&mut img, |_, row| seuil_row(isa, row, threshold, light, dark));
+    }
+    img
+}
+
+/// `modify_image_seuil` to black and white on a grayscale image, without the
+/// conversion to RGB; a grey pixel gets the same result in both.
+pub fn modify_image_seuil_gray(img: GrayImage, threshold: u8, linear: bool) -> GrayImage {
+    seuil_gray(img, threshold, 255, 0, linear)
+}
+
+/// `modify_image_seuil` on an RGBA image, whose alpha channel is kept as it is.
+pub fn modify_image_seuil_rgba(mut img: RgbaImage, threshold: u8, light: Rgb<u8>, dark: Rgb<u8>, linear: bool) -> RgbaImage {
+    map_pixels(&mut img, |_, _, Rgba([r, g, b, a])| {
+        let [r, g, b] = if is_light(luma(&Rgb([r, g, b]), linear), threshold) { light.0 } else { dark.0 };
+        Rgba([r, g, b, a])
+    });
+    img
+}
+
+/// `modify_image_seuil` on any image, whose pixel type is kept when it can
+/// hold the result: a grayscale image stays one when both colours are greys,
+/// and the alpha channel, if any, is kept as it is. The other images give
+/// 8-bit RGB or RGBA.
+///
+/// ```
+/// use image::{DynamicImage, GrayImage};
+/// use tp_eval::ops::seuil::modify_image_seuil_dynamic;
+/// use tp_eval::{BLACK, WHITE};
+///
+/// let img = DynamicImage::ImageLuma8(GrayImage::from_pixel(4, 4, image::Luma([200])));
+/// let result = modify_image_seuil_dynamic(img, 128, WHITE, BLACK, false);
+/// assert!(result.as_luma8().is_some_and(|gray| gray.pixels().all(|pixel| pixel[0] == 255)));
+/// ```
+pub fn modify_image_seuil_dynamic(img: DynamicImage, threshold: u8, light: Rgb<u8>, dark: Rgb<u8>, linear: bool) -> DynamicImage {
+    let is_grey = |colour: Rgb<u8>| colour.0.iter().all(|&c| c == colour[0]);
+    match img {
+        DynamicImage::ImageLuma8(gray) if is_grey(light) && is_grey(dark) => {
+            DynamicImage::ImageLuma8(seuil_gray(gray, threshold, light[0], dark[0], linear))
+        }
+        DynamicImage::ImageRgb8(rgb) => DynamicImage::ImageRgb8(modify_image_seuil(rgb, threshold, light, dark, linear)),
+        img if img.color().has_alpha() => DynamicImage::ImageRgba8(modify_image_seuil_rgba(img.into_rgba8(), threshold, light, dark, linear)),
+        img => DynamicImage::ImageRgb8(modify_image_seuil(img.into_rgb8(), threshold, light, dark, linear)),
+    }
+}
+
+// Grey pixels whose luma reaches `threshold` become `light`, the others `dark`
+fn seuil_gray(mut img: GrayImage, threshold: u8, light: u8, dark: u8, linear: bool) -> GrayImage {
+    map_pixels(&mut img, |_, _, Luma([value])| Luma([if is_light(gray_luma(value, linear), threshold) { light } else { dark }]));
+    img
+}
+
+// The luma of a grey pixel, as `luma` computes it for the same value on all
+// three channels
+fn gray_luma(value: u8, linear: bool) -> f64 {
+    if linear { rec709_luma([working_value(value, true); 3]) } else { value as f64 }
+}
+
+// The luma compared against the threshold, in 0..=255
+fn luma(pixel: &Rgb<u8>, linear: bool) -> f64 {
+    if linear {
+        rec709_luma(pixel.0.map(|c| working_value(c, true)))
+    } else {
+        let Luma(luminosite_) = pixel.to_luma();
+        luminosite_[0] as f64
+    }
+}
+
+/// The number of pixels of each luma, the one compared against the threshold.
+pub fn luma_histogram(img: &RgbImage, linear: bool) -> [u64; 256] {
+    let mut histogram = [0u64; 256];
+    for pixel in img.pixels() {
+        histogram[(luma(pixel, linear) as usize).min(255)] += 1;
+    }
+    histogram
+}
+
+// Otsu's threshold: the t maximizing the between-class variance of the
+// histogram split into the lumas below t and those from t on. When several
+// thresholds separate the classes equally well, the lowest one is kept.
+fn otsu_threshold(img: &RgbImage, linear: bool) -> u8 {
+    let histogram = luma_histogram(img, linear);
+    let total: u64 = histogram.iter().sum();
+    let total_sum: f64 = histogram.iter().enumerate().map(|(value, &count)| value as f64 * count as f64).sum();
+    let (mut below, mut below_sum) = (0u64, 0.0);
+    let (mut best_threshold, mut best_variance) = (DEFAULT_THRESHOLD, 0.0);
+
+    for t in 1..256 {
+        below += histogram[t - 1];
+        below_sum += (t - 1) as f64 * histogram[t - 1] as f64;
+        let above = total - below;
+        if below == 0 || above == 0 {
+            continue;
+        }
+        let mean_below = below_sum / below as f64;
+        let mean_above = (total_sum - below_sum) / above as f64;
+        let variance = below as f64 * above as f64 * (mean_below - mean_above).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best_threshold = t as u8;
+        }
+    }
+    best_threshold
+}
+
+/// Adaptive thresholding: a pixel is light when its luma reaches the mean
+/// luma of the `window` × `window` square around it, minus `bias`. Near the
+/// borders, the square is clipped to the image. An even window has one more
+/// pixel above and to the left of the current one than below and to the right.
+/// The window must be at least one pixel wide.
+pub fn modify_image_seuil_adaptatif(mut img: RgbImage, window: u32, bias: f64, light: Rgb<u8>, dark: Rgb<u8>, linear: bool) -> Result<RgbImage, DitherError> {
+    if window == 0 {
+        return Err(DitherError::InvalidParameter("la fenêtre doit mesurer au moins un pixel".to_string()));
+    }
+    let (width, height) = img.dimensions();
+    let (w, h) = (width as usize, height as usize);
+
+    // Summed-area table, with a leading row and column of zeros so that
+    // every window sum takes four lookups
+    let mut integral = vec![0.0; (w + 1) * (h + 1)];
+    for (y, row) in img.rows().enumerate() {
+        let mut row_sum = 0.0;
+        for (x, pixel) in row.enumerate() {
+            row_sum += luma(pixel, linear);
+            integral[(y + 1) * (w + 1) + x + 1] = integral[y * (w + 1) + x + 1] + row_sum;
+        }
+    }
+
+    let before = (window / 2) as usize;
+    let after = ((window - 1) / 2) as usize;
+    for (y, row) in img.rows_mut().enumerate() {
+        let (top, bottom) = (y.saturating_sub(before), (y + after + 1).min(h));
+        for (x, pixel) in row.enumerate() {
+            let (left, right) = (x.saturating_sub(before), (x + after + 1).min(w));
+            let sum = integral[bottom * (w + 1) + right] - integral[top * (w + 1) + right] - integral[bottom * (w + 1) + left] + integral[top * (w + 1) + left];
+            let mean = sum / ((bottom - top) * (right - left)) as f64;
+            *pixel = if luma(pixel, linear) >= mean - bias { light } else { dark };
+        }
+    }
+    Ok(img)
+}
+
+/// Hysteresis thresholding: pixels whose luma reaches `high` are light, those
+/// below `low` are dark, and the ones in between are light only when they are
+/// 8-connected to a light pixel through other in-between pixels. `low` cannot
+/// be above `high`.
+pub fn modify_image_seuil_hysteresis(mut img: RgbImage, low: u8, high: u8, light: Rgb<u8>, dark: Rgb<u8>, linear: bool) -> Result<RgbImage, DitherError> {
+    if low > high {
+        return Err(DitherError::InvalidParameter(format!("le seuil bas {} dépasse le seuil haut {}", low, high)));
+    }
+    let (width, height) = img.dimensions();
+    let lumas: Vec<f64> = img.pixels().map(|pixel| luma(pixel, linear)).collect();
+    let mut is_light: Vec<bool> = lumas.iter().map(|&l| l >= high as f64).collect();
+
+    // Breadth-first search from every strong pixel through the weak ones
+    let mut queue: VecDeque<usize> = (0..lumas.len()).filter(|&i| is_light[i]).collect();
+    while let Some(i) = queue.pop_front() {
+        let (x, y) = ((i % width as usize) as i64, (i / width as usize) as i64);
+        for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                continue;
+            }
+            let neighbour = (ny * width as i64 + nx) as usize;
+            if !is_light[neighbour] && lumas[neighbour] >= low as f64 {
+                is_light[neighbour] = true;
+                queue.push_back(neighbour);
+            }
+        }
+    }
+
+    for (pixel, light_pixel) in img.pixels_mut().zip(is_light) {
+        *pixel = if light_pixel { light } else { dark };
+    }
+    Ok(img)
+}
+
+/// Thresholds each channel on its own, which gives one of the eight corners
+/// of the RGB cube per pixel. With `linear`, the channels are compared in
+/// linear light, on the same 0..=255 scale.
+pub fn modify_image_seuil_rgb(mut img: RgbImage, thresholds: [u8; 3], linear: bool) -> RgbImage {
+    map_pixels(&mut img, |_, _, pixel| {
+        let [r, g, b] = [0, 1, 2].map(|c| is_light(working_value(pixel[c], linear), thresholds[c]));
+        match (r, g, b) {
+            (false, false, false) => BLACK,
+            (true, false, false) => RED,
+            (false, true, false) => GREEN,
+            (false, false, true) => BLUE,
+            (true, true, false) => YELLOW,
+            (true, false, true) => MAGENTA,
+            (false, true, true) => CYAN,
+            (true, true, true) => WHITE,
+        }
+    });
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn otsu_splits_a_bimodal_image_between_its_modes() {
+        // Two levels are separated by every threshold from just above the
+        // lower one to the higher one: the lowest of them is kept
+        for (dark, light, dark_share) in [(40, 200, 1), (40, 200, 3), (10, 11, 1), (0, 255, 7)] {
+            let img = RgbImage::from_fn(8, 8, |x, _| Rgb([if x < dark_share { dark } else { light }; 3]));
+            assert_eq!(ThresholdMethod::Otsu.threshold(&img, false), dark + 1, "{} et {}", dark, light);
+        }
+        // Two bands of lumas, 20 to 60 and 150 to 190, as many of each
+        let img = RgbImage::from_fn(41, 2, |x, y| Rgb([x as u8 + if y == 0 { 20 } else { 150 }; 3]));
+        let threshold = ThresholdMethod::Otsu.threshold(&img, false);
+        assert_eq!(threshold, 61);
+        let binary = modify_image_seuil(img, threshold, WHITE, BLACK, false);
+        assert!(binary.enumerate_pixels().all(|(_, y, &pixel)| pixel == if y == 0 { BLACK } else { WHITE }));
+    }
+
+    #[test]
+    fn hysteresis_keeps_thin_strokes_whole() {
+        // A one-pixel diagonal stroke, bright at one end and fading along
+        // it, and a lone faint pixel away from it, on a dark background
+        let stroke = |x: u32, y: u32| x == y && x < 24;
+        let img = RgbImage::from_fn(32, 32, |x, y| match (x, y) {
+            _ if stroke(x, y) => Rgb([(200 - 4 * x) as u8; 3]),
+            (28, 4) => Rgb([150; 3]),
+            _ => Rgb([30; 3]),
+        });
+        let linked = modify_image_seuil_hysteresis(img.clone(), 100, 180, WHITE, BLACK, false).unwrap();
+        for (x, y, &pixel) in linked.enumerate_pixels() {
+            assert_eq!(pixel == WHITE, stroke(x, y), "({}, {})", x, y);
+        }
+        // A single threshold either breaks the stroke or keeps the faint pixel
+        for threshold in 100..=180 {
+            let single = modify_image_seuil(img.clone(), threshold, WHITE, BLACK, false);
+            let broken = (0..24).any(|x| *single.get_pixel(x, x) == BLACK);
+            assert!(broken || *single.get_pixel(28, 4) == WHITE, "seuil {}", threshold);
+        }
+    }
+
+    #[test]
+    fn greys_of_127_128_and_129_fall_on_the_same_side_in_every_mode() {
+        // Three bands, dark then light from 128 on
+        let img = RgbImage::from_fn(3, 4, |x, _| Rgb([127 + x as u8; 3]));
+        let expected = RgbImage::from_fn(3, 4, |x, _| if x == 0 { BLACK } else { WHITE });
+        assert_eq!(modify_image_seuil(img.clone(), DEFAULT_THRESHOLD, WHITE, BLACK, false), expected);
+        let gray = DynamicImage::ImageRgb8(img.clone()).to_luma8();
+        assert_eq!(DynamicImage::ImageLuma8(modify_image_seuil_gray(gray.clone(), DEFAULT_THRESHOLD, false)).to_rgb8(), expected);
+        assert_eq!(modify_image_seuil_rgb(img.clone(), [DEFAULT_THRESHOLD; 3], false), expected);
+        assert_eq!(crate::Dither::new().strength(0.0).apply(&img).unwrap(), expected);
+        // A pixel alone has no error to receive
+        for grey in [127, 128, 129] {
+            let pixel = RgbImage::from_pixel(1, 1, Rgb([grey; 3]));
+            let light = is_light(grey as f64, DEFAULT_THRESHOLD);
+            assert_eq!(light, grey >= 128);
+            let expected = if light { WHITE } else { BLACK };
+            assert_eq!(*crate::Dither::new().apply(&pixel).unwrap().get_pixel(0, 0), expected, "gris {}", grey);
+            let gray = GrayImage::from_pixel(1, 1, Luma([grey]));
+            assert_eq!(crate::Dither::new().apply_gray(&gray).unwrap().get_pixel(0, 0)[0] == 255, light, "gris {}", grey);
+        }
+    }
+}
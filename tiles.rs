@@ -0,0 +1,316 @@
+//! `--tuiles`: an image processed one square tile at a time, so that the
+//! copies and the floats an operation works on are the size of a tile rather
+//! than of the image. The operations where a pixel depends on nothing but
+//! itself and its place give the same result as on the whole image. Error
+//! diffusion dithers the tiles in order and carries its error across their
+//! right and bottom edges through small buffers; the error that would go back
+//! into a tile already dithered is lost, as is the one falling off the image.
+
+use std::collections::VecDeque;
+
+use image::{GenericImage, GenericImageView, GrayImage, Pixel, Rgb, Rgb32FImage, RgbImage};
+
+use crate::alpha::TRANSPARENT;
+use crate::diffusion::{input_value, quantize_with_error, Diffusion, DitherOptions};
+use crate::ditherer::PixelQuantizer;
+use crate::ordered::ThresholdSource;
+use crate::parallel::map_pixels;
+
+/// A rectangle of an image: a square of the tile size, cut short on the
+/// right and bottom edges of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    /// The column of its left edge.
+    pub x: u32,
+    /// The row of its top edge.
+    pub y: u32,
+    /// Its width, the tile size but on the right edge of the image.
+    pub width: u32,
+    /// Its height, the tile size but on the bottom edge of the image.
+    pub height: u32,
+}
+
+/// Parses the `--tuiles` size, the side of the tiles in pixels.
+pub fn parse_tile_size(value: &str) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(side) if side >= 1 => Ok(side),
+        _ => Err(format!("taille de tuile invalide : {} (attendu : un entier strictement positif)", value)),
+    }
+}
+
+/// The tiles of `side` pixels covering a `width` × `height` image, from left
+/// to right and then from top to bottom.
+///
+/// ```
+/// use tp_eval::tiles::{tiles, Tile};
+///
+/// let all: Vec<Tile> = tiles(5, 3, 2).collect();
+/// assert_eq!(all.len(), 6);
+/// assert_eq!(all[2], Tile { x: 4, y: 0, width: 1, height: 2 });
+/// assert_eq!(all[5], Tile { x: 4, y: 2, width: 1, height: 1 });
+/// ```
+///
+/// # Panics
+///
+/// When `side` is 0.
+pub fn tiles(width: u32, height: u32, side: u32) -> impl Iterator<Item = Tile> {
+    assert!(side > 0, "des tuiles de 0 pixel");
+    (0..height).step_by(side as usize).flat_map(move |y| (0..width).step_by(side as usize).map(move |x| tile_at(width, height, side, x, y)))
+}
+
+// The tile whose top left corner is (x, y)
+fn tile_at(width: u32, height: u32, side: u32, x: u32, y: u32) -> Tile {
+    Tile { x, y, width: side.min(width - x), height: side.min(height - y) }
+}
+
+/// Replaces each tile of `img`, in the order of `tiles`, by what `f` makes
+/// of its place and of a copy of its pixels, an image of the same size.
+///
+/// # Panics
+///
+/// When `f` returns an image of another size than the tile.
+pub fn map_tiles<E>(img: &mut RgbImage, side: u32, mut f: impl FnMut(Tile, RgbImage) -> Result<RgbImage, E>) -> Result<(), E> {
+    for tile in tiles(img.width(), img.height(), side) {
+        let done = f(tile, img.view(tile.x, tile.y, tile.width, tile.height).to_image())?;
+        assert_eq!(done.dimensions(), (tile.width, tile.height), "une tuile traitée change de taille");
+        img.copy_from(&done, tile.x, tile.y).expect("the tile lies inside the image");
+    }
+    Ok(())
+}
+
+// How the pixels of each tile are quantized
+pub(crate) enum TileMethod<'a> {
+    // The error carried to the next tiles
+    Diffusion(TileDiffusion<'a>),
+    // Against the threshold at each place, `force` perturbing palette lookups
+    Ordered { source: &'a ThresholdSource, force: f32 },
+    // Ordered dithering against white noise of its own
+    Random { source: ThresholdSource, force: f32 },
+}
+
+/// Dithers an image tile by tile, in the order of `tiles`, with the settings
+/// of the `Dither` it comes from (see `Dither::tiler`). Ordered dithering and
+/// random thresholding give the same colours as on the whole image.
+///
+/// ```
+/// use image::RgbImage;
+/// use tp_eval::ops::tramage::{ThresholdMatrix, ThresholdSource};
+/// use tp_eval::tiles::map_tiles;
+/// use tp_eval::Dither;
+///
+/// let img = RgbImage::from_fn(40, 24, |x, y| image::Rgb([(x * 6) as u8, (y * 10) as u8, 128]));
+/// let dither = Dither::new().ordered(ThresholdSource::Matrix(ThresholdMatrix::bayer(2)));
+/// let mut tiler = dither.tiler(img.width(), img.height(), 16).unwrap();
+/// let mut tiled = img.clone();
+/// map_tiles(&mut tiled, 16, |_, tile| Ok::<_, ()>(tiler.push_tile(tile, None, None))).unwrap();
+/// assert_eq!(tiled, dither.apply(&img).unwrap());
+/// ```
+pub struct Tiler<'a> {
+    width: u32,
+    height: u32,
+    side: u32,
+    next: Option<Tile>,
+    linear: bool,
+    quantizer: Box<dyn PixelQuantizer + 'a>,
+    method: TileMethod<'a>,
+}
+
+impl<'a> Tiler<'a> {
+    pub(crate) fn new(width: u32, height: u32, side: u32, linear: bool, quantizer: Box<dyn PixelQuantizer + 'a>, method: TileMethod<'a>) -> Tiler<'a> {
+        let next = (width > 0 && height > 0).then(|| tile_at(width, height, side, 0, 0));
+        Tiler { width, height, side, next, linear, quantizer, method }
+    }
+
+    /// The tile `push_tile` expects, or None once they are all done.
+    pub fn next_tile(&self) -> Option<Tile> {
+        self.next
+    }
+
+    /// The next tile, `img`, dithered. `alpha` is its alpha plane, whose
+    /// fully transparent pixels spread no error, and `source` its
+    /// full-precision pixels, diffused instead of `img`.
+    ///
+    /// # Panics
+    ///
+    /// When all the tiles are done, or when `img`, `alpha` or `source` is not
+    /// the size of `next_tile`.
+    pub fn push_tile(&mut self, mut img: RgbImage, alpha: Option<&GrayImage>, source: Option<&Rgb32FImage>) -> RgbImage {
+        let tile = self.next.expect("toutes les tuiles sont déjà tramées");
+        let size = (tile.width, tile.height);
+        assert_eq!(img.dimensions(), size, "une tuile de {} × {} était attendue", tile.width, tile.height);
+        assert!(alpha.is_none_or(|alpha| alpha.dimensions() == size), "le plan alpha n’a pas la taille de la tuile");
+        assert!(source.is_none_or(|source| source.dimensions() == size), "la pleine précision n’a pas la taille de la tuile");
+
+        let (linear, quantizer) = (self.linear, &*self.quantizer);
+        let (thresholds, force) = match &mut self.method {
+            TileMethod::Diffusion(diffusion) => {
+                diffusion.dither_tile(tile, &mut img, alpha, source, quantizer);
+                (None, 0.0)
+            }
+            TileMethod::Ordered { source, force } => (Some(&**source), *force),
+            TileMethod::Random { source, force } => (Some(&*source), *force),
+        };
+        if let Some(thresholds) = thresholds {
+            map_pixels(&mut img, |x, y, pixel| quantizer.quantize_ordered(pixel, thresholds.threshold(tile.x + x, tile.y + y), force, linear));
+        }
+
+        self.next = match tile.x + tile.width < self.width {
+            true => Some(tile_at(self.width, self.height, self.side, tile.x + tile.width, tile.y)),
+            false => (tile.y + tile.height < self.height).then(|| tile_at(self.width, self.height, self.side, 0, tile.y + tile.height)),
+        };
+        img
+    }
+}
+
+/// The state of error diffusion between two tiles: the error carried across
+/// the right edge of the last tile to the first columns of the next one, and
+/// across the bottom edge of the current row of tiles to the first rows of
+/// the next row, over the whole width of the image.
+pub(crate) struct TileDiffusion<'a> {
+    diffusion: Diffusion<'a>,
+    serpentin: bool,
+    linear: bool,
+    strength: f64,
+    width: u32,
+    // The errors carried to the first rows of the current row of tiles, and
+    // to those of the next row
+    above: Vec<Vec<[f64; 3]>>,
+    below: Vec<Vec<[f64; 3]>>,
+    // The errors carried from each row of the last tile to the first columns
+    // of the next one
+    right: Vec<Vec<[f64; 3]>>,
+}
+
+impl<'a> TileDiffusion<'a> {
+    /// Diffusion through `diffusion` over an image `width` pixels wide, with
+    /// the scan, the light and the strength of `options`.
+    pub(crate) fn new(diffusion: Diffusion<'a>, width: u32, options: &DitherOptions) -> TileDiffusion<'a> {
+        let rows = || (1..diffusion.depth()).map(|_| vec![[0.0; 3]; width as usize]).collect();
+        TileDiffusion {
+            diffusion,
+            serpentin: options.serpentin,
+            linear: options.linear,
+            strength: options.strength,
+            width,
+            above: rows(),
+            below: rows(),
+            right: Vec::new(),
+        }
+    }
+
+    /// The smallest side of the tiles whose errors reach no further than the
+    /// next tile to the right or the next row of tiles.
+    pub(crate) fn min_side(diffusion: Diffusion) -> u32 {
+        diffusion.reach().max(diffusion.depth() - 1).max(1) as u32
+    }
+
+    // Dithers `img`, the pixels of `tile`, row by row as `RowDiffusion` does
+    // with the rows of the whole image
+    fn dither_tile(&mut self, tile: Tile, img: &mut RgbImage, alpha: Option<&GrayImage>, source: Option<&Rgb32FImage>, quantizer: &dyn PixelQuantizer) {
+        let (depth, reach) = (self.diffusion.depth(), self.diffusion.reach());
+        let (w, h, left) = (tile.width as usize, tile.height as usize, tile.x as usize);
+        // The errors of the rows still to come, over the columns of the tile
+        // and `reach` more on each side
+        let mut rows: VecDeque<Vec<[f64; 3]>> = (0..depth).map(|_| vec![[0.0; 3]; w + 2 * reach]).collect();
+        for (row, above) in rows.iter_mut().zip(&self.above) {
+            row[reach..reach + w].copy_from_slice(&above[left..left + w]);
+        }
+
+        let mut right = Vec::with_capacity(h);
+        let buffer: &mut [u8] = img;
+        for r in 0..h {
+            if left > 0 {
+                for (error, carried) in rows[0][reach..reach + w].iter_mut().zip(&self.right[r]) {
+                    for c in 0..3 {
+                        error[c] += carried[c];
+                    }
+                }
+            }
+            let reversed = self.serpentin && (tile.y as usize + r) % 2 == 1;
+            let direction = if reversed { -1 } else { 1 };
+            for i in 0..w {
+                let x = if reversed { w - 1 - i } else { i };
+                let index = r * w + x;
+                let pixel = *Rgb::from_slice(&buffer[index * 3..index * 3 + 3]);
+                let input = input_value(pixel, source, self.linear, x as u32, r as u32);
+                let (new_color, error) = quantize_with_error(input, rows[0][reach + x], self.linear, self.strength, |value| quantizer.quantize(value));
+                buffer[index * 3..index * 3 + 3].copy_from_slice(&new_color.0);
+                if alpha.is_some_and(|alpha| alpha.as_raw()[index] == TRANSPARENT) {
+                    continue;
+                }
+
+                self.diffusion.for_each_tap(input, |dx, dy, weight| {
+                    let nx = x as i64 + dx * direction;
+                    if !(0..self.width as i64).contains(&(left as i64 + nx)) {
+                        return;
+                    }
+                    let neighbor = &mut rows[dy as usize][(nx + reach as i64) as usize];
+                    for c in 0..3 {
+                        neighbor[c] += error[c] * weight;
+                    }
+                });
+            }
+
+            // Its right margin goes to the next tile, its left margin to
+            // pixels already dithered
+            let mut done = rows.pop_front().expect("the kernel reaches at least the current row");
+            right.push(done[reach + w..].to_vec());
+            done.fill([0.0; 3]);
+            rows.push_back(done);
+        }
+
+        // What is left reaches the rows below the tile
+        for (below, row) in self.below.iter_mut().zip(&rows) {
+            for (k, error) in row.iter().enumerate() {
+                let Some(x) = (left + k).checked_sub(reach).filter(|&x| x < self.width as usize) else {
+                    continue;
+                };
+                for c in 0..3 {
+                    below[x][c] += error[c];
+                }
+            }
+        }
+        self.right = right;
+        if tile.x + tile.width == self.width {
+            std::mem::swap(&mut self.above, &mut self.below);
+            for row in &mut self.below {
+                row.fill([0.0; 3]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_tiles_cover_the_image_once() {
+        for (width, height, side) in [(1, 1, 1), (10, 7, 3), (64, 64, 64), (5, 200, 16), (0, 4, 2)] {
+            let mut covered = vec![0; (width * height) as usize];
+            for tile in tiles(width, height, side) {
+                assert!(tile.width >= 1 && tile.width <= side && tile.height <= side);
+                for (x, y) in (tile.y..tile.y + tile.height).flat_map(|y| (tile.x..tile.x + tile.width).map(move |x| (x, y))) {
+                    covered[(y * width + x) as usize] += 1;
+                }
+            }
+            assert!(covered.iter().all(|&count| count == 1), "{} × {} en tuiles de {}", width, height, side);
+        }
+        assert_eq!(parse_tile_size("256"), Ok(256));
+        assert!(parse_tile_size("0").is_err() && parse_tile_size("-4").is_err() && parse_tile_size("grand").is_err());
+    }
+
+    #[test]
+    fn mapping_the_tiles_sees_each_of_their_pixels() {
+        let mut img = RgbImage::from_fn(9, 5, |x, y| Rgb([x as u8, y as u8, 0]));
+        map_tiles(&mut img, 4, |tile, mut pixels| {
+            for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+                assert_eq!((pixel[0], pixel[1]), ((tile.x + x) as u8, (tile.y + y) as u8));
+                pixel[2] = (tile.x / 4 + tile.y / 4 * 3) as u8;
+            }
+            Ok::<_, ()>(pixels)
+        }).unwrap();
+        assert!(img.enumerate_pixels().all(|(x, y, pixel)| pixel[2] == (x / 4 + y / 4 * 3) as u8));
+        assert_eq!(map_tiles(&mut img, 4, |_, _| Err("arrêt")), Err("arrêt"));
+    }
+}
@@ -0,0 +1,63 @@
+//! The walk of `--recursif` through the input directories, which lists the
+//! images they contain, in subdirectories too.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::ImageFormat;
+
+use crate::error::Error;
+
+/// The images found under a directory.
+pub struct Walk {
+    /// Paths of the images, sorted within each directory
+    pub images: Vec<PathBuf>,
+    /// Number of files skipped for not having an image extension
+    pub skipped: usize,
+}
+
+/// Lists the files under `root` whose extension names an image format, leaving
+/// out `excluded`, where the outputs are written; the files skipped are logged
+/// at the debug level. Symbolic links are followed, but a directory is never
+/// entered twice, so that a link to one of its parents does not loop.
+pub fn walk(root: &Path, excluded: Option<&Path>) -> Result<Walk, Error> {
+    let mut walk = Walk { images: Vec::new(), skipped: 0 };
+    let excluded = excluded.and_then(|excluded| excluded.canonicalize().ok());
+    let mut visited = HashSet::new();
+    let root_real = root.canonicalize().map_err(|error| Error::reading(root.to_path_buf(), error))?;
+    visited.insert(root_real);
+    let entries = read_sorted(root).map_err(|error| Error::reading(root.to_path_buf(), error))?;
+    let mut pending: Vec<PathBuf> = entries.into_iter().rev().collect();
+
+    // Depth first, in the order of the names
+    while let Some(path) = pending.pop() {
+        if path.is_dir() {
+            let Ok(real) = path.canonicalize() else { continue };
+            if excluded.as_ref() == Some(&real) {
+                log::debug!("ignoré : {} (dossier de sortie)", path.display());
+                continue;
+            }
+            if !visited.insert(real) {
+                log::debug!("ignoré : {} (dossier déjà parcouru)", path.display());
+                continue;
+            }
+            match read_sorted(&path) {
+                Ok(entries) => pending.extend(entries.into_iter().rev()),
+                Err(error) => log::warn!("impossible de lire le dossier {} : {}", path.display(), error),
+            }
+        } else if ImageFormat::from_path(&path).is_ok() {
+            walk.images.push(path);
+        } else {
+            log::debug!("ignoré : {} (pas une image)", path.display());
+            walk.skipped += 1;
+        }
+    }
+    Ok(walk)
+}
+
+fn read_sorted(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    Ok(entries)
+}
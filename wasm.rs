@@ -0,0 +1,162 @@
+//! The wrapper for JavaScript, through wasm-bindgen: the RGBA buffer of a
+//! canvas dithered with options given in JSON, named as the options of the
+//! `dithering` and `tramage` modes.
+//!
+//! ```js
+//! import init, { dither_rgba } from "./pkg/tp_eval.js";
+//!
+//! await init();
+//! const pixels = context.getImageData(0, 0, width, height);
+//! const result = dither_rgba(pixels.data, width, height, '{"algo": "atkinson", "couleurs": "#000,#fff,#f80"}');
+//! context.putImageData(new ImageData(new Uint8ClampedArray(result), width, height), 0, 0);
+//! ```
+
+use image::RgbaImage;
+use serde::Deserialize;
+use wasm_bindgen::prelude::{wasm_bindgen, JsError};
+
+use crate::diffusion::{Algo, Kernel};
+use crate::dither::Dither;
+use crate::ordered::{ThresholdMatrix, ThresholdSource, DEFAULT_BAYER_ORDER, MAX_BAYER_ORDER};
+use crate::palette::{parse_colour_list, Palette};
+
+// The options of `dither_rgba`, all optional: Floyd-Steinberg to black and
+// white by default
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Options {
+    // One of the names of --algo
+    algo: Option<String>,
+    // A custom kernel, as --noyau, and its divisor
+    noyau: Option<String>,
+    diviseur: Option<u32>,
+    // Ordered dithering instead of error diffusion: bayer, bruit-bleu,
+    // halftone or ign
+    tramage: Option<String>,
+    // The order of the Bayer matrix
+    ordre: Option<u32>,
+    // Hex colours separated by commas
+    couleurs: Option<String>,
+    // The first colours of the built-in list
+    palette: Option<usize>,
+    lineaire: bool,
+    serpentin: bool,
+    // The strength of error diffusion, or the amplitude of the perturbation
+    // of ordered dithering to a palette
+    force: Option<f64>,
+    valeur: Option<u8>,
+    seed: Option<u64>,
+}
+
+impl Options {
+    // The settings of the library, or why these options make none
+    fn dither(&self) -> Result<Dither<'static>, String> {
+        if self.diviseur.is_some() && self.noyau.is_none() {
+            return Err("diviseur n’a de sens qu’avec noyau".to_string());
+        }
+        let mut dither = match (&self.tramage, &self.noyau) {
+            (Some(_), Some(_)) => return Err("tramage et noyau ne peuvent pas être utilisés ensemble".to_string()),
+            (Some(_), None) | (None, Some(_)) if self.algo.is_some() => return Err("algo n’a pas de sens avec tramage ou noyau".to_string()),
+            (Some(tramage), None) => Dither::new().ordered(self.threshold_source(tramage)?),
+            (None, Some(noyau)) => {
+                let kernel: Kernel = noyau.parse()?;
+                Dither::new().kernel(match self.diviseur {
+                    Some(divisor) => kernel.with_divisor(divisor),
+                    None => kernel,
+                })
+            }
+            (None, None) => Dither::new().algorithm(self.algo.as_deref().map_or(Ok(Algo::FloydSteinberg), str::parse)?),
+        };
+        dither = dither.linear_light(self.lineaire).serpentine(self.serpentin);
+        let palette = match (&self.couleurs, self.palette) {
+            (Some(_), Some(_)) => return Err("palette et couleurs ne peuvent pas être utilisés ensemble".to_string()),
+            (Some(couleurs), None) => Some(parse_colour_list(couleurs)?),
+            (None, Some(count)) => Some(Palette::builtin(count)),
+            (None, None) => None,
+        };
+        if let Some(palette) = palette {
+            dither = dither.palette(palette);
+        }
+        // Their ranges are checked by the library
+        if let Some(force) = self.force {
+            dither = match self.tramage {
+                Some(_) => dither.force(force as f32),
+                None => dither.strength(force),
+            };
+        }
+        if let Some(valeur) = self.valeur {
+            dither = dither.threshold(valeur);
+        }
+        if let Some(seed) = self.seed {
+            dither = dither.seed(seed);
+        }
+        Ok(dither)
+    }
+
+    fn threshold_source(&self, tramage: &str) -> Result<ThresholdSource, String> {
+        let order = match self.ordre {
+            Some(order) if !(1..=MAX_BAYER_ORDER).contains(&order) => return Err(format!("l’ordre doit être un entier entre 1 et {}", MAX_BAYER_ORDER)),
+            order => order,
+        };
+        match tramage {
+            "bayer" => Ok(ThresholdSource::Matrix(ThresholdMatrix::bayer(order.unwrap_or(DEFAULT_BAYER_ORDER)))),
+            _ if order.is_some() => Err("ordre n’a de sens qu’avec le tramage bayer".to_string()),
+            "bruit-bleu" => Ok(ThresholdSource::Matrix(ThresholdMatrix::blue_noise())),
+            "halftone" => Ok(ThresholdSource::Matrix(ThresholdMatrix::clustered_dot())),
+            "ign" => Ok(ThresholdSource::InterleavedGradientNoise),
+            _ => Err(format!("tramage inconnu : {} (attendus : bayer, bruit-bleu, halftone, ign)", tramage)),
+        }
+    }
+}
+
+/// The RGBA pixels `data`, of an image of `width` × `height`, dithered with
+/// the options of `options_json`; the alpha channel is kept as it is. An
+/// empty string stands for Floyd-Steinberg to black and white.
+#[wasm_bindgen]
+pub fn dither_rgba(data: &[u8], width: u32, height: u32, options_json: &str) -> Result<Vec<u8>, JsError> {
+    dither_buffer(data, width, height, options_json).map_err(|message| JsError::new(&message))
+}
+
+// `dither_rgba` with its errors as text
+fn dither_buffer(data: &[u8], width: u32, height: u32, options_json: &str) -> Result<Vec<u8>, String> {
+    let options: Options = match options_json.trim() {
+        "" => Options::default(),
+        json => serde_json::from_str(json).map_err(|error| format!("options invalides : {}", error))?,
+    };
+    if data.len() as u64 != width as u64 * height as u64 * 4 {
+        return Err(format!("{} octets ne font pas une image RGBA de {} × {}", data.len(), width, height));
+    }
+    let img = RgbaImage::from_raw(width, height, data.to_vec()).expect("the length is the one of the image");
+    let dithered = options.dither()?.apply_rgba(&img).map_err(|error| error.to_string())?;
+    Ok(dithered.into_raw())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflicting_options_are_refused() {
+        for (json, message) in [
+            (r#"{"tramage": "bayer", "noyau": "1/1"}"#, "tramage et noyau"),
+            (r#"{"algo": "atkinson", "tramage": "ign"}"#, "algo n’a pas de sens"),
+            (r#"{"diviseur": 4}"#, "diviseur n’a de sens"),
+            (r#"{"tramage": "ign", "ordre": 2}"#, "ordre n’a de sens"),
+            (r#"{"tramage": "bayer", "ordre": 9}"#, "l’ordre doit être"),
+            (r##"{"palette": 4, "couleurs": "#fff"}"##, "palette et couleurs"),
+            (r##"{"couleur": "#fff"}"##, "options invalides"),
+        ] {
+            let error = dither_buffer(&[0; 4], 1, 1, json).unwrap_err();
+            assert!(error.contains(message), "{} : {}", json, error);
+        }
+        assert!(dither_buffer(&[0; 5], 1, 1, "").unwrap_err().contains("5 octets"));
+    }
+
+    #[test]
+    fn the_alpha_channel_is_kept() {
+        let data: Vec<u8> = (0..16u8).flat_map(|x| [x * 16, 100, 200, x * 15]).collect();
+        let result = dither_buffer(&data, 4, 4, r##"{"tramage": "bayer", "couleurs": "#000,#fff,#f80"}"##).unwrap();
+        assert!(result.chunks_exact(4).zip(data.chunks_exact(4)).all(|(pixel, input)| pixel[3] == input[3]));
+        assert!(result.chunks_exact(4).all(|pixel| [[0, 0, 0], [255, 255, 255], [255, 136, 0]].contains(&[pixel[0], pixel[1], pixel[2]])));
+    }
+}
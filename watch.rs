@@ -0,0 +1,59 @@
+//! The files followed by `--watch`, polled for a new modification time rather
+//! than through the notifications of each system, which editors that replace
+//! the file on save tend to confuse.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// How often the files are looked at
+const POLL: Duration = Duration::from_millis(100);
+
+// How long the files must stay unchanged before a rebuild, since editors
+// often write twice in a row
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+pub struct Watcher {
+    paths: Vec<PathBuf>,
+    // When each path was last modified, None while it is missing
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl Watcher {
+    pub fn new(paths: Vec<PathBuf>) -> Watcher {
+        let modified = paths.iter().map(|path| modified(path)).collect();
+        Watcher { paths, modified }
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Waits for one of the files to change and then to stay as it is for a
+    /// moment, all of them existing. Returns false as soon as `stop` is set.
+    pub fn wait(&mut self, stop: &AtomicBool) -> bool {
+        let mut changed = false;
+        let mut quiet_for = Duration::ZERO;
+        while !stop.load(Ordering::Relaxed) {
+            thread::sleep(POLL);
+            let now: Vec<Option<SystemTime>> = self.paths.iter().map(|path| modified(path)).collect();
+            if now != self.modified {
+                self.modified = now;
+                changed = true;
+                quiet_for = Duration::ZERO;
+            } else if changed && self.modified.iter().all(Option::is_some) {
+                quiet_for += POLL;
+                if quiet_for >= DEBOUNCE {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}